@@ -0,0 +1,41 @@
+//! Measures lexer throughput on generated programs of several sizes.
+//!
+//! Run with `cargo bench --bench lexer`.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use compyl::lexer::Lexer;
+use compyl::testgen;
+
+const SIZES: [usize; 3] = [10, 100, 1_000];
+const RUNS: usize = 10;
+
+fn main() {
+    for functions in SIZES {
+        let source = testgen::program(1, functions);
+
+        let mut best = f64::INFINITY;
+        let mut tokens = 0;
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            tokens = 0;
+            for token in Lexer::new(black_box(&source)) {
+                black_box(token.unwrap());
+                tokens += 1;
+            }
+            best = best.min(start.elapsed().as_secs_f64());
+        }
+
+        let mib = source.len() as f64 / (1024.0 * 1024.0);
+        println!(
+            "{:>5} functions: lexed {} tokens ({:.2} MiB) in {:.2} ms: {:.1} Mtokens/s, {:.1} MiB/s",
+            functions,
+            tokens,
+            mib,
+            best * 1000.0,
+            tokens as f64 / best / 1e6,
+            mib / best
+        );
+    }
+}
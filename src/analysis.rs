@@ -0,0 +1,224 @@
+//! Data-flow analyses over a function's [`Cfg`]: liveness, and reaching
+//! definitions. Both iterate to a fixed point, so they handle loops.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::cfg::Cfg;
+use crate::ir::{BlockId, Function, Operand, Reg};
+
+/// The registers live at the start and end of each block: those that some
+/// path from there reads before writing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Liveness {
+    pub live_in: Vec<BTreeSet<Reg>>,
+    pub live_out: Vec<BTreeSet<Reg>>,
+}
+
+/// A place that writes a register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Def {
+    /// A parameter, written on entry.
+    Param(Reg),
+    /// The instruction at `index` in `block`.
+    Inst { block: BlockId, index: usize },
+}
+
+/// The definitions that may reach the start and end of each block without
+/// their register being written again on the way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReachingDefinitions {
+    pub reach_in: Vec<BTreeSet<Def>>,
+    pub reach_out: Vec<BTreeSet<Def>>,
+}
+
+impl ReachingDefinitions {
+    /// The definitions of `reg` that reach the start of `block`.
+    pub fn of(&self, func: &Function, block: BlockId, reg: Reg) -> Vec<Def> {
+        self.reach_in[block.0 as usize]
+            .iter()
+            .copied()
+            .filter(|&def| defined(func, def) == reg)
+            .collect()
+    }
+}
+
+fn defined(func: &Function, def: Def) -> Reg {
+    match def {
+        Def::Param(reg) => reg,
+        Def::Inst { block, index } => func.blocks[block.0 as usize].insts[index]
+            .dst()
+            .expect("definitions write a register"),
+    }
+}
+
+pub fn liveness(func: &Function, cfg: &Cfg) -> Liveness {
+    // What each block reads before writing, and what it writes.
+    let mut uses = Vec::with_capacity(func.blocks.len());
+    let mut defs = Vec::with_capacity(func.blocks.len());
+    for block in &func.blocks {
+        let (mut used, mut defined) = (BTreeSet::new(), BTreeSet::new());
+        for inst in &block.insts {
+            for operand in inst.operands() {
+                if let Operand::Reg(reg) = operand {
+                    if !defined.contains(reg) {
+                        used.insert(*reg);
+                    }
+                }
+            }
+            defined.extend(inst.dst());
+        }
+        if let Some(Operand::Reg(reg)) = block.term.operand() {
+            if !defined.contains(reg) {
+                used.insert(*reg);
+            }
+        }
+        uses.push(used);
+        defs.push(defined);
+    }
+
+    let mut live_in = vec![BTreeSet::new(); func.blocks.len()];
+    let mut live_out = vec![BTreeSet::<Reg>::new(); func.blocks.len()];
+    let order = cfg.reverse_postorder();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in order.iter().rev() {
+            let b = block.0 as usize;
+            let out: BTreeSet<Reg> = cfg.succs[b]
+                .iter()
+                .flat_map(|succ| live_in[succ.0 as usize].iter().copied())
+                .collect();
+            let mut into = uses[b].clone();
+            into.extend(out.difference(&defs[b]));
+            if into != live_in[b] || out != live_out[b] {
+                (live_in[b], live_out[b]) = (into, out);
+                changed = true;
+            }
+        }
+    }
+
+    Liveness { live_in, live_out }
+}
+
+pub fn reaching_definitions(func: &Function, cfg: &Cfg) -> ReachingDefinitions {
+    let count = func.blocks.len();
+    // The definitions each block leaves behind, and the registers it writes.
+    let mut gens = Vec::with_capacity(count);
+    let mut kills = Vec::with_capacity(count);
+    for (id, block) in func.blocks.iter().enumerate() {
+        let mut last = BTreeMap::new();
+        for (index, inst) in block.insts.iter().enumerate() {
+            if let Some(dst) = inst.dst() {
+                let block = BlockId(id as u32);
+                last.insert(dst, Def::Inst { block, index });
+            }
+        }
+        kills.push(last.keys().copied().collect::<BTreeSet<_>>());
+        gens.push(last.into_values().collect::<BTreeSet<_>>());
+    }
+
+    let mut reach_in = vec![BTreeSet::new(); count];
+    let mut reach_out = vec![BTreeSet::<Def>::new(); count];
+    if count > 0 {
+        reach_in[0] = func.params.iter().map(|&reg| Def::Param(reg)).collect();
+    }
+    let order = cfg.reverse_postorder();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in &order {
+            let b = block.0 as usize;
+            let mut into = reach_in[b].clone();
+            for pred in &cfg.preds[b] {
+                into.extend(reach_out[pred.0 as usize].iter().copied());
+            }
+            let mut out = gens[b].clone();
+            out.extend(
+                into.iter()
+                    .copied()
+                    .filter(|&def| !kills[b].contains(&defined(func, def))),
+            );
+            if into != reach_in[b] || out != reach_out[b] {
+                (reach_in[b], reach_out[b]) = (into, out);
+                changed = true;
+            }
+        }
+    }
+
+    ReachingDefinitions {
+        reach_in,
+        reach_out,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{liveness, reaching_definitions, Def};
+    use crate::cfg::Cfg;
+    use crate::ir::{self, BlockId, Reg};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::{sema, types};
+
+    fn lower(input: &str) -> ir::Module {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.");
+        let resolution = sema::analyze(&program).expect("Program failed analysis.");
+        let types = types::check(&program, &resolution).expect("Program failed to type check.");
+        ir::lower(&program, &resolution, &types).expect("Program failed to lower.")
+    }
+
+    fn regs(regs: &[u32]) -> Vec<Reg> {
+        regs.iter().map(|&reg| Reg(reg)).collect()
+    }
+
+    /// `n` counts down to zero; `x` is written before the loop and in it.
+    const LOOP: &str =
+        "fn f(n: int): int { let x = 1; while (n > 0) { x = x * 2; n -= 1; } return x; }";
+
+    #[test]
+    fn test_liveness() {
+        let module = lower(LOOP);
+        let func = &module.functions[0];
+        let live = liveness(func, &Cfg::new(func));
+
+        // bb0 enters the loop header bb1, whose body is bb2; bb3 returns.
+        assert_eq!(
+            live.live_in[0].iter().copied().collect::<Vec<_>>(),
+            regs(&[0])
+        );
+        assert_eq!(
+            live.live_in[1].iter().copied().collect::<Vec<_>>(),
+            regs(&[0, 1])
+        );
+        assert_eq!(
+            live.live_out[2].iter().copied().collect::<Vec<_>>(),
+            regs(&[0, 1])
+        );
+        assert_eq!(
+            live.live_in[3].iter().copied().collect::<Vec<_>>(),
+            regs(&[1])
+        );
+        assert!(live.live_out[3].is_empty());
+    }
+
+    #[test]
+    fn test_reaching_definitions() {
+        let module = lower(LOOP);
+        let func = &module.functions[0];
+        let reaching = reaching_definitions(func, &Cfg::new(func));
+        let (n, x) = (Reg(0), Reg(1));
+        let def = |block, index| Def::Inst {
+            block: BlockId(block),
+            index,
+        };
+
+        // `let x = 1;` and `x = x * 2;` both reach the loop and what follows.
+        let (before_loop, in_loop) = (def(0, 0), def(2, 2));
+        assert_eq!(reaching.of(func, BlockId(1), x), [before_loop, in_loop]);
+        assert_eq!(reaching.of(func, BlockId(3), x), [before_loop, in_loop]);
+        assert_eq!(reaching.of(func, BlockId(0), n), [Def::Param(n)]);
+        assert_eq!(reaching.of(func, BlockId(1), n), [Def::Param(n), def(2, 4)]);
+    }
+}
@@ -0,0 +1,571 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::intern::Symbol;
+use crate::span::Span;
+
+/// Identifies an expression or declared name within one parsed program, so that
+/// later passes can attach information to it in side tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub u32);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub stmts: Vec<Stmt>,
+    /// The files the program was linked from, each with its own run of
+    /// `stmts`; empty for a program parsed from a single source.
+    pub modules: Vec<Module>,
+}
+
+/// One file of a program linked by a [`crate::source::SourceManager`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub path: String,
+    /// The indices of the top-level statements that came from this file.
+    pub stmts: Range<usize>,
+    /// The modules the file imports, as indices into `Program::modules`.
+    pub imports: Vec<usize>,
+}
+
+pub type Block = Vec<Stmt>;
+
+/// A name at the place it is declared or assigned to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: Symbol,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    Let {
+        name: Ident,
+        ty: Option<TypeExpr>,
+        value: Expr,
+    },
+    /// A `let` whose value is known at compile time, and which can never be
+    /// assigned.
+    Const {
+        name: Ident,
+        ty: Option<TypeExpr>,
+        value: Expr,
+    },
+    /// `target = value`, or `target op= value` when `op` is set.
+    Assign {
+        target: Ident,
+        op: Option<BinaryOp>,
+        value: Expr,
+    },
+    /// `array[index] = value`, or `array[index] op= value` when `op` is set.
+    IndexAssign {
+        array: Expr,
+        index: Expr,
+        op: Option<BinaryOp>,
+        value: Expr,
+    },
+    /// `object.field = value`, or `object.field op= value` when `op` is set.
+    FieldAssign {
+        object: Expr,
+        field: Ident,
+        op: Option<BinaryOp>,
+        value: Expr,
+    },
+    Fn(FnDecl),
+    /// `extern fn name(params): ret;`, a function the embedder provides. Its
+    /// declaration has an empty body.
+    Extern(FnDecl),
+    Struct(StructDecl),
+    If {
+        cond: Expr,
+        then_branch: Block,
+        else_branch: Option<Block>,
+    },
+    While {
+        cond: Expr,
+        body: Block,
+    },
+    /// `for (i in start..end) { ... }`, counting up to just before `end`,
+    /// which is evaluated once before the loop.
+    For {
+        var: Ident,
+        start: Expr,
+        end: Expr,
+        body: Block,
+    },
+    Return(Option<Expr>),
+    Break,
+    Continue,
+    /// `import "path";` or `import name;`, which imports `name.cpl`. The path
+    /// is relative to the importing file.
+    Import(String),
+    Block(Block),
+    Expr(Expr),
+    /// A statement that failed to parse, kept by
+    /// [`Parser::parse_partial`](crate::parser::Parser::parse_partial).
+    /// Programs with one are never compiled.
+    Error,
+}
+
+/// The name given to a function expression written without one.
+pub const ANONYMOUS: &str = "<anonymous>";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnDecl {
+    pub name: Ident,
+    pub params: Vec<Param>,
+    pub ret: Option<TypeExpr>,
+    pub body: Block,
+    /// The `??` comment right before the declaration, without the `??`s.
+    pub doc: Option<String>,
+}
+
+impl FnDecl {
+    pub fn is_anonymous(&self) -> bool {
+        self.name.name.as_str() == ANONYMOUS
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: Ident,
+    pub ty: Option<TypeExpr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructDecl {
+    pub name: Ident,
+    pub fields: Vec<Field>,
+    pub doc: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: Ident,
+    pub ty: TypeExpr,
+}
+
+/// A type annotation as written, e.g. the `int` in `let x: int = 1;`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeExpr {
+    pub kind: TypeExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeExprKind {
+    Named(Symbol),
+    Void,
+    /// `[int]`, an array of `int`s.
+    Array(Box<TypeExpr>),
+    /// `fn(int, bool): int`; the return type defaults to `void`.
+    Fn {
+        params: Vec<TypeExpr>,
+        ret: Option<Box<TypeExpr>>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+    pub id: NodeId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Int(i64),
+    Float(f64),
+    Char(char),
+    Str(String),
+    Bool(bool),
+    Ident(Symbol),
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    /// `${expr}` in a string literal: the value as a string, as `str` gives
+    /// it. The parser joins the parts of the literal with `+`.
+    Interpolation(Box<Expr>),
+    /// `if (cond) a else b`: one of the arms, which have the same type.
+    If {
+        cond: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
+    },
+    /// `match (value) { pattern => result, ... }`: the result of the first
+    /// arm whose pattern matches.
+    Match {
+        scrutinee: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
+    /// `fn(x) { ... }`. A name, if given, is only in scope in the body.
+    Fn(Box<FnDecl>),
+    Array(Vec<Expr>),
+    Index {
+        expr: Box<Expr>,
+        index: Box<Expr>,
+    },
+    /// `Point { x: 1, y: 2 }`, where `name` is a use of the struct.
+    Struct {
+        name: Ident,
+        fields: Vec<FieldInit>,
+    },
+    Field {
+        expr: Box<Expr>,
+        field: Ident,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldInit {
+    pub name: Ident,
+    pub value: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternKind {
+    Int(i64),
+    Char(char),
+    Str(String),
+    Bool(bool),
+    /// A name, which matches anything and is bound to it in the arm.
+    Binding(Ident),
+    /// `_`, which matches anything.
+    Wildcard,
+}
+
+impl PatternKind {
+    /// Whether the pattern matches every value.
+    pub fn is_irrefutable(&self) -> bool {
+        matches!(self, PatternKind::Binding(_) | PatternKind::Wildcard)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    And,
+    Or,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Not => "!",
+            UnaryOp::BitNot => "~",
+        })
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+        })
+    }
+}
+
+// The `Display` impls below print the tree as S-expressions, one top-level
+// statement per line, e.g. `(let x (+ 1 2))`.
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for stmt in &self.stmts {
+            writeln!(f, "{}", stmt)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_block(f: &mut fmt::Formatter<'_>, block: &Block) -> fmt::Result {
+    write!(f, "(block")?;
+    for stmt in block {
+        write!(f, " {}", stmt)?;
+    }
+    write!(f, ")")
+}
+
+fn write_annotation(f: &mut fmt::Formatter<'_>, ty: &Option<TypeExpr>) -> fmt::Result {
+    match ty {
+        Some(ty) => write!(f, ":{}", ty),
+        None => Ok(()),
+    }
+}
+
+impl fmt::Display for TypeExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            TypeExprKind::Named(name) => write!(f, "{}", name),
+            TypeExprKind::Void => write!(f, "void"),
+            TypeExprKind::Array(elem) => write!(f, "[{}]", elem),
+            TypeExprKind::Fn { params, ret } => {
+                write!(f, "fn(")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", param)?;
+                }
+                write!(f, ")")?;
+                match ret {
+                    Some(ret) => write!(f, ":{}", ret),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            StmtKind::Let { name, ty, value } => {
+                write!(f, "(let {}", name.name)?;
+                write_annotation(f, ty)?;
+                write!(f, " {})", value)
+            }
+            StmtKind::Const { name, ty, value } => {
+                write!(f, "(const {}", name.name)?;
+                write_annotation(f, ty)?;
+                write!(f, " {})", value)
+            }
+            StmtKind::Assign {
+                target,
+                op: None,
+                value,
+            } => write!(f, "(= {} {})", target.name, value),
+            StmtKind::Assign {
+                target,
+                op: Some(op),
+                value,
+            } => write!(f, "({}= {} {})", op, target.name, value),
+            StmtKind::IndexAssign {
+                array,
+                index,
+                op,
+                value,
+            } => {
+                let op = op.map_or(String::new(), |op| op.to_string());
+                write!(f, "({}= (index {} {}) {})", op, array, index, value)
+            }
+            StmtKind::FieldAssign {
+                object,
+                field,
+                op,
+                value,
+            } => {
+                let op = op.map_or(String::new(), |op| op.to_string());
+                write!(f, "({}= (. {} {}) {})", op, object, field.name, value)
+            }
+            StmtKind::Fn(decl) => write!(f, "{}", decl),
+            StmtKind::Extern(decl) => {
+                write!(f, "(extern ")?;
+                write_signature(f, decl)?;
+                write!(f, ")")
+            }
+            StmtKind::Struct(decl) => {
+                write!(f, "(struct {} (", decl.name.name)?;
+                for (i, field) in decl.fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}:{}", field.name.name, field.ty)?;
+                }
+                write!(f, "))")
+            }
+            StmtKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                write!(f, "(if {} ", cond)?;
+                write_block(f, then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    write!(f, " ")?;
+                    write_block(f, else_branch)?;
+                }
+                write!(f, ")")
+            }
+            StmtKind::While { cond, body } => {
+                write!(f, "(while {} ", cond)?;
+                write_block(f, body)?;
+                write!(f, ")")
+            }
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                write!(f, "(for {} (.. {} {}) ", var.name, start, end)?;
+                write_block(f, body)?;
+                write!(f, ")")
+            }
+            StmtKind::Return(Some(value)) => write!(f, "(return {})", value),
+            StmtKind::Return(None) => write!(f, "(return)"),
+            StmtKind::Break => write!(f, "(break)"),
+            StmtKind::Continue => write!(f, "(continue)"),
+            StmtKind::Import(path) => write!(f, "(import {:?})", path),
+            StmtKind::Block(block) => write_block(f, block),
+            StmtKind::Expr(expr) => write!(f, "{}", expr),
+            StmtKind::Error => write!(f, "(error)"),
+        }
+    }
+}
+
+impl fmt::Display for FnDecl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(fn ")?;
+        write_signature(f, self)?;
+        write!(f, " ")?;
+        write_block(f, &self.body)?;
+        write!(f, ")")
+    }
+}
+
+fn write_signature(f: &mut fmt::Formatter<'_>, decl: &FnDecl) -> fmt::Result {
+    write!(f, "{} (", decl.name.name)?;
+    for (i, param) in decl.params.iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", param.name.name)?;
+        write_annotation(f, &param.ty)?;
+    }
+    write!(f, ")")?;
+    write_annotation(f, &decl.ret)
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            PatternKind::Int(n) => write!(f, "{}", n),
+            PatternKind::Char(ch) => write!(f, "{:?}", ch),
+            PatternKind::Str(s) => write!(f, "{:?}", s),
+            PatternKind::Bool(b) => write!(f, "{}", b),
+            PatternKind::Binding(name) => write!(f, "{}", name.name),
+            PatternKind::Wildcard => write!(f, "_"),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ExprKind::Int(n) => write!(f, "{}", n),
+            ExprKind::Float(n) => write!(f, "{:?}", n),
+            ExprKind::Char(ch) => write!(f, "{:?}", ch),
+            ExprKind::Str(s) => write!(f, "{:?}", s),
+            ExprKind::Interpolation(expr) => write!(f, "(interpolate {})", expr),
+            ExprKind::Bool(b) => write!(f, "{}", b),
+            ExprKind::Ident(name) => write!(f, "{}", name),
+            ExprKind::Unary { op, expr } => write!(f, "({} {})", op, expr),
+            ExprKind::Binary { op, lhs, rhs } => write!(f, "({} {} {})", op, lhs, rhs),
+            ExprKind::Call { callee, args } => {
+                write!(f, "(call {}", callee)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                write!(f, ")")
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => write!(f, "(if {} {} {})", cond, then_branch, else_branch),
+            ExprKind::Match { scrutinee, arms } => {
+                write!(f, "(match {}", scrutinee)?;
+                for arm in arms {
+                    write!(f, " ({} {})", arm.pattern, arm.body)?;
+                }
+                write!(f, ")")
+            }
+            ExprKind::Fn(decl) => write!(f, "{}", decl),
+            ExprKind::Array(elems) => {
+                write!(f, "(array")?;
+                for elem in elems {
+                    write!(f, " {}", elem)?;
+                }
+                write!(f, ")")
+            }
+            ExprKind::Index { expr, index } => write!(f, "(index {} {})", expr, index),
+            ExprKind::Struct { name, fields } => {
+                write!(f, "(new {}", name.name)?;
+                for field in fields {
+                    write!(f, " ({} {})", field.name.name, field.value)?;
+                }
+                write!(f, ")")
+            }
+            ExprKind::Field { expr, field } => write!(f, "(. {} {})", expr, field.name),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Checking many files at once. Each file is linked with its imports and
+//! checked on its own, on a pool of worker threads, and the diagnostics of
+//! them all come back merged in one order, whichever worker finished first.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::{fs, io, thread};
+
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::error::CompileError;
+use crate::lexer;
+use crate::source::SourceManager;
+use crate::span::FileId;
+use crate::{lints, sema, types};
+
+/// The stack each worker runs on: as much as the compiler's main thread, which
+/// has room for the parser's limits even in debug builds.
+const STACK_SIZE: usize = 64 << 20;
+
+/// A diagnostic rendered against the file it points into. Messages sort by
+/// file and then by where in it they point, the order they are reported in.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Message {
+    pub path: PathBuf,
+    /// The byte offset the diagnostic points at, or 0 if it has no span.
+    pub offset: usize,
+    pub severity: Severity,
+    pub text: String,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub messages: Vec<Message>,
+    /// Every file that was read, those imported among them, sorted.
+    pub files: Vec<PathBuf>,
+}
+
+/// Checks each of `paths` as `compyl check` does, on up to `jobs` threads. A
+/// diagnostic in a file that several of them import is reported once.
+pub fn check(paths: &[PathBuf], jobs: usize, deny_warnings: bool) -> Report {
+    let next = AtomicUsize::new(0);
+    let report = Mutex::new(Report::default());
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, paths.len().max(1)) {
+            let worker = || {
+                while let Some(path) = paths.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let mut files = vec![path.clone()];
+                    let messages = check_file(path, deny_warnings, &mut files);
+                    let mut report = report.lock().unwrap();
+                    report.messages.extend(messages);
+                    report.files.extend(files);
+                }
+            };
+            thread::Builder::new()
+                .stack_size(STACK_SIZE)
+                .spawn_scoped(scope, worker)
+                .expect("failed to start a worker thread");
+        }
+    });
+
+    let mut report = report.into_inner().unwrap();
+    report.messages.sort();
+    report.messages.dedup();
+    report.files.sort();
+    report.files.dedup();
+    report
+}
+
+/// The `.cpl` files in `dir` and its subdirectories, sorted.
+pub fn sources_in(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "cpl") {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Checks the file at `path`, adding the files it imports to `files`.
+fn check_file(path: &Path, deny_warnings: bool, files: &mut Vec<PathBuf>) -> Vec<Message> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            return vec![Message {
+                path: path.to_path_buf(),
+                offset: 0,
+                severity: Severity::Error,
+                text: format!("error: could not read `{}`: {}\n", path.display(), err),
+            }]
+        }
+    };
+    let source = match lexer::decode(&bytes) {
+        Ok(source) => source,
+        Err(err) => {
+            let diag = Diagnostic::from(&err);
+            let text = diag.render(
+                &path.display().to_string(),
+                &String::from_utf8_lossy(&bytes),
+            );
+            return vec![Message {
+                path: path.to_path_buf(),
+                offset: err.span().start,
+                severity: diag.severity,
+                text,
+            }];
+        }
+    };
+
+    let mut sources = SourceManager::new();
+    let root = sources.add(path, source);
+    let checked = sources.link(root).and_then(|program| {
+        let resolution = sema::analyze(&program)?;
+        types::check(&program, &resolution)?;
+        let lints = lints::check(&program, &resolution);
+        match deny_warnings && !lints.is_empty() {
+            true => Err(CompileError::Lints(lints)),
+            false => Ok(lints),
+        }
+    });
+    files.extend(sources.files()[1..].iter().map(|file| file.path.clone()));
+    let diags = match checked {
+        Ok(lints) => lints.iter().map(Diagnostic::from).collect(),
+        Err(err) => err.diagnostics(),
+    };
+
+    diags
+        .iter()
+        .map(|diag| Message {
+            path: sources
+                .file(diag.span.map_or(FileId(0), |span| span.file))
+                .path
+                .clone(),
+            offset: diag.span.map_or(0, |span| span.start),
+            severity: diag.severity,
+            text: sources.render(diag),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::{check, sources_in};
+    use crate::diagnostics::Severity;
+
+    #[test]
+    fn test_check() {
+        let paths = sources_in(Path::new("testdata")).unwrap();
+        assert!(paths.contains(&PathBuf::from("testdata/modules/math.cpl")));
+
+        let report = check(&paths, 4, false);
+        assert_eq!(report, check(&paths, 1, false));
+        assert_eq!(report.files, paths);
+        let messages = report.messages;
+        assert!(messages.is_sorted());
+        let files: Vec<_> = messages
+            .iter()
+            .filter(|message| message.severity == Severity::Error)
+            .map(|message| message.path.to_str().unwrap())
+            .collect();
+        assert!(files.contains(&"testdata/syntax_errors.cpl"));
+        assert!(files.contains(&"testdata/type_errors.cpl"));
+        assert!(!files.contains(&"testdata/arithmetic.cpl"));
+
+        // Warnings become errors, each still reported once.
+        let denied = check(&paths, 4, true).messages;
+        assert_eq!(denied.len(), messages.len());
+        assert!(denied
+            .iter()
+            .all(|message| message.severity == Severity::Error));
+
+        let imports = check(&[PathBuf::from("testdata/imports.cpl")], 1, false);
+        assert_eq!(
+            imports.files,
+            vec![
+                PathBuf::from("testdata/imports.cpl"),
+                PathBuf::from("testdata/modules/math.cpl"),
+            ]
+        );
+
+        let missing = check(&[PathBuf::from("testdata/missing.cpl")], 2, false).messages;
+        assert_eq!(missing.len(), 1);
+        assert!(missing[0].text.starts_with("error: could not read"));
+    }
+}
@@ -0,0 +1,351 @@
+//! Functions every program can call without declaring them, like `print`.
+//!
+//! Semantic analysis resolves a name to a [`Builtin`] only when no declaration
+//! of it is in scope, so programs may shadow them. The interpreter and the VM
+//! both run them with [`Builtin::call`], which reads and writes through an
+//! [`Io`].
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::interp::{RuntimeError, RuntimeResult, Value};
+use crate::types::Type;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    Print,
+    Println,
+    Len,
+    Int,
+    Float,
+    Char,
+    Str,
+    Input,
+}
+
+impl Builtin {
+    pub const ALL: [Builtin; 8] = [
+        Builtin::Print,
+        Builtin::Println,
+        Builtin::Len,
+        Builtin::Int,
+        Builtin::Float,
+        Builtin::Char,
+        Builtin::Str,
+        Builtin::Input,
+    ];
+
+    pub fn lookup(name: &str) -> Option<Builtin> {
+        Builtin::ALL
+            .into_iter()
+            .find(|builtin| builtin.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Print => "print",
+            Builtin::Println => "println",
+            Builtin::Len => "len",
+            Builtin::Int => "int",
+            Builtin::Float => "float",
+            Builtin::Char => "char",
+            Builtin::Str => "str",
+            Builtin::Input => "input",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            Builtin::Input => 0,
+            _ => 1,
+        }
+    }
+
+    /// Whether an argument of type `ty` is accepted.
+    pub fn accepts(self, ty: &Type) -> bool {
+        match (self, ty) {
+            (_, Type::Unknown) => true,
+            (Builtin::Print | Builtin::Println | Builtin::Str, ty) => *ty != Type::Void,
+            (Builtin::Len, ty) => matches!(ty, Type::Str | Type::Array(_)),
+            (Builtin::Int, ty) => matches!(
+                ty,
+                Type::Int | Type::Float | Type::Bool | Type::Char | Type::Str
+            ),
+            (Builtin::Float, ty) => matches!(ty, Type::Int | Type::Float | Type::Str),
+            (Builtin::Char, ty) => matches!(ty, Type::Int | Type::Char),
+            (Builtin::Input, _) => false,
+        }
+    }
+
+    pub fn ret(self) -> Type {
+        match self {
+            Builtin::Print | Builtin::Println => Type::Void,
+            Builtin::Len | Builtin::Int => Type::Int,
+            Builtin::Float => Type::Float,
+            Builtin::Char => Type::Char,
+            Builtin::Str | Builtin::Input => Type::Str,
+        }
+    }
+
+    /// The type of the builtin used as a value. Calls are checked more
+    /// precisely, with [`Builtin::accepts`].
+    pub fn ty(self) -> Type {
+        Type::Fn {
+            params: vec![Type::Unknown; self.arity()],
+            ret: Box::new(self.ret()),
+        }
+    }
+
+    pub fn call(self, args: Vec<Value>, io: &mut Io) -> RuntimeResult<Value> {
+        if args.len() != self.arity() {
+            return Err(RuntimeError::ArityMismatch {
+                name: self.name().to_string(),
+                expected: self.arity(),
+                found: args.len(),
+            });
+        }
+        let mismatch = |value: &Value| RuntimeError::TypeMismatch {
+            op: self.name().to_string(),
+            lhs: value.type_name(),
+            rhs: None,
+        };
+        let invalid = |value: &Value, to| RuntimeError::InvalidConversion {
+            value: value.to_string(),
+            to,
+        };
+
+        let mut args = args.into_iter();
+        let arg = args.next().unwrap_or(Value::Void);
+        Ok(match (self, &arg) {
+            (Builtin::Print | Builtin::Println | Builtin::Str, Value::Void) => {
+                return Err(mismatch(&arg))
+            }
+            (Builtin::Print, _) => {
+                io.write(&arg.to_string())?;
+                Value::Void
+            }
+            (Builtin::Println, _) => {
+                io.write(&format!("{}\n", arg))?;
+                Value::Void
+            }
+            (Builtin::Len, Value::Str(s)) => Value::Int(s.chars().count() as i64),
+            (Builtin::Len, Value::Array(elems)) => Value::Int(elems.borrow().len() as i64),
+            (Builtin::Int, Value::Int(n)) => Value::Int(*n),
+            (Builtin::Int, Value::Float(n)) => {
+                // `i64::MAX as f64` rounds up to 2^63, which is out of range.
+                if !(n.is_finite() && *n >= i64::MIN as f64 && *n < i64::MAX as f64) {
+                    return Err(invalid(&arg, "int"));
+                }
+                Value::Int(*n as i64)
+            }
+            (Builtin::Int, Value::Bool(b)) => Value::Int(*b as i64),
+            (Builtin::Int, Value::Char(ch)) => Value::Int(*ch as i64),
+            (Builtin::Int, Value::Str(s)) => {
+                Value::Int(s.trim().parse().map_err(|_| invalid(&arg, "int"))?)
+            }
+            (Builtin::Float, Value::Int(n)) => Value::Float(*n as f64),
+            (Builtin::Float, Value::Float(n)) => Value::Float(*n),
+            (Builtin::Float, Value::Str(s)) => {
+                Value::Float(s.trim().parse().map_err(|_| invalid(&arg, "float"))?)
+            }
+            (Builtin::Char, Value::Int(n)) => u32::try_from(*n)
+                .ok()
+                .and_then(char::from_u32)
+                .map(Value::Char)
+                .ok_or_else(|| invalid(&arg, "char"))?,
+            (Builtin::Char, Value::Char(ch)) => Value::Char(*ch),
+            (Builtin::Str, _) => Value::Str(arg.to_string().into()),
+            (Builtin::Input, _) => Value::Str(io.read_line()?.into()),
+            _ => return Err(mismatch(&arg)),
+        })
+    }
+}
+
+impl fmt::Display for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Where `print` writes and `input` reads.
+pub struct Io {
+    input: Box<dyn BufRead>,
+    output: Output,
+}
+
+enum Output {
+    Stdout(io::Stdout),
+    Buffer(Vec<u8>),
+}
+
+impl Default for Io {
+    fn default() -> Self {
+        Io::stdio()
+    }
+}
+
+impl Io {
+    pub fn stdio() -> Io {
+        Io {
+            input: Box::new(io::BufReader::new(io::stdin())),
+            output: Output::Stdout(io::stdout()),
+        }
+    }
+
+    /// Reads lines from `input`, and keeps what is printed for
+    /// [`Io::take_output`].
+    pub fn buffered(input: &str) -> Io {
+        Io {
+            input: Box::new(io::Cursor::new(input.as_bytes().to_vec())),
+            output: Output::Buffer(Vec::new()),
+        }
+    }
+
+    /// Everything printed since the last call, if the output is buffered.
+    pub fn take_output(&mut self) -> String {
+        match &mut self.output {
+            Output::Stdout(_) => String::new(),
+            Output::Buffer(buffer) => String::from_utf8_lossy(&std::mem::take(buffer)).into(),
+        }
+    }
+
+    fn write(&mut self, text: &str) -> RuntimeResult<()> {
+        let result = match &mut self.output {
+            Output::Stdout(stdout) => stdout.write_all(text.as_bytes()),
+            Output::Buffer(buffer) => buffer.write_all(text.as_bytes()),
+        };
+        result.map_err(|err| RuntimeError::Io(err.to_string()))
+    }
+
+    /// The next line of input without its line ending, or an empty string at
+    /// the end of the input.
+    fn read_line(&mut self) -> RuntimeResult<String> {
+        // A prompt printed without a newline must show before waiting.
+        if let Output::Stdout(stdout) = &mut self.output {
+            stdout
+                .flush()
+                .map_err(|err| RuntimeError::Io(err.to_string()))?;
+        }
+
+        let mut line = String::new();
+        self.input
+            .read_line(&mut line)
+            .map_err(|err| RuntimeError::Io(err.to_string()))?;
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Builtin, Io};
+    use crate::interp::{RuntimeError, Value};
+    use crate::types::Type;
+
+    fn call(builtin: Builtin, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        builtin.call(args, &mut Io::buffered(""))
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(
+            call(Builtin::Int, vec![Value::Float(-2.9)]),
+            Ok(Value::Int(-2))
+        );
+        assert_eq!(
+            call(Builtin::Int, vec![Value::Str(" 42 ".into())]),
+            Ok(Value::Int(42))
+        );
+        assert_eq!(
+            call(Builtin::Int, vec![Value::Bool(true)]),
+            Ok(Value::Int(1))
+        );
+        assert_eq!(
+            call(Builtin::Float, vec![Value::Int(3)]),
+            Ok(Value::Float(3.0))
+        );
+        assert_eq!(
+            call(Builtin::Str, vec![Value::Float(1.0)]),
+            Ok(Value::Str("1.0".into()))
+        );
+        assert_eq!(
+            call(Builtin::Len, vec![Value::Str("héllo".into())]),
+            Ok(Value::Int(5))
+        );
+        assert_eq!(
+            call(Builtin::Int, vec![Value::Char('é')]),
+            Ok(Value::Int(0xe9))
+        );
+        assert_eq!(
+            call(Builtin::Char, vec![Value::Int(0x41)]),
+            Ok(Value::Char('A'))
+        );
+        assert_eq!(
+            call(Builtin::Char, vec![Value::Int(0xd800)]),
+            Err(RuntimeError::InvalidConversion {
+                value: "55296".into(),
+                to: "char",
+            })
+        );
+
+        assert_eq!(
+            call(Builtin::Int, vec![Value::Str("4x".into())]),
+            Err(RuntimeError::InvalidConversion {
+                value: "4x".into(),
+                to: "int",
+            })
+        );
+        assert_eq!(
+            call(Builtin::Int, vec![Value::Float(1e19)]),
+            Err(RuntimeError::InvalidConversion {
+                value: "1e19".into(),
+                to: "int",
+            })
+        );
+        assert_eq!(
+            call(Builtin::Len, vec![Value::Int(1)]),
+            Err(RuntimeError::TypeMismatch {
+                op: "len".into(),
+                lhs: "int",
+                rhs: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_io() {
+        let mut io = Io::buffered("first\r\nsecond");
+        Builtin::Print.call(vec![Value::Int(1)], &mut io).unwrap();
+        Builtin::Println
+            .call(vec![Value::Str("a".into())], &mut io)
+            .unwrap();
+        assert_eq!(io.take_output(), "1a\n");
+        assert_eq!(io.take_output(), "");
+
+        let mut input = || Builtin::Input.call(vec![], &mut io);
+        assert_eq!(input(), Ok(Value::Str("first".into())));
+        assert_eq!(input(), Ok(Value::Str("second".into())));
+        assert_eq!(input(), Ok(Value::Str("".into())));
+    }
+
+    #[test]
+    fn test_signatures() {
+        assert_eq!(Builtin::lookup("println"), Some(Builtin::Println));
+        assert_eq!(Builtin::lookup("printf"), None);
+        assert!(Builtin::Int.accepts(&Type::Str));
+        assert!(!Builtin::Float.accepts(&Type::Bool));
+        assert!(!Builtin::Print.accepts(&Type::Void));
+        assert_eq!(
+            Builtin::Len.ty(),
+            Type::Fn {
+                params: vec![Type::Unknown],
+                ret: Box::new(Type::Int),
+            }
+        );
+    }
+}
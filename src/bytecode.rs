@@ -0,0 +1,803 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::{
+    BinaryOp, Block, Expr, ExprKind, FnDecl, NodeId, PatternKind, Program, Stmt, StmtKind, UnaryOp,
+};
+use crate::builtins::Builtin;
+use crate::intern::Symbol;
+use crate::interp::{literal, Layout, Signature, Value};
+use crate::sema::Resolution;
+use crate::span::Span;
+
+/// A single VM instruction. Operands index into the constant pool, the
+/// current frame's slots, or the current function's code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Const(u32),
+    Void,
+    Pop,
+    Swap,
+    LoadLocal(u32),
+    StoreLocal(u32),
+    LoadGlobal(u32),
+    StoreGlobal(u32),
+    /// Puts a fresh cell in a cell slot, for a local captured by a closure.
+    NewCell(u32),
+    LoadCell(u32),
+    StoreCell(u32),
+    LoadUpvalue(u32),
+    StoreUpvalue(u32),
+    /// Creates a closure over the function at this index in the module.
+    Closure(u32),
+    /// Binds the `extern fn` at this index in the module to its host function.
+    Extern(u32),
+    Unary(UnaryOp),
+    Binary(BinaryOp),
+    /// Pops this many values into a new array.
+    Array(u32),
+    Index,
+    /// Pops a value, an index and an array, and stores the value as that
+    /// element; with an operator, combined with the element's current value.
+    StoreIndex(Option<BinaryOp>),
+    /// Pops the field values of the struct literal at this index in the
+    /// module into a new instance.
+    Struct(u32),
+    Field(Symbol),
+    /// Pops a value and an object, and stores the value as that field.
+    StoreField(Symbol, Option<BinaryOp>),
+    Jump(u32),
+    /// Pops a condition and jumps if it is false.
+    JumpIfFalse(u32),
+    /// The left side of `&&` or `||`: jumps, keeping the value, if it decides
+    /// the result, and pops it otherwise.
+    ShortCircuit(BinaryOp, u32),
+    /// Checks that the right side of `&&` or `||` is a bool.
+    CheckBool(BinaryOp),
+    /// Checks that the top two values, the bounds of a `for` loop, are ints.
+    CheckRange,
+    /// Fails with the value on top, which no arm of a `match` matched.
+    NoMatch,
+    Call(u32),
+    Return,
+}
+
+/// Where a closure finds a captured variable when it is created: a cell slot
+/// of the enclosing frame, or one of the enclosing closure's own upvalues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    Cell(u32),
+    Upvalue(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub locals: usize,
+    pub cells: usize,
+    pub captures: Vec<Capture>,
+    pub code: Vec<Op>,
+    /// The span of the statement or expression each op was compiled from.
+    pub spans: Vec<Span>,
+}
+
+/// A struct literal: the struct, and the fields it sets, in the order their
+/// values are pushed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructLiteral {
+    pub layout: Rc<Layout>,
+    pub fields: Vec<Symbol>,
+}
+
+/// A compiled program. `functions[0]` holds the top-level code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub functions: Vec<Rc<Function>>,
+    pub constants: Vec<Value>,
+    pub literals: Vec<StructLiteral>,
+    pub externs: Vec<Signature>,
+    pub globals: usize,
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, func) in self.functions.iter().enumerate() {
+            writeln!(
+                f,
+                "fn {} #{} (arity {}, locals {}, cells {}, captures {:?}):",
+                func.name, i, func.arity, func.locals, func.cells, func.captures
+            )?;
+            for (ip, op) in func.code.iter().enumerate() {
+                write!(f, "  {:04} {:?}", ip, op)?;
+                if let Op::Const(idx) = op {
+                    write!(f, "\t; {:?}", self.constants[*idx as usize])?;
+                }
+                writeln!(f)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Lowers `program` to bytecode, using its name resolution to give every
+/// variable a fixed slot.
+pub fn compile(program: &Program, resolution: &Resolution) -> Module {
+    // Top-level names are globals; captured locals must live in cells.
+    let globals = program
+        .stmts
+        .iter()
+        .filter_map(|stmt| match &stmt.kind {
+            StmtKind::Let { name, .. } | StmtKind::Const { name, .. } => Some(name.id),
+            StmtKind::Fn(decl) | StmtKind::Extern(decl) => Some(decl.name.id),
+            _ => None,
+        })
+        .collect();
+    let captured = resolution.captures.values().flatten().copied().collect();
+
+    let mut compiler = Compiler {
+        resolution,
+        globals,
+        captured,
+        storage: HashMap::new(),
+        global_count: 0,
+        constants: Vec::new(),
+        layouts: HashMap::new(),
+        literals: Vec::new(),
+        externs: Vec::new(),
+        functions: vec![None],
+        states: vec![FnState::new("main", 0)],
+        span: Span::default(),
+    };
+    compiler.block(&program.stmts);
+    compiler.emit(Op::Void);
+    compiler.emit(Op::Return);
+
+    let main = compiler.states.pop().expect("main function state");
+    compiler.functions[0] = Some(main.func);
+
+    Module {
+        functions: compiler
+            .functions
+            .into_iter()
+            .map(|func| Rc::new(func.expect("every function is compiled")))
+            .collect(),
+        constants: compiler.constants,
+        literals: compiler.literals,
+        externs: compiler.externs,
+        globals: compiler.global_count as usize,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Storage {
+    Global(u32),
+    Local(u32),
+    /// A cell slot of the function at this depth.
+    Cell(usize, u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Access {
+    Global(u32),
+    Local(u32),
+    Cell(u32),
+    Upvalue(u32),
+}
+
+struct FnState {
+    func: Function,
+    upvalues: HashMap<NodeId, u32>,
+    loops: Vec<Loop>,
+}
+
+/// The pending jumps out of an enclosing loop.
+#[derive(Default)]
+struct Loop {
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+impl FnState {
+    fn new(name: &str, arity: usize) -> FnState {
+        FnState {
+            func: Function {
+                name: name.to_string(),
+                arity,
+                locals: 0,
+                cells: 0,
+                captures: Vec::new(),
+                code: Vec::new(),
+                spans: Vec::new(),
+            },
+            upvalues: HashMap::new(),
+            loops: Vec::new(),
+        }
+    }
+}
+
+struct Compiler<'a> {
+    resolution: &'a Resolution,
+    globals: HashSet<NodeId>,
+    captured: HashSet<NodeId>,
+    storage: HashMap<NodeId, Storage>,
+    global_count: u32,
+    constants: Vec<Value>,
+    /// The layout of each struct, by the id of its name.
+    layouts: HashMap<NodeId, Rc<Layout>>,
+    literals: Vec<StructLiteral>,
+    externs: Vec<Signature>,
+    /// Filled in as each function finishes compiling.
+    functions: Vec<Option<Function>>,
+    states: Vec<FnState>,
+    /// The statement or expression being compiled.
+    span: Span,
+}
+
+impl Compiler<'_> {
+    fn state(&mut self) -> &mut FnState {
+        self.states.last_mut().expect("no function being compiled")
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let span = self.span;
+        let func = &mut self.state().func;
+        func.spans.push(span);
+        func.code.push(op);
+        func.code.len() - 1
+    }
+
+    fn here(&mut self) -> u32 {
+        self.state().func.code.len() as u32
+    }
+
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        self.patch_to(at, target);
+    }
+
+    fn patch_to(&mut self, at: usize, target: u32) {
+        match &mut self.state().func.code[at] {
+            Op::Jump(to) | Op::JumpIfFalse(to) | Op::ShortCircuit(_, to) => *to = target,
+            op => unreachable!("cannot patch {:?}", op),
+        }
+    }
+
+    fn constant(&mut self, value: Value) -> Op {
+        self.constants.push(value);
+        Op::Const(self.constants.len() as u32 - 1)
+    }
+
+    fn new_local(&mut self) -> u32 {
+        let func = &mut self.state().func;
+        func.locals += 1;
+        func.locals as u32 - 1
+    }
+
+    fn new_cell(&mut self) -> u32 {
+        let func = &mut self.state().func;
+        func.cells += 1;
+        func.cells as u32 - 1
+    }
+
+    /// Gives the name declared by `id` a slot in the current function, creating
+    /// its cell straight away if a closure captures it.
+    fn declare(&mut self, id: NodeId) {
+        let depth = self.states.len() - 1;
+        let storage = if self.globals.contains(&id) {
+            self.global_count += 1;
+            Storage::Global(self.global_count - 1)
+        } else if self.captured.contains(&id) {
+            let cell = self.new_cell();
+            self.emit(Op::NewCell(cell));
+            Storage::Cell(depth, cell)
+        } else {
+            Storage::Local(self.new_local())
+        };
+        self.storage.insert(id, storage);
+    }
+
+    fn access(&mut self, decl: NodeId) -> Access {
+        let depth = self.states.len() - 1;
+        match self.storage[&decl] {
+            Storage::Global(slot) => Access::Global(slot),
+            Storage::Local(slot) => Access::Local(slot),
+            Storage::Cell(owner, slot) if owner == depth => Access::Cell(slot),
+            Storage::Cell(..) => Access::Upvalue(self.upvalue(depth, decl)),
+        }
+    }
+
+    /// Finds or adds the upvalue through which the function at `depth` reaches
+    /// the cell of `decl`, threading it through every function in between.
+    fn upvalue(&mut self, depth: usize, decl: NodeId) -> u32 {
+        if let Some(&index) = self.states[depth].upvalues.get(&decl) {
+            return index;
+        }
+
+        let Storage::Cell(owner, cell) = self.storage[&decl] else {
+            unreachable!("only cells are captured");
+        };
+        let capture = if owner == depth - 1 {
+            Capture::Cell(cell)
+        } else {
+            Capture::Upvalue(self.upvalue(depth - 1, decl))
+        };
+
+        let state = &mut self.states[depth];
+        state.func.captures.push(capture);
+        let index = state.func.captures.len() as u32 - 1;
+        state.upvalues.insert(decl, index);
+        index
+    }
+
+    fn load(&mut self, use_: NodeId) {
+        if let Some(&builtin) = self.resolution.builtins.get(&use_) {
+            let op = self.constant(Value::Builtin(builtin));
+            self.emit(op);
+            return;
+        }
+        self.load_decl(self.resolution.uses[&use_]);
+    }
+
+    fn load_decl(&mut self, decl: NodeId) {
+        let op = match self.access(decl) {
+            Access::Global(slot) => Op::LoadGlobal(slot),
+            Access::Local(slot) => Op::LoadLocal(slot),
+            Access::Cell(slot) => Op::LoadCell(slot),
+            Access::Upvalue(slot) => Op::LoadUpvalue(slot),
+        };
+        self.emit(op);
+    }
+
+    fn store(&mut self, decl: NodeId) {
+        let op = match self.access(decl) {
+            Access::Global(slot) => Op::StoreGlobal(slot),
+            Access::Local(slot) => Op::StoreLocal(slot),
+            Access::Cell(slot) => Op::StoreCell(slot),
+            Access::Upvalue(slot) => Op::StoreUpvalue(slot),
+        };
+        self.emit(op);
+    }
+
+    fn block(&mut self, block: &Block) {
+        // Functions are hoisted: every closure in the block exists before its
+        // first statement runs, so the names they may capture need slots first.
+        for stmt in block {
+            match &stmt.kind {
+                StmtKind::Let { name, .. } | StmtKind::Const { name, .. } => self.declare(name.id),
+                StmtKind::Fn(decl) | StmtKind::Extern(decl) => self.declare(decl.name.id),
+                StmtKind::Struct(decl) => {
+                    let layout = Rc::new(Layout::new(decl));
+                    self.layouts.insert(decl.name.id, layout);
+                }
+                _ => {}
+            }
+        }
+        for stmt in block {
+            match &stmt.kind {
+                StmtKind::Fn(decl) => {
+                    let index = self.function(decl);
+                    self.emit(Op::Closure(index));
+                    self.store(decl.name.id);
+                }
+                StmtKind::Extern(decl) => {
+                    self.externs.push(Signature::of(decl));
+                    self.emit(Op::Extern(self.externs.len() as u32 - 1));
+                    self.store(decl.name.id);
+                }
+                _ => {}
+            }
+        }
+
+        for stmt in block {
+            self.stmt(stmt);
+        }
+    }
+
+    fn function(&mut self, decl: &FnDecl) -> u32 {
+        let index = self.functions.len();
+        self.functions.push(None);
+        self.states
+            .push(FnState::new(decl.name.name.as_str(), decl.params.len()));
+
+        // Arguments arrive in the first slots; captured ones move to a cell.
+        let depth = self.states.len() - 1;
+        for param in &decl.params {
+            let slot = self.new_local();
+            if self.captured.contains(&param.name.id) {
+                let cell = self.new_cell();
+                self.emit(Op::NewCell(cell));
+                self.emit(Op::LoadLocal(slot));
+                self.emit(Op::StoreCell(cell));
+                self.storage
+                    .insert(param.name.id, Storage::Cell(depth, cell));
+            } else {
+                self.storage.insert(param.name.id, Storage::Local(slot));
+            }
+        }
+
+        self.block(&decl.body);
+        self.emit(Op::Void);
+        self.emit(Op::Return);
+
+        let state = self.states.pop().expect("function state");
+        self.functions[index] = Some(state.func);
+        index as u32
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        let outer = std::mem::replace(&mut self.span, stmt.span);
+        self.stmt_kind(stmt);
+        self.span = outer;
+    }
+
+    fn stmt_kind(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { name, value, .. } | StmtKind::Const { name, value, .. } => {
+                self.expr(value);
+                self.store(name.id);
+            }
+            StmtKind::Assign { target, op, value } => {
+                // Like the interpreter, evaluate the value before reading the
+                // current one for a compound assignment.
+                self.expr(value);
+                if let Some(op) = op {
+                    self.load(target.id);
+                    self.emit(Op::Swap);
+                    self.emit(Op::Binary(*op));
+                }
+                self.store(self.resolution.uses[&target.id]);
+            }
+            StmtKind::IndexAssign {
+                array,
+                index,
+                op,
+                value,
+            } => {
+                self.expr(array);
+                self.expr(index);
+                self.expr(value);
+                self.emit(Op::StoreIndex(*op));
+            }
+            StmtKind::FieldAssign {
+                object,
+                field,
+                op,
+                value,
+            } => {
+                self.expr(object);
+                self.expr(value);
+                self.emit(Op::StoreField(field.name, *op));
+            }
+            StmtKind::Fn(_) | StmtKind::Extern(_) | StmtKind::Struct(_) => {}
+            StmtKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(cond);
+                let skip_then = self.emit(Op::JumpIfFalse(0));
+                self.block(then_branch);
+                match else_branch {
+                    Some(else_branch) => {
+                        let skip_else = self.emit(Op::Jump(0));
+                        self.patch(skip_then);
+                        self.block(else_branch);
+                        self.patch(skip_else);
+                    }
+                    None => self.patch(skip_then),
+                }
+            }
+            StmtKind::While { cond, body } => {
+                let start = self.here();
+                self.expr(cond);
+                let exit = self.emit(Op::JumpIfFalse(0));
+                self.state().loops.push(Loop::default());
+                self.block(body);
+                let lp = self.state().loops.pop().expect("loop state");
+                for jump in lp.continues {
+                    self.patch_to(jump, start);
+                }
+                self.emit(Op::Jump(start));
+                for jump in lp.breaks.into_iter().chain([exit]) {
+                    self.patch(jump);
+                }
+            }
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                // The counter and the end live in hidden locals; the variable
+                // is declared afresh each iteration, like a `let` in the body.
+                let (counter, last) = (self.new_local(), self.new_local());
+                self.expr(start);
+                self.expr(end);
+                self.emit(Op::CheckRange);
+                self.emit(Op::StoreLocal(last));
+                self.emit(Op::StoreLocal(counter));
+
+                let top = self.here();
+                self.emit(Op::LoadLocal(counter));
+                self.emit(Op::LoadLocal(last));
+                self.emit(Op::Binary(BinaryOp::Lt));
+                let exit = self.emit(Op::JumpIfFalse(0));
+                self.declare(var.id);
+                self.emit(Op::LoadLocal(counter));
+                self.store(var.id);
+                self.state().loops.push(Loop::default());
+                self.block(body);
+
+                let lp = self.state().loops.pop().expect("loop state");
+                for jump in lp.continues {
+                    self.patch(jump);
+                }
+                let one = self.constant(Value::Int(1));
+                self.emit(Op::LoadLocal(counter));
+                self.emit(one);
+                self.emit(Op::Binary(BinaryOp::Add));
+                self.emit(Op::StoreLocal(counter));
+                self.emit(Op::Jump(top));
+                for jump in lp.breaks.into_iter().chain([exit]) {
+                    self.patch(jump);
+                }
+            }
+            StmtKind::Return(value) => {
+                match value {
+                    Some(value) => self.expr(value),
+                    None => {
+                        self.emit(Op::Void);
+                    }
+                }
+                self.emit(Op::Return);
+            }
+            StmtKind::Break => {
+                let jump = self.emit(Op::Jump(0));
+                self.state()
+                    .loops
+                    .last_mut()
+                    .expect("semantic analysis rejects `break` outside a loop")
+                    .breaks
+                    .push(jump);
+            }
+            StmtKind::Continue => {
+                let jump = self.emit(Op::Jump(0));
+                self.state()
+                    .loops
+                    .last_mut()
+                    .expect("semantic analysis rejects `continue` outside a loop")
+                    .continues
+                    .push(jump);
+            }
+            // The files were linked into one program before compiling.
+            StmtKind::Import(_) | StmtKind::Error => {}
+            StmtKind::Block(block) => self.block(block),
+            StmtKind::Expr(expr) => {
+                self.expr(expr);
+                self.emit(Op::Pop);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        let outer = std::mem::replace(&mut self.span, expr.span);
+        self.expr_kind(expr);
+        self.span = outer;
+    }
+
+    fn expr_kind(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Int(n) => {
+                let op = self.constant(Value::Int(*n));
+                self.emit(op);
+            }
+            ExprKind::Float(n) => {
+                let op = self.constant(Value::Float(*n));
+                self.emit(op);
+            }
+            ExprKind::Char(ch) => {
+                let op = self.constant(Value::Char(*ch));
+                self.emit(op);
+            }
+            ExprKind::Str(s) => {
+                let op = self.constant(Value::Str(s.as_str().into()));
+                self.emit(op);
+            }
+            ExprKind::Interpolation(expr) => {
+                // A call to `str`, which no declaration can shadow here.
+                let op = self.constant(Value::Builtin(Builtin::Str));
+                self.emit(op);
+                self.expr(expr);
+                self.emit(Op::Call(1));
+            }
+            ExprKind::Bool(b) => {
+                let op = self.constant(Value::Bool(*b));
+                self.emit(op);
+            }
+            ExprKind::Ident(_) => self.load(expr.id),
+            ExprKind::Unary { op, expr } => {
+                self.expr(expr);
+                self.emit(Op::Unary(*op));
+            }
+            ExprKind::Binary {
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                lhs,
+                rhs,
+            } => {
+                self.expr(lhs);
+                let done = self.emit(Op::ShortCircuit(*op, 0));
+                self.expr(rhs);
+                self.emit(Op::CheckBool(*op));
+                self.patch(done);
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                self.expr(lhs);
+                self.expr(rhs);
+                self.emit(Op::Binary(*op));
+            }
+            ExprKind::Call { callee, args } => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+                self.emit(Op::Call(args.len() as u32));
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(cond);
+                let skip_then = self.emit(Op::JumpIfFalse(0));
+                self.expr(then_branch);
+                let skip_else = self.emit(Op::Jump(0));
+                self.patch(skip_then);
+                self.expr(else_branch);
+                self.patch(skip_else);
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                // The value lives in a hidden local that each arm tests.
+                let value = self.new_local();
+                self.expr(scrutinee);
+                self.emit(Op::StoreLocal(value));
+                let mut ends = Vec::new();
+                for arm in arms {
+                    let mut next = None;
+                    match &arm.pattern.kind {
+                        PatternKind::Binding(name) => {
+                            self.declare(name.id);
+                            self.emit(Op::LoadLocal(value));
+                            self.store(name.id);
+                        }
+                        PatternKind::Wildcard => {}
+                        kind => {
+                            let literal = literal(kind).expect("the other patterns are literals");
+                            self.emit(Op::LoadLocal(value));
+                            let op = self.constant(literal);
+                            self.emit(op);
+                            self.emit(Op::Binary(BinaryOp::Eq));
+                            next = Some(self.emit(Op::JumpIfFalse(0)));
+                        }
+                    }
+                    self.expr(&arm.body);
+                    ends.push(self.emit(Op::Jump(0)));
+                    if let Some(next) = next {
+                        self.patch(next);
+                    }
+                }
+                self.emit(Op::LoadLocal(value));
+                self.emit(Op::NoMatch);
+                for end in ends {
+                    self.patch(end);
+                }
+            }
+            ExprKind::Fn(decl) => {
+                // The function's name gets a slot, where it finds itself.
+                self.declare(decl.name.id);
+                let index = self.function(decl);
+                self.emit(Op::Closure(index));
+                self.store(decl.name.id);
+                self.load_decl(decl.name.id);
+            }
+            ExprKind::Array(elems) => {
+                for elem in elems {
+                    self.expr(elem);
+                }
+                self.emit(Op::Array(elems.len() as u32));
+            }
+            ExprKind::Index { expr, index } => {
+                self.expr(expr);
+                self.expr(index);
+                self.emit(Op::Index);
+            }
+            ExprKind::Struct { name, fields } => {
+                for field in fields {
+                    self.expr(&field.value);
+                }
+                let layout = self.layouts[&self.resolution.uses[&name.id]].clone();
+                self.literals.push(StructLiteral {
+                    layout,
+                    fields: fields.iter().map(|field| field.name.name).collect(),
+                });
+                self.emit(Op::Struct(self.literals.len() as u32 - 1));
+            }
+            ExprKind::Field { expr, field } => {
+                self.expr(expr);
+                self.emit(Op::Field(field.name));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compile, Capture, Op};
+    use crate::ast::BinaryOp;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::sema;
+
+    fn compile_str(input: &str) -> super::Module {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.");
+        let resolution = sema::analyze(&program).expect("Program failed analysis.");
+        compile(&program, &resolution)
+    }
+
+    #[test]
+    fn test_globals_and_jumps() {
+        let module = compile_str("let a = 1; while (a < 3) { a += 1; }");
+
+        assert_eq!(module.globals, 1);
+        assert_eq!(
+            module.functions[0].code,
+            vec![
+                Op::Const(0),
+                Op::StoreGlobal(0),
+                Op::LoadGlobal(0),
+                Op::Const(1),
+                Op::Binary(BinaryOp::Lt),
+                Op::JumpIfFalse(12),
+                Op::Const(2),
+                Op::LoadGlobal(0),
+                Op::Swap,
+                Op::Binary(BinaryOp::Add),
+                Op::StoreGlobal(0),
+                Op::Jump(2),
+                Op::Void,
+                Op::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_captures() {
+        let module = compile_str(
+            "
+            fn outer(n) {
+                let unused = 0;
+                fn middle() {
+                    fn inner() {
+                        return n;
+                    }
+                    return inner;
+                }
+                return middle;
+            }
+            ",
+        );
+
+        let names: Vec<_> = module.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["main", "outer", "middle", "inner"]);
+
+        let outer = &module.functions[1];
+        assert_eq!((outer.locals, outer.cells), (3, 1));
+        assert_eq!(module.functions[2].captures, vec![Capture::Cell(0)]);
+        assert_eq!(module.functions[3].captures, vec![Capture::Upvalue(0)]);
+        assert_eq!(module.functions[3].code[0], Op::LoadUpvalue(0));
+    }
+}
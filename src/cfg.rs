@@ -0,0 +1,196 @@
+//! Control-flow graphs of IR functions, and their Graphviz rendering for
+//! `--emit=cfg`.
+
+use std::fmt::Write;
+
+use crate::analysis;
+use crate::ir::{BlockId, Function, Module, Terminator};
+
+/// The edges between a function's blocks, indexed by block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cfg {
+    pub succs: Vec<Vec<BlockId>>,
+    pub preds: Vec<Vec<BlockId>>,
+}
+
+impl Cfg {
+    pub fn new(func: &Function) -> Cfg {
+        let mut succs = Vec::with_capacity(func.blocks.len());
+        let mut preds = vec![Vec::new(); func.blocks.len()];
+        for (id, block) in func.blocks.iter().enumerate() {
+            let mut targets = block.term.successors();
+            targets.dedup();
+            for &target in &targets {
+                preds[target.0 as usize].push(BlockId(id as u32));
+            }
+            succs.push(targets);
+        }
+
+        Cfg { succs, preds }
+    }
+
+    pub fn len(&self) -> usize {
+        self.succs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.succs.is_empty()
+    }
+
+    /// The blocks reachable from the entry, each before its successors except
+    /// along loop back edges.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.len()];
+        let mut order = Vec::new();
+        // Each entry is a block and how many of its successors were visited.
+        let mut stack = vec![(BlockId(0), 0)];
+        if self.is_empty() {
+            return order;
+        }
+        visited[0] = true;
+        while let Some((block, next)) = stack.pop() {
+            match self.succs[block.0 as usize].get(next) {
+                Some(&succ) => {
+                    stack.push((block, next + 1));
+                    if !std::mem::replace(&mut visited[succ.0 as usize], true) {
+                        stack.push((succ, 0));
+                    }
+                }
+                None => order.push(block),
+            }
+        }
+
+        order.reverse();
+        order
+    }
+}
+
+/// Renders every function of `module` as a cluster of one Graphviz digraph.
+/// Each block shows its instructions and the registers live on entry.
+///
+/// ```
+/// use compyl::lexer::Lexer;
+/// use compyl::parser::Parser;
+/// use compyl::{cfg, ir, sema, types};
+///
+/// let program = Parser::new(Lexer::new("return 1;")).parse_program().unwrap();
+/// let resolution = sema::analyze(&program).unwrap();
+/// let types = types::check(&program, &resolution).unwrap();
+/// let module = ir::lower(&program, &resolution, &types).unwrap();
+/// assert!(cfg::dot(&module).starts_with("digraph cfg {"));
+/// ```
+pub fn dot(module: &Module) -> String {
+    let mut out = String::new();
+    out.push_str("digraph cfg {\n");
+    out.push_str("    node [shape=box, fontname=monospace];\n");
+
+    for (index, func) in module.functions.iter().enumerate() {
+        let cfg = Cfg::new(func);
+        let liveness = analysis::liveness(func, &cfg);
+        let node = |block: BlockId| format!("f{}_bb{}", index, block.0);
+
+        writeln!(out, "    subgraph cluster_{} {{", index).unwrap();
+        writeln!(out, "        label=\"fn {}\";", escape(&func.name)).unwrap();
+        for (id, block) in func.blocks.iter().enumerate() {
+            let id = BlockId(id as u32);
+            let mut label = format!("{}:", id);
+            let live = &liveness.live_in[id.0 as usize];
+            if !live.is_empty() {
+                let regs = live.iter().map(|reg| reg.to_string());
+                write!(label, "  ; live: {}", regs.collect::<Vec<_>>().join(", ")).unwrap();
+            }
+            label.push_str("\\l");
+            for inst in &block.insts {
+                write!(
+                    label,
+                    "    {}\\l",
+                    escape(&module.display(inst).to_string())
+                )
+                .unwrap();
+            }
+            write!(label, "    {}\\l", block.term).unwrap();
+            writeln!(out, "        {} [label=\"{}\"];", node(id), label).unwrap();
+        }
+
+        for (id, block) in func.blocks.iter().enumerate() {
+            let from = node(BlockId(id as u32));
+            match block.term {
+                Terminator::Branch {
+                    then_block,
+                    else_block,
+                    ..
+                } => {
+                    let (then_node, else_node) = (node(then_block), node(else_block));
+                    writeln!(out, "        {} -> {} [label=\"T\"];", from, then_node).unwrap();
+                    writeln!(out, "        {} -> {} [label=\"F\"];", from, else_node).unwrap();
+                }
+                ref term => {
+                    for succ in term.successors() {
+                        writeln!(out, "        {} -> {};", from, node(succ)).unwrap();
+                    }
+                }
+            }
+        }
+        out.push_str("    }\n");
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dot, Cfg};
+    use crate::ir::{self, BlockId};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::{sema, types};
+
+    fn lower(input: &str) -> ir::Module {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.");
+        let resolution = sema::analyze(&program).expect("Program failed analysis.");
+        let types = types::check(&program, &resolution).expect("Program failed to type check.");
+        ir::lower(&program, &resolution, &types).expect("Program failed to lower.")
+    }
+
+    #[test]
+    fn test_edges() {
+        let module = lower("let i = 0; while (i < 3) { if (i == 1) { break; } i += 1; }");
+        let cfg = Cfg::new(&module.functions[0]);
+        assert_eq!(cfg.len(), 6);
+        let ids = |ids: &[u32]| ids.iter().map(|&id| BlockId(id)).collect::<Vec<_>>();
+
+        assert_eq!(cfg.succs[1], ids(&[2, 3]));
+        assert_eq!(cfg.preds[1], ids(&[0, 5]));
+        assert_eq!(cfg.preds[3], ids(&[1, 4]));
+        assert_eq!(cfg.reverse_postorder(), ids(&[0, 1, 2, 5, 4, 3]));
+    }
+
+    #[test]
+    fn test_dot() {
+        let module = lower("fn neg(x: int): int { if (x > 0) { return -x; } return x; }");
+        let expected = r#"digraph cfg {
+    node [shape=box, fontname=monospace];
+    subgraph cluster_0 {
+        label="fn neg";
+        f0_bb0 [label="bb0:  ; live: %0\l    %1 = %0\l    %2 = %1 > 0\l    branch %2, bb1, bb2\l"];
+        f0_bb1 [label="bb1:  ; live: %0\l    %3 = %0\l    %4 = -%3\l    ret %4\l"];
+        f0_bb2 [label="bb2:  ; live: %0\l    %5 = %0\l    ret %5\l"];
+        f0_bb0 -> f0_bb1 [label="T"];
+        f0_bb0 -> f0_bb2 [label="F"];
+    }
+    subgraph cluster_1 {
+        label="fn main";
+        f1_bb0 [label="bb0:\l    ret 0\l"];
+    }
+}
+"#;
+        assert_eq!(dot(&module), expected);
+    }
+}
@@ -0,0 +1,646 @@
+use std::fmt;
+
+use compyl::opt::{OptLevel, PassManager};
+
+pub const USAGE: &str = "\
+Usage: compyl <command> [options] <file>
+       compyl check [options] <file or directory>...
+       compyl [repl]
+       compyl lsp
+
+The file `-` means standard input, which is also read when no file is given
+and stdin is not a terminal.
+
+Commands:
+    lex      Print the tokens of a file
+    parse    Print the syntax tree of a file
+    check    Report errors without running the program; given several
+             files or a directory of them, checks each in parallel
+    run      Run a program
+    build    Compile a program to LLVM IR, x86-64 assembly or WebAssembly
+             text
+    fmt      Print a program formatted
+    highlight
+             Print the class an editor would color each token by: keyword,
+             function, parameter, variable, type, property, number,
+             string, comment or operator
+    doc      Print the signatures and `??` doc comments of a program's
+             top-level functions and structs as Markdown
+    repl     Evaluate lines from stdin interactively (the default)
+    lsp      Serve the Language Server Protocol on stdin and stdout
+
+Options:
+    --emit=tokens|ast    Also print the tokens or syntax tree
+    --emit=ir            Also print the intermediate representation
+    --emit=cfg           Also print the IR's control-flow graph as Graphviz
+                         dot
+    -O0, -O1             Leave the IR as lowered (default), or optimize it
+    --emit=llvm-ir       Print the program as LLVM IR (`build` only)
+    --emit=asm           Print x86-64 assembly instead (`build` only)
+    --format=text|json   How `--emit=tokens|ast` and `highlight` print; JSON
+                         puts each on one line
+    --format=html        With `doc`, print HTML instead of Markdown
+    --target=native|wasm32
+                         What `build` compiles for: LLVM IR for a native
+                         executable (default) or a `.wat` module
+    --backend=interp|vm  Run with the tree-walking interpreter (default)
+                         or the bytecode VM
+    --check              With `fmt`, print nothing and fail if the file is
+                         not formatted
+    --deny-warnings      Treat warnings as errors
+    --watch              With `check`, check again whenever a file changes
+    -j, --jobs=N         How many files `check` works on at once (default:
+                         the number of CPUs)
+    --trace              Print how long each phase took, and what it
+                         produced, to stderr
+    -Z dump-after=PASS   Print each function's IR to stderr whenever the
+                         optimization pass PASS changes it (with `-O1`)
+    -q, --quiet          Only print diagnostics
+    -h, --help           Print this message
+
+Exit codes: 0 on success, 1 on compile errors, 2 on usage errors,
+3 on runtime errors.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Lex,
+    Parse,
+    Check,
+    Run,
+    Build,
+    Fmt,
+    Highlight,
+    Doc,
+    Repl,
+    Lsp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    Ir,
+    Cfg,
+    LlvmIr,
+    Asm,
+    Wat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+    Html,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Target {
+    #[default]
+    Native,
+    Wasm32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    Interp,
+    Vm,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub command: Command,
+    /// One file, or with `check` any number of files and directories.
+    pub inputs: Vec<String>,
+    pub emit: Vec<Emit>,
+    pub format: Format,
+    pub check: bool,
+    pub deny_warnings: bool,
+    pub quiet: bool,
+    pub backend: Backend,
+    pub target: Target,
+    pub opt_level: OptLevel,
+    pub jobs: Option<usize>,
+    pub watch: bool,
+    pub trace: bool,
+    /// The optimization pass to print the IR after, from `-Z dump-after`.
+    pub dump_after: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CliError {
+    Help,
+    Usage(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Help => write!(f, "{}", USAGE),
+            CliError::Usage(msg) => write!(f, "error: {}\n\n{}", msg, USAGE),
+        }
+    }
+}
+
+fn usage<T>(msg: impl Into<String>) -> Result<T, CliError> {
+    Err(CliError::Usage(msg.into()))
+}
+
+/// Parses the arguments after the program name. A missing file falls back to
+/// stdin (`-`) when it is piped, which `stdin_is_terminal` tells.
+pub fn parse_args(
+    args: impl IntoIterator<Item = String>,
+    stdin_is_terminal: bool,
+) -> Result<Options, CliError> {
+    let mut args = args.into_iter();
+    let command = match args.next().as_deref() {
+        Some("lex") => Command::Lex,
+        Some("parse") => Command::Parse,
+        Some("check") => Command::Check,
+        Some("run") => Command::Run,
+        Some("build") => Command::Build,
+        Some("fmt") => Command::Fmt,
+        Some("highlight") => Command::Highlight,
+        Some("doc") => Command::Doc,
+        Some("repl") | None => Command::Repl,
+        Some("lsp") => Command::Lsp,
+        Some("-h" | "--help") => return Err(CliError::Help),
+        Some(other) => return usage(format!("unknown command `{}`", other)),
+    };
+
+    // `lex` and `parse` exist to print their stage's output; `build` prints
+    // its target's, added below.
+    let mut emit = match command {
+        Command::Lex => vec![Emit::Tokens],
+        Command::Parse => vec![Emit::Ast],
+        Command::Check
+        | Command::Run
+        | Command::Build
+        | Command::Fmt
+        | Command::Highlight
+        | Command::Doc
+        | Command::Repl
+        | Command::Lsp => vec![],
+    };
+    let mut format = Format::default();
+    let mut check = false;
+    let mut deny_warnings = false;
+    let mut quiet = false;
+    let mut backend = Backend::default();
+    let mut target = Target::default();
+    let mut opt_level = OptLevel::default();
+    let mut jobs = None;
+    let mut watch = false;
+    let mut trace = false;
+    let mut dump_after = None;
+    let mut inputs = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "-q" | "--quiet" => quiet = true,
+            "--check" if command == Command::Fmt => check = true,
+            "--check" => return usage("`--check` requires the `fmt` command"),
+            "--deny-warnings" => deny_warnings = true,
+            "--watch" if command == Command::Check => watch = true,
+            "--watch" => return usage("`--watch` requires the `check` command"),
+            "--emit=tokens" => emit.push(Emit::Tokens),
+            "--emit=ast" => emit.push(Emit::Ast),
+            "--emit=ir" => emit.push(Emit::Ir),
+            "--emit=cfg" => emit.push(Emit::Cfg),
+            "--emit=llvm-ir" if command == Command::Build => emit.push(Emit::LlvmIr),
+            "--emit=llvm-ir" => return usage("`--emit=llvm-ir` requires the `build` command"),
+            "--emit=asm" if command == Command::Build => emit.push(Emit::Asm),
+            "--emit=asm" => return usage("`--emit=asm` requires the `build` command"),
+            flag if flag.starts_with("--emit=") => {
+                return usage(format!("unknown emit kind `{}`", &flag["--emit=".len()..]))
+            }
+            "--format=text" => format = Format::Text,
+            "--format=json" => format = Format::Json,
+            "--format=html" if command == Command::Doc => format = Format::Html,
+            "--format=html" => return usage("`--format=html` requires the `doc` command"),
+            flag if flag.starts_with("--format=") => {
+                return usage(format!("unknown format `{}`", &flag["--format=".len()..]))
+            }
+            "--backend=interp" => backend = Backend::Interp,
+            "--backend=vm" => backend = Backend::Vm,
+            flag if flag.starts_with("--backend=") => {
+                return usage(format!("unknown backend `{}`", &flag["--backend=".len()..]))
+            }
+            flag if flag.starts_with("--target=") && command != Command::Build => {
+                return usage(format!("`{}` requires the `build` command", flag))
+            }
+            "--target=native" => target = Target::Native,
+            "--target=wasm32" => target = Target::Wasm32,
+            flag if flag.starts_with("--target=") => {
+                return usage(format!("unknown target `{}`", &flag["--target=".len()..]))
+            }
+            "-O0" => opt_level = OptLevel::O0,
+            "-O1" => opt_level = OptLevel::O1,
+            flag if flag.starts_with("-O") => {
+                return usage(format!(
+                    "unknown optimization level `{}`",
+                    &flag["-O".len()..]
+                ))
+            }
+            flag if (flag.starts_with("--jobs=") || flag == "-j") && command != Command::Check => {
+                return usage(format!("`{}` requires the `check` command", flag))
+            }
+            "-j" => match args.next() {
+                Some(count) => jobs = Some(job_count(&count)?),
+                None => return usage("`-j` requires a job count"),
+            },
+            flag if flag.starts_with("--jobs=") => {
+                jobs = Some(job_count(&flag["--jobs=".len()..])?);
+            }
+            "--trace" => trace = true,
+            "-Z" => match args.next() {
+                Some(option) => dump_after = Some(debug_option(&option)?),
+                None => return usage("`-Z` requires an option"),
+            },
+            flag if flag.starts_with("-Z") => dump_after = Some(debug_option(&flag["-Z".len()..])?),
+            flag if flag.starts_with('-') && flag != "-" => {
+                return usage(format!("unknown flag `{}`", flag))
+            }
+            _ if !inputs.is_empty() && command != Command::Check => {
+                return usage(format!("unexpected argument `{}`", arg))
+            }
+            _ => inputs.push(arg),
+        }
+    }
+
+    // The REPL and the language server read stdin; their input names only
+    // label diagnostics.
+    let inputs = match (command, inputs.first()) {
+        (Command::Repl | Command::Lsp, Some(arg)) => {
+            return usage(format!("unexpected argument `{}`", arg))
+        }
+        (Command::Repl, None) => vec!["<repl>".into()],
+        (Command::Lsp, None) => vec!["<lsp>".into()],
+        (_, Some(_)) => inputs,
+        (_, None) if !stdin_is_terminal => vec!["-".into()],
+        (_, None) => return usage("missing input file"),
+    };
+    if inputs.len() > 1 && inputs.iter().any(|input| input == "-") {
+        return usage("`-` cannot be combined with other input files");
+    }
+    if inputs.len() > 1 && !emit.is_empty() {
+        return usage("`--emit` requires a single input file");
+    }
+    if watch && !emit.is_empty() {
+        return usage("`--emit` cannot be combined with `--watch`");
+    }
+    if watch && inputs.iter().any(|input| input == "-") {
+        return usage("`--watch` cannot watch standard input");
+    }
+    if command == Command::Doc && format == Format::Json {
+        return usage("`doc` prints Markdown or HTML, not JSON");
+    }
+    if dump_after.is_some() && opt_level == OptLevel::O0 {
+        return usage("`-Z dump-after` requires `-O1`");
+    }
+    if command == Command::Build {
+        match target {
+            Target::Native if emit.contains(&Emit::Asm) => {}
+            Target::Native => emit.push(Emit::LlvmIr),
+            Target::Wasm32 if emit.contains(&Emit::LlvmIr) => {
+                return usage("`--emit=llvm-ir` cannot be combined with `--target=wasm32`")
+            }
+            Target::Wasm32 if emit.contains(&Emit::Asm) => {
+                return usage("`--emit=asm` cannot be combined with `--target=wasm32`")
+            }
+            Target::Wasm32 => emit.push(Emit::Wat),
+        }
+    }
+    emit.sort_by_key(|emit| *emit as u8);
+    emit.dedup();
+
+    Ok(Options {
+        command,
+        inputs,
+        emit,
+        format,
+        check,
+        deny_warnings,
+        quiet,
+        backend,
+        target,
+        opt_level,
+        jobs,
+        watch,
+        trace,
+        dump_after,
+    })
+}
+
+/// Parses a `-Z` option, the only one being `dump-after=<pass>`.
+fn debug_option(option: &str) -> Result<String, CliError> {
+    let Some(pass) = option.strip_prefix("dump-after=") else {
+        return usage(format!("unknown `-Z` option `{}`", option));
+    };
+    let passes = PassManager::for_level(OptLevel::O1);
+    let names: Vec<_> = passes.passes().collect();
+    if !names.contains(&pass) {
+        let names: Vec<_> = names.iter().map(|name| format!("`{}`", name)).collect();
+        return usage(format!(
+            "unknown optimization pass `{}`, expected one of {}",
+            pass,
+            names.join(", ")
+        ));
+    }
+
+    Ok(pass.to_string())
+}
+
+fn job_count(count: &str) -> Result<usize, CliError> {
+    match count.parse() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => usage(format!("invalid job count `{}`", count)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_args, Backend, CliError, Command, Emit, Format, OptLevel, Options, Target};
+
+    fn parse(args: &str) -> Result<Options, CliError> {
+        parse_args(args.split_whitespace().map(String::from), true)
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(
+            parse("run main.cpl"),
+            Ok(Options {
+                command: Command::Run,
+                inputs: vec!["main.cpl".into()],
+                emit: vec![],
+                format: Format::Text,
+                check: false,
+                deny_warnings: false,
+                quiet: false,
+                backend: Backend::Interp,
+                target: Target::Native,
+                opt_level: OptLevel::O0,
+                jobs: None,
+                watch: false,
+                trace: false,
+                dump_after: None,
+            })
+        );
+        assert_eq!(
+            parse("lex --quiet --emit=ast main.cpl"),
+            Ok(Options {
+                command: Command::Lex,
+                inputs: vec!["main.cpl".into()],
+                emit: vec![Emit::Tokens, Emit::Ast],
+                format: Format::Text,
+                check: false,
+                deny_warnings: false,
+                quiet: true,
+                backend: Backend::Interp,
+                target: Target::Native,
+                opt_level: OptLevel::O0,
+                jobs: None,
+                watch: false,
+                trace: false,
+                dump_after: None,
+            })
+        );
+        assert_eq!(
+            parse("run --backend=vm main.cpl").unwrap().backend,
+            Backend::Vm
+        );
+        assert_eq!(
+            parse("check main.cpl --emit=ast").unwrap().emit,
+            vec![Emit::Ast]
+        );
+        assert_eq!(
+            parse("parse --emit=ast main.cpl").unwrap().emit,
+            vec![Emit::Ast]
+        );
+        assert_eq!(
+            parse("build --emit=llvm-ir main.cpl").unwrap().emit,
+            vec![Emit::LlvmIr]
+        );
+        assert_eq!(
+            parse("build --emit=llvm-ir --emit=ir main.cpl")
+                .unwrap()
+                .emit,
+            vec![Emit::Ir, Emit::LlvmIr]
+        );
+        assert_eq!(
+            parse("check -O1 --emit=ir main.cpl").unwrap().opt_level,
+            OptLevel::O1
+        );
+        assert_eq!(
+            parse("build -O1 --emit=asm main.cpl").unwrap().emit,
+            vec![Emit::Asm]
+        );
+        assert_eq!(
+            parse("run --emit=cfg --emit=ir main.cpl").unwrap().emit,
+            vec![Emit::Ir, Emit::Cfg]
+        );
+        assert_eq!(
+            parse("parse --format=json main.cpl").unwrap().format,
+            Format::Json
+        );
+        assert_eq!(parse("lex -").unwrap().inputs, vec!["-"]);
+        let piped = parse_args(["run".to_string()], false).unwrap();
+        assert_eq!(piped.inputs, vec!["-"]);
+        let options = parse("check a.cpl -j 3 src --deny-warnings").unwrap();
+        assert_eq!(options.inputs, vec!["a.cpl", "src"]);
+        assert_eq!(options.jobs, Some(3));
+        assert_eq!(parse("check --jobs=8 src").unwrap().jobs, Some(8));
+        assert!(parse("check --watch main.cpl").unwrap().watch);
+        assert!(parse("fmt --check main.cpl").unwrap().check);
+        assert!(parse("run --trace main.cpl").unwrap().trace);
+        assert_eq!(
+            parse("check -O1 -Z dump-after=dead-code main.cpl")
+                .unwrap()
+                .dump_after,
+            Some("dead-code".into())
+        );
+        assert_eq!(
+            parse("build -Zdump-after=constant-folding -O1 main.cpl")
+                .unwrap()
+                .dump_after,
+            Some("constant-folding".into())
+        );
+        assert!(
+            parse("check --deny-warnings main.cpl")
+                .unwrap()
+                .deny_warnings
+        );
+        assert_eq!(parse("").unwrap().command, Command::Repl);
+        assert_eq!(parse("repl").unwrap().command, Command::Repl);
+        assert_eq!(parse("lsp").unwrap().command, Command::Lsp);
+        assert_eq!(
+            parse("highlight --format=json main.cpl").unwrap().command,
+            Command::Highlight
+        );
+        let options = parse("doc --format=html main.cpl").unwrap();
+        assert_eq!(options.command, Command::Doc);
+        assert_eq!(options.format, Format::Html);
+        let options = parse("build --target=wasm32 --emit=ast main.cpl").unwrap();
+        assert_eq!(options.target, Target::Wasm32);
+        assert_eq!(options.emit, vec![Emit::Ast, Emit::Wat]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse("--help"), Err(CliError::Help));
+        assert_eq!(parse("run -h"), Err(CliError::Help));
+        assert_eq!(
+            parse("repl a.cpl"),
+            Err(CliError::Usage("unexpected argument `a.cpl`".into()))
+        );
+        assert_eq!(
+            parse("compile main.cpl"),
+            Err(CliError::Usage("unknown command `compile`".into()))
+        );
+        assert_eq!(
+            parse("run --emit=llvm-ir main.cpl"),
+            Err(CliError::Usage(
+                "`--emit=llvm-ir` requires the `build` command".into()
+            ))
+        );
+        assert_eq!(
+            parse("run"),
+            Err(CliError::Usage("missing input file".into()))
+        );
+        assert_eq!(
+            parse("run a.cpl b.cpl"),
+            Err(CliError::Usage("unexpected argument `b.cpl`".into()))
+        );
+        assert_eq!(
+            parse("run --emit=asm a.cpl"),
+            Err(CliError::Usage(
+                "`--emit=asm` requires the `build` command".into()
+            ))
+        );
+        assert_eq!(
+            parse("build --target=wasm32 --emit=asm a.cpl"),
+            Err(CliError::Usage(
+                "`--emit=asm` cannot be combined with `--target=wasm32`".into()
+            ))
+        );
+        assert_eq!(
+            parse("check -O3 a.cpl"),
+            Err(CliError::Usage("unknown optimization level `3`".into()))
+        );
+        assert_eq!(
+            parse("lex --emit=mir a.cpl"),
+            Err(CliError::Usage("unknown emit kind `mir`".into()))
+        );
+        assert_eq!(
+            parse("check --check a.cpl"),
+            Err(CliError::Usage(
+                "`--check` requires the `fmt` command".into()
+            ))
+        );
+        assert_eq!(
+            parse("lex --format=yaml a.cpl"),
+            Err(CliError::Usage("unknown format `yaml`".into()))
+        );
+        assert_eq!(
+            parse("parse --format=html a.cpl"),
+            Err(CliError::Usage(
+                "`--format=html` requires the `doc` command".into()
+            ))
+        );
+        assert_eq!(
+            parse("doc --format=json a.cpl"),
+            Err(CliError::Usage(
+                "`doc` prints Markdown or HTML, not JSON".into()
+            ))
+        );
+        assert_eq!(
+            parse("build --target=arm a.cpl"),
+            Err(CliError::Usage("unknown target `arm`".into()))
+        );
+        assert_eq!(
+            parse("run --target=wasm32 a.cpl"),
+            Err(CliError::Usage(
+                "`--target=wasm32` requires the `build` command".into()
+            ))
+        );
+        assert_eq!(
+            parse("build --target=wasm32 --emit=llvm-ir a.cpl"),
+            Err(CliError::Usage(
+                "`--emit=llvm-ir` cannot be combined with `--target=wasm32`".into()
+            ))
+        );
+        assert_eq!(
+            parse("check --jobs=0 a.cpl"),
+            Err(CliError::Usage("invalid job count `0`".into()))
+        );
+        assert_eq!(
+            parse("check -j"),
+            Err(CliError::Usage("`-j` requires a job count".into()))
+        );
+        assert_eq!(
+            parse("run -j 2 a.cpl"),
+            Err(CliError::Usage("`-j` requires the `check` command".into()))
+        );
+        assert_eq!(
+            parse("check --emit=ast a.cpl b.cpl"),
+            Err(CliError::Usage(
+                "`--emit` requires a single input file".into()
+            ))
+        );
+        assert_eq!(
+            parse("run --watch a.cpl"),
+            Err(CliError::Usage(
+                "`--watch` requires the `check` command".into()
+            ))
+        );
+        assert_eq!(
+            parse("check --watch --emit=ir a.cpl"),
+            Err(CliError::Usage(
+                "`--emit` cannot be combined with `--watch`".into()
+            ))
+        );
+        assert_eq!(
+            parse_args(["check".into(), "--watch".into()], false),
+            Err(CliError::Usage(
+                "`--watch` cannot watch standard input".into()
+            ))
+        );
+        assert_eq!(
+            parse("check a.cpl -"),
+            Err(CliError::Usage(
+                "`-` cannot be combined with other input files".into()
+            ))
+        );
+        assert_eq!(
+            parse("check -O1 -Z dump-after=inlining a.cpl"),
+            Err(CliError::Usage(
+                "unknown optimization pass `inlining`, expected one of `constant-folding`, \
+                 `algebraic-simplification`, `copy-propagation`, `unreachable-code`, `dead-code`"
+                    .into()
+            ))
+        );
+        assert_eq!(
+            parse("check -Z time-passes a.cpl"),
+            Err(CliError::Usage("unknown `-Z` option `time-passes`".into()))
+        );
+        assert_eq!(
+            parse("check -O1 -Z"),
+            Err(CliError::Usage("`-Z` requires an option".into()))
+        );
+        assert_eq!(
+            parse("check -Z dump-after=dead-code a.cpl"),
+            Err(CliError::Usage("`-Z dump-after` requires `-O1`".into()))
+        );
+        assert_eq!(
+            parse("run --backend=jit a.cpl"),
+            Err(CliError::Usage("unknown backend `jit`".into()))
+        );
+        assert_eq!(
+            parse("lex --verbose a.cpl"),
+            Err(CliError::Usage("unknown flag `--verbose`".into()))
+        );
+    }
+}
@@ -0,0 +1,837 @@
+//! Lowers a type-checked program to textual LLVM IR (`.ll`).
+//!
+//! Top-level code becomes `main`, top-level `let`s become globals and every
+//! other variable gets an `alloca`, leaving register promotion to LLVM. Only
+//! `int`, `float` and `bool` values are supported, so every parameter and
+//! return type that matters must be annotated. Integer arithmetic wraps and
+//! division by zero is undefined, as in LLVM itself.
+//!
+//! The output uses opaque pointers (`ptr`), the default since LLVM 15.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::{reject_builtin, unsupported, CodegenError};
+use crate::ast::{
+    BinaryOp, Block, Expr, ExprKind, FnDecl, NodeId, Program, Stmt, StmtKind, UnaryOp,
+};
+use crate::sema::Resolution;
+use crate::span::Span;
+use crate::types::{Type, TypeTable};
+
+type CodegenResult<T> = Result<T, CodegenError>;
+
+/// Translates `program` to an LLVM module.
+///
+/// ```
+/// use compyl::codegen::llvm;
+/// use compyl::lexer::Lexer;
+/// use compyl::parser::Parser;
+/// use compyl::{sema, types};
+///
+/// let program = Parser::new(Lexer::new("return 6 * 7;"))
+///     .parse_program()
+///     .unwrap();
+/// let resolution = sema::analyze(&program).unwrap();
+/// let types = types::check(&program, &resolution).unwrap();
+/// let ir = llvm::emit(&program, &resolution, &types).unwrap();
+/// assert!(ir.contains("define i32 @main()"));
+/// ```
+pub fn emit(
+    program: &Program,
+    resolution: &Resolution,
+    types: &TypeTable,
+) -> CodegenResult<String> {
+    let mut ctx = Context {
+        resolution,
+        types,
+        globals: HashMap::new(),
+        functions: HashMap::new(),
+    };
+    let mut out = String::from("; generated by compyl\n\n");
+
+    for stmt in &program.stmts {
+        match &stmt.kind {
+            StmtKind::Let { name, .. } | StmtKind::Const { name, .. } => {
+                let ty = ctx.decl_type(name.id, name.span)?;
+                let symbol = format!("@{}", ident(name.name.as_str()));
+                writeln!(
+                    out,
+                    "{} = internal global {} {}",
+                    symbol,
+                    ty.ir(),
+                    ty.zero()
+                )
+                .unwrap();
+                ctx.globals.insert(name.id, (symbol, ty));
+            }
+            StmtKind::Fn(decl) => {
+                if decl.name.name.as_str() == "main" {
+                    return unsupported("a function named `main`", decl.name.span);
+                }
+                let Type::Fn { params, ret } = &types.decls[&decl.name.id] else {
+                    unreachable!("functions have function types");
+                };
+                let params = params
+                    .iter()
+                    .zip(&decl.params)
+                    .map(|(ty, param)| IrType::value(ty, param.name.span))
+                    .collect::<CodegenResult<_>>()?;
+                let ret = match **ret {
+                    Type::Void => IrType::Void,
+                    ref ty => IrType::value(ty, decl.name.span)?,
+                };
+                ctx.functions.insert(
+                    decl.name.id,
+                    Signature {
+                        symbol: format!("@{}", ident(decl.name.name.as_str())),
+                        params,
+                        ret,
+                    },
+                );
+            }
+            StmtKind::Extern(decl) => return unsupported("an `extern` function", decl.name.span),
+            _ => {}
+        }
+    }
+    if !ctx.globals.is_empty() {
+        out.push('\n');
+    }
+
+    for stmt in &program.stmts {
+        if let StmtKind::Fn(decl) = &stmt.kind {
+            out.push_str(&FnGen::function(&ctx, decl)?);
+            out.push('\n');
+        }
+    }
+    out.push_str(&FnGen::main(&ctx, &program.stmts)?);
+
+    Ok(out)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IrType {
+    I64,
+    Double,
+    I1,
+    Void,
+}
+
+impl IrType {
+    fn value(ty: &Type, span: Span) -> CodegenResult<IrType> {
+        match ty {
+            Type::Int => Ok(IrType::I64),
+            Type::Float => Ok(IrType::Double),
+            Type::Bool => Ok(IrType::I1),
+            Type::Unknown => unsupported("a value of unknown type (add a type annotation)", span),
+            ty => unsupported(format!("a value of type `{}`", ty), span),
+        }
+    }
+
+    fn ir(self) -> &'static str {
+        match self {
+            IrType::I64 => "i64",
+            IrType::Double => "double",
+            IrType::I1 => "i1",
+            IrType::Void => "void",
+        }
+    }
+
+    fn zero(self) -> &'static str {
+        match self {
+            IrType::I64 => "0",
+            IrType::Double => "0.0",
+            IrType::I1 => "false",
+            IrType::Void => unreachable!("void has no values"),
+        }
+    }
+}
+
+struct Signature {
+    symbol: String,
+    params: Vec<IrType>,
+    ret: IrType,
+}
+
+struct Context<'a> {
+    resolution: &'a Resolution,
+    types: &'a TypeTable,
+    globals: HashMap<NodeId, (String, IrType)>,
+    functions: HashMap<NodeId, Signature>,
+}
+
+impl Context<'_> {
+    fn decl_type(&self, id: NodeId, span: Span) -> CodegenResult<IrType> {
+        IrType::value(&self.types.decls[&id], span)
+    }
+}
+
+/// An operand together with its type.
+type Operand = (String, IrType);
+
+struct FnGen<'a> {
+    ctx: &'a Context<'a>,
+    locals: HashMap<NodeId, (String, IrType)>,
+    allocas: String,
+    code: String,
+    next_tmp: usize,
+    next_label: usize,
+    /// The label of the block being filled, for `phi` nodes.
+    block: String,
+    terminated: bool,
+    /// The exit label of each enclosing loop, and where `continue` goes.
+    loops: Vec<(String, String)>,
+    ret: IrType,
+    is_main: bool,
+}
+
+impl<'a> FnGen<'a> {
+    fn new(ctx: &'a Context<'a>, ret: IrType, is_main: bool) -> FnGen<'a> {
+        FnGen {
+            ctx,
+            locals: HashMap::new(),
+            allocas: String::new(),
+            code: String::new(),
+            next_tmp: 0,
+            next_label: 0,
+            block: "entry".into(),
+            terminated: false,
+            loops: Vec::new(),
+            ret,
+            is_main,
+        }
+    }
+
+    fn function(ctx: &'a Context<'a>, decl: &FnDecl) -> CodegenResult<String> {
+        let sig = &ctx.functions[&decl.name.id];
+        let mut gen = FnGen::new(ctx, sig.ret, false);
+
+        let mut params = Vec::new();
+        for (param, ty) in decl.params.iter().zip(&sig.params) {
+            let arg = format!("%{}", ident(param.name.name.as_str()));
+            params.push(format!("{} {}", ty.ir(), arg));
+            let slot = gen.alloca(param.name.name.as_str(), *ty);
+            gen.inst(format!("store {} {}, ptr {}", ty.ir(), arg, slot));
+            gen.locals.insert(param.name.id, (slot, *ty));
+        }
+
+        gen.block(&decl.body)?;
+        if !gen.terminated {
+            gen.inst(match sig.ret {
+                IrType::Void => "ret void".into(),
+                _ => "unreachable".to_string(),
+            });
+        }
+
+        Ok(gen.finish(&format!(
+            "define {} {}({})",
+            sig.ret.ir(),
+            sig.symbol,
+            params.join(", ")
+        )))
+    }
+
+    fn main(ctx: &'a Context<'a>, stmts: &[Stmt]) -> CodegenResult<String> {
+        let mut gen = FnGen::new(ctx, IrType::I64, true);
+        gen.stmts(stmts)?;
+        if !gen.terminated {
+            gen.inst("ret i32 0".into());
+        }
+
+        Ok(gen.finish("define i32 @main()"))
+    }
+
+    fn finish(self, header: &str) -> String {
+        format!("{} {{\nentry:\n{}{}}}\n", header, self.allocas, self.code)
+    }
+
+    fn tmp(&mut self) -> String {
+        self.next_tmp += 1;
+        format!("%t{}", self.next_tmp)
+    }
+
+    fn new_label(&mut self, kind: &str) -> String {
+        self.next_label += 1;
+        format!("{}{}", kind, self.next_label)
+    }
+
+    fn alloca(&mut self, name: &str, ty: IrType) -> String {
+        self.next_tmp += 1;
+        let slot = format!("%{}", ident(&format!("{}.{}", name, self.next_tmp)));
+        writeln!(self.allocas, "  {} = alloca {}", slot, ty.ir()).unwrap();
+        slot
+    }
+
+    /// Appends an instruction, starting an unreachable block if the current
+    /// one has already ended (e.g. code after `return`).
+    fn inst(&mut self, inst: String) {
+        if self.terminated {
+            let label = self.new_label("dead");
+            self.start(&label);
+        }
+        self.terminated =
+            inst.starts_with("br ") || inst.starts_with("ret ") || inst == "unreachable";
+        writeln!(self.code, "  {}", inst).unwrap();
+    }
+
+    /// Appends an instruction producing a value.
+    fn value(&mut self, inst: String, ty: IrType) -> Operand {
+        let tmp = self.tmp();
+        self.inst(format!("{} = {}", tmp, inst));
+        (tmp, ty)
+    }
+
+    /// Starts the block `label`, falling through into it from the current block.
+    fn start(&mut self, label: &str) {
+        if !self.terminated {
+            writeln!(self.code, "  br label %{}", label).unwrap();
+        }
+        writeln!(self.code, "{}:", label).unwrap();
+        self.block = label.to_string();
+        self.terminated = false;
+    }
+
+    fn slot(&self, decl: NodeId) -> Option<&(String, IrType)> {
+        self.locals
+            .get(&decl)
+            .or_else(|| self.ctx.globals.get(&decl))
+    }
+
+    fn block(&mut self, block: &Block) -> CodegenResult<()> {
+        self.stmts(block)
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) -> CodegenResult<()> {
+        for stmt in stmts {
+            self.stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> CodegenResult<()> {
+        match &stmt.kind {
+            StmtKind::Let { name, value, .. } | StmtKind::Const { name, value, .. } => {
+                let (value, _) = self.expr(value)?;
+                let (slot, ty) = match self.ctx.globals.get(&name.id) {
+                    Some(global) => global.clone(),
+                    None => {
+                        let ty = self.ctx.decl_type(name.id, name.span)?;
+                        let slot = self.alloca(name.name.as_str(), ty);
+                        self.locals.insert(name.id, (slot.clone(), ty));
+                        (slot, ty)
+                    }
+                };
+                self.inst(format!("store {} {}, ptr {}", ty.ir(), value, slot));
+            }
+            StmtKind::Assign { target, op, value } => {
+                let (slot, ty) = self
+                    .slot(self.ctx.resolution.uses[&target.id])
+                    .cloned()
+                    .expect("assigned names are variables");
+                let mut value = self.expr(value)?;
+                if let Some(op) = op {
+                    let current = self.value(format!("load {}, ptr {}", ty.ir(), slot), ty);
+                    value = self.binary(*op, current, value, stmt.span)?;
+                }
+                self.inst(format!("store {} {}, ptr {}", ty.ir(), value.0, slot));
+            }
+            StmtKind::Fn(decl)
+                if self.is_main && self.ctx.functions.contains_key(&decl.name.id) => {}
+            StmtKind::Fn(decl) => return unsupported("a nested function", decl.name.span),
+            StmtKind::Extern(decl) => return unsupported("an `extern` function", decl.name.span),
+            StmtKind::IndexAssign { .. } => {
+                return unsupported("assigning to an array element", stmt.span)
+            }
+            StmtKind::FieldAssign { .. } => {
+                return unsupported("assigning to a struct field", stmt.span)
+            }
+            StmtKind::Struct(decl) => return unsupported("a struct", decl.name.span),
+            StmtKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                let (cond, _) = self.expr(cond)?;
+                let then_label = self.new_label("then");
+                let else_label = self.new_label("else");
+                let end_label = self.new_label("endif");
+                let false_label = if else_branch.is_some() {
+                    &else_label
+                } else {
+                    &end_label
+                };
+                self.inst(format!(
+                    "br i1 {}, label %{}, label %{}",
+                    cond, then_label, false_label
+                ));
+
+                self.start(&then_label);
+                self.block(then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    self.inst(format!("br label %{}", end_label));
+                    self.start(&else_label);
+                    self.block(else_branch)?;
+                }
+                self.start(&end_label);
+            }
+            StmtKind::While { cond, body } => {
+                let cond_label = self.new_label("while");
+                let body_label = self.new_label("body");
+                let end_label = self.new_label("endwhile");
+
+                self.start(&cond_label);
+                let (cond, _) = self.expr(cond)?;
+                self.inst(format!(
+                    "br i1 {}, label %{}, label %{}",
+                    cond, body_label, end_label
+                ));
+
+                self.start(&body_label);
+                self.loops.push((end_label.clone(), cond_label.clone()));
+                self.block(body)?;
+                self.loops.pop();
+                self.inst(format!("br label %{}", cond_label));
+                self.start(&end_label);
+            }
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                let (start, _) = self.expr(start)?;
+                let last = self.expr(end)?;
+                let counter = self.alloca("for", IrType::I64);
+                self.inst(format!("store i64 {}, ptr {}", start, counter));
+                let cond_label = self.new_label("for");
+                let body_label = self.new_label("body");
+                let step_label = self.new_label("step");
+                let end_label = self.new_label("endfor");
+
+                self.start(&cond_label);
+                let index = self.value(format!("load i64, ptr {}", counter), IrType::I64);
+                let (cond, _) = self.binary(BinaryOp::Lt, index.clone(), last, stmt.span)?;
+                self.inst(format!(
+                    "br i1 {}, label %{}, label %{}",
+                    cond, body_label, end_label
+                ));
+
+                self.start(&body_label);
+                let slot = self.alloca(var.name.as_str(), IrType::I64);
+                self.locals.insert(var.id, (slot.clone(), IrType::I64));
+                self.inst(format!("store i64 {}, ptr {}", index.0, slot));
+                self.loops.push((end_label.clone(), step_label.clone()));
+                self.block(body)?;
+                self.loops.pop();
+
+                self.start(&step_label);
+                let one = ("1".to_string(), IrType::I64);
+                let (next, _) = self.binary(BinaryOp::Add, index, one, stmt.span)?;
+                self.inst(format!("store i64 {}, ptr {}", next, counter));
+                self.inst(format!("br label %{}", cond_label));
+                self.start(&end_label);
+            }
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(value) => Some((self.expr(value)?, value.span)),
+                    None => None,
+                };
+                match value {
+                    Some(((value, IrType::I64), _)) if self.is_main => {
+                        let (code, _) =
+                            self.value(format!("trunc i64 {} to i32", value), IrType::I64);
+                        self.inst(format!("ret i32 {}", code));
+                    }
+                    Some((_, span)) if self.is_main => {
+                        return unsupported("returning a non-integer exit code", span)
+                    }
+                    None if self.is_main => self.inst("ret i32 0".into()),
+                    Some(((value, ty), _)) => self.inst(format!("ret {} {}", ty.ir(), value)),
+                    None => self.inst(format!("ret {}", self.ret.ir())),
+                }
+            }
+            StmtKind::Break => {
+                let (exit, _) = self
+                    .loops
+                    .last()
+                    .cloned()
+                    .expect("semantic analysis rejects `break` outside a loop");
+                self.inst(format!("br label %{}", exit));
+            }
+            StmtKind::Continue => {
+                let (_, next) = self
+                    .loops
+                    .last()
+                    .cloned()
+                    .expect("semantic analysis rejects `continue` outside a loop");
+                self.inst(format!("br label %{}", next));
+            }
+            StmtKind::Import(_) | StmtKind::Error => {}
+            StmtKind::Block(block) => self.block(block)?,
+            StmtKind::Expr(expr) => {
+                self.expr(expr)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &Expr) -> CodegenResult<Operand> {
+        Ok(match &expr.kind {
+            ExprKind::Int(n) => (n.to_string(), IrType::I64),
+            // LLVM accepts any double written as its bit pattern.
+            ExprKind::Float(n) => (format!("0x{:016X}", n.to_bits()), IrType::Double),
+            ExprKind::Bool(b) => (b.to_string(), IrType::I1),
+            ExprKind::Str(_) | ExprKind::Interpolation(_) => {
+                return unsupported("a string", expr.span)
+            }
+            ExprKind::Char(_) => return unsupported("a character", expr.span),
+            ExprKind::Match { .. } => return unsupported("a `match` expression", expr.span),
+            ExprKind::Fn(_) => return unsupported("a function expression", expr.span),
+            ExprKind::Array(_) => return unsupported("an array", expr.span),
+            ExprKind::Index { .. } => return unsupported("indexing an array", expr.span),
+            ExprKind::Struct { .. } => return unsupported("a struct", expr.span),
+            ExprKind::Field { .. } => return unsupported("a struct field", expr.span),
+            ExprKind::Ident(_) => {
+                reject_builtin(expr, self.ctx.resolution)?;
+                let decl = self.ctx.resolution.uses[&expr.id];
+                match self.slot(decl).cloned() {
+                    Some((slot, ty)) => self.value(format!("load {}, ptr {}", ty.ir(), slot), ty),
+                    None => return unsupported("a function used as a value", expr.span),
+                }
+            }
+            ExprKind::Unary { op, expr: operand } => {
+                let (value, ty) = self.expr(operand)?;
+                let inst = match (op, ty) {
+                    (UnaryOp::Neg, IrType::I64) => format!("sub i64 0, {}", value),
+                    (UnaryOp::Neg, IrType::Double) => format!("fneg double {}", value),
+                    (UnaryOp::Not, IrType::I1) => format!("xor i1 {}, true", value),
+                    (UnaryOp::BitNot, IrType::I64) => format!("xor i64 {}, -1", value),
+                    _ => return unsupported(format!("`{}` on `{}`", op, ty.ir()), expr.span),
+                };
+                self.value(inst, ty)
+            }
+            ExprKind::Binary {
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                lhs,
+                rhs,
+            } => self.logical(*op, lhs, rhs)?,
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => self.conditional(cond, then_branch, else_branch, expr.span)?,
+            ExprKind::Binary { op, lhs, rhs } => {
+                let lhs = self.expr(lhs)?;
+                let rhs = self.expr(rhs)?;
+                self.binary(*op, lhs, rhs, expr.span)?
+            }
+            ExprKind::Call { callee, args } => {
+                reject_builtin(callee, self.ctx.resolution)?;
+                let sig = match &callee.kind {
+                    ExprKind::Ident(_) => self
+                        .ctx
+                        .functions
+                        .get(&self.ctx.resolution.uses[&callee.id]),
+                    _ => None,
+                };
+                let Some(sig) = sig else {
+                    return unsupported(
+                        "calling something other than a top-level function",
+                        callee.span,
+                    );
+                };
+                let (symbol, ret) = (sig.symbol.clone(), sig.ret);
+
+                let mut operands = Vec::new();
+                for arg in args {
+                    let (value, ty) = self.expr(arg)?;
+                    operands.push(format!("{} {}", ty.ir(), value));
+                }
+                let call = format!("call {} {}({})", ret.ir(), symbol, operands.join(", "));
+                if ret == IrType::Void {
+                    self.inst(call);
+                    (String::new(), IrType::Void)
+                } else {
+                    self.value(call, ret)
+                }
+            }
+        })
+    }
+
+    /// Short-circuiting `&&` and `||`, joining the two paths with a `phi`.
+    fn logical(&mut self, op: BinaryOp, lhs: &Expr, rhs: &Expr) -> CodegenResult<Operand> {
+        let (lhs, _) = self.expr(lhs)?;
+        let from = self.block.clone();
+        let rhs_label = self.new_label("rhs");
+        let end_label = self.new_label("endlogic");
+        let (short, branches) = match op {
+            BinaryOp::And => (
+                "false",
+                format!("label %{}, label %{}", rhs_label, end_label),
+            ),
+            _ => (
+                "true",
+                format!("label %{}, label %{}", end_label, rhs_label),
+            ),
+        };
+        self.inst(format!("br i1 {}, {}", lhs, branches));
+
+        self.start(&rhs_label);
+        let (rhs, _) = self.expr(rhs)?;
+        let rhs_from = self.block.clone();
+        self.start(&end_label);
+
+        Ok(self.value(
+            format!(
+                "phi i1 [ {}, %{} ], [ {}, %{} ]",
+                short, from, rhs, rhs_from
+            ),
+            IrType::I1,
+        ))
+    }
+
+    /// `if (cond) a else b`, joining the arms with a `phi`.
+    fn conditional(
+        &mut self,
+        cond: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+        span: Span,
+    ) -> CodegenResult<Operand> {
+        let (cond, _) = self.expr(cond)?;
+        let then_label = self.new_label("then");
+        let else_label = self.new_label("else");
+        let end_label = self.new_label("endif");
+        self.inst(format!(
+            "br i1 {}, label %{}, label %{}",
+            cond, then_label, else_label
+        ));
+
+        self.start(&then_label);
+        let (then_value, ty) = self.expr(then_branch)?;
+        let then_from = self.block.clone();
+        self.inst(format!("br label %{}", end_label));
+        self.start(&else_label);
+        let (else_value, _) = self.expr(else_branch)?;
+        let else_from = self.block.clone();
+        self.start(&end_label);
+
+        if ty == IrType::Void {
+            return unsupported("an `if` expression without a value", span);
+        }
+        Ok(self.value(
+            format!(
+                "phi {} [ {}, %{} ], [ {}, %{} ]",
+                ty.ir(),
+                then_value,
+                then_from,
+                else_value,
+                else_from
+            ),
+            ty,
+        ))
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        lhs: Operand,
+        rhs: Operand,
+        span: Span,
+    ) -> CodegenResult<Operand> {
+        use IrType::{Double, I1, I64};
+
+        // Mixed arithmetic promotes the integer side, as the interpreter does.
+        let (lhs, rhs) = match (lhs.1, rhs.1) {
+            (I64, Double) => (
+                self.value(format!("sitofp i64 {} to double", lhs.0), Double),
+                rhs,
+            ),
+            (Double, I64) => {
+                let rhs = self.value(format!("sitofp i64 {} to double", rhs.0), Double);
+                (lhs, rhs)
+            }
+            _ => (lhs, rhs),
+        };
+        if lhs.1 != rhs.1 {
+            return unsupported(
+                format!("`{}` on `{}` and `{}`", op, lhs.1.ir(), rhs.1.ir()),
+                span,
+            );
+        }
+
+        let ty = lhs.1;
+        let (inst, result) = match (op, ty) {
+            (BinaryOp::Add, I64) => ("add", I64),
+            (BinaryOp::Sub, I64) => ("sub", I64),
+            (BinaryOp::Mul, I64) => ("mul", I64),
+            (BinaryOp::Div, I64) => ("sdiv", I64),
+            (BinaryOp::Mod, I64) => ("srem", I64),
+            (BinaryOp::Add, Double) => ("fadd", Double),
+            (BinaryOp::Sub, Double) => ("fsub", Double),
+            (BinaryOp::Mul, Double) => ("fmul", Double),
+            (BinaryOp::Div, Double) => ("fdiv", Double),
+            (BinaryOp::Mod, Double) => ("frem", Double),
+            (BinaryOp::Gt, I64) => ("icmp sgt", I1),
+            (BinaryOp::Ge, I64) => ("icmp sge", I1),
+            (BinaryOp::Lt, I64) => ("icmp slt", I1),
+            (BinaryOp::Le, I64) => ("icmp sle", I1),
+            (BinaryOp::Eq, I64 | I1) => ("icmp eq", I1),
+            (BinaryOp::Ne, I64 | I1) => ("icmp ne", I1),
+            (BinaryOp::Gt, Double) => ("fcmp ogt", I1),
+            (BinaryOp::Ge, Double) => ("fcmp oge", I1),
+            (BinaryOp::Lt, Double) => ("fcmp olt", I1),
+            (BinaryOp::Le, Double) => ("fcmp ole", I1),
+            (BinaryOp::Eq, Double) => ("fcmp oeq", I1),
+            (BinaryOp::Ne, Double) => ("fcmp une", I1),
+            (BinaryOp::BitAnd, I64 | I1) => ("and", ty),
+            (BinaryOp::BitOr, I64 | I1) => ("or", ty),
+            (BinaryOp::BitXor, I64 | I1) => ("xor", ty),
+            (BinaryOp::Shl, I64) => ("shl", I64),
+            (BinaryOp::Shr, I64) => ("ashr", I64),
+            _ => return unsupported(format!("`{}` on `{}`", op, ty.ir()), span),
+        };
+
+        Ok(self.value(format!("{} {} {}, {}", inst, ty.ir(), lhs.0, rhs.0), result))
+    }
+}
+
+/// Quotes a name that is not a plain LLVM identifier, such as one with
+/// non-ASCII letters.
+fn ident(name: &str) -> Cow<'_, str> {
+    if name
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'.')
+    {
+        name.into()
+    } else {
+        format!("\"{}\"", name).into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::emit;
+    use crate::codegen::CodegenError;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::span::Span;
+    use crate::{sema, types};
+
+    fn compile(input: &str) -> Result<String, CodegenError> {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.");
+        let resolution = sema::analyze(&program).expect("Program failed analysis.");
+        let types = types::check(&program, &resolution).expect("Program failed to type check.");
+        emit(&program, &resolution, &types)
+    }
+
+    #[test]
+    fn test_function() {
+        let ir =
+            compile("fn add(a: int, b: int): int { return a + b; } return add(1, 2);").unwrap();
+        let expected = "\
+define i64 @add(i64 %a, i64 %b) {
+entry:
+  %a.1 = alloca i64
+  %b.2 = alloca i64
+  store i64 %a, ptr %a.1
+  store i64 %b, ptr %b.2
+  %t3 = load i64, ptr %a.1
+  %t4 = load i64, ptr %b.2
+  %t5 = add i64 %t3, %t4
+  ret i64 %t5
+}
+
+define i32 @main() {
+entry:
+  %t1 = call i64 @add(i64 1, i64 2)
+  %t2 = trunc i64 %t1 to i32
+  ret i32 %t2
+}
+";
+        assert!(ir.ends_with(expected), "{}", ir);
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let ir = compile(
+            "
+            let total = 0;
+            let i = 0;
+            while (i < 10) {
+                if (i % 2 == 0 && i != 4) {
+                    total += i;
+                } else {
+                    break;
+                }
+                i = i + 1;
+            }
+            let f = 1.5 * 2;
+            return total;
+            ",
+        )
+        .unwrap();
+
+        for line in [
+            "@total = internal global i64 0",
+            "@f = internal global double 0.0",
+            "  br i1 %t2, label %body2, label %endwhile3",
+            "  %t8 = phi i1 [ false, %body2 ], [ %t7, %rhs4 ]",
+            "else7:\n  br label %endwhile3\nendif8:",
+            "  %t15 = fmul double 0x3FF8000000000000, %t14",
+        ] {
+            assert!(ir.contains(line), "missing `{}` in\n{}", line, ir);
+        }
+    }
+
+    #[test]
+    fn test_for_loops() {
+        let ir = compile(
+            "
+            fn sum(n: int): int {
+                let total = 0;
+                for (i in 0..n) {
+                    if (i % 3 == 0) {
+                        continue;
+                    }
+                    total += i;
+                }
+                return total;
+            }
+            ",
+        )
+        .unwrap();
+
+        // The end is loaded once, before the loop.
+        for line in [
+            "  %t3 = load i64, ptr %n.1\n  store i64 0, ptr %for.4\n  br label %for1",
+            "  %t6 = icmp slt i64 %t5, %t3",
+            "then5:\n  br label %step3",
+            "step3:\n  %t14 = add i64 %t5, 1\n  store i64 %t14, ptr %for.4\n  br label %for1",
+        ] {
+            assert!(ir.contains(line), "missing `{}` in\n{}", line, ir);
+        }
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert_eq!(
+            compile("let s = \"hi\";"),
+            Err(CodegenError::Unsupported {
+                what: "a value of type `str`".into(),
+                span: Span::new(4, 5, 1, 5),
+            })
+        );
+        assert_eq!(
+            compile("fn id(x) { return x; }"),
+            Err(CodegenError::Unsupported {
+                what: "a value of unknown type (add a type annotation)".into(),
+                span: Span::new(6, 7, 1, 7),
+            })
+        );
+    }
+}
@@ -0,0 +1,53 @@
+//! Backends that translate a checked program into another language.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::ast::Expr;
+use crate::sema::Resolution;
+use crate::span::Span;
+
+pub mod llvm;
+pub mod wasm;
+pub mod x86_64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodegenError {
+    /// The program uses something this backend cannot translate.
+    Unsupported { what: String, span: Span },
+}
+
+impl CodegenError {
+    pub fn span(&self) -> Span {
+        match self {
+            CodegenError::Unsupported { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodegenError::Unsupported { what, .. } => {
+                write!(f, "{} cannot be compiled by this backend", what)
+            }
+        }
+    }
+}
+
+impl Error for CodegenError {}
+
+pub(crate) fn unsupported<T>(what: impl Into<String>, span: Span) -> Result<T, CodegenError> {
+    Err(CodegenError::Unsupported {
+        what: what.into(),
+        span,
+    })
+}
+
+/// Fails on a use of a builtin function, which no backend implements yet.
+pub(crate) fn reject_builtin(expr: &Expr, resolution: &Resolution) -> Result<(), CodegenError> {
+    match resolution.builtins.get(&expr.id) {
+        Some(builtin) => unsupported(format!("the builtin `{}`", builtin), expr.span),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,697 @@
+//! Lowers a type-checked program to a WebAssembly module in text format
+//! (`.wat`), for running in a browser or any other wasm runtime.
+//!
+//! `int` maps to `i64`, `float` to `f64` and `bool` to `i32`. Every function
+//! is exported under its own name and the top-level code becomes the exported
+//! `main`, which returns the value of a top-level `return` (or 0). As with the
+//! LLVM backend, only these value types are supported, integer arithmetic
+//! wraps, and `%` on floats is rejected since wasm has no instruction for it.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::{reject_builtin, unsupported, CodegenError};
+use crate::ast::{
+    BinaryOp, Block, Expr, ExprKind, FnDecl, NodeId, Program, Stmt, StmtKind, UnaryOp,
+};
+use crate::sema::Resolution;
+use crate::span::Span;
+use crate::types::{Type, TypeTable};
+
+type CodegenResult<T> = Result<T, CodegenError>;
+
+/// Translates `program` to a wasm module.
+pub fn emit(
+    program: &Program,
+    resolution: &Resolution,
+    types: &TypeTable,
+) -> CodegenResult<String> {
+    let mut ctx = Context {
+        resolution,
+        types,
+        globals: HashMap::new(),
+        functions: HashMap::new(),
+    };
+    let mut out = String::from("(module\n");
+
+    for stmt in &program.stmts {
+        match &stmt.kind {
+            StmtKind::Let { name, .. } | StmtKind::Const { name, .. } => {
+                let ty = ValType::of(&types.decls[&name.id], name.span)?;
+                let symbol = format!("${}", id(name.name.as_str()));
+                writeln!(
+                    out,
+                    "  (global {} (mut {}) ({}.const 0))",
+                    symbol,
+                    ty.wat(),
+                    ty.wat()
+                )
+                .unwrap();
+                ctx.globals.insert(name.id, (symbol, ty));
+            }
+            StmtKind::Fn(decl) => {
+                if decl.name.name.as_str() == "main" {
+                    return unsupported("a function named `main`", decl.name.span);
+                }
+                let Type::Fn { params, ret } = &types.decls[&decl.name.id] else {
+                    unreachable!("functions have function types");
+                };
+                let params = params
+                    .iter()
+                    .zip(&decl.params)
+                    .map(|(ty, param)| ValType::of(ty, param.name.span))
+                    .collect::<CodegenResult<_>>()?;
+                let ret = match **ret {
+                    Type::Void => None,
+                    ref ty => Some(ValType::of(ty, decl.name.span)?),
+                };
+                ctx.functions.insert(
+                    decl.name.id,
+                    Signature {
+                        symbol: format!("${}", id(decl.name.name.as_str())),
+                        params,
+                        ret,
+                    },
+                );
+            }
+            StmtKind::Extern(decl) => return unsupported("an `extern` function", decl.name.span),
+            _ => {}
+        }
+    }
+
+    for stmt in &program.stmts {
+        if let StmtKind::Fn(decl) = &stmt.kind {
+            out.push_str(&FnGen::function(&ctx, decl)?);
+        }
+    }
+    out.push_str(&FnGen::main(&ctx, &program.stmts)?);
+    out.push_str(")\n");
+
+    Ok(out)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ValType {
+    I64,
+    F64,
+    I32,
+}
+
+impl ValType {
+    fn of(ty: &Type, span: Span) -> CodegenResult<ValType> {
+        match ty {
+            Type::Int => Ok(ValType::I64),
+            Type::Float => Ok(ValType::F64),
+            Type::Bool => Ok(ValType::I32),
+            Type::Unknown => unsupported("a value of unknown type (add a type annotation)", span),
+            ty => unsupported(format!("a value of type `{}`", ty), span),
+        }
+    }
+
+    fn wat(self) -> &'static str {
+        match self {
+            ValType::I64 => "i64",
+            ValType::F64 => "f64",
+            ValType::I32 => "i32",
+        }
+    }
+}
+
+struct Signature {
+    symbol: String,
+    params: Vec<ValType>,
+    ret: Option<ValType>,
+}
+
+struct Context<'a> {
+    resolution: &'a Resolution,
+    types: &'a TypeTable,
+    globals: HashMap<NodeId, (String, ValType)>,
+    functions: HashMap<NodeId, Signature>,
+}
+
+struct FnGen<'a> {
+    ctx: &'a Context<'a>,
+    locals: HashMap<NodeId, (String, ValType)>,
+    declared: String,
+    code: String,
+    depth: usize,
+    next_id: usize,
+    /// The labels to `br` to for a `break` and a `continue` in each
+    /// enclosing loop.
+    loops: Vec<(String, String)>,
+    /// Locals used to reorder the operands of compound assignments.
+    scratch: HashMap<ValType, String>,
+    is_main: bool,
+}
+
+impl<'a> FnGen<'a> {
+    fn new(ctx: &'a Context<'a>, is_main: bool) -> FnGen<'a> {
+        FnGen {
+            ctx,
+            locals: HashMap::new(),
+            declared: String::new(),
+            code: String::new(),
+            depth: 2,
+            next_id: 0,
+            loops: Vec::new(),
+            scratch: HashMap::new(),
+            is_main,
+        }
+    }
+
+    fn function(ctx: &'a Context<'a>, decl: &FnDecl) -> CodegenResult<String> {
+        let sig = &ctx.functions[&decl.name.id];
+        let mut gen = FnGen::new(ctx, false);
+
+        let mut header = format!("  (func {} (export \"{}\")", sig.symbol, decl.name.name);
+        for (param, ty) in decl.params.iter().zip(&sig.params) {
+            let name = format!("${}", id(param.name.name.as_str()));
+            write!(header, " (param {} {})", name, ty.wat()).unwrap();
+            gen.locals.insert(param.name.id, (name, *ty));
+        }
+        if let Some(ret) = sig.ret {
+            write!(header, " (result {})", ret.wat()).unwrap();
+        }
+
+        gen.block(&decl.body)?;
+        if sig.ret.is_some() {
+            // Falling off the end of a function that returns a value.
+            gen.inst("unreachable");
+        }
+
+        Ok(gen.finish(&header))
+    }
+
+    fn main(ctx: &'a Context<'a>, stmts: &[Stmt]) -> CodegenResult<String> {
+        let mut gen = FnGen::new(ctx, true);
+        gen.stmts(stmts)?;
+        gen.inst("i64.const 0");
+
+        Ok(gen.finish("  (func $main (export \"main\") (result i64)"))
+    }
+
+    fn finish(self, header: &str) -> String {
+        format!("{}\n{}{}  )\n", header, self.declared, self.code)
+    }
+
+    fn inst(&mut self, inst: &str) {
+        writeln!(self.code, "{}{}", "  ".repeat(self.depth), inst).unwrap();
+    }
+
+    fn new_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn local(&mut self, name: &str, ty: ValType) -> String {
+        let local = format!("${}.{}", id(name), self.new_id());
+        writeln!(self.declared, "    (local {} {})", local, ty.wat()).unwrap();
+        local
+    }
+
+    fn ty(&self, expr: &Expr) -> CodegenResult<ValType> {
+        ValType::of(&self.ctx.types.exprs[&expr.id], expr.span)
+    }
+
+    fn variable(&self, decl: NodeId) -> Option<(String, ValType, &'static str)> {
+        if let Some((name, ty)) = self.locals.get(&decl) {
+            return Some((name.clone(), *ty, "local"));
+        }
+        let (name, ty) = self.ctx.globals.get(&decl)?;
+        Some((name.clone(), *ty, "global"))
+    }
+
+    fn block(&mut self, block: &Block) -> CodegenResult<()> {
+        self.stmts(block)
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) -> CodegenResult<()> {
+        for stmt in stmts {
+            self.stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> CodegenResult<()> {
+        match &stmt.kind {
+            StmtKind::Let { name, value, .. } | StmtKind::Const { name, value, .. } => {
+                self.expr(value)?;
+                match self.ctx.globals.get(&name.id) {
+                    Some((global, _)) => self.inst(&format!("global.set {}", global)),
+                    None => {
+                        let ty = ValType::of(&self.ctx.types.decls[&name.id], name.span)?;
+                        let local = self.local(name.name.as_str(), ty);
+                        self.inst(&format!("local.set {}", local));
+                        self.locals.insert(name.id, (local, ty));
+                    }
+                }
+            }
+            StmtKind::Assign { target, op, value } => {
+                let (name, ty, kind) = self
+                    .variable(self.ctx.resolution.uses[&target.id])
+                    .expect("assigned names are variables");
+                let value_ty = self.ty(value)?;
+                self.expr(value)?;
+                if let Some(op) = op {
+                    // The value is evaluated first but is the right operand.
+                    let scratch = self.scratch(value_ty);
+                    self.inst(&format!("local.set {}", scratch));
+                    self.inst(&format!("{}.get {}", kind, name));
+                    self.promote(ty, value_ty);
+                    self.inst(&format!("local.get {}", scratch));
+                    self.promote(value_ty, ty);
+                    self.binary(*op, widest(ty, value_ty), stmt.span)?;
+                }
+                self.inst(&format!("{}.set {}", kind, name));
+            }
+            StmtKind::Fn(decl)
+                if self.is_main && self.ctx.functions.contains_key(&decl.name.id) => {}
+            StmtKind::Fn(decl) => return unsupported("a nested function", decl.name.span),
+            StmtKind::Extern(decl) => return unsupported("an `extern` function", decl.name.span),
+            StmtKind::IndexAssign { .. } => {
+                return unsupported("assigning to an array element", stmt.span)
+            }
+            StmtKind::FieldAssign { .. } => {
+                return unsupported("assigning to a struct field", stmt.span)
+            }
+            StmtKind::Struct(decl) => return unsupported("a struct", decl.name.span),
+            StmtKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(cond)?;
+                self.inst("if");
+                self.depth += 1;
+                self.block(then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    self.depth -= 1;
+                    self.inst("else");
+                    self.depth += 1;
+                    self.block(else_branch)?;
+                }
+                self.depth -= 1;
+                self.inst("end");
+            }
+            StmtKind::While { cond, body } => {
+                let id = self.new_id();
+                let (exit, top) = (format!("$exit{}", id), format!("$loop{}", id));
+                self.inst(&format!("block {}", exit));
+                self.depth += 1;
+                self.inst(&format!("loop {}", top));
+                self.depth += 1;
+                self.expr(cond)?;
+                self.inst("i32.eqz");
+                self.inst(&format!("br_if {}", exit));
+                self.loops.push((exit, top.clone()));
+                self.block(body)?;
+                self.loops.pop();
+                self.inst(&format!("br {}", top));
+                self.depth -= 1;
+                self.inst("end");
+                self.depth -= 1;
+                self.inst("end");
+            }
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                let counter = self.local("for", ValType::I64);
+                let last = self.local("end", ValType::I64);
+                for bound in [start, end] {
+                    self.ty(bound)?;
+                    self.expr(bound)?;
+                }
+                self.inst(&format!("local.set {}", last));
+                self.inst(&format!("local.set {}", counter));
+
+                let id = self.new_id();
+                let (exit, top) = (format!("$exit{}", id), format!("$loop{}", id));
+                let next = format!("$next{}", id);
+                self.inst(&format!("block {}", exit));
+                self.depth += 1;
+                self.inst(&format!("loop {}", top));
+                self.depth += 1;
+                self.inst(&format!("local.get {}", counter));
+                self.inst(&format!("local.get {}", last));
+                self.inst("i64.lt_s");
+                self.inst("i32.eqz");
+                self.inst(&format!("br_if {}", exit));
+
+                let local = self.local(var.name.as_str(), ValType::I64);
+                self.inst(&format!("local.get {}", counter));
+                self.inst(&format!("local.set {}", local));
+                self.locals.insert(var.id, (local, ValType::I64));
+                // `continue` leaves this block to reach the increment.
+                self.inst(&format!("block {}", next));
+                self.depth += 1;
+                self.loops.push((exit, next));
+                self.block(body)?;
+                self.loops.pop();
+                self.depth -= 1;
+                self.inst("end");
+
+                self.inst(&format!("local.get {}", counter));
+                self.inst("i64.const 1");
+                self.inst("i64.add");
+                self.inst(&format!("local.set {}", counter));
+                self.inst(&format!("br {}", top));
+                self.depth -= 1;
+                self.inst("end");
+                self.depth -= 1;
+                self.inst("end");
+            }
+            StmtKind::Return(value) => {
+                match value {
+                    Some(value) => {
+                        let ty = self.ty(value)?;
+                        if self.is_main && ty != ValType::I64 {
+                            return unsupported("returning a non-integer exit code", value.span);
+                        }
+                        self.expr(value)?;
+                    }
+                    None if self.is_main => self.inst("i64.const 0"),
+                    None => {}
+                }
+                self.inst("return");
+            }
+            StmtKind::Break => {
+                let (exit, _) = self
+                    .loops
+                    .last()
+                    .cloned()
+                    .expect("semantic analysis rejects `break` outside a loop");
+                self.inst(&format!("br {}", exit));
+            }
+            StmtKind::Continue => {
+                let (_, next) = self
+                    .loops
+                    .last()
+                    .cloned()
+                    .expect("semantic analysis rejects `continue` outside a loop");
+                self.inst(&format!("br {}", next));
+            }
+            StmtKind::Import(_) | StmtKind::Error => {}
+            StmtKind::Block(block) => self.block(block)?,
+            StmtKind::Expr(expr) => {
+                let produces = !matches!(self.ctx.types.exprs[&expr.id], Type::Void);
+                self.expr(expr)?;
+                if produces {
+                    self.inst("drop");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn scratch(&mut self, ty: ValType) -> String {
+        if let Some(local) = self.scratch.get(&ty) {
+            return local.clone();
+        }
+        let local = self.local("tmp", ty);
+        self.scratch.insert(ty, local.clone());
+        local
+    }
+
+    /// Converts the value on top of the stack from `from` to `to` when mixed
+    /// arithmetic promotes it, as the interpreter does.
+    fn promote(&mut self, from: ValType, to: ValType) {
+        if from == ValType::I64 && to == ValType::F64 {
+            self.inst("f64.convert_i64_s");
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> CodegenResult<()> {
+        match &expr.kind {
+            ExprKind::Int(n) => self.inst(&format!("i64.const {}", n)),
+            ExprKind::Float(n) => self.inst(&format!("f64.const {:?}", n)),
+            ExprKind::Bool(b) => self.inst(&format!("i32.const {}", *b as i32)),
+            ExprKind::Str(_) | ExprKind::Interpolation(_) => {
+                return unsupported("a string", expr.span)
+            }
+            ExprKind::Char(_) => return unsupported("a character", expr.span),
+            ExprKind::Match { .. } => return unsupported("a `match` expression", expr.span),
+            ExprKind::Fn(_) => return unsupported("a function expression", expr.span),
+            ExprKind::Array(_) => return unsupported("an array", expr.span),
+            ExprKind::Index { .. } => return unsupported("indexing an array", expr.span),
+            ExprKind::Struct { .. } => return unsupported("a struct", expr.span),
+            ExprKind::Field { .. } => return unsupported("a struct field", expr.span),
+            ExprKind::Ident(_) => {
+                reject_builtin(expr, self.ctx.resolution)?;
+                match self.variable(self.ctx.resolution.uses[&expr.id]) {
+                    Some((name, _, kind)) => self.inst(&format!("{}.get {}", kind, name)),
+                    None => return unsupported("a function used as a value", expr.span),
+                }
+            }
+            ExprKind::Unary { op, expr: operand } => {
+                let ty = self.ty(operand)?;
+                self.expr(operand)?;
+                match (op, ty) {
+                    (UnaryOp::Neg, ValType::I64) => {
+                        self.inst("i64.const -1");
+                        self.inst("i64.mul");
+                    }
+                    (UnaryOp::Neg, ValType::F64) => self.inst("f64.neg"),
+                    (UnaryOp::Not, ValType::I32) => self.inst("i32.eqz"),
+                    (UnaryOp::BitNot, ValType::I64) => {
+                        self.inst("i64.const -1");
+                        self.inst("i64.xor");
+                    }
+                    _ => return unsupported(format!("`{}` on `{}`", op, ty.wat()), expr.span),
+                }
+            }
+            ExprKind::Binary {
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                lhs,
+                rhs,
+            } => {
+                // Short-circuit by only evaluating `rhs` in one arm of an `if`.
+                self.expr(lhs)?;
+                self.inst("if (result i32)");
+                self.depth += 1;
+                if *op == BinaryOp::And {
+                    self.expr(rhs)?;
+                } else {
+                    self.inst("i32.const 1");
+                }
+                self.depth -= 1;
+                self.inst("else");
+                self.depth += 1;
+                if *op == BinaryOp::And {
+                    self.inst("i32.const 0");
+                } else {
+                    self.expr(rhs)?;
+                }
+                self.depth -= 1;
+                self.inst("end");
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                let ty = self.ty(expr)?;
+                self.expr(cond)?;
+                self.inst(&format!("if (result {})", ty.wat()));
+                self.depth += 1;
+                self.expr(then_branch)?;
+                self.depth -= 1;
+                self.inst("else");
+                self.depth += 1;
+                self.expr(else_branch)?;
+                self.depth -= 1;
+                self.inst("end");
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let (lhs_ty, rhs_ty) = (self.ty(lhs)?, self.ty(rhs)?);
+                let ty = widest(lhs_ty, rhs_ty);
+                self.expr(lhs)?;
+                self.promote(lhs_ty, ty);
+                self.expr(rhs)?;
+                self.promote(rhs_ty, ty);
+                self.binary(*op, ty, expr.span)?;
+            }
+            ExprKind::Call { callee, args } => {
+                reject_builtin(callee, self.ctx.resolution)?;
+                let sig = match &callee.kind {
+                    ExprKind::Ident(_) => self
+                        .ctx
+                        .functions
+                        .get(&self.ctx.resolution.uses[&callee.id]),
+                    _ => None,
+                };
+                let Some(sig) = sig else {
+                    return unsupported(
+                        "calling something other than a top-level function",
+                        callee.span,
+                    );
+                };
+                let symbol = sig.symbol.clone();
+                for arg in args {
+                    self.expr(arg)?;
+                }
+                self.inst(&format!("call {}", symbol));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies `op` to two operands of type `ty` on the stack.
+    fn binary(&mut self, op: BinaryOp, ty: ValType, span: Span) -> CodegenResult<()> {
+        use ValType::{F64, I32, I64};
+
+        let inst = match (op, ty) {
+            (BinaryOp::Add, I64 | F64) => "add",
+            (BinaryOp::Sub, I64 | F64) => "sub",
+            (BinaryOp::Mul, I64 | F64) => "mul",
+            (BinaryOp::Div, I64) => "div_s",
+            (BinaryOp::Div, F64) => "div",
+            (BinaryOp::Mod, I64) => "rem_s",
+            (BinaryOp::Gt, I64) => "gt_s",
+            (BinaryOp::Ge, I64) => "ge_s",
+            (BinaryOp::Lt, I64) => "lt_s",
+            (BinaryOp::Le, I64) => "le_s",
+            (BinaryOp::Gt, F64) => "gt",
+            (BinaryOp::Ge, F64) => "ge",
+            (BinaryOp::Lt, F64) => "lt",
+            (BinaryOp::Le, F64) => "le",
+            (BinaryOp::Eq, _) => "eq",
+            (BinaryOp::Ne, _) => "ne",
+            (BinaryOp::BitAnd, I64 | I32) => "and",
+            (BinaryOp::BitOr, I64 | I32) => "or",
+            (BinaryOp::BitXor, I64 | I32) => "xor",
+            (BinaryOp::Shl, I64) => "shl",
+            (BinaryOp::Shr, I64) => "shr_s",
+            _ => return unsupported(format!("`{}` on `{}`", op, ty.wat()), span),
+        };
+
+        self.inst(&format!("{}.{}", ty.wat(), inst));
+        Ok(())
+    }
+}
+
+fn widest(a: ValType, b: ValType) -> ValType {
+    if a == ValType::F64 || b == ValType::F64 {
+        ValType::F64
+    } else {
+        a
+    }
+}
+
+/// Escapes the characters of `name` that WAT ids cannot hold, which are the
+/// non-ASCII ones, as `\u` and six hex digits.
+fn id(name: &str) -> Cow<'_, str> {
+    if name.is_ascii() {
+        return name.into();
+    }
+    let mut id = String::new();
+    for ch in name.chars() {
+        if ch.is_ascii() {
+            id.push(ch);
+        } else {
+            write!(id, "\\u{:06x}", ch as u32).unwrap();
+        }
+    }
+    id.into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::emit;
+    use crate::codegen::CodegenError;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::span::Span;
+    use crate::{sema, types};
+
+    fn compile(input: &str) -> Result<String, CodegenError> {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.");
+        let resolution = sema::analyze(&program).expect("Program failed analysis.");
+        let types = types::check(&program, &resolution).expect("Program failed to type check.");
+        emit(&program, &resolution, &types)
+    }
+
+    #[test]
+    fn test_module() {
+        let wat = compile(
+            "
+            fn add(a: int, b: float): float { return a + b; }
+            let n = 0;
+            while (n < 3) {
+                if (n == 1 || false) { break; }
+                n += 1;
+            }
+            return n;
+            ",
+        )
+        .unwrap();
+        let expected = "\
+(module
+  (global $n (mut i64) (i64.const 0))
+  (func $add (export \"add\") (param $a i64) (param $b f64) (result f64)
+    local.get $a
+    f64.convert_i64_s
+    local.get $b
+    f64.add
+    return
+    unreachable
+  )
+  (func $main (export \"main\") (result i64)
+    (local $tmp.2 i64)
+    i64.const 0
+    global.set $n
+    block $exit1
+      loop $loop1
+        global.get $n
+        i64.const 3
+        i64.lt_s
+        i32.eqz
+        br_if $exit1
+        global.get $n
+        i64.const 1
+        i64.eq
+        if (result i32)
+          i32.const 1
+        else
+          i32.const 0
+        end
+        if
+          br $exit1
+        end
+        i64.const 1
+        local.set $tmp.2
+        global.get $n
+        local.get $tmp.2
+        i64.add
+        global.set $n
+        br $loop1
+      end
+    end
+    global.get $n
+    return
+    i64.const 0
+  )
+)
+";
+        assert_eq!(wat, expected);
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert_eq!(
+            compile("let a = 1.5 % 2.0;"),
+            Err(CodegenError::Unsupported {
+                what: "`%` on `f64`".into(),
+                span: Span::new(8, 17, 1, 9),
+            })
+        );
+    }
+}
@@ -0,0 +1,590 @@
+//! Emits x86-64 assembly in AT&T syntax, for the System V ABI, from the
+//! [`ir`](crate::ir).
+//!
+//! A [`RegisterAllocator`] places each virtual register in a machine register
+//! or a stack slot; [`LinearScan`] unless the caller picks another. Each
+//! instruction then loads its operands into `%rax`/`%rcx` (or `%xmm0`/`%xmm1`
+//! for floats), computes, and stores the result to its location, so those and
+//! the argument registers are never allocated. `bool`s are 0 or 1 in a full
+//! word. As in the LLVM backend, integer arithmetic wraps; division by zero
+//! raises `SIGFPE`.
+//!
+//! The output assembles and links with a C toolchain, e.g.
+//! `cc prog.s -o prog -lm`; `%` on floats calls `fmod` from the C library.
+
+use std::fmt::Write;
+
+use crate::ast::{BinaryOp, UnaryOp};
+use crate::ir::{BlockId, Function, Inst, Module, Operand, Reg, Terminator, Ty};
+use crate::regalloc::{
+    Allocation, LinearScan, Location, Machine, PhysReg, RegisterAllocator, RegisterClass,
+};
+
+/// The registers that pass the first integer and float arguments.
+const INT_ARGS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+const FLOAT_ARGS: usize = 8;
+
+/// The registers left to the allocator. No `%xmm` register survives a call.
+pub fn machine() -> Machine {
+    fn is_call(func: &Function, inst: &Inst) -> bool {
+        match *inst {
+            Inst::Call { .. } => true,
+            // `fmod`.
+            Inst::Binary {
+                op: BinaryOp::Mod,
+                dst,
+                ..
+            } => func.regs[dst.0 as usize] == Ty::Float,
+            _ => false,
+        }
+    }
+
+    Machine {
+        int: RegisterClass {
+            callee_saved: vec!["%rbx", "%r12", "%r13", "%r14", "%r15"],
+            caller_saved: vec!["%r10", "%r11"],
+        },
+        float: RegisterClass {
+            callee_saved: vec![],
+            caller_saved: vec![
+                "%xmm8", "%xmm9", "%xmm10", "%xmm11", "%xmm12", "%xmm13", "%xmm14", "%xmm15",
+            ],
+        },
+        is_call,
+    }
+}
+
+/// Translates `module` to an assembly file whose `main` runs the program.
+///
+/// ```
+/// use compyl::codegen::x86_64;
+/// use compyl::lexer::Lexer;
+/// use compyl::parser::Parser;
+/// use compyl::{ir, sema, types};
+///
+/// let program = Parser::new(Lexer::new("return 6 * 7;")).parse_program().unwrap();
+/// let resolution = sema::analyze(&program).unwrap();
+/// let types = types::check(&program, &resolution).unwrap();
+/// let module = ir::lower(&program, &resolution, &types).unwrap();
+/// let asm = x86_64::emit(&module);
+/// assert!(asm.contains("\n    .globl main\nmain:\n"));
+/// ```
+pub fn emit(module: &Module) -> String {
+    emit_with(module, &LinearScan)
+}
+
+/// Like [`emit`], with registers placed by `allocator`.
+pub fn emit_with(module: &Module, allocator: &dyn RegisterAllocator) -> String {
+    let machine = machine();
+    let mut out = String::from("# generated by compyl\n");
+
+    if !module.globals.is_empty() {
+        out.push_str("\n    .bss\n    .p2align 3\n");
+        for (id, global) in module.globals.iter().enumerate() {
+            writeln!(out, "{}:  # {}", global_symbol(id), global.name).unwrap();
+            out.push_str("    .zero 8\n");
+        }
+    }
+
+    out.push_str("\n    .text\n");
+    for (index, func) in module.functions.iter().enumerate() {
+        let allocation = allocator.allocate(func, &machine);
+        let mut gen = FnGen {
+            module,
+            func,
+            index,
+            saved: allocation.callee_saved(&machine),
+            allocation,
+            out: &mut out,
+        };
+        gen.function();
+    }
+    out.push_str("\n    .section .note.GNU-stack,\"\",@progbits\n");
+
+    out
+}
+
+/// The symbol of each function: `main` for the program, and otherwise one
+/// that cannot clash with the C library's, since `.` never appears in names.
+/// Names outside ASCII are quoted.
+fn function_symbol(module: &Module, index: usize) -> String {
+    let name = &module.functions[index].name;
+    if index == module.functions.len() - 1 {
+        name.clone()
+    } else if name.is_ascii() {
+        format!("compyl.{}", name)
+    } else {
+        format!("\"compyl.{}\"", name)
+    }
+}
+
+fn global_symbol(id: usize) -> String {
+    format!(".Lglobal{}", id)
+}
+
+struct FnGen<'a> {
+    module: &'a Module,
+    func: &'a Function,
+    index: usize,
+    allocation: Allocation,
+    /// The callee-saved registers in use, saved in the first frame slots.
+    saved: Vec<PhysReg>,
+    out: &'a mut String,
+}
+
+impl FnGen<'_> {
+    fn line(&mut self, line: impl AsRef<str>) {
+        self.out.push_str("    ");
+        self.out.push_str(line.as_ref());
+        self.out.push('\n');
+    }
+
+    fn label(&self, block: BlockId) -> String {
+        format!(".L{}_bb{}", self.index, block.0)
+    }
+
+    fn slot(index: usize) -> String {
+        format!("-{}(%rbp)", 8 * (index + 1))
+    }
+
+    fn location(&self, reg: Reg) -> String {
+        match self.allocation.location(reg) {
+            Location::Reg(reg) => reg.to_string(),
+            Location::Stack(slot) => FnGen::slot(self.saved.len() + slot as usize),
+        }
+    }
+
+    /// Moves between registers and memory, at most one of them memory.
+    fn mov(&mut self, src: &str, dst: &str) {
+        let xmm = |place: &str| place.starts_with("%xmm");
+        let mov = match (xmm(src), xmm(dst)) {
+            _ if src == dst => return,
+            (true, true) => "movapd",
+            (true, false) | (false, true) if src.ends_with(')') || dst.ends_with(')') => "movsd",
+            _ => "movq",
+        };
+        self.line(format!("{} {}, {}", mov, src, dst));
+    }
+
+    fn ty(&self, operand: Operand) -> Ty {
+        match operand {
+            Operand::Reg(reg) => self.func.regs[reg.0 as usize],
+            Operand::Int(_) => Ty::Int,
+            Operand::Float(_) => Ty::Float,
+            Operand::Bool(_) => Ty::Bool,
+        }
+    }
+
+    fn function(&mut self) {
+        let symbol = function_symbol(self.module, self.index);
+        self.out.push('\n');
+        if symbol == "main" {
+            self.line(".globl main");
+        }
+        writeln!(self.out, "{}:", symbol).unwrap();
+        self.line("pushq %rbp");
+        self.line("movq %rsp, %rbp");
+        // Keep `%rsp` 16-byte aligned for calls.
+        let slots = self.saved.len() + self.allocation.stack_slots as usize;
+        let frame = (8 * slots).next_multiple_of(16);
+        if frame > 0 {
+            self.line(format!("subq ${}, %rsp", frame));
+        }
+        for (index, reg) in self.saved.clone().into_iter().enumerate() {
+            self.mov(reg, &FnGen::slot(index));
+        }
+
+        let (mut ints, mut floats, mut stack) = (0, 0, 0);
+        for &param in &self.func.params {
+            let location = self.location(param);
+            match self.func.regs[param.0 as usize] {
+                Ty::Float if floats < FLOAT_ARGS => {
+                    self.mov(&format!("%xmm{}", floats), &location);
+                    floats += 1;
+                }
+                Ty::Int | Ty::Bool if ints < INT_ARGS.len() => {
+                    self.mov(INT_ARGS[ints], &location);
+                    ints += 1;
+                }
+                _ => {
+                    // Past the saved `%rbp` and the return address.
+                    self.line(format!("movq {}(%rbp), %rax", 16 + 8 * stack));
+                    self.mov("%rax", &location);
+                    stack += 1;
+                }
+            }
+        }
+
+        for (id, block) in self.func.blocks.iter().enumerate() {
+            let id = BlockId(id as u32);
+            writeln!(self.out, "{}:", self.label(id)).unwrap();
+            for inst in &block.insts {
+                self.inst(inst);
+            }
+            self.terminator(&block.term, id);
+        }
+    }
+
+    /// Loads an operand into a register. An `int`, `bool` or the bits of a
+    /// `float` may go to a general-purpose register.
+    fn load(&mut self, operand: Operand, reg: &str) {
+        let bits = match operand {
+            Operand::Reg(src) => return self.mov(&self.location(src), reg),
+            Operand::Int(n) => n,
+            Operand::Float(n) => n.to_bits() as i64,
+            Operand::Bool(b) => b as i64,
+        };
+        if reg.starts_with("%xmm") {
+            self.load(Operand::Int(bits), "%rax");
+            self.mov("%rax", reg);
+        } else if i32::try_from(bits).is_ok() {
+            self.line(format!("movq ${}, {}", bits, reg));
+        } else {
+            self.line(format!("movabsq ${}, {}", bits, reg));
+        }
+    }
+
+    fn store(&mut self, reg: &str, dst: Reg) {
+        let location = self.location(dst);
+        self.mov(reg, &location);
+    }
+
+    fn inst(&mut self, inst: &Inst) {
+        match *inst {
+            Inst::Copy { dst, src } => {
+                let location = self.location(dst);
+                match src {
+                    _ if location.starts_with('%') => self.load(src, &location),
+                    Operand::Reg(src) if self.location(src).starts_with('%') => {
+                        self.mov(&self.location(src), &location)
+                    }
+                    _ => {
+                        self.load(src, "%rax");
+                        self.mov("%rax", &location);
+                    }
+                }
+            }
+            Inst::Unary { dst, op, src } => {
+                self.load(src, "%rax");
+                match (op, self.ty(src)) {
+                    (UnaryOp::Neg, Ty::Float) => {
+                        self.line("btcq $63, %rax");
+                    }
+                    (UnaryOp::Neg, _) => self.line("negq %rax"),
+                    (UnaryOp::Not, _) => self.line("xorq $1, %rax"),
+                    (UnaryOp::BitNot, _) => self.line("notq %rax"),
+                }
+                self.store("%rax", dst);
+            }
+            Inst::Binary { dst, op, lhs, rhs } if self.ty(lhs) == Ty::Float => {
+                self.float_binary(dst, op, lhs, rhs)
+            }
+            Inst::Binary { dst, op, lhs, rhs } => {
+                self.load(lhs, "%rax");
+                self.load(rhs, "%rcx");
+                let result = match op {
+                    BinaryOp::Add => Some("addq"),
+                    BinaryOp::Sub => Some("subq"),
+                    BinaryOp::Mul => Some("imulq"),
+                    BinaryOp::BitAnd => Some("andq"),
+                    BinaryOp::BitOr => Some("orq"),
+                    BinaryOp::BitXor => Some("xorq"),
+                    _ => None,
+                };
+                match op {
+                    _ if result.is_some() => {
+                        self.line(format!("{} %rcx, %rax", result.unwrap()));
+                    }
+                    BinaryOp::Div | BinaryOp::Mod => {
+                        self.line("cqto");
+                        self.line("idivq %rcx");
+                        if op == BinaryOp::Mod {
+                            self.line("movq %rdx, %rax");
+                        }
+                    }
+                    BinaryOp::Shl => self.line("salq %cl, %rax"),
+                    BinaryOp::Shr => self.line("sarq %cl, %rax"),
+                    _ => {
+                        let set = match op {
+                            BinaryOp::Eq => "sete",
+                            BinaryOp::Ne => "setne",
+                            BinaryOp::Lt => "setl",
+                            BinaryOp::Le => "setle",
+                            BinaryOp::Gt => "setg",
+                            BinaryOp::Ge => "setge",
+                            op => unreachable!("`{}` is lowered to branches", op),
+                        };
+                        self.line("cmpq %rcx, %rax");
+                        self.line(format!("{} %al", set));
+                        self.line("movzbq %al, %rax");
+                    }
+                }
+                self.store("%rax", dst);
+            }
+            Inst::IntToFloat { dst, src } => {
+                self.load(src, "%rax");
+                self.line("cvtsi2sdq %rax, %xmm0");
+                self.store("%xmm0", dst);
+            }
+            Inst::LoadGlobal { dst, global } => {
+                self.line(format!(
+                    "movq {}(%rip), %rax",
+                    global_symbol(global.0 as usize)
+                ));
+                self.store("%rax", dst);
+            }
+            Inst::StoreGlobal { global, src } => {
+                self.load(src, "%rax");
+                self.line(format!(
+                    "movq %rax, {}(%rip)",
+                    global_symbol(global.0 as usize)
+                ));
+            }
+            Inst::Call {
+                dst,
+                func,
+                ref args,
+            } => self.call(dst, func.0 as usize, args),
+        }
+    }
+
+    fn float_binary(&mut self, dst: Reg, op: BinaryOp, lhs: Operand, rhs: Operand) {
+        self.load(lhs, "%xmm0");
+        self.load(rhs, "%xmm1");
+        let arithmetic = match op {
+            BinaryOp::Add => Some("addsd"),
+            BinaryOp::Sub => Some("subsd"),
+            BinaryOp::Mul => Some("mulsd"),
+            BinaryOp::Div => Some("divsd"),
+            _ => None,
+        };
+        if let Some(arithmetic) = arithmetic {
+            self.line(format!("{} %xmm1, %xmm0", arithmetic));
+            self.store("%xmm0", dst);
+            return;
+        }
+        if op == BinaryOp::Mod {
+            self.line("call fmod@PLT");
+            self.store("%xmm0", dst);
+            return;
+        }
+
+        // `ucomisd` sets the flags of an unsigned comparison, with the parity
+        // flag set when either side is NaN.
+        match op {
+            BinaryOp::Gt | BinaryOp::Ge => self.line("ucomisd %xmm1, %xmm0"),
+            _ => self.line("ucomisd %xmm0, %xmm1"),
+        }
+        match op {
+            BinaryOp::Gt | BinaryOp::Lt => self.line("seta %al"),
+            BinaryOp::Ge | BinaryOp::Le => self.line("setae %al"),
+            BinaryOp::Eq => {
+                self.line("sete %al");
+                self.line("setnp %cl");
+                self.line("andb %cl, %al");
+            }
+            BinaryOp::Ne => {
+                self.line("setne %al");
+                self.line("setp %cl");
+                self.line("orb %cl, %al");
+            }
+            op => unreachable!("`{}` is not defined on floats", op),
+        }
+        self.line("movzbq %al, %rax");
+        self.store("%rax", dst);
+    }
+
+    fn call(&mut self, dst: Option<Reg>, func: usize, args: &[Operand]) {
+        let (mut ints, mut floats) = (0, 0);
+        let mut in_regs = Vec::new();
+        let mut on_stack = Vec::new();
+        for &arg in args {
+            match self.ty(arg) {
+                Ty::Float if floats < FLOAT_ARGS => {
+                    in_regs.push((arg, format!("%xmm{}", floats)));
+                    floats += 1;
+                }
+                Ty::Int | Ty::Bool if ints < INT_ARGS.len() => {
+                    in_regs.push((arg, INT_ARGS[ints].to_string()));
+                    ints += 1;
+                }
+                _ => on_stack.push(arg),
+            }
+        }
+
+        // The stack arguments go last to first, keeping `%rsp` aligned.
+        let padding = on_stack.len() % 2 == 1;
+        if padding {
+            self.line("subq $8, %rsp");
+        }
+        for &arg in on_stack.iter().rev() {
+            self.load(arg, "%rax");
+            self.line("pushq %rax");
+        }
+        for (arg, reg) in in_regs {
+            self.load(arg, &reg);
+        }
+
+        self.line(format!("call {}", function_symbol(self.module, func)));
+        let popped = 8 * (on_stack.len() + padding as usize);
+        if popped > 0 {
+            self.line(format!("addq ${}, %rsp", popped));
+        }
+        if let Some(dst) = dst {
+            match self.func.regs[dst.0 as usize] {
+                Ty::Float => self.store("%xmm0", dst),
+                Ty::Int | Ty::Bool => self.store("%rax", dst),
+            }
+        }
+    }
+
+    fn terminator(&mut self, term: &Terminator, block: BlockId) {
+        let next = BlockId(block.0 + 1);
+        match *term {
+            Terminator::Jump(target) if target == next => {}
+            Terminator::Jump(target) => self.line(format!("jmp {}", self.label(target))),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                self.load(cond, "%rax");
+                self.line("testq %rax, %rax");
+                if then_block == next {
+                    self.line(format!("je {}", self.label(else_block)));
+                } else {
+                    self.line(format!("jne {}", self.label(then_block)));
+                    if else_block != next {
+                        self.line(format!("jmp {}", self.label(else_block)));
+                    }
+                }
+            }
+            Terminator::Return(value) => {
+                match value {
+                    Some(value) if self.ty(value) == Ty::Float => self.load(value, "%xmm0"),
+                    Some(value) => self.load(value, "%rax"),
+                    None => {}
+                }
+                for (index, reg) in self.saved.clone().into_iter().enumerate() {
+                    self.mov(&FnGen::slot(index), reg);
+                }
+                self.line("leave");
+                self.line("ret");
+            }
+            Terminator::Unreachable => self.line("ud2"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{emit, emit_with};
+    use crate::ir;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::regalloc::SpillEverything;
+    use crate::{sema, types};
+
+    fn lower(input: &str) -> ir::Module {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.");
+        let resolution = sema::analyze(&program).expect("Program failed analysis.");
+        let types = types::check(&program, &resolution).expect("Program failed to type check.");
+        ir::lower(&program, &resolution, &types).expect("Program failed to lower.")
+    }
+
+    fn compile(input: &str) -> String {
+        emit(&lower(input))
+    }
+
+    #[test]
+    fn test_function() {
+        let asm = compile("fn add(a: int, b: int): int { return a + b; } return add(1, 2);");
+        let expected = "\
+# generated by compyl
+
+    .text
+
+compyl.add:
+    pushq %rbp
+    movq %rsp, %rbp
+    movq %rdi, %r10
+    movq %rsi, %r11
+.L0_bb0:
+    movq %r10, %rax
+    movq %r11, %rcx
+    addq %rcx, %rax
+    movq %rax, %r10
+    movq %r10, %rax
+    leave
+    ret
+
+    .globl main
+main:
+    pushq %rbp
+    movq %rsp, %rbp
+.L1_bb0:
+    movq $1, %rdi
+    movq $2, %rsi
+    call compyl.add
+    movq %rax, %r10
+    movq %r10, %rax
+    leave
+    ret
+
+    .section .note.GNU-stack,\"\",@progbits
+";
+        assert_eq!(asm, expected);
+    }
+
+    #[test]
+    fn test_control_flow_and_floats() {
+        let asm = compile(
+            "let x = 0.5; fn f(a: float, b: bool): bool { return b && a >= 1.5; } if (f(x, true)) { x = -x; }",
+        );
+
+        assert!(asm.contains(".Lglobal0:  # x\n    .zero 8\n"));
+        assert!(asm.contains("    movapd %xmm0, %xmm8\n    movq %rdi, %r10\n"));
+        assert!(asm.contains("    ucomisd %xmm1, %xmm0\n    setae %al\n"));
+        assert!(asm.contains("    movabsq $4609434218613702656, %rax\n    movq %rax, %xmm1\n"));
+        assert!(asm.contains("    testq %rax, %rax\n    je .L1_bb2\n"));
+        assert!(asm.contains("    btcq $63, %rax\n"));
+    }
+
+    #[test]
+    fn test_stack_arguments() {
+        let asm = compile(
+            "fn f(a: int, b: int, c: int, d: int, e: int, f: int, g: int): int { return g; } f(1, 2, 3, 4, 5, 6, 7);",
+        );
+
+        assert!(asm.contains("    movq 16(%rbp), %rax\n    movq %rax, %r10\n"));
+        assert!(asm
+            .contains("    subq $8, %rsp\n    movq $7, %rax\n    pushq %rax\n    movq $1, %rdi\n"));
+        assert!(asm.contains("    call compyl.f\n    addq $16, %rsp\n"));
+    }
+
+    #[test]
+    fn test_callee_saved() {
+        let asm = compile(
+            "fn g(): int { return 1; } fn f(x: int): int { let y = g(); return x + y; } return f(2);",
+        );
+
+        // `x` lives across the call, so it is kept in a callee-saved register
+        // that the function saves on entry and restores before returning.
+        assert!(asm.contains("    subq $16, %rsp\n    movq %rbx, -8(%rbp)\n    movq %rdi, %rbx\n"));
+        assert!(asm.contains("    call compyl.g\n    movq %rax, %r10\n    movq %rbx, %r11\n"));
+        assert!(asm.contains("    movq -8(%rbp), %rbx\n    leave\n    ret\n"));
+    }
+
+    #[test]
+    fn test_spill_everything() {
+        let module = lower("fn add(a: int, b: int): int { return a + b; } return add(1, 2);");
+        let asm = emit_with(&module, &SpillEverything);
+
+        assert!(
+            asm.contains("    subq $48, %rsp\n    movq %rdi, -8(%rbp)\n    movq %rsi, -16(%rbp)\n")
+        );
+        assert!(asm.contains("    addq %rcx, %rax\n    movq %rax, -40(%rbp)\n"));
+    }
+}
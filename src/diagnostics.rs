@@ -0,0 +1,544 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::codegen::CodegenError;
+use crate::interp::{Backtrace, RuntimeError};
+use crate::lexer::LexError;
+use crate::lints::Lint;
+use crate::parser::ParseError;
+use crate::sema::SemaError;
+use crate::source::ImportError;
+use crate::span::{FileId, Span};
+use crate::types::TypeError;
+
+/// How many calls of a backtrace a diagnostic lists.
+const MAX_CALLS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// A message about the source being compiled, rendered rustc-style with the
+/// offending line and a caret underline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub span: Option<Span>,
+    pub label: Option<String>,
+    pub notes: Vec<Note>,
+}
+
+/// A line under a diagnostic. One about another place in the source ends in
+/// where that is, as `path:line:column`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &'static str, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code,
+            message: message.into(),
+            span: None,
+            label: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(code: &'static str, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Severity::Error, code, message)
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, code, message)
+    }
+
+    pub fn with_span(mut self, span: Span) -> Diagnostic {
+        self.span = Some(span);
+        self
+    }
+
+    /// Sets the text printed next to the caret underline.
+    pub fn with_label(mut self, label: impl Into<String>) -> Diagnostic {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(Note {
+            message: note.into(),
+            span: None,
+        });
+        self
+    }
+
+    /// Adds a note about `span`, such as "previously defined at", which the
+    /// location is printed after.
+    pub fn with_note_at(mut self, note: impl Into<String>, span: Span) -> Diagnostic {
+        self.notes.push(Note {
+            message: note.into(),
+            span: Some(span),
+        });
+        self
+    }
+
+    /// Points at where a runtime error happened, with a note for each of the
+    /// innermost calls that led there.
+    pub fn with_backtrace(mut self, backtrace: &Backtrace) -> Diagnostic {
+        self.span = backtrace.span.or(self.span);
+        for call in backtrace.calls.iter().take(MAX_CALLS) {
+            self = self.with_note_at(format!("in `{}`, called at", call.function), call.span);
+        }
+        if backtrace.calls.len() > MAX_CALLS {
+            let more = backtrace.calls.len() - MAX_CALLS;
+            self = self.with_note(format!("... and {} more calls", more));
+        }
+        self
+    }
+
+    /// Renders the diagnostic against `source`, the text of the file `filename`.
+    ///
+    /// ```
+    /// use compyl::diagnostics::Diagnostic;
+    /// use compyl::span::Span;
+    ///
+    /// let diag = Diagnostic::error("E0001", "unexpected character `@`")
+    ///     .with_span(Span::new(8, 9, 1, 9))
+    ///     .with_label("not valid here");
+    /// assert_eq!(
+    ///     diag.render("main.cpl", "let a = @;"),
+    ///     "error[E0001]: unexpected character `@`
+    ///  --> main.cpl:1:9
+    ///   |
+    /// 1 | let a = @;
+    ///   |         ^ not valid here
+    /// "
+    /// );
+    /// ```
+    pub fn render(&self, filename: &str, source: &str) -> String {
+        self.render_in(source, |_| filename.to_string())
+    }
+
+    /// Like [`Diagnostic::render`], naming the file of each span with `path`.
+    /// `source` is the text of the file the diagnostic's own span is in.
+    pub fn render_in(&self, source: &str, path: impl Fn(FileId) -> String) -> String {
+        let mut out = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+
+        let Some(span) = self.span else {
+            out.push_str(&format!(" --> {}\n", path(FileId(0))));
+            for note in &self.notes {
+                out.push_str(&format!(" = note: {}\n", note.render(&path)));
+            }
+            return out;
+        };
+
+        let line_no = span.line.to_string();
+        let gutter = " ".repeat(line_no.len());
+        out.push_str(&format!(
+            "{}--> {}:{}:{}\n",
+            gutter,
+            path(span.file),
+            span.line,
+            span.column
+        ));
+        out.push_str(&format!("{} |\n", gutter));
+
+        let line = source.lines().nth(span.line - 1).unwrap_or("");
+        // Columns count characters; the span's length counts bytes.
+        let prefix = (span.column - 1).min(line.chars().count());
+        let start = line
+            .char_indices()
+            .nth(prefix)
+            .map_or(line.len(), |(index, _)| index);
+        let end = (start + span.len()).clamp(start, line.len());
+        let width = line.get(start..end).map_or(1, |text| text.chars().count());
+
+        out.push_str(&format!("{} | {}\n", line_no, line));
+        out.push_str(&format!(
+            "{} | {}{}",
+            gutter,
+            " ".repeat(prefix),
+            "^".repeat(width.max(1))
+        ));
+        if let Some(label) = &self.label {
+            out.push_str(&format!(" {}", label));
+        }
+        out.push('\n');
+
+        for note in &self.notes {
+            out.push_str(&format!("{} = note: {}\n", gutter, note.render(&path)));
+        }
+
+        out
+    }
+
+    /// Writes the rendered diagnostic to stderr.
+    pub fn emit(&self, filename: &str, source: &str) {
+        let _ = io::stderr().write_all(self.render(filename, source).as_bytes());
+    }
+}
+
+impl Note {
+    /// The note's text, naming the file of its span with `path`.
+    pub fn render(&self, path: impl Fn(FileId) -> String) -> String {
+        match self.span {
+            Some(span) => format!(
+                "{} {}:{}:{}",
+                self.message,
+                path(span.file),
+                span.line,
+                span.column
+            ),
+            None => self.message.clone(),
+        }
+    }
+}
+
+impl From<&LexError> for Diagnostic {
+    fn from(err: &LexError) -> Self {
+        let (code, label) = match err {
+            LexError::UnexpectedChar(..) => ("E0001", "unexpected character"),
+            LexError::UnterminatedString(_) => ("E0002", "string starts here"),
+            LexError::UnterminatedComment(_) => ("E0007", "comment starts here"),
+            LexError::InvalidEscape(..) => ("E0003", "unknown escape"),
+            LexError::IntegerOverflow(_) => ("E0004", "does not fit in a 64-bit integer"),
+            LexError::MissingDigits(..) => ("E0005", "expected digits"),
+            LexError::InvalidDigit(..) => ("E0006", "invalid digit"),
+            LexError::InvalidUtf8(_) => ("E0008", "not UTF-8"),
+            LexError::NestingTooDeep(_) => ("E0009", "nested too deeply"),
+            LexError::UnterminatedChar(_) => ("E0010", "character literal starts here"),
+            LexError::InvalidCharLiteral(_) => ("E0011", "not one character"),
+            LexError::DisabledOperator(..) => ("E0012", "disabled operator"),
+        };
+
+        Diagnostic::error(code, err.to_string())
+            .with_span(err.span())
+            .with_label(label)
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(err: &ParseError) -> Self {
+        let diag = match err {
+            ParseError::UnexpectedToken { expected, .. } => {
+                Diagnostic::error("E0100", err.to_string())
+                    .with_label(format!("expected {}", expected))
+            }
+            ParseError::UnexpectedEof { expected, .. } => {
+                Diagnostic::error("E0101", err.to_string())
+                    .with_label(format!("expected {}", expected))
+            }
+            ParseError::TooDeep { .. } => {
+                Diagnostic::error("E0102", err.to_string()).with_label("nested too deeply")
+            }
+            ParseError::TooLong { .. } => {
+                Diagnostic::error("E0104", err.to_string()).with_label("one operator too many")
+            }
+            ParseError::InvalidAssignment(_) => Diagnostic::error("E0103", err.to_string())
+                .with_label("cannot be assigned to")
+                .with_note("only names, array elements and struct fields can be assigned"),
+            ParseError::Lex(err) => return err.into(),
+        };
+
+        diag.with_span(err.span())
+    }
+}
+
+impl From<&ImportError> for Diagnostic {
+    fn from(err: &ImportError) -> Self {
+        let (code, label) = match err {
+            ImportError::Unreadable { .. } => ("E0500", "imported here"),
+            ImportError::Cycle { .. } => ("E0501", "the cycle closes here"),
+        };
+
+        Diagnostic::error(code, err.to_string())
+            .with_span(err.span())
+            .with_label(label)
+    }
+}
+
+impl From<&Lint> for Diagnostic {
+    fn from(lint: &Lint) -> Self {
+        let diag = match lint {
+            Lint::UnusedVariable { name, .. } | Lint::UnusedParameter { name, .. } => {
+                let code = match lint {
+                    Lint::UnusedVariable { .. } => "W0001",
+                    _ => "W0002",
+                };
+                Diagnostic::warning(code, lint.to_string())
+                    .with_label("never used")
+                    .with_note(format!("if this is intended, name it `_{}`", name))
+            }
+            Lint::UnreachableCode(_) => {
+                Diagnostic::warning("W0003", lint.to_string()).with_label("never runs")
+            }
+            Lint::NonExhaustiveMatch(_) => Diagnostic::warning("W0005", lint.to_string())
+                .with_label("some values match no arm")
+                .with_note("add a `_ => ...` arm for the rest"),
+            Lint::UnreachablePattern(_) => Diagnostic::warning("W0006", lint.to_string())
+                .with_label("an earlier arm matches everything this does"),
+            Lint::Shadowed { previous, .. } => Diagnostic::warning("W0004", lint.to_string())
+                .with_label("shadows the earlier one")
+                .with_note_at("previously declared at", *previous),
+        };
+
+        diag.with_span(lint.span())
+    }
+}
+
+impl From<&TypeError> for Diagnostic {
+    fn from(err: &TypeError) -> Self {
+        let (code, label) = match err {
+            TypeError::UnknownType(..) => ("E0300", "not a type".to_string()),
+            TypeError::Mismatch { expected, .. } => ("E0301", format!("expected `{}`", expected)),
+            TypeError::InvalidOperands { .. } => ("E0302", "invalid operands".to_string()),
+            TypeError::NotCallable(..) => ("E0303", "called here".to_string()),
+            TypeError::NotIndexable(..) => ("E0306", "indexed here".to_string()),
+            TypeError::NotAssignable(..) => ("E0308", "cannot change".to_string()),
+            TypeError::NoSuchField { .. } => ("E0307", "unknown field".to_string()),
+            TypeError::ArityMismatch { .. } => ("E0304", "in this call".to_string()),
+            TypeError::InvalidArgument { .. } => ("E0305", "invalid argument".to_string()),
+            TypeError::MissingReturn { .. } => ("E0309", "declared here".to_string()),
+            TypeError::VoidValue(_) => ("E0310", "has type `void`".to_string()),
+        };
+
+        Diagnostic::error(code, err.to_string())
+            .with_span(err.span())
+            .with_label(label)
+    }
+}
+
+impl From<&CodegenError> for Diagnostic {
+    fn from(err: &CodegenError) -> Self {
+        Diagnostic::error("E0400", err.to_string())
+            .with_span(err.span())
+            .with_label("not supported here")
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(err: &RuntimeError) -> Self {
+        let code = match err {
+            RuntimeError::UndefinedVariable(_) => "E0900",
+            RuntimeError::TypeMismatch { .. } => "E0901",
+            RuntimeError::NotCallable(_) => "E0902",
+            RuntimeError::ArityMismatch { .. } => "E0903",
+            RuntimeError::NonBoolCondition(_) => "E0904",
+            RuntimeError::DivisionByZero => "E0905",
+            RuntimeError::IntegerOverflow => "E0906",
+            RuntimeError::BreakOutsideLoop => "E0907",
+            RuntimeError::ShiftOutOfRange(_) => "E0908",
+            RuntimeError::InvalidConversion { .. } => "E0909",
+            RuntimeError::Io(_) => "E0910",
+            RuntimeError::IndexOutOfBounds { .. } => "E0911",
+            RuntimeError::NoSuchField { .. } => "E0912",
+            RuntimeError::MissingField { .. } => "E0913",
+            RuntimeError::ContinueOutsideLoop => "E0914",
+            RuntimeError::StackOverflow(_) => "E0915",
+            RuntimeError::NoMatch(_) => "E0916",
+            RuntimeError::UnregisteredExtern(_) => "E0917",
+            RuntimeError::Host { .. } => "E0918",
+            RuntimeError::HostArgument { .. } => "E0919",
+            RuntimeError::HostResult { .. } => "E0920",
+        };
+
+        Diagnostic::error(code, err.to_string())
+    }
+}
+
+impl From<&SemaError> for Diagnostic {
+    fn from(err: &SemaError) -> Self {
+        let diag = match err {
+            SemaError::UndefinedName(..) => {
+                Diagnostic::error("E0200", err.to_string()).with_label("not found in this scope")
+            }
+            SemaError::DuplicateDefinition { previous, .. } => {
+                Diagnostic::error("E0201", err.to_string())
+                    .with_label("redefined here")
+                    .with_note_at("previously defined at", *previous)
+            }
+            SemaError::BreakOutsideLoop(_) => {
+                Diagnostic::error("E0202", err.to_string()).with_label("cannot `break` here")
+            }
+            SemaError::ContinueOutsideLoop(_) => {
+                Diagnostic::error("E0210", err.to_string()).with_label("cannot `continue` here")
+            }
+            SemaError::AssignToBuiltin(..) => {
+                Diagnostic::error("E0203", err.to_string()).with_label("assigned here")
+            }
+            SemaError::AssignToConst(..) => {
+                Diagnostic::error("E0216", err.to_string()).with_label("assigned here")
+            }
+            SemaError::AssignToFunction(..) => {
+                Diagnostic::error("E0217", err.to_string()).with_label("assigned here")
+            }
+            SemaError::NotConstant(_) => {
+                Diagnostic::error("E0214", err.to_string()).with_label("not a constant")
+            }
+            SemaError::ConstEval { .. } => {
+                Diagnostic::error("E0215", err.to_string()).with_label("evaluated here")
+            }
+            SemaError::BuiltinArity { .. } => {
+                Diagnostic::error("E0204", err.to_string()).with_label("in this call")
+            }
+            SemaError::NestedStruct(_) => {
+                Diagnostic::error("E0205", err.to_string()).with_label("declared here")
+            }
+            SemaError::NotAStruct(..) => {
+                Diagnostic::error("E0206", err.to_string()).with_label("not a struct")
+            }
+            SemaError::StructAsValue(..) => {
+                Diagnostic::error("E0207", err.to_string()).with_label("not a value")
+            }
+            SemaError::UnknownField { .. } => {
+                Diagnostic::error("E0208", err.to_string()).with_label("unknown field")
+            }
+            SemaError::MissingField { .. } => {
+                Diagnostic::error("E0209", err.to_string()).with_label("in this literal")
+            }
+            SemaError::NestedImport(_) => {
+                Diagnostic::error("E0211", err.to_string()).with_label("imported here")
+            }
+            SemaError::NestedExtern(_) => {
+                Diagnostic::error("E0213", err.to_string()).with_label("declared here")
+            }
+            SemaError::NotImported { .. } => {
+                Diagnostic::error("E0212", err.to_string()).with_label("used here")
+            }
+        };
+
+        diag.with_span(err.span())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Diagnostic;
+    use crate::interp::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::sema;
+    use crate::span::Span;
+
+    fn render_parse_error(source: &str) -> String {
+        let errs = Parser::new(Lexer::new(source))
+            .parse_program()
+            .expect_err("Program parsed when it should not have.");
+        Diagnostic::from(&errs[0]).render("test.cpl", source)
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let source = "let a = 1;\nlet = 2;\n";
+        let expected = "\
+error[E0100]: expected identifier, found `=`
+ --> test.cpl:2:5
+  |
+2 | let = 2;
+  |     ^ expected identifier
+";
+
+        assert_eq!(render_parse_error(source), expected);
+    }
+
+    #[test]
+    fn test_lex_error_through_parser() {
+        let source = "let s = \"open";
+        let expected = "\
+error[E0002]: unterminated string literal
+ --> test.cpl:1:9
+  |
+1 | let s = \"open
+  |         ^^^^^ string starts here
+";
+
+        assert_eq!(render_parse_error(source), expected);
+    }
+
+    #[test]
+    fn test_sema_error() {
+        let source = "let a = 1;\nlet a = 2;\n";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let errs = sema::analyze(&program).unwrap_err();
+        let expected = "\
+error[E0201]: `a` is defined more than once in this scope
+ --> test.cpl:2:5
+  |
+2 | let a = 2;
+  |     ^ redefined here
+  = note: previously defined at test.cpl:1:5
+";
+
+        assert_eq!(
+            Diagnostic::from(&errs[0]).render("test.cpl", source),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_multibyte_and_notes() {
+        let diag = Diagnostic::warning("W0001", "odd name")
+            .with_span(Span::new(12, 16, 1, 12))
+            .with_note("names are case sensitive");
+        let expected = "\
+warning[W0001]: odd name
+ --> test.cpl:1:12
+  |
+1 | let é = 1; éé;
+  |            ^^
+  = note: names are case sensitive
+";
+
+        assert_eq!(diag.render("test.cpl", "let é = 1; éé;"), expected);
+    }
+
+    #[test]
+    fn test_without_span() {
+        let diag = Diagnostic::error("E0905", "division by zero");
+        assert_eq!(
+            diag.render("test.cpl", ""),
+            "error[E0905]: division by zero\n --> test.cpl\n"
+        );
+    }
+
+    #[test]
+    fn test_runtime_error() {
+        let source = "fn f(a) {\n    return a[3];\n}\nf([1]);\n";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let mut interp = Interpreter::new();
+        let err = interp.run(&program).unwrap_err();
+        let expected = "\
+error[E0911]: index 3 is out of bounds for an array of length 1
+ --> test.cpl:2:12
+  |
+2 |     return a[3];
+  |            ^^^^
+  = note: in `f`, called at test.cpl:4:1
+";
+
+        assert_eq!(
+            Diagnostic::from(&err)
+                .with_backtrace(interp.backtrace())
+                .render("test.cpl", source),
+            expected
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! Documentation for `compyl doc`: the functions, `extern` functions and
+//! structs each file of a program declares at the top level, with their
+//! signatures and `??` comments, as Markdown or HTML.
+//!
+//! ```
+//! use compyl::doc;
+//! use compyl::lexer::Lexer;
+//! use compyl::parser::Parser;
+//!
+//! let source = "?? Adds `a` to `b`.\nfn add(a: int, b: int): int { return a + b; }";
+//! let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+//! assert_eq!(
+//!     doc::markdown(&program, "math.cpl"),
+//!     "# math.cpl\n\n## `fn add(a: int, b: int): int`\n\nAdds `a` to `b`.\n"
+//! );
+//! ```
+
+use std::fmt::Write;
+use std::ops::Range;
+
+use crate::ast::{FnDecl, Program, StmtKind, TypeExpr, TypeExprKind};
+
+/// A documented declaration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    /// The declaration as it would be written, without a body.
+    pub signature: String,
+    pub doc: Option<String>,
+}
+
+/// The items of each file of `program`, by path; `root` names the file of a
+/// program parsed from a single source.
+pub fn files(program: &Program, root: &str) -> Vec<(String, Vec<Item>)> {
+    if program.modules.is_empty() {
+        return vec![(root.to_string(), items(program, 0..program.stmts.len()))];
+    }
+    program
+        .modules
+        .iter()
+        .map(|module| (module.path.clone(), items(program, module.stmts.clone())))
+        .collect()
+}
+
+fn items(program: &Program, stmts: Range<usize>) -> Vec<Item> {
+    program.stmts[stmts]
+        .iter()
+        .filter_map(|stmt| match &stmt.kind {
+            StmtKind::Fn(decl) => Some(Item {
+                signature: signature("fn", decl),
+                doc: decl.doc.clone(),
+            }),
+            StmtKind::Extern(decl) => Some(Item {
+                signature: signature("extern fn", decl),
+                doc: decl.doc.clone(),
+            }),
+            StmtKind::Struct(decl) => {
+                let fields: Vec<String> = decl
+                    .fields
+                    .iter()
+                    .map(|field| format!("{}: {}", field.name.name, ty(&field.ty)))
+                    .collect();
+                Some(Item {
+                    signature: format!("struct {} {{ {} }}", decl.name.name, fields.join(", ")),
+                    doc: decl.doc.clone(),
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn signature(keyword: &str, decl: &FnDecl) -> String {
+    let params: Vec<String> = decl
+        .params
+        .iter()
+        .map(|param| match &param.ty {
+            Some(annotation) => format!("{}: {}", param.name.name, ty(annotation)),
+            None => param.name.name.to_string(),
+        })
+        .collect();
+    let mut signature = format!("{} {}({})", keyword, decl.name.name, params.join(", "));
+    if let Some(ret) = &decl.ret {
+        let _ = write!(signature, ": {}", ty(ret));
+    }
+    signature
+}
+
+/// Prints a type as it is written, which [`TypeExpr`]'s `Display` abbreviates.
+fn ty(ty: &TypeExpr) -> String {
+    match &ty.kind {
+        TypeExprKind::Fn { params, ret } => {
+            let params: Vec<String> = params.iter().map(self::ty).collect();
+            match ret {
+                Some(ret) => format!("fn({}): {}", params.join(", "), self::ty(ret)),
+                None => format!("fn({})", params.join(", ")),
+            }
+        }
+        TypeExprKind::Array(elem) => format!("[{}]", self::ty(elem)),
+        TypeExprKind::Named(_) | TypeExprKind::Void => ty.to_string(),
+    }
+}
+
+/// A section per file, with a heading per item, its signature as code, and
+/// its doc comment below.
+pub fn markdown(program: &Program, root: &str) -> String {
+    let mut out = String::new();
+    for (i, (path, items)) in files(program, root).iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let _ = writeln!(out, "# {}", path);
+        for item in items {
+            let _ = writeln!(out, "\n## `{}`", item.signature);
+            if let Some(doc) = &item.doc {
+                let _ = writeln!(out, "\n{}", doc);
+            }
+        }
+    }
+    out
+}
+
+/// A standalone page laid out as [`markdown`] is, with each blank-line
+/// separated paragraph of a doc comment in a `<p>`.
+pub fn html(program: &Program, root: &str) -> String {
+    let mut out = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    let _ = writeln!(out, "<title>{}</title>\n</head>\n<body>", escape(root));
+    for (path, items) in files(program, root) {
+        let _ = writeln!(out, "<h1>{}</h1>", escape(&path));
+        for item in items {
+            let _ = writeln!(out, "<h2><code>{}</code></h2>", escape(&item.signature));
+            for paragraph in item.doc.iter().flat_map(|doc| doc.split("\n\n")) {
+                let _ = writeln!(out, "<p>{}</p>", escape(paragraph.trim()));
+            }
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            ch => out.push(ch),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{files, html, markdown, Item};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::source::SourceManager;
+
+    const SOURCE: &str = "\
+?? A point.
+struct P { x: int, next: [P], }
+?? Calls `f`.
+??
+?? Returns <what it returns>.
+fn call(f: fn(int, bool): int, x) {}
+extern fn now(): int;
+let a = fn(b) {};
+";
+
+    #[test]
+    fn test_items() {
+        let program = Parser::new(Lexer::new(SOURCE)).parse_program().unwrap();
+        let items = files(&program, "main.cpl");
+        assert_eq!(
+            items,
+            [(
+                "main.cpl".to_string(),
+                vec![
+                    Item {
+                        signature: "struct P { x: int, next: [P] }".into(),
+                        doc: Some("A point.".into()),
+                    },
+                    Item {
+                        signature: "fn call(f: fn(int, bool): int, x)".into(),
+                        doc: Some("Calls `f`.\n\nReturns <what it returns>.".into()),
+                    },
+                    Item {
+                        signature: "extern fn now(): int".into(),
+                        doc: None,
+                    },
+                ]
+            )]
+        );
+
+        let mut sources = SourceManager::new();
+        let root = sources.load(Path::new("testdata/imports.cpl")).unwrap();
+        let linked = sources.link(root).unwrap();
+        let paths: Vec<_> = files(&linked, "unused")
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(paths, ["testdata/modules/math.cpl", "testdata/imports.cpl"]);
+    }
+
+    #[test]
+    fn test_output() {
+        let program = Parser::new(Lexer::new(SOURCE)).parse_program().unwrap();
+        assert_eq!(
+            markdown(&program, "main.cpl"),
+            "\
+# main.cpl
+
+## `struct P { x: int, next: [P] }`
+
+A point.
+
+## `fn call(f: fn(int, bool): int, x)`
+
+Calls `f`.
+
+Returns <what it returns>.
+
+## `extern fn now(): int`
+"
+        );
+        assert_eq!(
+            html(&program, "main.cpl"),
+            "\
+<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>main.cpl</title>
+</head>
+<body>
+<h1>main.cpl</h1>
+<h2><code>struct P { x: int, next: [P] }</code></h2>
+<p>A point.</p>
+<h2><code>fn call(f: fn(int, bool): int, x)</code></h2>
+<p>Calls `f`.</p>
+<p>Returns &lt;what it returns&gt;.</p>
+<h2><code>extern fn now(): int</code></h2>
+</body>
+</html>
+"
+        );
+    }
+}
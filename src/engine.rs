@@ -0,0 +1,450 @@
+//! Running compyl from a Rust program. An [`Engine`] evaluates source on the
+//! interpreter, keeping top-level bindings from one evaluation to the next,
+//! and converts values between the two languages with [`IntoValue`] and
+//! [`FromValue`].
+//!
+//! Each evaluation is analyzed and type checked before it runs, like a
+//! program `compyl run` is given, with the top-level names of earlier ones
+//! and those the host set in scope.
+//!
+//! Evaluations run on the calling thread, recursing on its stack. The limits
+//! on nesting and calls keep that within the 2 MiB a spawned thread gets by
+//! default in release builds. Debug builds need about ten times as much, so
+//! run them on a thread with a larger [`std::thread::Builder::stack_size`].
+//!
+//! ```
+//! use compyl::Engine;
+//!
+//! let mut engine = Engine::new();
+//! engine.set("width", 6);
+//! engine.register_fn("double", |args| match args {
+//!     [compyl::interp::Value::Int(n)] => Ok(compyl::interp::Value::Int(n * 2)),
+//!     _ => Err("expected an int".to_string()),
+//! });
+//! engine
+//!     .eval_str::<()>("extern fn double(n: int): int; let area = double(width) * 3;")
+//!     .unwrap();
+//! assert_eq!(engine.get::<i64>("area"), Ok(36));
+//! assert_eq!(engine.eval_str::<i64>("area + 1").unwrap(), 37);
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::ast::{NodeId, Program};
+use crate::builtins::Io;
+use crate::error::CompileError;
+use crate::gc::Gc;
+use crate::intern::Symbol;
+use crate::interp::{Interpreter, RuntimeError, Value};
+use crate::repl;
+use crate::sema::{self, DeclKind, Globals};
+use crate::source::SourceManager;
+use crate::span::FileId;
+use crate::types::{self, Type, TypeTable};
+
+/// Converts a Rust value to a compyl one.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+/// Converts a compyl value to a Rust one, failing with a
+/// [`RuntimeError::InvalidConversion`] if it has another type.
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self, RuntimeError>;
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        Ok(value)
+    }
+}
+
+fn mismatch<T>(value: Value, to: &'static str) -> Result<T, RuntimeError> {
+    Err(RuntimeError::InvalidConversion {
+        value: value.to_string(),
+        to,
+    })
+}
+
+macro_rules! scalar {
+    ($ty:ty, $variant:ident, $name:literal) => {
+        impl IntoValue for $ty {
+            fn into_value(self) -> Value {
+                Value::$variant(self)
+            }
+        }
+
+        impl FromValue for $ty {
+            fn from_value(value: Value) -> Result<Self, RuntimeError> {
+                match value {
+                    Value::$variant(value) => Ok(value),
+                    value => mismatch(value, $name),
+                }
+            }
+        }
+    };
+}
+
+scalar!(i64, Int, "int");
+scalar!(bool, Bool, "bool");
+scalar!(char, Char, "char");
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+/// Ints convert too, as they mix with floats in arithmetic.
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Float(value) => Ok(value),
+            Value::Int(value) => Ok(value as f64),
+            value => mismatch(value, "float"),
+        }
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::Str(self.into())
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::Str(self.into())
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Str(s) => Ok(s.to_string()),
+            value => mismatch(value, "str"),
+        }
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Void
+    }
+}
+
+/// Any value converts, so evaluations can be run for their effects alone.
+impl FromValue for () {
+    fn from_value(_: Value) -> Result<Self, RuntimeError> {
+        Ok(())
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        let elems = self.into_iter().map(IntoValue::into_value).collect();
+        Value::Array(Gc::new(RefCell::new(elems)))
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Array(elems) => elems.borrow().iter().cloned().map(T::from_value).collect(),
+            value => mismatch(value, "array"),
+        }
+    }
+}
+
+/// Evaluates compyl source, keeping its top-level bindings between calls.
+pub struct Engine {
+    interp: Interpreter,
+    /// The top-level names evaluations have declared, and their types.
+    globals: Globals,
+    types: TypeTable,
+    /// The first node id of the next evaluation, so that ids in `globals`
+    /// never clash with its own.
+    next_id: NodeId,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    /// An engine whose programs print to stdout and read stdin.
+    pub fn new() -> Engine {
+        Engine::with_io(Io::stdio())
+    }
+
+    pub fn with_io(io: Io) -> Engine {
+        Engine {
+            interp: Interpreter::with_io(io),
+            globals: Globals::new(),
+            types: TypeTable::default(),
+            next_id: NodeId(0),
+        }
+    }
+
+    pub fn io(&mut self) -> &mut Io {
+        self.interp.io()
+    }
+
+    /// Registers `func` to be called for `extern fn name`. Evaluations that
+    /// declare it must come after. It is only passed arguments of the types
+    /// the declaration gives, and calls fail if it returns another type.
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        func: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) {
+        self.interp.register_fn(name, func);
+    }
+
+    /// Runs `source`, returning its final expression statement, or the value
+    /// of a top-level `return`. The semicolon after a final expression may be
+    /// left out.
+    pub fn eval_str<T: FromValue>(&mut self, source: &str) -> Result<T, CompileError> {
+        let value = self.eval_in(source, FileId(0))?;
+        Ok(T::from_value(value)?)
+    }
+
+    /// Like [`Engine::eval_str`], with the spans of `source` in `file`.
+    pub(crate) fn eval_in(&mut self, source: &str, file: FileId) -> Result<Value, CompileError> {
+        let (mut program, next_id) = repl::parse(source, file, self.next_id)?;
+        self.next_id = next_id;
+        repl::return_last(&mut program);
+        self.run(&program)
+    }
+
+    /// Runs the file at `path` and everything it imports, returning what
+    /// [`Engine::eval_str`] would.
+    pub fn eval_file<T: FromValue>(&mut self, path: impl AsRef<Path>) -> Result<T, CompileError> {
+        let mut sources = SourceManager::new();
+        let root = sources.load(path.as_ref())?;
+        let (mut program, next_id) = sources.link_from(root, self.next_id)?;
+        self.next_id = next_id;
+        repl::return_last(&mut program);
+        let value = self.run(&program)?;
+        Ok(T::from_value(value)?)
+    }
+
+    fn run(&mut self, program: &Program) -> Result<Value, CompileError> {
+        let resolution = sema::analyze_in(program, &self.globals)?;
+        let table = types::check_in(program, &resolution, &self.types)?;
+        let value = self
+            .interp
+            .run(program)
+            .map_err(|err| CompileError::Runtime(err, Box::new(self.interp.backtrace().clone())))?;
+
+        // Only a run that finished declared its names.
+        self.globals.extend(program, &resolution);
+        self.types = TypeTable {
+            exprs: HashMap::new(),
+            ..table
+        };
+        Ok(value)
+    }
+
+    /// The value of the top-level binding `name`.
+    pub fn get<T: FromValue>(&self, name: &str) -> Result<T, RuntimeError> {
+        let value = self
+            .interp
+            .global(name)
+            .ok_or_else(|| RuntimeError::UndefinedVariable(name.to_string()))?;
+        T::from_value(value)
+    }
+
+    /// Binds `name` at the top level, where later evaluations see it.
+    pub fn set(&mut self, name: &str, value: impl IntoValue) {
+        let value = value.into_value();
+        let id = self.next_id;
+        self.next_id = NodeId(id.0 + 1);
+        self.globals
+            .declare(Symbol::intern(name), DeclKind::Let, id);
+        self.types.decls.insert(id, type_of(&value));
+        self.interp.set_global(name, value);
+    }
+}
+
+/// The type evaluations see a value the host set as.
+fn type_of(value: &Value) -> Type {
+    match value {
+        Value::Int(_) => Type::Int,
+        Value::Float(_) => Type::Float,
+        Value::Bool(_) => Type::Bool,
+        Value::Char(_) => Type::Char,
+        Value::Str(_) => Type::Str,
+        // The first element decides, as in an array literal.
+        Value::Array(elems) => {
+            let elem = elems.borrow().first().map_or(Type::Unknown, type_of);
+            Type::Array(Box::new(elem))
+        }
+        Value::Struct(value) => Type::Struct(value.layout.name),
+        Value::Void => Type::Void,
+        Value::Function(_) | Value::Closure(_) | Value::Builtin(_) | Value::Extern(_) => {
+            Type::Unknown
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Engine, FromValue, IntoValue};
+    use crate::builtins::Io;
+    use crate::error::CompileError;
+    use crate::interp::{RuntimeError, Value};
+
+    #[test]
+    fn test_conversions() {
+        let array = vec![vec![1, 2], vec![3]].into_value();
+        assert_eq!(array.to_string(), "[[1, 2], [3]]");
+        assert_eq!(
+            Vec::<Vec<i64>>::from_value(array),
+            Ok(vec![vec![1, 2], vec![3]])
+        );
+        assert_eq!(String::from_value("hi".into_value()), Ok("hi".to_string()));
+        assert_eq!(f64::from_value(Value::Int(2)), Ok(2.0));
+        assert_eq!(
+            Vec::<bool>::from_value(vec!['a'].into_value()),
+            Err(RuntimeError::InvalidConversion {
+                value: "a".into(),
+                to: "bool",
+            })
+        );
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut engine = Engine::with_io(Io::buffered(""));
+        engine.set("names", vec!["a", "b"]);
+        let source =
+            "fn greet(name) { println(\"hi ${name}\"); return len(name); } greet(names[1])";
+        assert_eq!(engine.eval_str::<i64>(source).unwrap(), 1);
+        assert_eq!(engine.io().take_output(), "hi b\n");
+
+        // Bindings persist, and can be read back.
+        engine
+            .eval_str::<()>("let total = greet(\"abc\") + 0.5;")
+            .unwrap();
+        assert_eq!(engine.get::<f64>("total"), Ok(3.5));
+        assert_eq!(
+            engine.get::<i64>("missing"),
+            Err(RuntimeError::UndefinedVariable("missing".into()))
+        );
+        assert!(matches!(
+            engine.eval_str::<String>("total"),
+            Err(CompileError::Runtime(
+                RuntimeError::InvalidConversion { to: "str", .. },
+                _
+            ))
+        ));
+        assert!(matches!(
+            engine.eval_str::<()>("let = 1;"),
+            Err(CompileError::Parse(_))
+        ));
+
+        engine.eval_file::<()>("testdata/imports.cpl").unwrap();
+        assert_eq!(engine.io().take_output(), "hi abc\n49\n");
+        assert_eq!(engine.eval_str::<i64>("square(3)").unwrap(), 9);
+        assert!(matches!(
+            engine.eval_file::<()>("testdata/missing.cpl"),
+            Err(CompileError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_checks() {
+        let mut engine = Engine::with_io(Io::buffered(""));
+        engine.set("width", 6);
+        engine
+            .eval_str::<()>(
+                "struct P { x: int } const TWO = 2; fn area(p: P): int { return p.x * width; }",
+            )
+            .unwrap();
+        assert_eq!(engine.eval_str::<i64>("area(P { x: TWO })").unwrap(), 12);
+
+        // Nothing runs if checking fails.
+        assert!(matches!(
+            engine.eval_str::<()>("println(\"ran\"); missing();"),
+            Err(CompileError::Sema(_))
+        ));
+        assert!(matches!(
+            engine.eval_str::<()>("println(\"ran\"); let b: bool = area(P { x: 1 });"),
+            Err(CompileError::Type(_))
+        ));
+        assert!(matches!(
+            engine.eval_str::<()>("width = \"wide\";"),
+            Err(CompileError::Type(_))
+        ));
+        assert!(matches!(
+            engine.eval_str::<()>("TWO = 3;"),
+            Err(CompileError::Sema(_))
+        ));
+        assert_eq!(engine.io().take_output(), "");
+        assert!(matches!(
+            engine.eval_str::<()>("let a = 1; let b = 10 / (a - 1);"),
+            Err(CompileError::Runtime(RuntimeError::DivisionByZero, _))
+        ));
+        assert!(matches!(
+            engine.eval_str::<i64>("b"),
+            Err(CompileError::Sema(_))
+        ));
+
+        // A later evaluation may declare a name again, with another type.
+        engine.eval_str::<()>("let width = \"wide\";").unwrap();
+        assert_eq!(engine.eval_str::<String>("width + \"r\"").unwrap(), "wider");
+        engine.set("width", 1.5);
+        assert_eq!(engine.eval_str::<f64>("width * 2.0").unwrap(), 3.0);
+    }
+
+    #[test]
+    fn test_extern_signatures() {
+        let mut engine = Engine::with_io(Io::buffered(""));
+        engine.register_fn("half", |args| match args {
+            [Value::Int(n)] => Ok(Value::Int(n / 2)),
+            [n] => Ok(Value::Str(n.to_string().into())),
+            _ => Err("expected one argument".to_string()),
+        });
+        engine
+            .eval_str::<()>("extern fn half(n: int): int;")
+            .unwrap();
+        assert_eq!(engine.eval_str::<i64>("half(9)").unwrap(), 4);
+
+        // Calls are checked against the declaration, and so are the values
+        // that cross to and from the host where the types are unknown.
+        assert!(matches!(
+            engine.eval_str::<i64>("half(\"s\")"),
+            Err(CompileError::Type(_))
+        ));
+        assert!(matches!(
+            engine.eval_str::<i64>("fn call(f, x) { return f(x); } call(half, \"s\")"),
+            Err(CompileError::Runtime(
+                RuntimeError::HostArgument {
+                    index: 0,
+                    found: "str",
+                    ..
+                },
+                _
+            ))
+        ));
+        engine.eval_str::<()>("extern fn half(n): int;").unwrap();
+        assert!(matches!(
+            engine.eval_str::<i64>("half(1.5)"),
+            Err(CompileError::Runtime(
+                RuntimeError::HostResult { found: "str", .. },
+                _
+            ))
+        ));
+    }
+}
@@ -0,0 +1,144 @@
+use std::error::Error;
+use std::{fmt, io};
+
+use crate::codegen::CodegenError;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::interp::{Backtrace, RuntimeError};
+use crate::lexer::LexError;
+use crate::lints::Lint;
+use crate::parser::ParseError;
+use crate::sema::SemaError;
+use crate::source::ImportError;
+use crate::types::TypeError;
+
+/// Any error produced while running the compiler pipeline.
+#[derive(Debug)]
+pub enum CompileError {
+    Io(io::Error),
+    Lex(LexError),
+    /// Every syntax error found, lexer errors among them; never empty.
+    Parse(Vec<ParseError>),
+    Import(ImportError),
+    /// Every problem found by semantic analysis; never empty.
+    Sema(Vec<SemaError>),
+    /// Every type error found; never empty.
+    Type(Vec<TypeError>),
+    Codegen(CodegenError),
+    /// A runtime error, and where it happened if the runtime said.
+    Runtime(RuntimeError, Box<Backtrace>),
+    /// Lints reported while warnings are denied; never empty.
+    Lints(Vec<Lint>),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Io(err) => write!(f, "{}", err),
+            CompileError::Lex(err) => write!(f, "{}", err),
+            CompileError::Parse(errs) => write_all(f, errs),
+            CompileError::Import(err) => write!(f, "{}", err),
+            CompileError::Sema(errs) => write_all(f, errs),
+            CompileError::Type(errs) => write_all(f, errs),
+            CompileError::Codegen(err) => write!(f, "{}", err),
+            CompileError::Runtime(err, _) => write!(f, "{}", err),
+            CompileError::Lints(lints) => write_all(f, lints),
+        }
+    }
+}
+
+fn write_all(f: &mut fmt::Formatter<'_>, errs: &[impl fmt::Display]) -> fmt::Result {
+    write!(f, "{}", errs[0])?;
+    if errs.len() > 1 {
+        write!(f, " (and {} more)", errs.len() - 1)?;
+    }
+    Ok(())
+}
+
+impl Error for CompileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CompileError::Io(err) => Some(err),
+            CompileError::Lex(err) => Some(err),
+            CompileError::Parse(errs) => errs.first().map(|err| err as &dyn Error),
+            CompileError::Import(err) => Some(err),
+            CompileError::Sema(errs) => errs.first().map(|err| err as &dyn Error),
+            CompileError::Type(errs) => errs.first().map(|err| err as &dyn Error),
+            CompileError::Codegen(err) => Some(err),
+            CompileError::Runtime(err, _) => Some(err),
+            CompileError::Lints(_) => None,
+        }
+    }
+}
+
+impl CompileError {
+    /// Converts the error into diagnostics, one per underlying problem.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            CompileError::Io(err) => vec![Diagnostic::error("E0000", err.to_string())],
+            CompileError::Lex(err) => vec![err.into()],
+            CompileError::Parse(errs) => errs.iter().map(Diagnostic::from).collect(),
+            CompileError::Import(err) => vec![err.into()],
+            CompileError::Sema(errs) => errs.iter().map(Diagnostic::from).collect(),
+            CompileError::Type(errs) => errs.iter().map(Diagnostic::from).collect(),
+            CompileError::Codegen(err) => vec![err.into()],
+            CompileError::Runtime(err, backtrace) => {
+                vec![Diagnostic::from(err).with_backtrace(backtrace)]
+            }
+            CompileError::Lints(lints) => lints
+                .iter()
+                .map(|lint| Diagnostic {
+                    severity: Severity::Error,
+                    ..lint.into()
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<io::Error> for CompileError {
+    fn from(err: io::Error) -> Self {
+        CompileError::Io(err)
+    }
+}
+
+impl From<LexError> for CompileError {
+    fn from(err: LexError) -> Self {
+        CompileError::Lex(err)
+    }
+}
+
+impl From<Vec<ParseError>> for CompileError {
+    fn from(errs: Vec<ParseError>) -> Self {
+        CompileError::Parse(errs)
+    }
+}
+
+impl From<ImportError> for CompileError {
+    fn from(err: ImportError) -> Self {
+        CompileError::Import(err)
+    }
+}
+
+impl From<Vec<SemaError>> for CompileError {
+    fn from(errs: Vec<SemaError>) -> Self {
+        CompileError::Sema(errs)
+    }
+}
+
+impl From<Vec<TypeError>> for CompileError {
+    fn from(errs: Vec<TypeError>) -> Self {
+        CompileError::Type(errs)
+    }
+}
+
+impl From<CodegenError> for CompileError {
+    fn from(err: CodegenError) -> Self {
+        CompileError::Codegen(err)
+    }
+}
+
+impl From<RuntimeError> for CompileError {
+    fn from(err: RuntimeError) -> Self {
+        CompileError::Runtime(err, Box::default())
+    }
+}
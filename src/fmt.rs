@@ -0,0 +1,380 @@
+//! A source formatter. It reprints a program's tokens with four-space
+//! indentation, one statement per line and spaces around operators, keeping
+//! comments where they were and at most one blank line in a row.
+
+use crate::error::CompileError;
+use crate::lexer::{Lexer, Token};
+use crate::parser::Parser;
+use crate::trivia::{self, TriviaKind, TriviaToken};
+
+const INDENT: &str = "    ";
+
+/// Formats `source`, which must parse.
+///
+/// ```
+/// use compyl::fmt::format;
+///
+/// let formatted = format("fn f(a,b){return a+-b;}").unwrap();
+/// assert_eq!(formatted, "fn f(a, b) {\n    return a + -b;\n}\n");
+/// ```
+pub fn format(source: &str) -> Result<String, CompileError> {
+    Parser::new(Lexer::new(source)).parse_program()?;
+
+    let mut formatter = Formatter::default();
+    for token in trivia::lex(source)? {
+        formatter.token(&token);
+    }
+
+    Ok(formatter.out)
+}
+
+#[derive(Default)]
+struct Formatter {
+    out: String,
+    indent: usize,
+    paren_depth: usize,
+    prev: Option<Token<'static>>,
+    /// Whether `prev` was a unary operator, which binds to what follows.
+    prev_unary: bool,
+    /// The paren depth outside each open `if` condition, innermost last.
+    conditions: Vec<usize>,
+    /// Whether `prev` closed an `if` condition, after which an operator
+    /// starts the `if` expression's first arm.
+    closed_condition: bool,
+    /// Whether the next token goes on a new line.
+    line_break: bool,
+    /// Whether the output ends in a line comment, which anything after it on
+    /// the same line would be part of.
+    line_comment: bool,
+    /// Newlines in the source since the last token or comment.
+    newlines: usize,
+    /// The kind of each open brace, innermost last.
+    braces: Vec<Brace>,
+    /// The kind of brace a `struct` or `fn` header ends with.
+    header: Option<Brace>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Brace {
+    Block,
+    /// A struct declaration's fields, one per line.
+    Fields,
+    /// A struct literal, kept on one line.
+    Literal,
+    /// The arms of a `match`, one per line, opened at this paren depth.
+    Arms(usize),
+}
+
+impl Formatter {
+    fn token(&mut self, token: &TriviaToken) {
+        for trivia in &token.leading {
+            self.trivia(trivia.kind, trivia.text);
+        }
+
+        match &token.token.token {
+            Token::TokEof => {
+                if !self.out.is_empty() && !self.out.ends_with('\n') {
+                    self.out.push('\n');
+                }
+                return;
+            }
+            Token::RSquirly if self.braces.last() != Some(&Brace::Literal) => {
+                self.indent = self.indent.saturating_sub(1);
+                // No blank line before a closing brace.
+                self.newlines = self.newlines.min(1);
+            }
+            _ => {}
+        }
+
+        let tok = &token.token.token;
+        if self.breaks_before(tok) {
+            self.new_line();
+        } else if self.spaced(tok) {
+            self.out.push(' ');
+        }
+        self.out.push_str(token.text);
+
+        self.line_break = match tok {
+            Token::LSquirly => {
+                let brace = self.header.take().unwrap_or(match self.prev {
+                    Some(Token::Identifier(_)) => Brace::Literal,
+                    _ => Brace::Block,
+                });
+                self.braces.push(brace);
+                if brace != Brace::Literal {
+                    self.indent += 1;
+                }
+                brace != Brace::Literal
+            }
+            Token::RSquirly => self.braces.pop() != Some(Brace::Literal),
+            Token::SemiColon => self.paren_depth == 0,
+            Token::Comma => match self.braces.last() {
+                Some(Brace::Fields) => true,
+                Some(Brace::Arms(depth)) => *depth == self.paren_depth,
+                _ => false,
+            },
+            _ => false,
+        };
+        match tok {
+            Token::KwStruct => self.header = Some(Brace::Fields),
+            Token::KwFn | Token::KwFor => self.header = Some(Brace::Block),
+            Token::KwIf => self.conditions.push(self.paren_depth),
+            Token::KwMatch => self.header = Some(Brace::Arms(self.paren_depth)),
+            Token::SemiColon => self.header = None,
+            _ => {}
+        }
+        match tok {
+            Token::Lparen | Token::LBracket => self.paren_depth += 1,
+            Token::RParen | Token::RBracket => {
+                self.paren_depth = self.paren_depth.saturating_sub(1)
+            }
+            _ => {}
+        }
+        self.prev_unary = self.is_unary(tok);
+        self.closed_condition =
+            *tok == Token::RParen && self.conditions.last() == Some(&self.paren_depth);
+        if self.closed_condition {
+            self.conditions.pop();
+        }
+        self.prev = Some(tok.clone().into_owned());
+        self.newlines = 0;
+
+        for trivia in &token.trailing {
+            self.trivia(trivia.kind, trivia.text);
+        }
+    }
+
+    fn trivia(&mut self, kind: TriviaKind, text: &str) {
+        match kind {
+            TriviaKind::Whitespace => self.newlines += text.matches('\n').count(),
+            // A comment after code on the same line stays there.
+            TriviaKind::Comment if self.newlines == 0 && !self.out.is_empty() => {
+                self.out.push(' ');
+                self.out.push_str(text.trim_end());
+                self.line_comment = !text.starts_with("?*");
+                self.line_break |= self.line_comment;
+            }
+            TriviaKind::Comment => {
+                self.new_line();
+                self.out.push_str(text.trim_end());
+                self.line_comment = !text.starts_with("?*");
+                self.line_break = true;
+                self.newlines = 0;
+            }
+        }
+    }
+
+    fn breaks_before(&self, tok: &Token) -> bool {
+        if self.out.is_empty() || self.line_comment {
+            return true;
+        }
+        match (&self.prev, tok) {
+            (Some(Token::LSquirly), Token::RSquirly) => false,
+            (_, Token::RSquirly)
+                if matches!(self.braces.last(), Some(Brace::Fields | Brace::Arms(_))) =>
+            {
+                true
+            }
+            (
+                Some(Token::RSquirly),
+                Token::KwElse | Token::SemiColon | Token::Comma | Token::RParen,
+            ) => false,
+            _ => self.line_break,
+        }
+    }
+
+    fn new_line(&mut self) {
+        if !self.out.is_empty() {
+            self.out.push('\n');
+            let opens_block = matches!(self.prev, Some(Token::LSquirly));
+            if self.newlines >= 2 && !opens_block && !self.out.ends_with("\n\n") {
+                self.out.push('\n');
+            }
+        }
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+        self.line_break = false;
+        self.line_comment = false;
+    }
+
+    /// Whether a space separates `tok` from the previous token on the same line.
+    fn spaced(&self, tok: &Token) -> bool {
+        if self.prev_unary {
+            return false;
+        }
+        match (&self.prev, tok) {
+            (None, _) => false,
+            (Some(Token::Lparen | Token::LBracket | Token::Dot | Token::DotDot), _) => false,
+            (Some(Token::LSquirly), Token::RSquirly) => false,
+            (
+                _,
+                Token::RParen
+                | Token::RBracket
+                | Token::Comma
+                | Token::SemiColon
+                | Token::Colon
+                | Token::Dot
+                | Token::DotDot,
+            ) => false,
+            // Calls, indexing and `fn(...)` types.
+            (
+                Some(Token::Identifier(_) | Token::RParen | Token::RBracket | Token::KwFn),
+                Token::Lparen | Token::LBracket,
+            ) => false,
+            _ => true,
+        }
+    }
+
+    /// Whether `tok`, coming after the previous token, is a prefix operator.
+    fn is_unary(&self, tok: &Token) -> bool {
+        match tok {
+            Token::OpNot | Token::OpBitNot => true,
+            Token::OpMinus if self.closed_condition => true,
+            Token::OpMinus => !matches!(
+                self.prev,
+                Some(
+                    Token::Identifier(_)
+                        | Token::IntLiteral(_)
+                        | Token::FloatLiteral(_)
+                        | Token::CharLiteral(_)
+                        | Token::StrLiteral(_)
+                        | Token::InterpolatedStr(_)
+                        | Token::KwTrue
+                        | Token::KwFalse
+                        | Token::RParen
+                        | Token::RBracket
+                )
+            ),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::format;
+
+    #[test]
+    fn test_layout() {
+        let source = "let x=1;fn add( a:int,b ) :int{return a*(b- -1);}\n\
+                      while(x<10){if(x%2==0){x+=1;}else{x=add(x,1);}}\n\
+                      fn nothing(){}";
+        let expected = "\
+let x = 1;
+fn add(a: int, b): int {
+    return a * (b - -1);
+}
+while (x < 10) {
+    if (x % 2 == 0) {
+        x += 1;
+    } else {
+        x = add(x, 1);
+    }
+}
+fn nothing() {}
+";
+
+        assert_eq!(format(source).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_comments_and_blank_lines() {
+        let source = "? header\n\n\n\nlet a = 0x1F;   ? trailing\nfn f(g: fn(int): bool) {\n\n  ?* block *?\n  return !g(~a);\n\n}\n";
+        let expected = "\
+? header
+
+let a = 0x1F; ? trailing
+fn f(g: fn(int): bool) {
+    ?* block *?
+    return !g(~a);
+}
+";
+
+        assert_eq!(format(source).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_structs() {
+        let source = "struct P{x:int,y:[P]}struct E {}\nfn f(): P { let p=P{x:1,y:[]}; p.y[0].x+=E{}.z; return p; }";
+        let expected = "\
+struct P {
+    x: int,
+    y: [P]
+}
+struct E {}
+fn f(): P {
+    let p = P { x: 1, y: [] };
+    p.y[0].x += E {}.z;
+    return p;
+}
+";
+
+        assert_eq!(format(source).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_if_expressions() {
+        // The minus after a condition is the arm's sign, not a subtraction.
+        let expected =
+            "let a = if (n < 0) -n else n - 1;\nif (f(x)) {\n    a = -a;\n} else if (g) {}\n";
+        assert_eq!(
+            format("let a=if(n<0)-n else n-1;if(f(x)){a=-a;}else if(g){}").unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_for_loops() {
+        // The brace after `n` opens the body, not a struct literal.
+        let expected = "for (i in 0..n) {\n    continue;\n}\n";
+        assert_eq!(format("for(i in 0 .. n){continue;}").unwrap(), expected);
+    }
+
+    #[test]
+    fn test_match() {
+        let source =
+            "let s=match(n){0=>\"zero\",-1=>f(a,b),_=>match(n>0){true=>\"up\",false=>\"down\"},};";
+        let expected = "\
+let s = match (n) {
+    0 => \"zero\",
+    -1 => f(a, b),
+    _ => match (n > 0) {
+        true => \"up\",
+        false => \"down\"
+    },
+};
+";
+
+        assert_eq!(format(source).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_rejects_invalid_programs() {
+        assert!(format("let = 1;").is_err());
+    }
+
+    #[test]
+    fn test_comments_before_closing_tokens() {
+        // Each token after a line comment must start a new line, or it would
+        // become part of the comment.
+        let cases = [
+            (
+                "fn f():int{\n? only a comment\n}",
+                "fn f(): int {\n    ? only a comment\n}\n",
+            ),
+            ("if(a){} ? note\nelse{}", "if (a) {} ? note\nelse {}\n"),
+            ("let g=fn(){} ? c\n;", "let g = fn() {} ? c\n;\n"),
+            ("let a=[fn(){} ? c\n,1];", "let a = [fn() {} ? c\n, 1];\n"),
+            ("f(fn(){} ? c\n);", "f(fn() {} ? c\n);\n"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(format(source).unwrap(), expected);
+            assert_eq!(format(expected).unwrap(), expected);
+        }
+    }
+}
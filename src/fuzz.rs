@@ -0,0 +1,213 @@
+//! Entry points for fuzzing the front end. Whatever the bytes, each returns
+//! tokens, a tree or structured errors: it must never panic, hang or use
+//! memory out of proportion to the input. The `cargo fuzz` targets in `fuzz/`
+//! call these, and the tests here run them over generated inputs.
+//!
+//! Recursion is bounded by [`Parser::DEFAULT_MAX_DEPTH`] and
+//! [`Parser::DEFAULT_MAX_OPERATORS`], which fit in 2 MiB of stack in release
+//! builds. Threads with less can parse with a lower [`Parser::with_max_depth`]
+//! and [`Parser::with_max_operators`].
+
+use crate::error::CompileError;
+use crate::lexer::{self, Lexer, LexerConfig, Token};
+use crate::lints::{self, Lint};
+use crate::parser::{ParseError, Parser};
+use crate::{sema, types};
+
+/// Lexes `data` up to the end or the first error, with comments kept and
+/// without, returning how many tokens there were.
+pub fn lex(data: &[u8]) -> Result<usize, CompileError> {
+    let source = lexer::decode(data)?;
+    let mut count = 0;
+    for keep_comments in [false, true] {
+        let mut lexer = Lexer::with_config(source, LexerConfig { keep_comments });
+        count = 0;
+        while lexer.next_token()?.token != Token::TokEof {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Parses `data`, recovering from errors, and prints what parsed.
+pub fn parse(data: &[u8]) -> Result<String, Vec<ParseError>> {
+    let source = lexer::decode(data).map_err(|err| vec![ParseError::Lex(err)])?;
+    let (program, errors) = Parser::new(Lexer::new(source)).parse_partial();
+    match errors.is_empty() {
+        true => Ok(program.to_string()),
+        false => Err(errors),
+    }
+}
+
+/// Runs everything `compyl check` does on a single file.
+pub fn check(data: &[u8]) -> Result<Vec<Lint>, CompileError> {
+    let source = lexer::decode(data)?;
+    let program = Parser::new(Lexer::new(source)).parse_program()?;
+    let resolution = sema::analyze(&program)?;
+    types::check(&program, &resolution)?;
+    Ok(lints::check(&program, &resolution))
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::{check, lex, parse};
+
+    /// A xorshift generator, so runs are reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    const PROGRAMS: &[&str] = &[
+        "fn f(a: int, b: int): int { return a + b; } let x = f(1, 2); print(x);",
+        "struct P { x: int, next: [P], } let p = P { x: 1, next: [], }; p.next[0].x *= 2;",
+        "for (i in 0..10) { if (i % 2 == 0) { continue; } println(\"${i} is odd\"); }",
+        "let a = [1, 2]; a[0] = len(a); while (true) { break; } let g = fn(x) { return x; };",
+        "let t = ~1 << 3 | 0xff ^ 0b1 && !false; ? a comment\n?* and ?* another *? *?",
+        "let m = match (x) { 0 => \"a\", -1 => if (y) 'b' else 'c', n => n, _ => 0, };",
+    ];
+
+    const TOKENS: &[&str] = &[
+        "let ",
+        "const ",
+        "fn ",
+        "struct ",
+        "if ",
+        "else ",
+        "while ",
+        "for ",
+        "in ",
+        "return ",
+        "break",
+        "import ",
+        "match ",
+        "extern ",
+        "=>",
+        "_",
+        "x",
+        "int",
+        "(",
+        ")",
+        "{",
+        "}",
+        "[",
+        "]",
+        ";",
+        ",",
+        ".",
+        "..",
+        ":",
+        "=",
+        "+=",
+        "+",
+        "-",
+        "*",
+        "<<",
+        "<=",
+        "==",
+        "&&",
+        "~",
+        "!",
+        "\"",
+        "\"${",
+        "}\"",
+        "'a'",
+        "'",
+        "0x",
+        "1.5",
+        "1e",
+        "99999999999999999999",
+        "?* ",
+        "?? ",
+        "\\",
+        "$",
+        "\n",
+        "é",
+    ];
+
+    fn run(data: &[u8]) {
+        let _ = lex(data);
+        let _ = parse(data);
+        let _ = check(data);
+    }
+
+    #[test]
+    fn test_random_bytes() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let alphabet = b" \n(){}[];,.:+-*/<>=!&|^~?\"\\$_ax09";
+        for _ in 0..2000 {
+            let data: Vec<u8> = (0..rng.below(100))
+                .map(|_| match rng.below(8) {
+                    0 => rng.below(256) as u8,
+                    _ => alphabet[rng.below(alphabet.len())],
+                })
+                .collect();
+            run(&data);
+        }
+    }
+
+    #[test]
+    fn test_random_tokens() {
+        let mut rng = Rng(42);
+        for _ in 0..2000 {
+            let data: String = (0..rng.below(60))
+                .map(|_| TOKENS[rng.below(TOKENS.len())])
+                .collect();
+            run(data.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_mutated_programs() {
+        let mut rng = Rng(7);
+        for _ in 0..2000 {
+            let mut data = PROGRAMS[rng.below(PROGRAMS.len())].as_bytes().to_vec();
+            for _ in 0..1 + rng.below(4) {
+                let at = rng.below(data.len());
+                let end = (at + rng.below(12)).min(data.len());
+                match rng.below(3) {
+                    0 => drop(data.drain(at..end)),
+                    1 => drop(data.splice(at..at, TOKENS[rng.below(TOKENS.len())].bytes())),
+                    _ => {
+                        let copy = data[at..end].to_vec();
+                        data.splice(at..at, copy);
+                    }
+                }
+            }
+            run(&data);
+        }
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        // The limits are sized for the main thread's stack, and debug builds
+        // need several times what release builds do.
+        let nest = || {
+            for (open, close) in [("(", ")"), ("[", "]"), ("{", "}"), ("\"${", "}\"")] {
+                let data = format!("let x = {}1{};", open.repeat(10_000), close.repeat(10_000));
+                assert!(parse(data.as_bytes()).is_err());
+                assert!(check(data.as_bytes()).is_err());
+            }
+            let data = format!("let x = {}1{};", "(".repeat(255), ")".repeat(255));
+            assert!(check(data.as_bytes()).is_ok());
+            let chain = |terms: usize| format!("let x = {};", vec!["1"; terms].join(" + "));
+            assert!(check(chain(100_000).as_bytes()).is_err());
+            assert!(check(chain(1000).as_bytes()).is_ok());
+        };
+        thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(nest)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
@@ -0,0 +1,292 @@
+//! The heap runtime values live on. Arrays, structs, functions and the
+//! environments they close over can refer to one another in cycles, which
+//! reference counting alone never frees: every such object is allocated
+//! through a [`Heap`], which from time to time marks what the program can
+//! still reach and sweeps the rest.
+//!
+//! Roots are found rather than registered. An object that something outside
+//! the heap refers to, be it a value on the VM's stack or in its globals, an
+//! environment the interpreter is running in or a temporary held while an
+//! expression is evaluated, has more references than the objects in the heap
+//! account for. Marking spreads from those, and each object left unmarked is
+//! cleared, dropping what it refers to so that the cycles it was part of fall
+//! apart. Strings refer to nothing, so their reference counts free them.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::rc::{Rc, Weak};
+
+/// An object that can hold [`Gc`] handles.
+pub trait Trace {
+    /// Reports each handle the object holds to `tracer`, once per reference.
+    fn trace(&self, tracer: &mut Tracer);
+
+    /// Drops the handles the object holds. Only called on unreachable objects.
+    fn clear(&self) {}
+}
+
+/// Collects the handles an object reports while it is traced.
+#[derive(Default)]
+pub struct Tracer {
+    found: Vec<*const ()>,
+}
+
+impl Tracer {
+    pub fn visit<T: ?Sized>(&mut self, gc: &Gc<T>) {
+        self.found.push(gc.addr());
+    }
+}
+
+/// A shared reference to an object on a [`Heap`].
+pub struct Gc<T: ?Sized>(Rc<T>);
+
+impl<T> Gc<T> {
+    /// An object no heap tracks, for values made outside a runtime. Reference
+    /// counting alone frees it, so a cycle through it is never freed.
+    pub fn new(value: T) -> Gc<T> {
+        Gc(Rc::new(value))
+    }
+}
+
+impl<T: ?Sized> Gc<T> {
+    pub fn ptr_eq(a: &Gc<T>, b: &Gc<T>) -> bool {
+        Rc::ptr_eq(&a.0, &b.0)
+    }
+
+    fn addr(&self) -> *const () {
+        Rc::as_ptr(&self.0) as *const ()
+    }
+}
+
+impl<T: ?Sized> Deref for Gc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: ?Sized> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        Gc(self.0.clone())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Gc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// When a [`Heap`] collects.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GcConfig {
+    /// How many objects the heap holds before its first collection.
+    pub initial_threshold: usize,
+    /// How far the heap may grow past what survived a collection before the
+    /// next one, as a multiple of that; never less than the initial threshold.
+    pub growth_factor: f64,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            initial_threshold: 10_000,
+            growth_factor: 2.0,
+        }
+    }
+}
+
+pub struct Heap {
+    objects: Vec<Weak<dyn Trace>>,
+    threshold: usize,
+    config: GcConfig,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap::new(GcConfig::default())
+    }
+}
+
+impl Heap {
+    pub fn new(config: GcConfig) -> Heap {
+        Heap {
+            objects: Vec::new(),
+            threshold: config.initial_threshold,
+            config,
+        }
+    }
+
+    /// Collects by `config` from now on.
+    pub fn configure(&mut self, config: GcConfig) {
+        self.config = config;
+        self.threshold = config.initial_threshold.max(self.objects.len());
+    }
+
+    pub fn alloc<T: Trace + 'static>(&mut self, value: T) -> Gc<T> {
+        let object = Rc::new(value);
+        let weak: Weak<dyn Trace> = Rc::downgrade(&object) as Weak<dyn Trace>;
+        self.objects.push(weak);
+        Gc(object)
+    }
+
+    /// How many objects are alive, reachable or not.
+    pub fn len(&self) -> usize {
+        self.objects
+            .iter()
+            .filter(|object| object.strong_count() > 0)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Collects if enough has been allocated since the last collection. The
+    /// runtimes call this where they hold no borrows of heap objects.
+    pub fn maybe_collect(&mut self) {
+        if self.objects.len() >= self.threshold {
+            self.collect();
+        }
+    }
+
+    /// Frees every object the program can no longer reach, returning how many.
+    pub fn collect(&mut self) -> usize {
+        let live: Vec<Rc<dyn Trace>> = self.objects.iter().filter_map(Weak::upgrade).collect();
+        let index: HashMap<*const (), usize> = live
+            .iter()
+            .enumerate()
+            .map(|(at, object)| (Rc::as_ptr(object) as *const (), at))
+            .collect();
+
+        // Count the references from outside the heap: all of them but `live`'s
+        // own and those of other objects. An object borrowed mutably reports
+        // nothing, which keeps what it refers to alive until next time.
+        let mut outside: Vec<usize> = live
+            .iter()
+            .map(|object| Rc::strong_count(object) - 1)
+            .collect();
+        let mut children = Vec::with_capacity(live.len());
+        for object in &live {
+            let mut tracer = Tracer::default();
+            object.trace(&mut tracer);
+            let found: Vec<usize> = tracer
+                .found
+                .iter()
+                .filter_map(|addr| index.get(addr).copied())
+                .collect();
+            for &child in &found {
+                outside[child] -= 1;
+            }
+            children.push(found);
+        }
+
+        let mut marked: Vec<bool> = outside.iter().map(|&count| count > 0).collect();
+        let mut pending: Vec<usize> = (0..live.len()).filter(|&at| marked[at]).collect();
+        while let Some(at) = pending.pop() {
+            for &child in &children[at] {
+                if !marked[child] {
+                    marked[child] = true;
+                    pending.push(child);
+                }
+            }
+        }
+
+        // `live` keeps every object allocated while the garbage is cleared, so
+        // none is dropped with another half cleared.
+        let mut freed = 0;
+        self.objects.clear();
+        for (object, &marked) in live.iter().zip(&marked) {
+            match marked {
+                true => self.objects.push(Rc::downgrade(object)),
+                false => {
+                    object.clear();
+                    freed += 1;
+                }
+            }
+        }
+        drop(live);
+
+        let grown = (self.objects.len() as f64 * self.config.growth_factor) as usize;
+        self.threshold = grown.max(self.config.initial_threshold);
+        freed
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        self.collect();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{Gc, GcConfig, Heap, Trace, Tracer};
+
+    struct Node {
+        next: RefCell<Option<Gc<Node>>>,
+    }
+
+    impl Trace for Node {
+        fn trace(&self, tracer: &mut Tracer) {
+            if let Some(next) = &*self.next.borrow() {
+                tracer.visit(next);
+            }
+        }
+
+        fn clear(&self) {
+            self.next.borrow_mut().take();
+        }
+    }
+
+    fn node(heap: &mut Heap) -> Gc<Node> {
+        heap.alloc(Node {
+            next: RefCell::new(None),
+        })
+    }
+
+    #[test]
+    fn test_cycles() {
+        let mut heap = Heap::default();
+        let (a, b) = (node(&mut heap), node(&mut heap));
+        *a.next.borrow_mut() = Some(b.clone());
+        *b.next.borrow_mut() = Some(a.clone());
+        let weak = Rc::downgrade(&b.0);
+        drop(b);
+
+        // `a` is a root, and keeps `b` alive through it.
+        assert_eq!(heap.collect(), 0);
+        assert!(weak.upgrade().is_some());
+
+        drop(a);
+        assert_eq!(heap.len(), 2);
+        assert_eq!(heap.collect(), 2);
+        assert!(weak.upgrade().is_none());
+        assert!(heap.is_empty());
+    }
+
+    #[test]
+    fn test_threshold() {
+        let config = GcConfig {
+            initial_threshold: 4,
+            growth_factor: 3.0,
+        };
+        let mut heap = Heap::new(config);
+        let kept: Vec<_> = (0..3).map(|_| node(&mut heap)).collect();
+        let garbage = node(&mut heap);
+        *garbage.next.borrow_mut() = Some(garbage.clone());
+        drop(garbage);
+
+        heap.maybe_collect();
+        assert_eq!(heap.len(), 3);
+        assert_eq!(heap.threshold, 9);
+        drop(kept);
+        assert_eq!(heap.collect(), 0);
+        assert_eq!(heap.threshold, 4);
+    }
+}
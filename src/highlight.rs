@@ -0,0 +1,537 @@
+//! Classifying source text for syntax highlighting. Every token but
+//! punctuation gets a [`Class`]; names get theirs from what they declare or
+//! resolve to, so that a parameter reads differently from a function wherever
+//! it is used. A program with errors is still classified: names that are not
+//! resolved fall back to what the syntax around them says.
+
+use std::collections::HashMap;
+
+use crate::ast::{
+    Block, Expr, ExprKind, FnDecl, NodeId, PatternKind, StmtKind, TypeExpr, TypeExprKind,
+};
+use crate::lexer::{Lexer, LexerConfig, SpannedToken, StrPart, Token};
+use crate::parser::Parser;
+use crate::sema::{self, DeclKind, Resolution};
+use crate::span::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Class {
+    Keyword,
+    Function,
+    Parameter,
+    Variable,
+    /// A struct or a builtin type.
+    Type,
+    /// A struct field.
+    Property,
+    Number,
+    /// A string or character literal, or the text of an interpolated one.
+    String,
+    Comment,
+    Operator,
+}
+
+impl Class {
+    /// Every class, in the order of [`Class::index`].
+    pub const ALL: [Class; 10] = [
+        Class::Keyword,
+        Class::Function,
+        Class::Parameter,
+        Class::Variable,
+        Class::Type,
+        Class::Property,
+        Class::Number,
+        Class::String,
+        Class::Comment,
+        Class::Operator,
+    ];
+
+    /// The name of the class, which is also its LSP semantic token type.
+    pub fn name(self) -> &'static str {
+        match self {
+            Class::Keyword => "keyword",
+            Class::Function => "function",
+            Class::Parameter => "parameter",
+            Class::Variable => "variable",
+            Class::Type => "type",
+            Class::Property => "property",
+            Class::Number => "number",
+            Class::String => "string",
+            Class::Comment => "comment",
+            Class::Operator => "operator",
+        }
+    }
+
+    /// The position of the class in [`Class::ALL`].
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A classified run of source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Highlight {
+    pub span: Span,
+    pub class: Class,
+}
+
+/// Classifies the tokens of `source`, in order. The spans of an interpolated
+/// string leave out its expressions, which are classified on their own.
+///
+/// ```
+/// use compyl::highlight::{highlight, Class};
+///
+/// let classes: Vec<Class> = highlight("fn f(x) { return x; }")
+///     .iter()
+///     .map(|highlight| highlight.class)
+///     .collect();
+/// assert_eq!(
+///     classes,
+///     [Class::Keyword, Class::Function, Class::Parameter, Class::Keyword, Class::Parameter]
+/// );
+/// ```
+pub fn highlight(source: &str) -> Vec<Highlight> {
+    let (program, _) = Parser::new(Lexer::new(source)).parse_partial();
+    // Names resolve where they can, even in a program with errors.
+    let (resolution, _) = sema::analyze_partial(&program);
+    let mut names = Names {
+        resolution: &resolution,
+        classes: HashMap::new(),
+    };
+    names.block(&program.stmts);
+
+    let config = LexerConfig {
+        keep_comments: true,
+    };
+    let mut out = Vec::new();
+    for token in Lexer::with_config(source, config).flatten() {
+        classify(&token, &names.classes, &mut out);
+    }
+    out
+}
+
+fn classify(token: &SpannedToken, names: &HashMap<usize, Class>, out: &mut Vec<Highlight>) {
+    let class = match &token.token {
+        Token::KwLet
+        | Token::KwFn
+        | Token::KwVoid
+        | Token::KwTrue
+        | Token::KwFalse
+        | Token::KwIf
+        | Token::KwElse
+        | Token::KwWhile
+        | Token::KwReturn
+        | Token::KwBreak
+        | Token::KwContinue
+        | Token::KwFor
+        | Token::KwIn
+        | Token::KwStruct
+        | Token::KwImport
+        | Token::KwMatch
+        | Token::KwExtern
+        | Token::KwConst => Class::Keyword,
+        Token::IntLiteral(_) | Token::FloatLiteral(_) => Class::Number,
+        Token::CharLiteral(_) | Token::StrLiteral(_) => Class::String,
+        Token::InterpolatedStr(parts) => {
+            // The text between the expressions, `${` and `}` included. Each
+            // expression's tokens end in a `TokEof` at its `}`.
+            let mut text = token.span;
+            for part in parts {
+                let StrPart::Expr(tokens) = part else {
+                    continue;
+                };
+                let (Some(first), Some(last)) = (tokens.first(), tokens.last()) else {
+                    continue;
+                };
+                push_string(text, first.span.start, out);
+                for token in tokens {
+                    classify(token, names, out);
+                }
+                text = Span {
+                    end: token.span.end,
+                    ..last.span
+                };
+            }
+            push_string(text, token.span.end, out);
+            return;
+        }
+        Token::OpPlus
+        | Token::OpMinus
+        | Token::OpMult
+        | Token::OpDiv
+        | Token::OpMod
+        | Token::OpAnd
+        | Token::OpOr
+        | Token::OpNot
+        | Token::OpGt
+        | Token::OpGe
+        | Token::OpEq
+        | Token::OpNe
+        | Token::OpLt
+        | Token::OpLe
+        | Token::OpBitAnd
+        | Token::OpBitOr
+        | Token::OpBitXor
+        | Token::OpBitNot
+        | Token::OpShl
+        | Token::OpShr
+        | Token::DotDot
+        | Token::FatArrow
+        | Token::Assignment
+        | Token::PlusAssign
+        | Token::MinusAssign
+        | Token::MultAssign
+        | Token::DivAssign
+        | Token::ModAssign => Class::Operator,
+        Token::Identifier(_) => names
+            .get(&token.span.start)
+            .copied()
+            .unwrap_or(Class::Variable),
+        Token::Comment(_) | Token::DocComment(_) => Class::Comment,
+        Token::SemiColon
+        | Token::Colon
+        | Token::Comma
+        | Token::Dot
+        | Token::Lparen
+        | Token::RParen
+        | Token::LSquirly
+        | Token::RSquirly
+        | Token::LBracket
+        | Token::RBracket
+        | Token::TokEof => return,
+    };
+    out.push(Highlight {
+        span: token.span,
+        class,
+    });
+}
+
+/// Pushes the text of a string literal from where `text` starts up to `end`,
+/// unless there is none.
+fn push_string(text: Span, end: usize, out: &mut Vec<Highlight>) {
+    if text.start < end {
+        out.push(Highlight {
+            span: Span { end, ..text },
+            class: Class::String,
+        });
+    }
+}
+
+/// Finds the class of each name in a program, by where its span starts.
+struct Names<'a> {
+    resolution: &'a Resolution,
+    classes: HashMap<usize, Class>,
+}
+
+impl Names<'_> {
+    fn name(&mut self, span: Span, class: Class) {
+        self.classes.insert(span.start, class);
+    }
+
+    /// A use of a name, classed as what it resolves to, or as `fallback`.
+    fn use_of(&mut self, id: NodeId, span: Span, fallback: Class) {
+        let class = match self.resolution.builtins.contains_key(&id) {
+            true => Some(Class::Function),
+            false => self
+                .resolution
+                .resolve(id)
+                .map(|(_, decl)| match decl.kind {
+                    DeclKind::Let | DeclKind::Const => Class::Variable,
+                    DeclKind::Fn => Class::Function,
+                    DeclKind::Param => Class::Parameter,
+                    DeclKind::Struct => Class::Type,
+                }),
+        };
+        self.name(span, class.unwrap_or(fallback));
+    }
+
+    fn ty(&mut self, ty: &TypeExpr) {
+        match &ty.kind {
+            TypeExprKind::Named(_) => self.name(ty.span, Class::Type),
+            TypeExprKind::Void => {}
+            TypeExprKind::Array(elem) => self.ty(elem),
+            TypeExprKind::Fn { params, ret } => {
+                for param in params {
+                    self.ty(param);
+                }
+                if let Some(ret) = ret {
+                    self.ty(ret);
+                }
+            }
+        }
+    }
+
+    fn function(&mut self, decl: &FnDecl) {
+        self.name(decl.name.span, Class::Function);
+        for param in &decl.params {
+            self.name(param.name.span, Class::Parameter);
+            if let Some(ty) = &param.ty {
+                self.ty(ty);
+            }
+        }
+        if let Some(ret) = &decl.ret {
+            self.ty(ret);
+        }
+        self.block(&decl.body);
+    }
+
+    fn block(&mut self, block: &Block) {
+        for stmt in block {
+            match &stmt.kind {
+                StmtKind::Let { name, ty, value } | StmtKind::Const { name, ty, value } => {
+                    self.name(name.span, Class::Variable);
+                    if let Some(ty) = ty {
+                        self.ty(ty);
+                    }
+                    self.expr(value);
+                }
+                StmtKind::Assign { target, value, .. } => {
+                    self.use_of(target.id, target.span, Class::Variable);
+                    self.expr(value);
+                }
+                StmtKind::IndexAssign {
+                    array,
+                    index,
+                    value,
+                    ..
+                } => {
+                    self.expr(array);
+                    self.expr(index);
+                    self.expr(value);
+                }
+                StmtKind::FieldAssign {
+                    object,
+                    field,
+                    value,
+                    ..
+                } => {
+                    self.expr(object);
+                    self.name(field.span, Class::Property);
+                    self.expr(value);
+                }
+                StmtKind::Fn(decl) | StmtKind::Extern(decl) => self.function(decl),
+                StmtKind::Struct(decl) => {
+                    self.name(decl.name.span, Class::Type);
+                    for field in &decl.fields {
+                        self.name(field.name.span, Class::Property);
+                        self.ty(&field.ty);
+                    }
+                }
+                StmtKind::If {
+                    cond,
+                    then_branch,
+                    else_branch,
+                } => {
+                    self.expr(cond);
+                    self.block(then_branch);
+                    if let Some(else_branch) = else_branch {
+                        self.block(else_branch);
+                    }
+                }
+                StmtKind::While { cond, body } => {
+                    self.expr(cond);
+                    self.block(body);
+                }
+                StmtKind::For {
+                    var,
+                    start,
+                    end,
+                    body,
+                } => {
+                    self.name(var.span, Class::Variable);
+                    self.expr(start);
+                    self.expr(end);
+                    self.block(body);
+                }
+                StmtKind::Return(value) => {
+                    if let Some(value) = value {
+                        self.expr(value);
+                    }
+                }
+                StmtKind::Break | StmtKind::Continue | StmtKind::Import(_) | StmtKind::Error => {}
+                StmtKind::Block(block) => self.block(block),
+                StmtKind::Expr(expr) => self.expr(expr),
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::Char(_)
+            | ExprKind::Str(_)
+            | ExprKind::Bool(_) => {}
+            ExprKind::Ident(_) => self.use_of(expr.id, expr.span, Class::Variable),
+            ExprKind::Unary { expr, .. } | ExprKind::Interpolation(expr) => self.expr(expr),
+            ExprKind::Binary { lhs, rhs, .. } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            ExprKind::Call { callee, args } => {
+                match &callee.kind {
+                    ExprKind::Ident(_) => self.use_of(callee.id, callee.span, Class::Function),
+                    _ => self.expr(callee),
+                }
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(cond);
+                self.expr(then_branch);
+                self.expr(else_branch);
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                self.expr(scrutinee);
+                for arm in arms {
+                    if let PatternKind::Binding(name) = &arm.pattern.kind {
+                        self.name(name.span, Class::Variable);
+                    }
+                    self.expr(&arm.body);
+                }
+            }
+            ExprKind::Fn(decl) => self.function(decl),
+            ExprKind::Array(elems) => {
+                for elem in elems {
+                    self.expr(elem);
+                }
+            }
+            ExprKind::Index { expr, index } => {
+                self.expr(expr);
+                self.expr(index);
+            }
+            ExprKind::Struct { name, fields } => {
+                self.name(name.span, Class::Type);
+                for field in fields {
+                    self.name(field.name.span, Class::Property);
+                    self.expr(&field.value);
+                }
+            }
+            ExprKind::Field { expr, field } => {
+                self.expr(expr);
+                self.name(field.span, Class::Property);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{highlight, Class};
+
+    /// Each highlighted run of `source`, with its class.
+    fn classes(source: &str) -> Vec<(&str, Class)> {
+        highlight(source)
+            .iter()
+            .map(|highlight| {
+                (
+                    &source[highlight.span.start..highlight.span.end],
+                    highlight.class,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_highlight() {
+        let source = "\
+?? A point.
+struct P { x: int, }
+fn f(p: P, g: fn(int): [P]): int {
+    let n = len(\"ab\") + p.x; ? note
+    p.x += 'c';
+    return f(P { x: n, }, g);
+}";
+        use Class::*;
+        assert_eq!(
+            classes(source),
+            [
+                ("?? A point.", Comment),
+                ("struct", Keyword),
+                ("P", Type),
+                ("x", Property),
+                ("int", Type),
+                ("fn", Keyword),
+                ("f", Function),
+                ("p", Parameter),
+                ("P", Type),
+                ("g", Parameter),
+                ("fn", Keyword),
+                ("int", Type),
+                ("P", Type),
+                ("int", Type),
+                ("let", Keyword),
+                ("n", Variable),
+                ("=", Operator),
+                ("len", Function),
+                ("\"ab\"", String),
+                ("+", Operator),
+                ("p", Parameter),
+                ("x", Property),
+                ("? note", Comment),
+                ("p", Parameter),
+                ("x", Property),
+                ("+=", Operator),
+                ("'c'", String),
+                ("return", Keyword),
+                ("f", Function),
+                ("P", Type),
+                ("x", Property),
+                ("n", Variable),
+                ("g", Parameter),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interpolation_and_errors() {
+        use Class::*;
+        assert_eq!(
+            classes("let a = \"x ${a + 1}!\";"),
+            [
+                ("let", Keyword),
+                ("a", Variable),
+                ("=", Operator),
+                ("\"x ${", String),
+                ("a", Variable),
+                ("+", Operator),
+                ("1", Number),
+                ("}!\"", String),
+            ]
+        );
+
+        // Unresolved names are classed by their syntax, and tokens in
+        // statements that fail to parse are still classified.
+        assert_eq!(
+            classes("g(y); let = 2;"),
+            [
+                ("g", Function),
+                ("y", Variable),
+                ("let", Keyword),
+                ("=", Operator),
+                ("2", Number),
+            ]
+        );
+
+        // A name that fails to resolve leaves the others resolved.
+        assert_eq!(
+            classes("fn f(x: int): int { return x + nope; }"),
+            [
+                ("fn", Keyword),
+                ("f", Function),
+                ("x", Parameter),
+                ("int", Type),
+                ("int", Type),
+                ("return", Keyword),
+                ("x", Parameter),
+                ("+", Operator),
+                ("nope", Variable),
+            ]
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! String interning: identifiers are stored once and referred to by [`Symbol`]s.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+/// A handle to an interned string. Symbols for equal strings are equal, so
+/// comparing and hashing them is O(1).
+///
+/// ```
+/// use compyl::intern::Symbol;
+///
+/// let a = Symbol::intern("count");
+/// assert_eq!(a, Symbol::intern("count"));
+/// assert_ne!(a, Symbol::intern("total"));
+/// assert_eq!(a.as_str(), "count");
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// The process-wide table behind every [`Symbol`]. Interned strings live for
+/// the rest of the program, which is what lets [`Symbol::as_str`] hand out
+/// `'static` references; the table grows with the distinct names a process
+/// sees, not with how often it sees them.
+///
+/// Interning takes a lock, but looking a symbol up does not: its string is
+/// in a chunk of slots that is never moved or freed, and each slot is set
+/// once, before the symbol is handed out.
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+}
+
+/// The slots in the first chunk. Each chunk after it has twice as many as
+/// the one before, so that [`CHUNKS`] of them hold every `u32`.
+const FIRST_CHUNK: usize = 64;
+const CHUNKS: usize = 27;
+
+type Chunk = Box<[OnceLock<&'static str>]>;
+
+static STRINGS: [OnceLock<Chunk>; CHUNKS] = [const { OnceLock::new() }; CHUNKS];
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(Default::default)
+}
+
+/// The chunk that symbol `index` is in, and its slot there.
+fn slot(index: usize) -> (usize, usize) {
+    let chunk = (index / FIRST_CHUNK + 1).ilog2() as usize;
+    (chunk, index - FIRST_CHUNK * ((1 << chunk) - 1))
+}
+
+impl Symbol {
+    pub fn intern(string: &str) -> Symbol {
+        let mut interner = interner().lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(&symbol) = interner.symbols.get(string) {
+            return symbol;
+        }
+
+        let index = interner.symbols.len();
+        let (chunk_index, slot) = slot(index);
+        let chunk = STRINGS[chunk_index].get_or_init(|| {
+            let len = FIRST_CHUNK << chunk_index;
+            (0..len).map(|_| OnceLock::new()).collect()
+        });
+        let string: &'static str = Box::leak(string.into());
+        // Only this call, holding the lock, sets the slot.
+        let _ = chunk[slot].set(string);
+
+        let symbol = Symbol(index as u32);
+        interner.symbols.insert(string, symbol);
+        symbol
+    }
+
+    pub fn as_str(self) -> &'static str {
+        let (chunk, slot) = slot(self.0 as usize);
+        STRINGS[chunk]
+            .get()
+            .and_then(|chunk| chunk[slot].get())
+            .expect("symbols are only made by interning")
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(string: &str) -> Symbol {
+        Symbol::intern(string)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{slot, Symbol, CHUNKS, FIRST_CHUNK};
+
+    #[test]
+    fn test_shared_across_threads() {
+        let here = Symbol::intern("shared");
+        let there = std::thread::spawn(|| Symbol::intern("shared"))
+            .join()
+            .unwrap();
+
+        assert_eq!(here, there);
+        assert_eq!(format!("{} {:?}", there, there), "shared \"shared\"");
+    }
+
+    #[test]
+    fn test_slots() {
+        assert_eq!(slot(0), (0, 0));
+        assert_eq!(slot(FIRST_CHUNK - 1), (0, FIRST_CHUNK - 1));
+        assert_eq!(slot(FIRST_CHUNK), (1, 0));
+        assert_eq!(slot(3 * FIRST_CHUNK), (2, 0));
+        assert_eq!(slot(u32::MAX as usize), (CHUNKS - 1, 63));
+
+        // Enough names to fill more than one chunk, from several threads.
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                std::thread::spawn(move || {
+                    (0..FIRST_CHUNK)
+                        .map(|n| Symbol::intern(&format!("slot{}_{}", thread, n)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for (thread, handle) in threads.into_iter().enumerate() {
+            for (n, symbol) in handle.join().unwrap().into_iter().enumerate() {
+                assert_eq!(symbol.as_str(), format!("slot{}_{}", thread, n));
+            }
+        }
+    }
+}
@@ -0,0 +1,1655 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::{
+    BinaryOp, Block, Expr, ExprKind, FnDecl, PatternKind, Program, Stmt, StmtKind, StructDecl,
+    UnaryOp,
+};
+use crate::builtins::{Builtin, Io};
+use crate::gc::{Gc, GcConfig, Heap, Trace, Tracer};
+use crate::intern::Symbol;
+use crate::span::Span;
+use crate::types::{self, Type};
+use crate::vm::Closure;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Str(Rc<str>),
+    /// Shared, so that assigning to an element is seen through every copy.
+    Array(Gc<RefCell<Vec<Value>>>),
+    /// Shared like arrays.
+    Struct(Gc<Struct>),
+    Function(Gc<Function>),
+    /// A function compiled for the [`vm`](crate::vm).
+    Closure(Gc<Closure>),
+    Builtin(Builtin),
+    /// An `extern fn`, bound to the function the embedder registered for it.
+    Extern(Rc<Extern>),
+    Void,
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::Char(_) => "char",
+            Value::Str(_) => "str",
+            Value::Array(_) => "array",
+            Value::Struct(value) => value.layout.name.as_str(),
+            Value::Function(_) | Value::Closure(_) | Value::Builtin(_) | Value::Extern(_) => "fn",
+            Value::Void => "void",
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Int(a), Value::Float(b)) | (Value::Float(b), Value::Int(a)) => *a as f64 == *b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Array(a), Value::Array(b)) => *a.borrow() == *b.borrow(),
+            (Value::Struct(a), Value::Struct(b)) => {
+                a.layout == b.layout && *a.fields.borrow() == *b.fields.borrow()
+            }
+            (Value::Function(a), Value::Function(b)) => Gc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Gc::ptr_eq(a, b),
+            (Value::Builtin(a), Value::Builtin(b)) => a == b,
+            (Value::Extern(a), Value::Extern(b)) => Rc::ptr_eq(a, b),
+            (Value::Void, Value::Void) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Trace for Value {
+    fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Value::Array(elems) => tracer.visit(elems),
+            Value::Struct(value) => tracer.visit(value),
+            Value::Function(func) => tracer.visit(func),
+            Value::Closure(closure) => tracer.visit(closure),
+            _ => {}
+        }
+    }
+}
+
+/// An array's elements.
+impl Trace for RefCell<Vec<Value>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(elems) = self.try_borrow() {
+            elems.iter().for_each(|elem| elem.trace(tracer));
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut elems) = self.try_borrow_mut() {
+            elems.clear();
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(n) => write!(f, "{:?}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Char(ch) => write!(f, "{}", ch),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Array(elems) => {
+                write!(f, "[")?;
+                for (i, elem) in elems.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write_nested(f, elem)?;
+                }
+                write!(f, "]")
+            }
+            Value::Struct(value) => {
+                write!(f, "{} {{", value.layout.name)?;
+                let fields = value.fields.borrow();
+                for (i, (name, field)) in value.layout.fields.iter().zip(&*fields).enumerate() {
+                    write!(f, "{} {}: ", if i > 0 { "," } else { "" }, name)?;
+                    write_nested(f, field)?;
+                }
+                write!(f, " }}")
+            }
+            Value::Function(func) => write!(f, "<fn {}>", func.decl.name.name),
+            Value::Closure(closure) => write!(f, "<fn {}>", closure.func.name),
+            Value::Extern(func) => write!(f, "<fn {}>", func.name),
+            Value::Builtin(builtin) => write!(f, "<builtin {}>", builtin),
+            Value::Void => write!(f, "void"),
+        }
+    }
+}
+
+/// Writes a value inside an array or struct, where strings and characters
+/// are quoted.
+fn write_nested(f: &mut fmt::Formatter<'_>, value: &Value) -> fmt::Result {
+    match value {
+        Value::Str(s) => write!(f, "{:?}", s),
+        Value::Char(ch) => write!(f, "{:?}", ch),
+        value => write!(f, "{}", value),
+    }
+}
+
+/// A struct's name and the names of its fields, in declaration order.
+#[derive(Debug, PartialEq)]
+pub struct Layout {
+    pub name: Symbol,
+    pub fields: Vec<Symbol>,
+}
+
+impl Layout {
+    pub fn new(decl: &StructDecl) -> Layout {
+        Layout {
+            name: decl.name.name,
+            fields: decl.fields.iter().map(|field| field.name.name).collect(),
+        }
+    }
+}
+
+/// An instance of a struct, with a value for each field of its layout.
+#[derive(Debug)]
+pub struct Struct {
+    pub layout: Rc<Layout>,
+    pub fields: RefCell<Vec<Value>>,
+}
+
+/// A function value: its declaration plus the environment it closes over.
+pub struct Function {
+    decl: Rc<FnDecl>,
+    env: Env,
+}
+
+impl Trace for Struct {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.fields.trace(tracer);
+    }
+
+    fn clear(&self) {
+        self.fields.clear();
+    }
+}
+
+impl Trace for Function {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.visit(&self.env);
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Function({})", self.decl.name.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    UndefinedVariable(String),
+    TypeMismatch {
+        op: String,
+        lhs: &'static str,
+        rhs: Option<&'static str>,
+    },
+    NotCallable(&'static str),
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    NonBoolCondition(&'static str),
+    DivisionByZero,
+    IntegerOverflow,
+    ShiftOutOfRange(i64),
+    IndexOutOfBounds {
+        index: i64,
+        len: usize,
+    },
+    NoSuchField {
+        ty: &'static str,
+        field: String,
+    },
+    MissingField {
+        ty: &'static str,
+        field: String,
+    },
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+    InvalidConversion {
+        value: String,
+        to: &'static str,
+    },
+    /// Reading input or writing output failed.
+    Io(String),
+    /// More calls were running at once than the limit, which is given.
+    StackOverflow(usize),
+    /// No arm of a `match` matched the value, which is given.
+    NoMatch(String),
+    /// An `extern fn` with no host function registered under its name.
+    UnregisteredExtern(String),
+    /// A host function failed.
+    Host {
+        name: String,
+        message: String,
+    },
+    /// An argument to an `extern fn` without the type it declares, which
+    /// the host function would not expect.
+    HostArgument {
+        name: String,
+        /// The argument's position, from 0.
+        index: usize,
+        expected: Type,
+        found: &'static str,
+    },
+    /// A value a host function returned without the type its `extern fn`
+    /// declares.
+    HostResult {
+        name: String,
+        expected: Type,
+        found: &'static str,
+    },
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::UndefinedVariable(name) => write!(f, "undefined variable `{}`", name),
+            RuntimeError::TypeMismatch {
+                op,
+                lhs,
+                rhs: Some(rhs),
+            } => write!(f, "cannot apply `{}` to {} and {}", op, lhs, rhs),
+            RuntimeError::TypeMismatch { op, lhs, rhs: None } => {
+                write!(f, "cannot apply `{}` to {}", op, lhs)
+            }
+            RuntimeError::NotCallable(ty) => write!(f, "value of type {} is not callable", ty),
+            RuntimeError::ArityMismatch {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "function `{}` takes {} arguments but {} were given",
+                name, expected, found
+            ),
+            RuntimeError::NonBoolCondition(ty) => {
+                write!(f, "condition must be a bool, found {}", ty)
+            }
+            RuntimeError::DivisionByZero => write!(f, "division by zero"),
+            RuntimeError::IntegerOverflow => write!(f, "integer overflow"),
+            RuntimeError::ShiftOutOfRange(n) => write!(f, "cannot shift by {} bits", n),
+            RuntimeError::IndexOutOfBounds { index, len } => write!(
+                f,
+                "index {} is out of bounds for an array of length {}",
+                index, len
+            ),
+            RuntimeError::NoSuchField { ty, field } => {
+                write!(f, "value of type {} has no field `{}`", ty, field)
+            }
+            RuntimeError::MissingField { ty, field } => {
+                write!(f, "missing field `{}` in {}", field, ty)
+            }
+            RuntimeError::BreakOutsideLoop => write!(f, "`break` outside of a loop"),
+            RuntimeError::ContinueOutsideLoop => write!(f, "`continue` outside of a loop"),
+            RuntimeError::InvalidConversion { value, to } => {
+                write!(f, "cannot convert `{}` to {}", value, to)
+            }
+            RuntimeError::Io(err) => write!(f, "{}", err),
+            RuntimeError::StackOverflow(limit) => {
+                write!(f, "stack overflow: more than {} nested calls", limit)
+            }
+            RuntimeError::NoMatch(value) => write!(f, "no `match` arm matches `{}`", value),
+            RuntimeError::UnregisteredExtern(name) => {
+                write!(f, "no host function is registered for `extern fn {}`", name)
+            }
+            RuntimeError::Host { name, message } => write!(f, "`{}` failed: {}", name, message),
+            RuntimeError::HostArgument {
+                name,
+                index,
+                expected,
+                found,
+            } => write!(
+                f,
+                "argument {} of `{}` must be `{}`, found {}",
+                index + 1,
+                name,
+                expected,
+                found
+            ),
+            RuntimeError::HostResult {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` returned {}, but is declared to return `{}`",
+                name, found, expected
+            ),
+        }
+    }
+}
+
+impl Error for RuntimeError {}
+
+/// Where a runtime error happened: the innermost expression or statement
+/// that failed, and the calls that led to it, innermost first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Backtrace {
+    pub span: Option<Span>,
+    pub calls: Vec<Call>,
+}
+
+/// A call that was running when a runtime error happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub function: String,
+    /// Where the function was called from.
+    pub span: Span,
+}
+
+pub(crate) type RuntimeResult<T> = Result<T, RuntimeError>;
+
+/// A function an embedder registers to be called for the `extern fn` of its
+/// name. An error it returns becomes a [`RuntimeError::Host`].
+pub type HostFn = Rc<dyn Fn(&[Value]) -> Result<Value, String>>;
+
+/// What an `extern fn` declares: its name, and the types of its parameters
+/// and result, which [`Type::Unknown`] leaves unchecked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub name: Symbol,
+    pub params: Vec<Type>,
+    pub ret: Type,
+}
+
+impl Signature {
+    pub(crate) fn of(decl: &FnDecl) -> Signature {
+        let (params, ret) = types::signature(decl);
+        Signature {
+            name: decl.name.name,
+            params,
+            ret,
+        }
+    }
+}
+
+/// An `extern fn`: what it declares, and the host function behind it. The
+/// values passed to and returned by the host function are checked against
+/// the declared types, so that it only sees and gives what it promised to.
+pub struct Extern {
+    pub name: Symbol,
+    signature: Signature,
+    func: HostFn,
+}
+
+impl Extern {
+    /// Binds the `extern fn` declared with `signature` to the host function
+    /// registered for its name in `hosts`.
+    pub(crate) fn bind(
+        hosts: &HashMap<Symbol, HostFn>,
+        signature: &Signature,
+    ) -> RuntimeResult<Value> {
+        let name = signature.name;
+        let func = hosts
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| RuntimeError::UnregisteredExtern(name.to_string()))?;
+        Ok(Value::Extern(Rc::new(Extern {
+            name,
+            signature: signature.clone(),
+            func,
+        })))
+    }
+
+    pub(crate) fn call(&self, args: Vec<Value>) -> RuntimeResult<Value> {
+        let params = &self.signature.params;
+        if args.len() != params.len() {
+            return Err(RuntimeError::ArityMismatch {
+                name: self.name.to_string(),
+                expected: params.len(),
+                found: args.len(),
+            });
+        }
+        for (index, (arg, expected)) in args.iter().zip(params).enumerate() {
+            if !has_type(arg, expected) {
+                return Err(RuntimeError::HostArgument {
+                    name: self.name.to_string(),
+                    index,
+                    expected: expected.clone(),
+                    found: arg.type_name(),
+                });
+            }
+        }
+        let value = (self.func)(&args).map_err(|message| RuntimeError::Host {
+            name: self.name.to_string(),
+            message,
+        })?;
+        if !has_type(&value, &self.signature.ret) {
+            return Err(RuntimeError::HostResult {
+                name: self.name.to_string(),
+                expected: self.signature.ret.clone(),
+                found: value.type_name(),
+            });
+        }
+        Ok(value)
+    }
+}
+
+/// Whether `value` has the type `ty`. Functions are not looked into.
+fn has_type(value: &Value, ty: &Type) -> bool {
+    match (ty, value) {
+        (Type::Unknown, _)
+        | (Type::Int, Value::Int(_))
+        | (Type::Float, Value::Float(_))
+        | (Type::Bool, Value::Bool(_))
+        | (Type::Char, Value::Char(_))
+        | (Type::Str, Value::Str(_))
+        | (Type::Void, Value::Void)
+        | (
+            Type::Fn { .. },
+            Value::Function(_) | Value::Closure(_) | Value::Builtin(_) | Value::Extern(_),
+        ) => true,
+        (Type::Array(elem), Value::Array(elems)) => {
+            elems.borrow().iter().all(|value| has_type(value, elem))
+        }
+        (Type::Struct(name), Value::Struct(value)) => value.layout.name == *name,
+        _ => false,
+    }
+}
+
+impl fmt::Debug for Extern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Extern({})", self.name)
+    }
+}
+
+/// How many calls the interpreter and the VM let run at once by default, so
+/// runaway recursion is an error rather than a crash. The interpreter makes
+/// each call on the Rust stack, and this many fit in the 2 MiB a spawned
+/// thread gets by default in release builds. Debug builds need about ten
+/// times as much, or a lower [`Interpreter::with_max_call_depth`].
+pub const MAX_CALL_DEPTH: usize = 500;
+
+type Env = Gc<RefCell<Scope>>;
+
+#[derive(Default)]
+struct Scope {
+    vars: HashMap<Symbol, Value>,
+    parent: Option<Env>,
+}
+
+impl Trace for RefCell<Scope> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(scope) = self.try_borrow() {
+            scope.vars.values().for_each(|value| value.trace(tracer));
+            if let Some(parent) = &scope.parent {
+                tracer.visit(parent);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut scope) = self.try_borrow_mut() {
+            *scope = Scope::default();
+        }
+    }
+}
+
+fn lookup(env: &Env, name: Symbol) -> Option<Value> {
+    let scope = env.borrow();
+    match scope.vars.get(&name) {
+        Some(value) => Some(value.clone()),
+        None => scope
+            .parent
+            .as_ref()
+            .and_then(|parent| lookup(parent, name)),
+    }
+}
+
+fn assign(env: &Env, name: Symbol, value: Value) -> RuntimeResult<()> {
+    let mut scope = env.borrow_mut();
+    if let Some(slot) = scope.vars.get_mut(&name) {
+        *slot = value;
+        return Ok(());
+    }
+
+    match &scope.parent {
+        Some(parent) => assign(parent, name, value),
+        None => Err(RuntimeError::UndefinedVariable(name.to_string())),
+    }
+}
+
+/// How control leaves a statement.
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+/// A tree-walking interpreter. Top-level bindings persist across calls to `run`.
+pub struct Interpreter {
+    globals: Env,
+    structs: HashMap<Symbol, Rc<Layout>>,
+    hosts: HashMap<Symbol, HostFn>,
+    io: Io,
+    /// How many calls are running, and how many may.
+    depth: usize,
+    max_call_depth: usize,
+    backtrace: Backtrace,
+    /// Last, so that it collects what the globals leave behind when dropped.
+    heap: Heap,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter::with_io(Io::stdio())
+    }
+
+    pub fn with_io(io: Io) -> Interpreter {
+        let mut heap = Heap::default();
+        Interpreter {
+            globals: heap.alloc(RefCell::new(Scope::default())),
+            structs: HashMap::new(),
+            hosts: HashMap::new(),
+            io,
+            depth: 0,
+            max_call_depth: MAX_CALL_DEPTH,
+            backtrace: Backtrace::default(),
+            heap,
+        }
+    }
+
+    /// Makes more than `max_call_depth` nested calls a
+    /// [`RuntimeError::StackOverflow`].
+    pub fn with_max_call_depth(mut self, max_call_depth: usize) -> Interpreter {
+        self.max_call_depth = max_call_depth;
+        self
+    }
+
+    /// Collects garbage when `config` says to rather than by default.
+    pub fn with_gc(mut self, config: GcConfig) -> Interpreter {
+        self.heap.configure(config);
+        self
+    }
+
+    /// Registers `func` to be called for `extern fn name`. Programs declaring
+    /// it must be run after it is registered.
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        func: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) {
+        self.hosts.insert(Symbol::intern(name), Rc::new(func));
+    }
+
+    pub fn io(&mut self) -> &mut Io {
+        &mut self.io
+    }
+
+    pub fn heap(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    /// Runs `program`, returning the value of a top-level `return`, or `Void`.
+    pub fn run(&mut self, program: &Program) -> RuntimeResult<Value> {
+        self.depth = 0;
+        self.backtrace = Backtrace::default();
+        let globals = self.globals.clone();
+        match self.exec_block(&program.stmts, &globals)? {
+            Flow::Normal => Ok(Value::Void),
+            Flow::Break => Err(RuntimeError::BreakOutsideLoop),
+            Flow::Continue => Err(RuntimeError::ContinueOutsideLoop),
+            Flow::Return(value) => Ok(value),
+        }
+    }
+
+    /// Where the error the last run failed with happened.
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
+    }
+
+    /// Looks up a top-level binding.
+    pub fn global(&self, name: &str) -> Option<Value> {
+        lookup(&self.globals, Symbol::intern(name))
+    }
+
+    /// Binds `name` at the top level, replacing any binding it had.
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals
+            .borrow_mut()
+            .vars
+            .insert(Symbol::intern(name), value);
+    }
+
+    fn exec_block(&mut self, stmts: &Block, env: &Env) -> RuntimeResult<Flow> {
+        // Functions and structs are hoisted so they can be used before their
+        // definition.
+        for stmt in stmts {
+            match &stmt.kind {
+                StmtKind::Fn(decl) => self.define_fn(decl, env),
+                StmtKind::Extern(decl) => {
+                    let func = Extern::bind(&self.hosts, &Signature::of(decl))?;
+                    env.borrow_mut().vars.insert(decl.name.name, func);
+                }
+                StmtKind::Struct(decl) => {
+                    let layout = Rc::new(Layout::new(decl));
+                    self.structs.insert(decl.name.name, layout);
+                }
+                _ => {}
+            }
+        }
+
+        for stmt in stmts {
+            self.heap.maybe_collect();
+            match self.exec_stmt(stmt, env)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn define_fn(&mut self, decl: &FnDecl, env: &Env) {
+        let func = Function {
+            decl: Rc::new(decl.clone()),
+            env: env.clone(),
+        };
+        let func = self.heap.alloc(func);
+        env.borrow_mut()
+            .vars
+            .insert(decl.name.name, Value::Function(func));
+    }
+
+    /// A new scope inside `parent`.
+    fn scope(&mut self, parent: &Env) -> Env {
+        self.heap.alloc(RefCell::new(Scope {
+            vars: HashMap::new(),
+            parent: Some(parent.clone()),
+        }))
+    }
+
+    /// Runs `stmt`, noting it as where an error happened if nothing inside it
+    /// was.
+    fn exec_stmt(&mut self, stmt: &Stmt, env: &Env) -> RuntimeResult<Flow> {
+        let flow = self.exec(stmt, env);
+        if flow.is_err() {
+            self.backtrace.span.get_or_insert(stmt.span);
+        }
+        flow
+    }
+
+    fn exec(&mut self, stmt: &Stmt, env: &Env) -> RuntimeResult<Flow> {
+        match &stmt.kind {
+            StmtKind::Let { name, value, .. } | StmtKind::Const { name, value, .. } => {
+                let value = self.eval(value, env)?;
+                env.borrow_mut().vars.insert(name.name, value);
+            }
+            StmtKind::Assign { target, op, value } => {
+                let name = target.name;
+                let mut value = self.eval(value, env)?;
+                if let Some(op) = op {
+                    let current = lookup(env, name)
+                        .ok_or_else(|| RuntimeError::UndefinedVariable(name.to_string()))?;
+                    value = eval_binary(*op, current, value)?;
+                }
+                assign(env, name, value)?;
+            }
+            StmtKind::IndexAssign {
+                array,
+                index,
+                op,
+                value,
+            } => {
+                let array = self.eval(array, env)?;
+                let index = self.eval(index, env)?;
+                let mut value = self.eval(value, env)?;
+                if let Some(op) = op {
+                    value = eval_binary(*op, eval_index(&array, &index)?, value)?;
+                }
+                store_index(&array, &index, value)?;
+            }
+            StmtKind::FieldAssign {
+                object,
+                field,
+                op,
+                value,
+            } => {
+                let object = self.eval(object, env)?;
+                let mut value = self.eval(value, env)?;
+                if let Some(op) = op {
+                    value = eval_binary(*op, eval_field(&object, field.name)?, value)?;
+                }
+                store_field(&object, field.name, value)?;
+            }
+            StmtKind::Fn(_) | StmtKind::Extern(_) | StmtKind::Struct(_) => {}
+            StmtKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                if self.eval_condition(cond, env)? {
+                    let scope = self.scope(env);
+                    return self.exec_block(then_branch, &scope);
+                } else if let Some(else_branch) = else_branch {
+                    let scope = self.scope(env);
+                    return self.exec_block(else_branch, &scope);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                while self.eval_condition(cond, env)? {
+                    let scope = self.scope(env);
+                    match self.exec_block(body, &scope)? {
+                        Flow::Normal | Flow::Continue => {}
+                        Flow::Break => break,
+                        flow @ Flow::Return(_) => return Ok(flow),
+                    }
+                }
+            }
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                let (start, end) = (self.eval(start, env)?, self.eval(end, env)?);
+                let (&Value::Int(first), &Value::Int(last)) = (&start, &end) else {
+                    return Err(RuntimeError::TypeMismatch {
+                        op: "..".to_string(),
+                        lhs: start.type_name(),
+                        rhs: Some(end.type_name()),
+                    });
+                };
+                for i in first..last {
+                    let scope = self.scope(env);
+                    scope.borrow_mut().vars.insert(var.name, Value::Int(i));
+                    let body_scope = self.scope(&scope);
+                    match self.exec_block(body, &body_scope)? {
+                        Flow::Normal | Flow::Continue => {}
+                        Flow::Break => break,
+                        flow @ Flow::Return(_) => return Ok(flow),
+                    }
+                }
+            }
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval(value, env)?,
+                    None => Value::Void,
+                };
+                return Ok(Flow::Return(value));
+            }
+            StmtKind::Break => return Ok(Flow::Break),
+            StmtKind::Continue => return Ok(Flow::Continue),
+            StmtKind::Import(_) | StmtKind::Error => {}
+            StmtKind::Block(stmts) => {
+                let scope = self.scope(env);
+                return self.exec_block(stmts, &scope);
+            }
+            StmtKind::Expr(expr) => {
+                self.eval(expr, env)?;
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn eval_condition(&mut self, cond: &Expr, env: &Env) -> RuntimeResult<bool> {
+        match self.eval(cond, env)? {
+            Value::Bool(b) => Ok(b),
+            other => Err(RuntimeError::NonBoolCondition(other.type_name())),
+        }
+    }
+
+    /// Evaluates `expr`, noting it as where an error happened like
+    /// [`Interpreter::exec_stmt`].
+    fn eval(&mut self, expr: &Expr, env: &Env) -> RuntimeResult<Value> {
+        let value = self.eval_expr(expr, env);
+        if value.is_err() {
+            self.backtrace.span.get_or_insert(expr.span);
+        }
+        value
+    }
+
+    fn eval_expr(&mut self, expr: &Expr, env: &Env) -> RuntimeResult<Value> {
+        match &expr.kind {
+            ExprKind::Int(n) => Ok(Value::Int(*n)),
+            ExprKind::Float(n) => Ok(Value::Float(*n)),
+            ExprKind::Char(ch) => Ok(Value::Char(*ch)),
+            ExprKind::Str(s) => Ok(Value::Str(s.as_str().into())),
+            ExprKind::Interpolation(expr) => {
+                let value = self.eval(expr, env)?;
+                Builtin::Str.call(vec![value], &mut self.io)
+            }
+            ExprKind::Bool(b) => Ok(Value::Bool(*b)),
+            // Builtins are only found when nothing in scope has their name.
+            ExprKind::Ident(name) => lookup(env, *name)
+                .or_else(|| Builtin::lookup(name.as_str()).map(Value::Builtin))
+                .ok_or_else(|| RuntimeError::UndefinedVariable(name.to_string())),
+            ExprKind::Unary { op, expr } => {
+                let value = self.eval(expr, env)?;
+                eval_unary(*op, value)
+            }
+            ExprKind::Binary {
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                lhs,
+                rhs,
+            } => self.eval_logical(*op, lhs, rhs, env),
+            ExprKind::Binary { op, lhs, rhs } => {
+                let lhs = self.eval(lhs, env)?;
+                let rhs = self.eval(rhs, env)?;
+                eval_binary(*op, lhs, rhs)
+            }
+            ExprKind::Call { callee, args } => {
+                let callee = self.eval(callee, env)?;
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg, env))
+                    .collect::<RuntimeResult<Vec<_>>>()?;
+                let function = match &callee {
+                    Value::Function(func) => Some(func.decl.name.name),
+                    _ => None,
+                };
+                let value = self.call(callee, args);
+                // An error inside the function's body, rather than in calling
+                // it, has been located already.
+                if let (Err(_), Some(function)) = (&value, function) {
+                    if self.backtrace.span.is_some() {
+                        self.backtrace.calls.push(Call {
+                            function: function.to_string(),
+                            span: expr.span,
+                        });
+                    }
+                }
+                value
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => match self.eval_condition(cond, env)? {
+                true => self.eval(then_branch, env),
+                false => self.eval(else_branch, env),
+            },
+            ExprKind::Match { scrutinee, arms } => {
+                let value = self.eval(scrutinee, env)?;
+                for arm in arms {
+                    let scope = self.scope(env);
+                    match &arm.pattern.kind {
+                        PatternKind::Binding(name) => {
+                            scope.borrow_mut().vars.insert(name.name, value.clone());
+                        }
+                        kind if literal(kind).is_some_and(|literal| literal != value) => continue,
+                        _ => {}
+                    }
+                    return self.eval(&arm.body, &scope);
+                }
+                Err(RuntimeError::NoMatch(value.to_string()))
+            }
+            ExprKind::Fn(decl) => {
+                // A scope of its own, where the function can find itself.
+                let env = self.scope(env);
+                self.define_fn(decl, &env);
+                Ok(lookup(&env, decl.name.name).expect("the function was just defined"))
+            }
+            ExprKind::Array(elems) => {
+                let elems = elems
+                    .iter()
+                    .map(|elem| self.eval(elem, env))
+                    .collect::<RuntimeResult<Vec<_>>>()?;
+                Ok(Value::Array(self.heap.alloc(RefCell::new(elems))))
+            }
+            ExprKind::Index { expr, index } => {
+                let array = self.eval(expr, env)?;
+                let index = self.eval(index, env)?;
+                eval_index(&array, &index)
+            }
+            ExprKind::Struct { name, fields } => {
+                let layout = self
+                    .structs
+                    .get(&name.name)
+                    .cloned()
+                    .ok_or_else(|| RuntimeError::UndefinedVariable(name.name.to_string()))?;
+                let fields = fields
+                    .iter()
+                    .map(|field| Ok((field.name.name, self.eval(&field.value, env)?)))
+                    .collect::<RuntimeResult<Vec<_>>>()?;
+                new_struct(&mut self.heap, layout, fields)
+            }
+            ExprKind::Field { expr, field } => {
+                let object = self.eval(expr, env)?;
+                eval_field(&object, field.name)
+            }
+        }
+    }
+
+    fn eval_logical(
+        &mut self,
+        op: BinaryOp,
+        lhs: &Expr,
+        rhs: &Expr,
+        env: &Env,
+    ) -> RuntimeResult<Value> {
+        let mismatch = |lhs: &Value, rhs: Option<&Value>| RuntimeError::TypeMismatch {
+            op: op.to_string(),
+            lhs: lhs.type_name(),
+            rhs: rhs.map(Value::type_name),
+        };
+
+        let lhs = match self.eval(lhs, env)? {
+            Value::Bool(b) => b,
+            other => return Err(mismatch(&other, None)),
+        };
+        // Short-circuit: the right operand is only evaluated when it decides the result.
+        if (op == BinaryOp::And && !lhs) || (op == BinaryOp::Or && lhs) {
+            return Ok(Value::Bool(lhs));
+        }
+
+        match self.eval(rhs, env)? {
+            Value::Bool(b) => Ok(Value::Bool(b)),
+            other => Err(mismatch(&Value::Bool(lhs), Some(&other))),
+        }
+    }
+
+    fn call(&mut self, callee: Value, args: Vec<Value>) -> RuntimeResult<Value> {
+        let func = match callee {
+            Value::Function(func) => func,
+            Value::Builtin(builtin) => return builtin.call(args, &mut self.io),
+            Value::Extern(func) => return func.call(args),
+            other => return Err(RuntimeError::NotCallable(other.type_name())),
+        };
+        if func.decl.params.len() != args.len() {
+            return Err(RuntimeError::ArityMismatch {
+                name: func.decl.name.name.to_string(),
+                expected: func.decl.params.len(),
+                found: args.len(),
+            });
+        }
+
+        if self.depth == self.max_call_depth {
+            return Err(RuntimeError::StackOverflow(self.max_call_depth));
+        }
+        let env = self.scope(&func.env);
+        for (param, arg) in func.decl.params.iter().zip(args) {
+            env.borrow_mut().vars.insert(param.name.name, arg);
+        }
+
+        self.depth += 1;
+        let flow = self.exec_block(&func.decl.body, &env);
+        self.depth -= 1;
+        match flow? {
+            Flow::Normal => Ok(Value::Void),
+            Flow::Break => Err(RuntimeError::BreakOutsideLoop),
+            Flow::Continue => Err(RuntimeError::ContinueOutsideLoop),
+            Flow::Return(value) => Ok(value),
+        }
+    }
+}
+
+pub(crate) fn eval_index(array: &Value, index: &Value) -> RuntimeResult<Value> {
+    // Strings index by character, like `len` counts them.
+    if let (Value::Str(s), Value::Int(_)) = (array, index) {
+        let at = position(index, s.chars().count())?;
+        return Ok(Value::Char(
+            s.chars().nth(at).expect("`position` checked the index"),
+        ));
+    }
+    let elems = elements(array, index)?;
+    let elems = elems.borrow();
+    let at = position(index, elems.len())?;
+    Ok(elems[at].clone())
+}
+
+pub(crate) fn store_index(array: &Value, index: &Value, value: Value) -> RuntimeResult<()> {
+    let elems = elements(array, index)?;
+    let mut elems = elems.borrow_mut();
+    let at = position(index, elems.len())?;
+    elems[at] = value;
+    Ok(())
+}
+
+fn elements<'a>(array: &'a Value, index: &Value) -> RuntimeResult<&'a RefCell<Vec<Value>>> {
+    match (array, index) {
+        (Value::Array(elems), Value::Int(_)) => Ok(elems),
+        _ => Err(RuntimeError::TypeMismatch {
+            op: "[]".to_string(),
+            lhs: array.type_name(),
+            rhs: Some(index.type_name()),
+        }),
+    }
+}
+
+/// Checks that `index`, known to be an int, is within an array of `len`.
+fn position(index: &Value, len: usize) -> RuntimeResult<usize> {
+    let Value::Int(index) = *index else {
+        unreachable!("`elements` checks the index is an int");
+    };
+    usize::try_from(index)
+        .ok()
+        .filter(|&at| at < len)
+        .ok_or(RuntimeError::IndexOutOfBounds { index, len })
+}
+
+/// Creates an instance of `layout` from field values in any order.
+pub(crate) fn new_struct(
+    heap: &mut Heap,
+    layout: Rc<Layout>,
+    fields: Vec<(Symbol, Value)>,
+) -> RuntimeResult<Value> {
+    let ty = layout.name.as_str();
+    let mut values = vec![None; layout.fields.len()];
+    for (name, value) in fields {
+        let at = layout
+            .fields
+            .iter()
+            .position(|&field| field == name)
+            .ok_or_else(|| RuntimeError::NoSuchField {
+                ty,
+                field: name.to_string(),
+            })?;
+        values[at] = Some(value);
+    }
+
+    let values = values
+        .into_iter()
+        .zip(&layout.fields)
+        .map(|(value, field)| {
+            value.ok_or_else(|| RuntimeError::MissingField {
+                ty,
+                field: field.to_string(),
+            })
+        })
+        .collect::<RuntimeResult<Vec<_>>>()?;
+    Ok(Value::Struct(heap.alloc(Struct {
+        layout,
+        fields: RefCell::new(values),
+    })))
+}
+
+pub(crate) fn eval_field(object: &Value, field: Symbol) -> RuntimeResult<Value> {
+    let (value, at) = field_position(object, field)?;
+    let fields = value.fields.borrow();
+    Ok(fields[at].clone())
+}
+
+pub(crate) fn store_field(object: &Value, field: Symbol, value: Value) -> RuntimeResult<()> {
+    let (object, at) = field_position(object, field)?;
+    object.fields.borrow_mut()[at] = value;
+    Ok(())
+}
+
+fn field_position(object: &Value, field: Symbol) -> RuntimeResult<(&Struct, usize)> {
+    let position = match object {
+        Value::Struct(value) => value
+            .layout
+            .fields
+            .iter()
+            .position(|&name| name == field)
+            .map(|at| (&**value, at)),
+        _ => None,
+    };
+    position.ok_or_else(|| RuntimeError::NoSuchField {
+        ty: object.type_name(),
+        field: field.to_string(),
+    })
+}
+
+pub(crate) fn eval_unary(op: UnaryOp, value: Value) -> RuntimeResult<Value> {
+    match (op, value) {
+        (UnaryOp::Neg, Value::Int(n)) => n
+            .checked_neg()
+            .map(Value::Int)
+            .ok_or(RuntimeError::IntegerOverflow),
+        (UnaryOp::Neg, Value::Float(n)) => Ok(Value::Float(-n)),
+        (UnaryOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+        (UnaryOp::BitNot, Value::Int(n)) => Ok(Value::Int(!n)),
+        (op, value) => Err(RuntimeError::TypeMismatch {
+            op: op.to_string(),
+            lhs: value.type_name(),
+            rhs: None,
+        }),
+    }
+}
+
+pub(crate) fn eval_binary(op: BinaryOp, lhs: Value, rhs: Value) -> RuntimeResult<Value> {
+    use Value::{Bool, Char, Float, Int, Str};
+
+    match op {
+        BinaryOp::Eq => return Ok(Bool(lhs == rhs)),
+        BinaryOp::Ne => return Ok(Bool(lhs != rhs)),
+        _ => {}
+    }
+
+    let result = match (&lhs, &rhs) {
+        (Int(a), Int(b)) => int_op(op, *a, *b)?,
+        (Float(a), Float(b)) => float_op(op, *a, *b),
+        (Int(a), Float(b)) => float_op(op, *a as f64, *b),
+        (Float(a), Int(b)) => float_op(op, *a, *b as f64),
+        (Bool(a), Bool(b)) => match op {
+            BinaryOp::BitAnd => Some(Bool(a & b)),
+            BinaryOp::BitOr => Some(Bool(a | b)),
+            BinaryOp::BitXor => Some(Bool(a ^ b)),
+            _ => None,
+        },
+        (Str(a), Str(b)) => match op {
+            BinaryOp::Add => Some(Str(format!("{}{}", a, b).into())),
+            _ => compare(op, a, b).map(Bool),
+        },
+        (Char(a), Char(b)) => compare(op, a, b).map(Bool),
+        _ => None,
+    };
+
+    result.ok_or_else(|| RuntimeError::TypeMismatch {
+        op: op.to_string(),
+        lhs: lhs.type_name(),
+        rhs: Some(rhs.type_name()),
+    })
+}
+
+fn int_op(op: BinaryOp, a: i64, b: i64) -> RuntimeResult<Option<Value>> {
+    let checked = match op {
+        BinaryOp::Add => a.checked_add(b),
+        BinaryOp::Sub => a.checked_sub(b),
+        BinaryOp::Mul => a.checked_mul(b),
+        BinaryOp::Div | BinaryOp::Mod if b == 0 => return Err(RuntimeError::DivisionByZero),
+        BinaryOp::Div => a.checked_div(b),
+        BinaryOp::Mod => a.checked_rem(b),
+        BinaryOp::BitAnd => Some(a & b),
+        BinaryOp::BitOr => Some(a | b),
+        BinaryOp::BitXor => Some(a ^ b),
+        BinaryOp::Shl | BinaryOp::Shr if !(0..64).contains(&b) => {
+            return Err(RuntimeError::ShiftOutOfRange(b))
+        }
+        BinaryOp::Shl => Some(a << b),
+        BinaryOp::Shr => Some(a >> b),
+        _ => return Ok(compare(op, &a, &b).map(Value::Bool)),
+    };
+
+    checked
+        .map(|n| Some(Value::Int(n)))
+        .ok_or(RuntimeError::IntegerOverflow)
+}
+
+fn float_op(op: BinaryOp, a: f64, b: f64) -> Option<Value> {
+    Some(Value::Float(match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Sub => a - b,
+        BinaryOp::Mul => a * b,
+        BinaryOp::Div => a / b,
+        BinaryOp::Mod => a % b,
+        _ => return compare(op, &a, &b).map(Value::Bool),
+    }))
+}
+
+/// The value a literal pattern matches, or `None` if it matches any.
+pub(crate) fn literal(pattern: &PatternKind) -> Option<Value> {
+    Some(match pattern {
+        PatternKind::Int(n) => Value::Int(*n),
+        PatternKind::Char(ch) => Value::Char(*ch),
+        PatternKind::Str(s) => Value::Str(s.as_str().into()),
+        PatternKind::Bool(b) => Value::Bool(*b),
+        PatternKind::Binding(_) | PatternKind::Wildcard => return None,
+    })
+}
+
+fn compare<T: PartialOrd + ?Sized>(op: BinaryOp, a: &T, b: &T) -> Option<bool> {
+    Some(match op {
+        BinaryOp::Gt => a > b,
+        BinaryOp::Ge => a >= b,
+        BinaryOp::Lt => a < b,
+        BinaryOp::Le => a <= b,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Interpreter, RuntimeError, Value};
+    use crate::builtins::{Builtin, Io};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn run(input: &str) -> Result<Value, RuntimeError> {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.");
+        Interpreter::new().run(&program)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(run("return 1 + 2 * 3 - 8 / 4 % 3;"), Ok(Value::Int(5)));
+        assert_eq!(run("return 1.5 * 2;"), Ok(Value::Float(3.0)));
+        assert_eq!(
+            run("return \"ab\" + \"cd\";"),
+            Ok(Value::Str("abcd".into()))
+        );
+        assert_eq!(
+            run("return -(2 - 5) >= 3 && !false;"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            run("return (6 & 3) | (1 << 4) ^ ~0 >> 1;"),
+            Ok(Value::Int(-17))
+        );
+        assert_eq!(run("return true ^ true | false;"), Ok(Value::Bool(false)));
+        assert_eq!(run("return 10 == 10.0;"), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_scoping() {
+        let input = "
+        let x = 1;
+        {
+            let x = 2;
+            x = 3;
+        }
+        let y = 0;
+        if (true) {
+            y = x;
+        }
+        return y;
+        ";
+
+        assert_eq!(run(input), Ok(Value::Int(1)));
+    }
+
+    #[test]
+    fn test_functions() {
+        let input = "
+        let total = fib(10);
+        fn fib(n) {
+            if (n < 2) {
+                return n;
+            }
+            return fib(n - 1) + fib(n - 2);
+        }
+
+        fn make_counter() {
+            let count = 0;
+            fn bump() {
+                count = count + 1;
+                return count;
+            }
+            return bump;
+        }
+
+        let counter = make_counter();
+        counter();
+        return total + counter();
+        ";
+
+        assert_eq!(run(input), Ok(Value::Int(57)));
+    }
+
+    #[test]
+    fn test_fn_expressions() {
+        let input = "
+        fn map(a, f) {
+            let out = [];
+            let i = 0;
+            let result = [0, 0, 0];
+            while (i < len(a)) {
+                result[i] = f(a[i]);
+                i += 1;
+            }
+            return result;
+        }
+        let offset = 10;
+        let adders = [fn(x) { return x + offset; }, fn(x) { return x * 2; }];
+        offset = 100;
+        let fact = fn fact(n) {
+            if (n <= 1) {
+                return 1;
+            }
+            return n * fact(n - 1);
+        };
+        let a = map([1, 2, 3], adders[0]);
+        return a[2] + adders[1](fact(4));
+        ";
+
+        assert_eq!(run(input), Ok(Value::Int(151)));
+        assert_eq!(
+            run("return str(fn() {});"),
+            Ok(Value::Str("<fn <anonymous>>".into()))
+        );
+    }
+
+    #[test]
+    fn test_loops() {
+        let input = "
+        let i = 0;
+        let sum = 0;
+        while (true) {
+            i += 1;
+            if (i > 10) {
+                break;
+            }
+            if (i % 2 == 0) {
+                sum = sum + i;
+            }
+        }
+        return sum;
+        ";
+
+        assert_eq!(run(input), Ok(Value::Int(30)));
+    }
+
+    #[test]
+    fn test_for_loops() {
+        let input = "
+        let sum = 0;
+        let n = 3;
+        for (i in 0..n * 2) {
+            n = 100;
+            if (i % 2 == 1) {
+                continue;
+            }
+            i += 10;
+            sum += i;
+        }
+        for (i in 5..0) {
+            return -1;
+        }
+        let j = 0;
+        while (j < 10) {
+            j += 1;
+            if (j > 2) {
+                continue;
+            }
+            sum += 100;
+        }
+        return sum;
+        ";
+
+        // The end is evaluated once, and assigning the variable does not
+        // change the next iteration.
+        assert_eq!(run(input), Ok(Value::Int(236)));
+        assert_eq!(
+            run("for (i in 0..2.5) {}"),
+            Err(RuntimeError::TypeMismatch {
+                op: "..".into(),
+                lhs: "int",
+                rhs: Some("float"),
+            })
+        );
+        assert_eq!(run("continue;"), Err(RuntimeError::ContinueOutsideLoop));
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        assert_eq!(
+            run("let x = 10; x += 5; x -= 3; x *= 4; x /= 6; x %= 5; return x;"),
+            Ok(Value::Int(3))
+        );
+        assert_eq!(
+            run("let s = \"a\"; s += \"b\"; return s;"),
+            Ok(Value::Str("ab".into()))
+        );
+        assert_eq!(
+            run("y += 1;"),
+            Err(RuntimeError::UndefinedVariable("y".into()))
+        );
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let input = r#"
+        struct P { x: int, name: str }
+        let p = P { x: 1, name: "origin" };
+        let xs = [1.5, 2.0];
+        fn str(s) { return "shadowed"; }
+        return "${p.name}: ${p} \${x} ${xs} ${len(xs) > 1}${"!"}";
+        "#;
+
+        // Strings print without quotes, except inside other values.
+        assert_eq!(
+            run(input),
+            Ok(Value::Str(
+                "origin: P { x: 1, name: \"origin\" } ${x} [1.5, 2.0] true!".into()
+            ))
+        );
+        assert_eq!(
+            run("fn f() {} return \"${f()}\";"),
+            Err(RuntimeError::TypeMismatch {
+                op: "str".into(),
+                lhs: "void",
+                rhs: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_builtins() {
+        let mut interp = Interpreter::with_io(Io::buffered("7\n"));
+        let program = Parser::new(Lexer::new(
+            "let n = int(input()); println(n * 6); print(str(true) + \"!\"); return len;",
+        ))
+        .parse_program()
+        .unwrap();
+        assert_eq!(interp.run(&program), Ok(Value::Builtin(Builtin::Len)));
+        assert_eq!(interp.io().take_output(), "42\ntrue!");
+
+        // A declaration hides the builtin of the same name.
+        assert_eq!(
+            run("fn len(s) { return 0; } return len(\"abc\");"),
+            Ok(Value::Int(0))
+        );
+        assert_eq!(
+            run("return len(1, 2);"),
+            Err(RuntimeError::ArityMismatch {
+                name: "len".into(),
+                expected: 1,
+                found: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn test_chars() {
+        assert_eq!(
+            run("let s = \"héllo\"; let n = 0; for (i in 0..len(s)) { if (s[i] > 'g') { n += 1; } } return n;"),
+            Ok(Value::Int(5))
+        );
+        assert_eq!(
+            run("return str(['a', '\\n']) + str(char(int('a') + 1)) + \"${'!'}\";"),
+            Ok(Value::Str("['a', '\\n']b!".into()))
+        );
+        assert_eq!(
+            run("return 'a' == 'a' && 'a' != \"a\";"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            run("return \"ab\"[2];"),
+            Err(RuntimeError::IndexOutOfBounds { index: 2, len: 2 })
+        );
+    }
+
+    #[test]
+    fn test_arrays() {
+        let input = "
+        let a = [1, 2, 3];
+        let b = a;
+        b[0] = 10;
+        a[2] *= 5;
+        let grid = [[0, 1], [2, 3]];
+        grid[1][0] += a[0];
+        return a[0] + a[2] + grid[1][0] + len(a) + len([]);
+        ";
+
+        assert_eq!(run(input), Ok(Value::Int(40)));
+        assert_eq!(
+            run("return [1, [\"a\"]] == [1, [\"a\"]];"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            run("return [1, 2][2];"),
+            Err(RuntimeError::IndexOutOfBounds { index: 2, len: 2 })
+        );
+        assert_eq!(
+            run("let a = [1]; a[-1] = 0;"),
+            Err(RuntimeError::IndexOutOfBounds { index: -1, len: 1 })
+        );
+        assert_eq!(
+            run("return 1[0];"),
+            Err(RuntimeError::TypeMismatch {
+                op: "[]".into(),
+                lhs: "int",
+                rhs: Some("int"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_structs() {
+        let input = "
+        struct Point { x: int, y: int }
+        let p = Point { y: 2, x: 1 };
+        let q = p;
+        q.x = 10;
+        p.y *= 3;
+        let line = [p, Point { x: 0, y: 0 }];
+        line[1].x -= 1;
+        return p.x + p.y + line[1].x;
+        ";
+
+        assert_eq!(run(input), Ok(Value::Int(15)));
+        assert_eq!(
+            run("struct S { s: str, n: [int] } return str(S { s: \"a\", n: [1] });"),
+            Ok(Value::Str("S { s: \"a\", n: [1] }".into()))
+        );
+        assert_eq!(
+            run("struct P { x: int } return P { x: 1 } == P { x: 1 };"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            run("struct P { x: int } return P { x: 1 }.y;"),
+            Err(RuntimeError::NoSuchField {
+                ty: "P",
+                field: "y".into(),
+            })
+        );
+        assert_eq!(
+            run("struct P { x: int, y: int } return P { y: 1 };"),
+            Err(RuntimeError::MissingField {
+                ty: "P",
+                field: "x".into(),
+            })
+        );
+        assert_eq!(
+            run("let n = 1; n.x = 2;"),
+            Err(RuntimeError::NoSuchField {
+                ty: "int",
+                field: "x".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let sign = "fn sign(n) { if (n < 0) { return -1; } else if (n == 0) { return 0; } else { return 1; } }";
+        assert_eq!(
+            run(&format!("{} return [sign(-4), sign(0), sign(9)];", sign))
+                .unwrap()
+                .to_string(),
+            "[-1, 0, 1]"
+        );
+        // Only the chosen arm runs.
+        assert_eq!(
+            run(
+                "let n = 0; let a = if (n > 0) undefined else if (n < 0) -1 else 2; return a * 10;"
+            ),
+            Ok(Value::Int(20))
+        );
+        assert_eq!(
+            run("return if (1) 2 else 3;"),
+            Err(RuntimeError::NonBoolCondition("int"))
+        );
+    }
+
+    #[test]
+    fn test_match() {
+        let describe = "fn describe(x) { return match (x) { 0 => \"zero\", -1 => \"minus one\", \"hi\" => \"greeting\", true => \"yes\", n => \"other ${n}\" }; }";
+        assert_eq!(
+            run(&format!(
+                "{} return [describe(0), describe(-1), describe(\"hi\"), describe(true), describe(7)];",
+                describe
+            ))
+            .unwrap()
+            .to_string(),
+            r#"["zero", "minus one", "greeting", "yes", "other 7"]"#
+        );
+        // A binding is only in scope in its arm, and arms after a match never run.
+        assert_eq!(
+            run("let n = 1; let m = match (2) { n => n * 10, _ => undefined }; return m + n;"),
+            Ok(Value::Int(21))
+        );
+        assert_eq!(
+            run("return match (3) { 1 => 1, 2 => 2 };"),
+            Err(RuntimeError::NoMatch("3".into()))
+        );
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        assert_eq!(run("return false && undefined;"), Ok(Value::Bool(false)));
+        assert_eq!(run("return true || undefined;"), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            run("return y;"),
+            Err(RuntimeError::UndefinedVariable("y".into()))
+        );
+        assert_eq!(run("return 1 / 0;"), Err(RuntimeError::DivisionByZero));
+        assert_eq!(
+            run("return 1 + true;"),
+            Err(RuntimeError::TypeMismatch {
+                op: "+".into(),
+                lhs: "int",
+                rhs: Some("bool"),
+            })
+        );
+        assert_eq!(
+            run("fn f(a) { return a; } f(1, 2);"),
+            Err(RuntimeError::ArityMismatch {
+                name: "f".into(),
+                expected: 1,
+                found: 2,
+            })
+        );
+        assert_eq!(run("5();"), Err(RuntimeError::NotCallable("int")));
+        assert_eq!(run("if (1) {}"), Err(RuntimeError::NonBoolCondition("int")));
+        assert_eq!(run("break;"), Err(RuntimeError::BreakOutsideLoop));
+        assert_eq!(
+            run("return 1 << 64;"),
+            Err(RuntimeError::ShiftOutOfRange(64))
+        );
+        assert_eq!(
+            run("return 9223372036854775807 + 1;"),
+            Err(RuntimeError::IntegerOverflow)
+        );
+    }
+}
@@ -0,0 +1,1207 @@
+//! A three-address intermediate representation between the typed AST and the
+//! backends.
+//!
+//! Each function is a control-flow graph of basic blocks over an unbounded
+//! set of virtual registers; a block is a list of instructions ending in one
+//! terminator. A variable is a register assigned in place, so the IR is not in
+//! SSA form. Lowering covers what the native backends can compile: `int`,
+//! `float` and `bool` values, globals, and top-level functions.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::{
+    BinaryOp, Block as AstBlock, Expr, ExprKind, FnDecl, NodeId, Program, Stmt, StmtKind, UnaryOp,
+};
+use crate::codegen::{reject_builtin, unsupported, CodegenError};
+use crate::interp::Value;
+use crate::sema::Resolution;
+use crate::span::Span;
+use crate::types::{Type, TypeTable};
+
+type LowerResult<T> = Result<T, CodegenError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ty {
+    Int,
+    Float,
+    Bool,
+}
+
+impl Ty {
+    fn of(ty: &Type, span: Span) -> LowerResult<Ty> {
+        match ty {
+            Type::Int => Ok(Ty::Int),
+            Type::Float => Ok(Ty::Float),
+            Type::Bool => Ok(Ty::Bool),
+            Type::Unknown => unsupported("a value of unknown type (add a type annotation)", span),
+            ty => unsupported(format!("a value of type `{}`", ty), span),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Reg(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GlobalId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FuncId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Reg(Reg),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    Copy {
+        dst: Reg,
+        src: Operand,
+    },
+    Unary {
+        dst: Reg,
+        op: UnaryOp,
+        src: Operand,
+    },
+    /// Never `&&` or `||`, which lower to branches. Both operands have the same
+    /// type.
+    Binary {
+        dst: Reg,
+        op: BinaryOp,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    /// Converts an `int` to a `float`, for mixed arithmetic.
+    IntToFloat {
+        dst: Reg,
+        src: Operand,
+    },
+    LoadGlobal {
+        dst: Reg,
+        global: GlobalId,
+    },
+    StoreGlobal {
+        global: GlobalId,
+        src: Operand,
+    },
+    Call {
+        dst: Option<Reg>,
+        func: FuncId,
+        args: Vec<Operand>,
+    },
+}
+
+impl Inst {
+    /// The register this instruction writes, if any.
+    pub fn dst(&self) -> Option<Reg> {
+        match self {
+            Inst::Copy { dst, .. }
+            | Inst::Unary { dst, .. }
+            | Inst::Binary { dst, .. }
+            | Inst::IntToFloat { dst, .. }
+            | Inst::LoadGlobal { dst, .. } => Some(*dst),
+            Inst::Call { dst, .. } => *dst,
+            Inst::StoreGlobal { .. } => None,
+        }
+    }
+
+    /// The operands this instruction reads.
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Inst::Copy { src, .. }
+            | Inst::Unary { src, .. }
+            | Inst::IntToFloat { src, .. }
+            | Inst::StoreGlobal { src, .. } => vec![src],
+            Inst::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            Inst::LoadGlobal { .. } => vec![],
+            Inst::Call { args, .. } => args.iter().collect(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Inst::Copy { src, .. }
+            | Inst::Unary { src, .. }
+            | Inst::IntToFloat { src, .. }
+            | Inst::StoreGlobal { src, .. } => vec![src],
+            Inst::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            Inst::LoadGlobal { .. } => vec![],
+            Inst::Call { args, .. } => args.iter_mut().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    Branch {
+        cond: Operand,
+        then_block: BlockId,
+        else_block: BlockId,
+    },
+    Return(Option<Operand>),
+    Unreachable,
+}
+
+impl Terminator {
+    pub fn operand(&self) -> Option<&Operand> {
+        match self {
+            Terminator::Branch { cond, .. } => Some(cond),
+            Terminator::Return(value) => value.as_ref(),
+            Terminator::Jump(_) | Terminator::Unreachable => None,
+        }
+    }
+
+    pub fn operand_mut(&mut self) -> Option<&mut Operand> {
+        match self {
+            Terminator::Branch { cond, .. } => Some(cond),
+            Terminator::Return(value) => value.as_mut(),
+            Terminator::Jump(_) | Terminator::Unreachable => None,
+        }
+    }
+
+    /// The blocks control may pass to next.
+    pub fn successors(&self) -> Vec<BlockId> {
+        match *self {
+            Terminator::Jump(block) => vec![block],
+            Terminator::Branch {
+                then_block,
+                else_block,
+                ..
+            } => vec![then_block, else_block],
+            Terminator::Return(_) | Terminator::Unreachable => vec![],
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Jump(block) => vec![block],
+            Terminator::Branch {
+                then_block,
+                else_block,
+                ..
+            } => vec![then_block, else_block],
+            Terminator::Return(_) | Terminator::Unreachable => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub insts: Vec<Inst>,
+    pub term: Terminator,
+}
+
+/// A function; `blocks[0]` is its entry, and `regs` holds the type of each
+/// register, parameters first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<Reg>,
+    pub ret: Option<Ty>,
+    pub regs: Vec<Ty>,
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    pub name: String,
+    pub ty: Ty,
+}
+
+/// A lowered program. The last function is `main`, which runs the top-level
+/// statements and returns the exit code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub globals: Vec<Global>,
+    pub functions: Vec<Function>,
+}
+
+/// Lowers a checked `program` to IR.
+///
+/// ```
+/// use compyl::lexer::Lexer;
+/// use compyl::parser::Parser;
+/// use compyl::{ir, sema, types};
+///
+/// let program = Parser::new(Lexer::new("let x = 1 + 2;")).parse_program().unwrap();
+/// let resolution = sema::analyze(&program).unwrap();
+/// let types = types::check(&program, &resolution).unwrap();
+/// let module = ir::lower(&program, &resolution, &types).unwrap();
+/// assert_eq!(
+///     module.to_string(),
+///     "global @x: int\n\nfn main(): int {\nbb0:\n    %0 = 1 + 2\n    store @x, %0\n    ret 0\n}\n"
+/// );
+/// ```
+pub fn lower(program: &Program, resolution: &Resolution, types: &TypeTable) -> LowerResult<Module> {
+    let mut ctx = Context {
+        resolution,
+        types,
+        globals: HashMap::new(),
+        functions: HashMap::new(),
+        signatures: Vec::new(),
+    };
+    let mut module = Module {
+        globals: Vec::new(),
+        functions: Vec::new(),
+    };
+
+    for stmt in &program.stmts {
+        match &stmt.kind {
+            StmtKind::Let { name, .. } | StmtKind::Const { name, .. } => {
+                let ty = Ty::of(&types.decls[&name.id], name.span)?;
+                let id = GlobalId(module.globals.len() as u32);
+                module.globals.push(Global {
+                    name: name.name.to_string(),
+                    ty,
+                });
+                ctx.globals.insert(name.id, (id, ty));
+            }
+            StmtKind::Fn(decl) => {
+                if decl.name.name.as_str() == "main" {
+                    return unsupported("a function named `main`", decl.name.span);
+                }
+                let Type::Fn { ret, .. } = &types.decls[&decl.name.id] else {
+                    unreachable!("functions have function types");
+                };
+                let ret = match **ret {
+                    Type::Void => None,
+                    ref ty => Some(Ty::of(ty, decl.name.span)?),
+                };
+                let id = FuncId(ctx.signatures.len() as u32);
+                ctx.functions.insert(decl.name.id, id);
+                ctx.signatures.push(ret);
+            }
+            StmtKind::Extern(decl) => return unsupported("an `extern` function", decl.name.span),
+            _ => {}
+        }
+    }
+
+    for stmt in &program.stmts {
+        if let StmtKind::Fn(decl) = &stmt.kind {
+            module.functions.push(FnBuilder::function(&ctx, decl)?);
+        }
+    }
+    module
+        .functions
+        .push(FnBuilder::main(&ctx, &program.stmts)?);
+
+    Ok(module)
+}
+
+struct Context<'a> {
+    resolution: &'a Resolution,
+    types: &'a TypeTable,
+    globals: HashMap<NodeId, (GlobalId, Ty)>,
+    functions: HashMap<NodeId, FuncId>,
+    /// The return type of each function, by `FuncId`.
+    signatures: Vec<Option<Ty>>,
+}
+
+struct FnBuilder<'a> {
+    ctx: &'a Context<'a>,
+    func: Function,
+    locals: HashMap<NodeId, Reg>,
+    current: usize,
+    /// Whether the current block has its terminator already.
+    terminated: bool,
+    /// The exit block of each enclosing loop, and where `continue` jumps.
+    loops: Vec<(BlockId, BlockId)>,
+    is_main: bool,
+}
+
+impl<'a> FnBuilder<'a> {
+    fn new(ctx: &'a Context<'a>, name: String, ret: Option<Ty>, is_main: bool) -> FnBuilder<'a> {
+        FnBuilder {
+            ctx,
+            func: Function {
+                name,
+                params: Vec::new(),
+                ret,
+                regs: Vec::new(),
+                blocks: vec![Block {
+                    insts: Vec::new(),
+                    term: Terminator::Unreachable,
+                }],
+            },
+            locals: HashMap::new(),
+            current: 0,
+            terminated: false,
+            loops: Vec::new(),
+            is_main,
+        }
+    }
+
+    fn function(ctx: &'a Context<'a>, decl: &FnDecl) -> LowerResult<Function> {
+        let ret = ctx.signatures[ctx.functions[&decl.name.id].0 as usize];
+        let mut builder = FnBuilder::new(ctx, decl.name.name.to_string(), ret, false);
+        for param in &decl.params {
+            let ty = Ty::of(&ctx.types.decls[&param.name.id], param.name.span)?;
+            let reg = builder.reg(ty);
+            builder.func.params.push(reg);
+            builder.locals.insert(param.name.id, reg);
+        }
+
+        builder.stmts(&decl.body)?;
+        if !builder.terminated {
+            builder.terminate(match ret {
+                None => Terminator::Return(None),
+                Some(_) => Terminator::Unreachable,
+            });
+        }
+
+        Ok(builder.func)
+    }
+
+    fn main(ctx: &'a Context<'a>, stmts: &[Stmt]) -> LowerResult<Function> {
+        let mut builder = FnBuilder::new(ctx, "main".into(), Some(Ty::Int), true);
+        builder.stmts(stmts)?;
+        if !builder.terminated {
+            builder.terminate(Terminator::Return(Some(Operand::Int(0))));
+        }
+
+        Ok(builder.func)
+    }
+
+    fn reg(&mut self, ty: Ty) -> Reg {
+        self.func.regs.push(ty);
+        Reg(self.func.regs.len() as u32 - 1)
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.func.blocks.push(Block {
+            insts: Vec::new(),
+            term: Terminator::Unreachable,
+        });
+        BlockId(self.func.blocks.len() as u32 - 1)
+    }
+
+    /// Makes `block` current, falling through into it from the current block.
+    fn start(&mut self, block: BlockId) {
+        if !self.terminated {
+            self.terminate(Terminator::Jump(block));
+        }
+        self.current = block.0 as usize;
+        self.terminated = false;
+    }
+
+    /// Starts an unreachable block if the current one has ended, e.g. for
+    /// code after `return`.
+    fn reachable(&mut self) {
+        if self.terminated {
+            let block = self.new_block();
+            self.start(block);
+        }
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.reachable();
+        self.func.blocks[self.current].insts.push(inst);
+    }
+
+    fn terminate(&mut self, term: Terminator) {
+        self.reachable();
+        self.func.blocks[self.current].term = term;
+        self.terminated = true;
+    }
+
+    fn ty(&self, operand: Operand) -> Ty {
+        match operand {
+            Operand::Reg(reg) => self.func.regs[reg.0 as usize],
+            Operand::Int(_) => Ty::Int,
+            Operand::Float(_) => Ty::Float,
+            Operand::Bool(_) => Ty::Bool,
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) -> LowerResult<()> {
+        for stmt in stmts {
+            self.stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, block: &AstBlock) -> LowerResult<()> {
+        self.stmts(block)
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> LowerResult<()> {
+        match &stmt.kind {
+            StmtKind::Let { name, value, .. } | StmtKind::Const { name, value, .. } => {
+                let value = self.expr(value)?;
+                if let Some(&(global, _)) = self.ctx.globals.get(&name.id) {
+                    self.emit(Inst::StoreGlobal { global, src: value });
+                } else {
+                    let ty = Ty::of(&self.ctx.types.decls[&name.id], name.span)?;
+                    let reg = self.reg(ty);
+                    self.locals.insert(name.id, reg);
+                    self.emit(Inst::Copy {
+                        dst: reg,
+                        src: value,
+                    });
+                }
+            }
+            StmtKind::Assign { target, op, value } => {
+                let decl = self.ctx.resolution.uses[&target.id];
+                let mut value = self.expr(value)?;
+                if let Some(op) = op {
+                    let current = self.variable(decl);
+                    value = self.binary(*op, current, value, stmt.span)?;
+                }
+                match self.locals.get(&decl) {
+                    Some(&dst) => self.emit(Inst::Copy { dst, src: value }),
+                    None => {
+                        let (global, _) = self.ctx.globals[&decl];
+                        self.emit(Inst::StoreGlobal { global, src: value });
+                    }
+                }
+            }
+            StmtKind::Fn(decl)
+                if self.is_main && self.ctx.functions.contains_key(&decl.name.id) => {}
+            StmtKind::Fn(decl) => return unsupported("a nested function", decl.name.span),
+            StmtKind::Extern(decl) => return unsupported("an `extern` function", decl.name.span),
+            StmtKind::IndexAssign { .. } => {
+                return unsupported("assigning to an array element", stmt.span)
+            }
+            StmtKind::FieldAssign { .. } => {
+                return unsupported("assigning to a struct field", stmt.span)
+            }
+            StmtKind::Struct(decl) => return unsupported("a struct", decl.name.span),
+            StmtKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                let cond = self.expr(cond)?;
+                let then_block = self.new_block();
+                let else_block = else_branch.as_ref().map(|_| self.new_block());
+                let end = self.new_block();
+                let else_block = else_block.unwrap_or(end);
+                self.terminate(Terminator::Branch {
+                    cond,
+                    then_block,
+                    else_block,
+                });
+
+                self.start(then_block);
+                self.block(then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    if !self.terminated {
+                        self.terminate(Terminator::Jump(end));
+                    }
+                    self.start(else_block);
+                    self.block(else_branch)?;
+                }
+                self.start(end);
+            }
+            StmtKind::While { cond, body } => {
+                let header = self.new_block();
+                let body_block = self.new_block();
+                let end = self.new_block();
+
+                self.start(header);
+                let cond = self.expr(cond)?;
+                self.terminate(Terminator::Branch {
+                    cond,
+                    then_block: body_block,
+                    else_block: end,
+                });
+
+                self.start(body_block);
+                self.loops.push((end, header));
+                self.block(body)?;
+                self.loops.pop();
+                if !self.terminated {
+                    self.terminate(Terminator::Jump(header));
+                }
+                self.start(end);
+            }
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                let counter = self.reg(Ty::Int);
+                let last = self.reg(Ty::Int);
+                let src = self.expr(start)?;
+                self.emit(Inst::Copy { dst: counter, src });
+                let src = self.expr(end)?;
+                self.emit(Inst::Copy { dst: last, src });
+
+                let header = self.new_block();
+                let body_block = self.new_block();
+                let step = self.new_block();
+                let end = self.new_block();
+
+                self.start(header);
+                let (index, last) = (Operand::Reg(counter), Operand::Reg(last));
+                let cond = self.binary(BinaryOp::Lt, index, last, stmt.span)?;
+                self.terminate(Terminator::Branch {
+                    cond,
+                    then_block: body_block,
+                    else_block: end,
+                });
+
+                self.start(body_block);
+                let reg = self.reg(Ty::Int);
+                self.locals.insert(var.id, reg);
+                self.emit(Inst::Copy {
+                    dst: reg,
+                    src: index,
+                });
+                self.loops.push((end, step));
+                self.block(body)?;
+                self.loops.pop();
+
+                self.start(step);
+                let next = self.binary(BinaryOp::Add, index, Operand::Int(1), stmt.span)?;
+                self.emit(Inst::Copy {
+                    dst: counter,
+                    src: next,
+                });
+                self.terminate(Terminator::Jump(header));
+                self.start(end);
+            }
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(value) => Some((self.expr(value)?, value.span)),
+                    None => None,
+                };
+                let value = match value {
+                    Some((value, span)) if self.is_main && self.ty(value) != Ty::Int => {
+                        return unsupported("returning a non-integer exit code", span)
+                    }
+                    None if self.is_main => Some(Operand::Int(0)),
+                    value => value.map(|(value, _)| value),
+                };
+                self.terminate(Terminator::Return(value));
+            }
+            StmtKind::Break => {
+                let (exit, _) = *self
+                    .loops
+                    .last()
+                    .expect("semantic analysis rejects `break` outside a loop");
+                self.terminate(Terminator::Jump(exit));
+            }
+            StmtKind::Continue => {
+                let (_, next) = *self
+                    .loops
+                    .last()
+                    .expect("semantic analysis rejects `continue` outside a loop");
+                self.terminate(Terminator::Jump(next));
+            }
+            StmtKind::Import(_) | StmtKind::Error => {}
+            StmtKind::Block(block) => self.block(block)?,
+            StmtKind::Expr(Expr {
+                kind: ExprKind::Call { callee, args },
+                ..
+            }) => {
+                self.call(callee, args, None)?;
+            }
+            StmtKind::Expr(expr) => {
+                self.expr(expr)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the variable declared by `decl`.
+    fn variable(&mut self, decl: NodeId) -> Operand {
+        if let Some(&reg) = self.locals.get(&decl) {
+            return Operand::Reg(reg);
+        }
+        let (global, ty) = self.ctx.globals[&decl];
+        let dst = self.reg(ty);
+        self.emit(Inst::LoadGlobal { dst, global });
+        Operand::Reg(dst)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> LowerResult<Operand> {
+        Ok(match &expr.kind {
+            ExprKind::Int(n) => Operand::Int(*n),
+            ExprKind::Float(n) => Operand::Float(*n),
+            ExprKind::Bool(b) => Operand::Bool(*b),
+            ExprKind::Str(_) | ExprKind::Interpolation(_) => {
+                return unsupported("a string", expr.span)
+            }
+            ExprKind::Char(_) => return unsupported("a character", expr.span),
+            ExprKind::Match { .. } => return unsupported("a `match` expression", expr.span),
+            ExprKind::Fn(_) => return unsupported("a function expression", expr.span),
+            ExprKind::Array(_) => return unsupported("an array", expr.span),
+            ExprKind::Index { .. } => return unsupported("indexing an array", expr.span),
+            ExprKind::Struct { .. } => return unsupported("a struct", expr.span),
+            ExprKind::Field { .. } => return unsupported("a struct field", expr.span),
+            ExprKind::Ident(_) => {
+                reject_builtin(expr, self.ctx.resolution)?;
+                let decl = self.ctx.resolution.uses[&expr.id];
+                if self.ctx.functions.contains_key(&decl) {
+                    return unsupported("a function used as a value", expr.span);
+                }
+                // Constants are folded in, for the optimizer to build on.
+                match self.ctx.resolution.consts.get(&decl) {
+                    Some(Value::Int(n)) => return Ok(Operand::Int(*n)),
+                    Some(Value::Float(n)) => return Ok(Operand::Float(*n)),
+                    Some(Value::Bool(b)) => return Ok(Operand::Bool(*b)),
+                    _ => {}
+                }
+                // A local is copied, so that later assignments to it do not
+                // change this value.
+                match self.variable(decl) {
+                    Operand::Reg(reg) if self.locals.get(&decl) == Some(&reg) => {
+                        let dst = self.reg(self.func.regs[reg.0 as usize]);
+                        self.emit(Inst::Copy {
+                            dst,
+                            src: Operand::Reg(reg),
+                        });
+                        Operand::Reg(dst)
+                    }
+                    value => value,
+                }
+            }
+            ExprKind::Unary { op, expr: operand } => {
+                let src = self.expr(operand)?;
+                let ty = self.ty(src);
+                match (op, ty) {
+                    (UnaryOp::Neg, Ty::Int | Ty::Float)
+                    | (UnaryOp::Not, Ty::Bool)
+                    | (UnaryOp::BitNot, Ty::Int) => {}
+                    _ => return unsupported(format!("`{}` on `{}`", op, ty), expr.span),
+                }
+                let dst = self.reg(ty);
+                self.emit(Inst::Unary { dst, op: *op, src });
+                Operand::Reg(dst)
+            }
+            ExprKind::Binary {
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                lhs,
+                rhs,
+            } => self.logical(*op, lhs, rhs)?,
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                let ty = Ty::of(&self.ctx.types.exprs[&expr.id], expr.span)?;
+                self.conditional(ty, cond, then_branch, else_branch)?
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let lhs = self.expr(lhs)?;
+                let rhs = self.expr(rhs)?;
+                self.binary(*op, lhs, rhs, expr.span)?
+            }
+            ExprKind::Call { callee, args } => {
+                let ty = Ty::of(&self.ctx.types.exprs[&expr.id], expr.span)?;
+                let dst = self.reg(ty);
+                self.call(callee, args, Some(dst))?;
+                Operand::Reg(dst)
+            }
+        })
+    }
+
+    fn call(&mut self, callee: &Expr, args: &[Expr], dst: Option<Reg>) -> LowerResult<()> {
+        reject_builtin(callee, self.ctx.resolution)?;
+        let func = match &callee.kind {
+            ExprKind::Ident(_) => self
+                .ctx
+                .functions
+                .get(&self.ctx.resolution.uses[&callee.id]),
+            _ => None,
+        };
+        let Some(&func) = func else {
+            return unsupported(
+                "calling something other than a top-level function",
+                callee.span,
+            );
+        };
+
+        let args = args
+            .iter()
+            .map(|arg| self.expr(arg))
+            .collect::<LowerResult<_>>()?;
+        self.emit(Inst::Call { dst, func, args });
+        Ok(())
+    }
+
+    /// Short-circuiting `&&` and `||`: both paths write one result register.
+    fn logical(&mut self, op: BinaryOp, lhs: &Expr, rhs: &Expr) -> LowerResult<Operand> {
+        let dst = self.reg(Ty::Bool);
+        let lhs = self.expr(lhs)?;
+        self.emit(Inst::Copy { dst, src: lhs });
+
+        let rhs_block = self.new_block();
+        let end = self.new_block();
+        let (then_block, else_block) = match op {
+            BinaryOp::And => (rhs_block, end),
+            _ => (end, rhs_block),
+        };
+        self.terminate(Terminator::Branch {
+            cond: Operand::Reg(dst),
+            then_block,
+            else_block,
+        });
+
+        self.start(rhs_block);
+        let rhs = self.expr(rhs)?;
+        self.emit(Inst::Copy { dst, src: rhs });
+        self.start(end);
+
+        Ok(Operand::Reg(dst))
+    }
+
+    /// `if (cond) a else b`: each arm writes the result register.
+    fn conditional(
+        &mut self,
+        ty: Ty,
+        cond: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> LowerResult<Operand> {
+        let dst = self.reg(ty);
+        let cond = self.expr(cond)?;
+        let then_block = self.new_block();
+        let else_block = self.new_block();
+        let end = self.new_block();
+        self.terminate(Terminator::Branch {
+            cond,
+            then_block,
+            else_block,
+        });
+
+        for (block, branch) in [(then_block, then_branch), (else_block, else_branch)] {
+            self.start(block);
+            let src = self.expr(branch)?;
+            self.emit(Inst::Copy { dst, src });
+            self.terminate(Terminator::Jump(end));
+        }
+        self.start(end);
+
+        Ok(Operand::Reg(dst))
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        lhs: Operand,
+        rhs: Operand,
+        span: Span,
+    ) -> LowerResult<Operand> {
+        // Mixed arithmetic promotes the integer side, as the interpreter does.
+        let (lhs, rhs) = match (self.ty(lhs), self.ty(rhs)) {
+            (Ty::Int, Ty::Float) => (self.int_to_float(lhs), rhs),
+            (Ty::Float, Ty::Int) => (lhs, self.int_to_float(rhs)),
+            _ => (lhs, rhs),
+        };
+        let ty = self.ty(lhs);
+        if ty != self.ty(rhs) {
+            return unsupported(format!("`{}` on `{}` and `{}`", op, ty, self.ty(rhs)), span);
+        }
+
+        use BinaryOp::*;
+        let result = match (op, ty) {
+            (Add | Sub | Mul | Div | Mod, Ty::Int | Ty::Float) => ty,
+            (Gt | Ge | Lt | Le, Ty::Int | Ty::Float) => Ty::Bool,
+            (Eq | Ne, _) => Ty::Bool,
+            (BitAnd | BitOr | BitXor, Ty::Int | Ty::Bool) => ty,
+            (Shl | Shr, Ty::Int) => Ty::Int,
+            _ => return unsupported(format!("`{}` on `{}`", op, ty), span),
+        };
+
+        let dst = self.reg(result);
+        self.emit(Inst::Binary { dst, op, lhs, rhs });
+        Ok(Operand::Reg(dst))
+    }
+
+    fn int_to_float(&mut self, src: Operand) -> Operand {
+        if let Operand::Int(n) = src {
+            return Operand::Float(n as f64);
+        }
+        let dst = self.reg(Ty::Float);
+        self.emit(Inst::IntToFloat { dst, src });
+        Operand::Reg(dst)
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Ty::Int => "int",
+            Ty::Float => "float",
+            Ty::Bool => "bool",
+        })
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Reg(reg) => write!(f, "{}", reg),
+            Operand::Int(n) => write!(f, "{}", n),
+            Operand::Float(n) => write!(f, "{:?}", n),
+            Operand::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Terminator::Jump(block) => write!(f, "jump {}", block),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => write!(f, "branch {}, {}, {}", cond, then_block, else_block),
+            Terminator::Return(Some(value)) => write!(f, "ret {}", value),
+            Terminator::Return(None) => write!(f, "ret"),
+            Terminator::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
+
+/// Prints a module as text, e.g.
+///
+/// ```text
+/// fn add(%0: int, %1: int): int {
+/// bb0:
+///     %2 = %0 + %1
+///     ret %2
+/// }
+/// ```
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for global in &self.globals {
+            writeln!(f, "global @{}: {}", global.name, global.ty)?;
+        }
+        if !self.globals.is_empty() {
+            writeln!(f)?;
+        }
+
+        for (i, func) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            self.write_function(f, func)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An instruction printed with the names of the globals and functions it uses.
+pub struct DisplayInst<'a> {
+    module: &'a Module,
+    inst: &'a Inst,
+}
+
+impl fmt::Display for DisplayInst<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.module.write_inst(f, self.inst)
+    }
+}
+
+/// One function of a module, printed as the module prints it.
+pub struct DisplayFunction<'a> {
+    module: &'a Module,
+    func: &'a Function,
+}
+
+impl fmt::Display for DisplayFunction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.module.write_function(f, self.func)
+    }
+}
+
+impl Module {
+    pub fn display<'a>(&'a self, inst: &'a Inst) -> DisplayInst<'a> {
+        DisplayInst { module: self, inst }
+    }
+
+    pub fn display_function<'a>(&'a self, func: &'a Function) -> DisplayFunction<'a> {
+        DisplayFunction { module: self, func }
+    }
+
+    fn write_function(&self, f: &mut fmt::Formatter<'_>, func: &Function) -> fmt::Result {
+        write!(f, "fn {}(", func.name)?;
+        for (i, param) in func.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", param, func.regs[param.0 as usize])?;
+        }
+        write!(f, ")")?;
+        if let Some(ret) = func.ret {
+            write!(f, ": {}", ret)?;
+        }
+        writeln!(f, " {{")?;
+
+        for (id, block) in func.blocks.iter().enumerate() {
+            writeln!(f, "{}:", BlockId(id as u32))?;
+            for inst in &block.insts {
+                write!(f, "    ")?;
+                self.write_inst(f, inst)?;
+                writeln!(f)?;
+            }
+            writeln!(f, "    {}", block.term)?;
+        }
+        writeln!(f, "}}")
+    }
+
+    fn write_inst(&self, f: &mut fmt::Formatter<'_>, inst: &Inst) -> fmt::Result {
+        let global = |id: &GlobalId| &self.globals[id.0 as usize].name;
+        match inst {
+            Inst::Copy { dst, src } => write!(f, "{} = {}", dst, src),
+            Inst::Unary { dst, op, src } => write!(f, "{} = {}{}", dst, op, src),
+            Inst::Binary { dst, op, lhs, rhs } => write!(f, "{} = {} {} {}", dst, lhs, op, rhs),
+            Inst::IntToFloat { dst, src } => write!(f, "{} = itof {}", dst, src),
+            Inst::LoadGlobal { dst, global: id } => write!(f, "{} = load @{}", dst, global(id)),
+            Inst::StoreGlobal { global: id, src } => write!(f, "store @{}, {}", global(id), src),
+            Inst::Call { dst, func, args } => {
+                if let Some(dst) = dst {
+                    write!(f, "{} = ", dst)?;
+                }
+                write!(f, "call {}(", self.functions[func.0 as usize].name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lower, Module};
+    use crate::codegen::CodegenError;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::span::Span;
+    use crate::{sema, types};
+
+    fn compile(input: &str) -> Result<Module, CodegenError> {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.");
+        let resolution = sema::analyze(&program).expect("Program failed analysis.");
+        let types = types::check(&program, &resolution).expect("Program failed to type check.");
+        lower(&program, &resolution, &types)
+    }
+
+    #[test]
+    fn test_function() {
+        let module =
+            compile("fn add(a: int, b: float): float { return a + b; } let big = add(1, 2.5) > 3;")
+                .unwrap();
+        let expected = "\
+global @big: bool
+
+fn add(%0: int, %1: float): float {
+bb0:
+    %2 = %0
+    %3 = %1
+    %4 = itof %2
+    %5 = %4 + %3
+    ret %5
+}
+
+fn main(): int {
+bb0:
+    %0 = call add(1, 2.5)
+    %1 = %0 > 3.0
+    store @big, %1
+    ret 0
+}
+";
+        assert_eq!(module.to_string(), expected);
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let module = compile(
+            "
+            let total = 0;
+            let i = 0;
+            while (i < 10) {
+                if (i % 2 == 0 && i != 4) {
+                    total += i;
+                } else {
+                    break;
+                }
+                i = i + 1;
+            }
+            return total;
+            ",
+        )
+        .unwrap();
+        let expected = "\
+global @total: int
+global @i: int
+
+fn main(): int {
+bb0:
+    store @total, 0
+    store @i, 0
+    jump bb1
+bb1:
+    %0 = load @i
+    %1 = %0 < 10
+    branch %1, bb2, bb3
+bb2:
+    %3 = load @i
+    %4 = %3 % 2
+    %5 = %4 == 0
+    %2 = %5
+    branch %2, bb4, bb5
+bb3:
+    %13 = load @total
+    ret %13
+bb4:
+    %6 = load @i
+    %7 = %6 != 4
+    %2 = %7
+    jump bb5
+bb5:
+    branch %2, bb6, bb7
+bb6:
+    %8 = load @i
+    %9 = load @total
+    %10 = %9 + %8
+    store @total, %10
+    jump bb8
+bb7:
+    jump bb3
+bb8:
+    %11 = load @i
+    %12 = %11 + 1
+    store @i, %12
+    jump bb1
+}
+";
+        assert_eq!(module.to_string(), expected);
+    }
+
+    #[test]
+    fn test_for_loops() {
+        let module = compile(
+            "
+            fn sum(n: int): int {
+                let total = 0;
+                for (i in 0..n) {
+                    if (i % 3 == 0) {
+                        continue;
+                    }
+                    total += i;
+                }
+                return total;
+            }
+            ",
+        )
+        .unwrap();
+        // `continue` jumps to the increment, in bb3.
+        let expected = "\
+fn sum(%0: int): int {
+bb0:
+    %1 = 0
+    %2 = 0
+    %4 = %0
+    %3 = %4
+    jump bb1
+bb1:
+    %5 = %2 < %3
+    branch %5, bb2, bb4
+bb2:
+    %6 = %2
+    %7 = %6
+    %8 = %7 % 3
+    %9 = %8 == 0
+    branch %9, bb5, bb6
+bb3:
+    %12 = %2 + 1
+    %2 = %12
+    jump bb1
+bb4:
+    %13 = %1
+    ret %13
+bb5:
+    jump bb3
+bb6:
+    %10 = %6
+    %11 = %1 + %10
+    %1 = %11
+    jump bb3
+}
+
+fn main(): int {
+bb0:
+    ret 0
+}
+";
+        assert_eq!(module.to_string(), expected);
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert_eq!(
+            compile("let s = \"hi\";"),
+            Err(CodegenError::Unsupported {
+                what: "a value of type `str`".into(),
+                span: Span::new(4, 5, 1, 5),
+            })
+        );
+        assert_eq!(
+            compile("fn f(): void { fn g(): void {} }"),
+            Err(CodegenError::Unsupported {
+                what: "a nested function".into(),
+                span: Span::new(18, 19, 1, 19),
+            })
+        );
+    }
+
+    #[test]
+    fn test_consts() {
+        let module = compile("const n = 2 * 3; fn f(x: int): int { return x * n; }").unwrap();
+        let expected = "\
+global @n: int
+
+fn f(%0: int): int {
+bb0:
+    %1 = %0
+    %2 = %1 * 6
+    ret %2
+}
+
+fn main(): int {
+bb0:
+    %0 = 2 * 3
+    store @n, %0
+    ret 0
+}
+";
+        assert_eq!(module.to_string(), expected);
+    }
+}
@@ -0,0 +1,805 @@
+//! JSON for editors and other external tools: output of the front end's data,
+//! and parsing of the messages the language server receives.
+//!
+//! Every node becomes an object tagged with a `"kind"`, and carries its
+//! `"span"` where it has one.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::ast::{
+    BinaryOp, Expr, ExprKind, Field, FieldInit, FnDecl, Ident, MatchArm, Param, Pattern,
+    PatternKind, Program, Stmt, StmtKind, StructDecl, TypeExpr, TypeExprKind, UnaryOp,
+};
+use crate::highlight::Highlight;
+use crate::lexer::{SpannedToken, StrPart, Token};
+use crate::span::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// Parses a single JSON value, surrounded by nothing but whitespace.
+    ///
+    /// ```
+    /// use compyl::json::Json;
+    ///
+    /// let json = Json::parse(r#"{"id": 1, "params": ["é"]}"#).unwrap();
+    /// assert_eq!(json.get("id").and_then(Json::as_i64), Some(1));
+    /// assert_eq!(json.to_string(), r#"{"id":1,"params":["é"]}"#);
+    /// ```
+    pub fn parse(text: &str) -> Result<Json, JsonError> {
+        let mut parser = JsonParser { text, position: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position < text.len() {
+            return Err(parser.error("trailing characters"));
+        }
+
+        Ok(value)
+    }
+
+    /// Looks up `key` when this is an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Int(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonError {
+    pub message: &'static str,
+    /// The byte offset in the text where parsing failed.
+    pub offset: usize,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid JSON: {} at byte {}", self.message, self.offset)
+    }
+}
+
+impl Error for JsonError {}
+
+struct JsonParser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl JsonParser<'_> {
+    fn error(&self, message: &'static str) -> JsonError {
+        JsonError {
+            message,
+            offset: self.position,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8, message: &'static str) -> Result<(), JsonError> {
+        self.skip_whitespace();
+        if self.peek() != Some(byte) {
+            return Err(self.error(message));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, JsonError> {
+        if !self.text[self.position..].starts_with(word) {
+            return Err(self.error("expected a value"));
+        }
+        self.position += word.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::Str),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b'[') => {
+                self.position += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(b']') {
+                    self.position += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => self.position += 1,
+                        Some(b']') => {
+                            self.position += 1;
+                            return Ok(Json::Array(items));
+                        }
+                        _ => return Err(self.error("expected `,` or `]`")),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.position += 1;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(b'}') {
+                    self.position += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() != Some(b'"') {
+                        return Err(self.error("expected a key"));
+                    }
+                    let key = self.string()?;
+                    self.expect(b':', "expected `:`")?;
+                    fields.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => self.position += 1,
+                        Some(b'}') => {
+                            self.position += 1;
+                            return Ok(Json::Object(fields));
+                        }
+                        _ => return Err(self.error("expected `,` or `}`")),
+                    }
+                }
+            }
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let start = self.position;
+        while matches!(
+            self.peek(),
+            Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+        ) {
+            self.position += 1;
+        }
+
+        let text = &self.text[start..self.position];
+        if let Ok(n) = text.parse() {
+            return Ok(Json::Int(n));
+        }
+        text.parse().map(Json::Float).map_err(|_| JsonError {
+            message: "invalid number",
+            offset: start,
+        })
+    }
+
+    /// Parses a string literal, starting at its opening quote.
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.position += 1;
+        let mut out = String::new();
+        loop {
+            let rest = &self.text[self.position..];
+            let Some(ch) = rest.chars().next() else {
+                return Err(self.error("unterminated string"));
+            };
+            self.position += ch.len_utf8();
+            match ch {
+                '"' => return Ok(out),
+                '\\' => {
+                    let escape = self
+                        .peek()
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.position += 1;
+                    out.push(match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape")),
+                    });
+                }
+                ch if ch.is_control() => return Err(self.error("control character in string")),
+                ch => out.push(ch),
+            }
+        }
+    }
+
+    /// Decodes the digits of a `\u` escape, combining a surrogate pair.
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !self.text[self.position..].starts_with("\\u") {
+                return Err(self.error("unpaired surrogate"));
+            }
+            self.position += 2;
+            let low = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("unpaired surrogate"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+
+        char::from_u32(code).ok_or_else(|| self.error("unpaired surrogate"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self
+            .text
+            .get(self.position..self.position + 4)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("invalid escape"))?;
+        self.position += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+}
+
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+/// Prints compactly, with no whitespace between elements.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Int(n) => write!(f, "{}", n),
+            // JSON has no NaN or infinities.
+            Json::Float(n) if !n.is_finite() => write!(f, "null"),
+            Json::Float(n) => write!(f, "{:?}", n),
+            Json::Str(s) => write_str(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_str(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_str(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for ch in s.chars() {
+        match ch {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            ch if ch.is_control() => write!(f, "\\u{:04x}", ch as u32)?,
+            ch => write!(f, "{}", ch)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+fn str(s: &str) -> Json {
+    Json::Str(s.into())
+}
+
+fn node(kind: &str, mut fields: Vec<(&str, Json)>) -> Json {
+    fields.insert(0, ("kind", str(kind)));
+    Json::object(fields)
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> Json {
+        Json::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> Json {
+        self.as_slice().to_json()
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> Json {
+        self.as_ref().map_or(Json::Null, ToJson::to_json)
+    }
+}
+
+impl<T: ToJson + ?Sized> ToJson for Box<T> {
+    fn to_json(&self) -> Json {
+        (**self).to_json()
+    }
+}
+
+impl ToJson for Span {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("start", Json::Int(self.start as i64)),
+            ("end", Json::Int(self.end as i64)),
+            ("line", Json::Int(self.line as i64)),
+            ("column", Json::Int(self.column as i64)),
+        ])
+    }
+}
+
+/// Tokens are tagged with their variant name; literals, identifiers and
+/// comments add a `"value"`. An interpolated string's value lists its parts:
+/// strings of text, and arrays of the tokens of each expression.
+impl ToJson for SpannedToken<'_> {
+    fn to_json(&self) -> Json {
+        let debug = format!("{:?}", self.token);
+        let kind = debug.split('(').next().unwrap_or_default();
+        let value = match &self.token {
+            Token::IntLiteral(n) => Some(Json::Int(*n)),
+            Token::FloatLiteral(n) => Some(Json::Float(*n)),
+            Token::CharLiteral(ch) => Some(str(ch.encode_utf8(&mut [0; 4]))),
+            Token::StrLiteral(s) | Token::Comment(s) | Token::DocComment(s) => Some(str(s)),
+            Token::InterpolatedStr(parts) => Some(Json::Array(
+                parts
+                    .iter()
+                    .map(|part| match part {
+                        StrPart::Text(text) => str(text),
+                        StrPart::Expr(tokens) => {
+                            Json::Array(tokens.iter().map(ToJson::to_json).collect())
+                        }
+                    })
+                    .collect(),
+            )),
+            Token::Identifier(name) => Some(str(name.as_str())),
+            _ => None,
+        };
+
+        let mut fields = Vec::new();
+        fields.extend(value.map(|value| ("value", value)));
+        fields.push(("span", self.span.to_json()));
+        node(kind, fields)
+    }
+}
+
+/// Highlights are tagged with the name of their class.
+impl ToJson for Highlight {
+    fn to_json(&self) -> Json {
+        node(self.class.name(), vec![("span", self.span.to_json())])
+    }
+}
+
+impl ToJson for UnaryOp {
+    fn to_json(&self) -> Json {
+        str(&self.to_string())
+    }
+}
+
+impl ToJson for BinaryOp {
+    fn to_json(&self) -> Json {
+        str(&self.to_string())
+    }
+}
+
+impl ToJson for Program {
+    fn to_json(&self) -> Json {
+        node("program", vec![("stmts", self.stmts.to_json())])
+    }
+}
+
+impl ToJson for Ident {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name", str(self.name.as_str())),
+            ("id", Json::Int(self.id.0.into())),
+            ("span", self.span.to_json()),
+        ])
+    }
+}
+
+impl ToJson for TypeExpr {
+    fn to_json(&self) -> Json {
+        let span = ("span", self.span.to_json());
+        match &self.kind {
+            TypeExprKind::Named(name) => node("named", vec![("name", str(name.as_str())), span]),
+            TypeExprKind::Void => node("void", vec![span]),
+            TypeExprKind::Array(elem) => node("array", vec![("elem", elem.to_json()), span]),
+            TypeExprKind::Fn { params, ret } => node(
+                "fn",
+                vec![("params", params.to_json()), ("ret", ret.to_json()), span],
+            ),
+        }
+    }
+}
+
+impl ToJson for Stmt {
+    fn to_json(&self) -> Json {
+        let span = ("span", self.span.to_json());
+        match &self.kind {
+            StmtKind::Let { name, ty, value } => node(
+                "let",
+                vec![
+                    ("name", name.to_json()),
+                    ("ty", ty.to_json()),
+                    ("value", value.to_json()),
+                    span,
+                ],
+            ),
+            StmtKind::Const { name, ty, value } => node(
+                "const",
+                vec![
+                    ("name", name.to_json()),
+                    ("ty", ty.to_json()),
+                    ("value", value.to_json()),
+                    span,
+                ],
+            ),
+            StmtKind::Assign { target, op, value } => node(
+                "assign",
+                vec![
+                    ("target", target.to_json()),
+                    ("op", op.to_json()),
+                    ("value", value.to_json()),
+                    span,
+                ],
+            ),
+            StmtKind::IndexAssign {
+                array,
+                index,
+                op,
+                value,
+            } => node(
+                "index_assign",
+                vec![
+                    ("array", array.to_json()),
+                    ("index", index.to_json()),
+                    ("op", op.to_json()),
+                    ("value", value.to_json()),
+                    span,
+                ],
+            ),
+            StmtKind::FieldAssign {
+                object,
+                field,
+                op,
+                value,
+            } => node(
+                "field_assign",
+                vec![
+                    ("object", object.to_json()),
+                    ("field", field.to_json()),
+                    ("op", op.to_json()),
+                    ("value", value.to_json()),
+                    span,
+                ],
+            ),
+            StmtKind::Fn(decl) => node("fn", vec![("decl", decl.to_json()), span]),
+            StmtKind::Extern(decl) => node("extern", vec![("decl", decl.to_json()), span]),
+            StmtKind::Struct(decl) => node("struct", vec![("decl", decl.to_json()), span]),
+            StmtKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => node(
+                "if",
+                vec![
+                    ("cond", cond.to_json()),
+                    ("then", then_branch.to_json()),
+                    ("else", else_branch.to_json()),
+                    span,
+                ],
+            ),
+            StmtKind::While { cond, body } => node(
+                "while",
+                vec![("cond", cond.to_json()), ("body", body.to_json()), span],
+            ),
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => node(
+                "for",
+                vec![
+                    ("var", var.to_json()),
+                    ("start", start.to_json()),
+                    ("end", end.to_json()),
+                    ("body", body.to_json()),
+                    span,
+                ],
+            ),
+            StmtKind::Return(value) => node("return", vec![("value", value.to_json()), span]),
+            StmtKind::Break => node("break", vec![span]),
+            StmtKind::Continue => node("continue", vec![span]),
+            StmtKind::Import(path) => node("import", vec![("path", str(path)), span]),
+            StmtKind::Block(body) => node("block", vec![("stmts", body.to_json()), span]),
+            StmtKind::Expr(expr) => node("expr", vec![("expr", expr.to_json()), span]),
+            StmtKind::Error => node("error", vec![span]),
+        }
+    }
+}
+
+impl ToJson for FnDecl {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name", self.name.to_json()),
+            ("params", self.params.to_json()),
+            ("ret", self.ret.to_json()),
+            ("body", self.body.to_json()),
+            ("doc", self.doc.as_deref().map_or(Json::Null, str)),
+        ])
+    }
+}
+
+impl ToJson for Param {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name", self.name.to_json()),
+            ("ty", self.ty.to_json()),
+        ])
+    }
+}
+
+impl ToJson for StructDecl {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name", self.name.to_json()),
+            ("fields", self.fields.to_json()),
+            ("doc", self.doc.as_deref().map_or(Json::Null, str)),
+        ])
+    }
+}
+
+impl ToJson for Field {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name", self.name.to_json()),
+            ("ty", self.ty.to_json()),
+        ])
+    }
+}
+
+impl ToJson for MatchArm {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("pattern", self.pattern.to_json()),
+            ("body", self.body.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Pattern {
+    fn to_json(&self) -> Json {
+        let span = ("span", self.span.to_json());
+        match &self.kind {
+            PatternKind::Int(n) => node("int", vec![("value", Json::Int(*n)), span]),
+            PatternKind::Char(ch) => node(
+                "char",
+                vec![("value", str(ch.encode_utf8(&mut [0; 4]))), span],
+            ),
+            PatternKind::Str(s) => node("str", vec![("value", str(s)), span]),
+            PatternKind::Bool(b) => node("bool", vec![("value", Json::Bool(*b)), span]),
+            PatternKind::Binding(name) => node("binding", vec![("name", name.to_json()), span]),
+            PatternKind::Wildcard => node("wildcard", vec![span]),
+        }
+    }
+}
+
+impl ToJson for FieldInit {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name", self.name.to_json()),
+            ("value", self.value.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Expr {
+    fn to_json(&self) -> Json {
+        let (kind, mut fields) = match &self.kind {
+            ExprKind::Int(n) => ("int", vec![("value", Json::Int(*n))]),
+            ExprKind::Float(n) => ("float", vec![("value", Json::Float(*n))]),
+            ExprKind::Char(ch) => ("char", vec![("value", str(ch.encode_utf8(&mut [0; 4])))]),
+            ExprKind::Str(s) => ("str", vec![("value", str(s))]),
+            ExprKind::Bool(b) => ("bool", vec![("value", Json::Bool(*b))]),
+            ExprKind::Ident(name) => ("ident", vec![("name", str(name.as_str()))]),
+            ExprKind::Interpolation(expr) => ("interpolation", vec![("expr", expr.to_json())]),
+            ExprKind::Unary { op, expr } => (
+                "unary",
+                vec![("op", op.to_json()), ("expr", expr.to_json())],
+            ),
+            ExprKind::Binary { op, lhs, rhs } => (
+                "binary",
+                vec![
+                    ("op", op.to_json()),
+                    ("lhs", lhs.to_json()),
+                    ("rhs", rhs.to_json()),
+                ],
+            ),
+            ExprKind::Call { callee, args } => (
+                "call",
+                vec![("callee", callee.to_json()), ("args", args.to_json())],
+            ),
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => (
+                "if",
+                vec![
+                    ("cond", cond.to_json()),
+                    ("then", then_branch.to_json()),
+                    ("else", else_branch.to_json()),
+                ],
+            ),
+            ExprKind::Match { scrutinee, arms } => (
+                "match",
+                vec![("scrutinee", scrutinee.to_json()), ("arms", arms.to_json())],
+            ),
+            ExprKind::Fn(decl) => ("fn", vec![("decl", decl.to_json())]),
+            ExprKind::Array(elems) => ("array", vec![("elems", elems.to_json())]),
+            ExprKind::Index { expr, index } => (
+                "index",
+                vec![("expr", expr.to_json()), ("index", index.to_json())],
+            ),
+            ExprKind::Struct { name, fields } => (
+                "struct",
+                vec![("name", name.to_json()), ("fields", fields.to_json())],
+            ),
+            ExprKind::Field { expr, field } => (
+                "field",
+                vec![("expr", expr.to_json()), ("field", field.to_json())],
+            ),
+        };
+        fields.push(("id", Json::Int(self.id.0.into())));
+        fields.push(("span", self.span.to_json()));
+        node(kind, fields)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Json, ToJson};
+    use crate::highlight::highlight;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_escapes() {
+        let json = Json::Array(vec![
+            Json::Str("a \"q\"\n\\ \u{1}".into()),
+            Json::Float(1.0),
+            Json::Float(f64::NAN),
+            Json::Null,
+        ]);
+
+        assert_eq!(json.to_string(), r#"["a \"q\"\n\\ \u0001",1.0,null,null]"#);
+    }
+
+    #[test]
+    fn test_parse() {
+        let json =
+            Json::parse(r#" {"a": [1, -2.5e1, true, null], "b": "\"\u00e9\ud83d\ude00\n"} "#);
+
+        assert_eq!(
+            json,
+            Ok(Json::object(vec![
+                (
+                    "a",
+                    Json::Array(vec![
+                        Json::Int(1),
+                        Json::Float(-25.0),
+                        Json::Bool(true),
+                        Json::Null,
+                    ])
+                ),
+                ("b", Json::Str("\"é😀\n".into())),
+            ]))
+        );
+        assert_eq!(Json::parse("[1,]").unwrap_err().offset, 3);
+        assert_eq!(
+            Json::parse("{\"a\" 1}").unwrap_err().message,
+            "expected `:`"
+        );
+        assert!(Json::parse("\"\\ud83d\"").is_err());
+        assert!(Json::parse("1 2").is_err());
+    }
+
+    #[test]
+    fn test_tokens() {
+        let tokens: Vec<_> = Lexer::new("x 1").map(Result::unwrap).collect();
+
+        assert_eq!(
+            tokens.to_json().to_string(),
+            concat!(
+                r#"[{"kind":"Identifier","value":"x","span":{"start":0,"end":1,"line":1,"column":1}},"#,
+                r#"{"kind":"IntLiteral","value":1,"span":{"start":2,"end":3,"line":1,"column":3}}]"#,
+            )
+        );
+    }
+
+    #[test]
+    fn test_highlights() {
+        assert_eq!(
+            highlight("f();").to_json().to_string(),
+            r#"[{"kind":"function","span":{"start":0,"end":1,"line":1,"column":1}}]"#
+        );
+    }
+
+    #[test]
+    fn test_ast() {
+        let program = Parser::new(Lexer::new("-a;")).parse_program().unwrap();
+
+        assert_eq!(
+            program.to_json().to_string(),
+            concat!(
+                r#"{"kind":"program","stmts":[{"kind":"expr","expr":{"kind":"unary","op":"-","#,
+                r#""expr":{"kind":"ident","name":"a","id":0,"span":{"start":1,"end":2,"line":1,"column":2}},"#,
+                r#""id":1,"span":{"start":0,"end":2,"line":1,"column":1}},"#,
+                r#""span":{"start":0,"end":3,"line":1,"column":1}}]}"#,
+            )
+        );
+    }
+}
@@ -1,8 +1,15 @@
-use std::fs;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
 
-#[allow(dead_code)]
-#[derive(Debug, PartialEq)]
-pub enum Token {
+use crate::intern::Symbol;
+use crate::profile::LanguageProfile;
+use crate::span::{FileId, Span};
+
+/// A token, borrowing its text from the lexer's input where it can.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token<'src> {
     // Keywords
     KwLet,
     KwFn,
@@ -14,9 +21,23 @@ pub enum Token {
     KwWhile,
     KwReturn,
     KwBreak,
+    KwContinue,
+    KwFor,
+    KwIn,
+    KwStruct,
+    KwImport,
+    KwMatch,
+    KwExtern,
+    KwConst,
 
     // Literals
-    NumLiteral(String),
+    IntLiteral(i64),
+    FloatLiteral(f64),
+    CharLiteral(char),
+    /// Borrowed unless the literal contains escapes.
+    StrLiteral(Cow<'src, str>),
+    /// A string literal with `${...}` in it.
+    InterpolatedStr(Vec<StrPart<'src>>),
 
     // Operators
     OpPlus,
@@ -33,12 +54,27 @@ pub enum Token {
     OpNe,
     OpLt,
     OpLe,
+    OpBitAnd,
+    OpBitOr,
+    OpBitXor,
+    OpBitNot,
+    OpShl,
+    OpShr,
 
     // Delimitters
     SemiColon,
     Colon,
     Comma,
+    Dot,
+    DotDot,
+    /// `=>`, between a `match` pattern and its value.
+    FatArrow,
     Assignment,
+    PlusAssign,
+    MinusAssign,
+    MultAssign,
+    DivAssign,
+    ModAssign,
     Lparen,
     RParen,
     LSquirly,
@@ -47,114 +83,744 @@ pub enum Token {
     RBracket,
 
     // Others
-    Identifier(String),
-    Comment(String),
+    Identifier(Symbol),
+    Comment(Cow<'src, str>),
+    /// A `??` line documenting the declaration after it. Skipped with other
+    /// comments, except by the parser.
+    DocComment(Cow<'src, str>),
     TokEof,
 }
 
-pub struct Lexer {
+#[derive(Debug, Clone, PartialEq)]
+pub enum StrPart<'src> {
+    Text(Cow<'src, str>),
+    /// The tokens between `${` and `}`, ending in a `TokEof` at the `}`.
+    Expr(Vec<SpannedToken<'src>>),
+}
+
+impl StrPart<'_> {
+    pub fn into_owned(self) -> StrPart<'static> {
+        match self {
+            StrPart::Text(text) => StrPart::Text(Cow::Owned(text.into_owned())),
+            StrPart::Expr(tokens) => {
+                StrPart::Expr(tokens.into_iter().map(SpannedToken::into_owned).collect())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken<'src> {
+    pub token: Token<'src>,
+    pub span: Span,
+}
+
+impl SpannedToken<'_> {
+    pub fn into_owned(self) -> SpannedToken<'static> {
+        SpannedToken {
+            token: self.token.into_owned(),
+            span: self.span,
+        }
+    }
+
+    /// Calls `f` on the token's span, and on those of the tokens interpolated
+    /// in it, so that they move together.
+    pub(crate) fn for_each_span(&mut self, f: &mut impl FnMut(&mut Span)) {
+        f(&mut self.span);
+        if let Token::InterpolatedStr(parts) = &mut self.token {
+            for part in parts {
+                if let StrPart::Expr(tokens) = part {
+                    for token in tokens {
+                        token.for_each_span(f);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Token<'_> {
+    /// Detaches the token from the input it was lexed from.
+    pub fn into_owned(self) -> Token<'static> {
+        match self {
+            Token::IntLiteral(n) => Token::IntLiteral(n),
+            Token::FloatLiteral(n) => Token::FloatLiteral(n),
+            Token::CharLiteral(ch) => Token::CharLiteral(ch),
+            Token::StrLiteral(s) => Token::StrLiteral(Cow::Owned(s.into_owned())),
+            Token::InterpolatedStr(parts) => {
+                Token::InterpolatedStr(parts.into_iter().map(StrPart::into_owned).collect())
+            }
+            Token::Identifier(name) => Token::Identifier(name),
+            Token::Comment(s) => Token::Comment(Cow::Owned(s.into_owned())),
+            Token::DocComment(s) => Token::DocComment(Cow::Owned(s.into_owned())),
+            Token::KwLet => Token::KwLet,
+            Token::KwFn => Token::KwFn,
+            Token::KwVoid => Token::KwVoid,
+            Token::KwTrue => Token::KwTrue,
+            Token::KwFalse => Token::KwFalse,
+            Token::KwIf => Token::KwIf,
+            Token::KwElse => Token::KwElse,
+            Token::KwWhile => Token::KwWhile,
+            Token::KwReturn => Token::KwReturn,
+            Token::KwBreak => Token::KwBreak,
+            Token::KwContinue => Token::KwContinue,
+            Token::KwFor => Token::KwFor,
+            Token::KwIn => Token::KwIn,
+            Token::KwStruct => Token::KwStruct,
+            Token::KwImport => Token::KwImport,
+            Token::KwMatch => Token::KwMatch,
+            Token::KwExtern => Token::KwExtern,
+            Token::KwConst => Token::KwConst,
+            Token::OpPlus => Token::OpPlus,
+            Token::OpMinus => Token::OpMinus,
+            Token::OpMult => Token::OpMult,
+            Token::OpDiv => Token::OpDiv,
+            Token::OpMod => Token::OpMod,
+            Token::OpAnd => Token::OpAnd,
+            Token::OpOr => Token::OpOr,
+            Token::OpNot => Token::OpNot,
+            Token::OpGt => Token::OpGt,
+            Token::OpGe => Token::OpGe,
+            Token::OpEq => Token::OpEq,
+            Token::OpNe => Token::OpNe,
+            Token::OpLt => Token::OpLt,
+            Token::OpLe => Token::OpLe,
+            Token::OpBitAnd => Token::OpBitAnd,
+            Token::OpBitOr => Token::OpBitOr,
+            Token::OpBitXor => Token::OpBitXor,
+            Token::OpBitNot => Token::OpBitNot,
+            Token::OpShl => Token::OpShl,
+            Token::OpShr => Token::OpShr,
+            Token::SemiColon => Token::SemiColon,
+            Token::Colon => Token::Colon,
+            Token::Comma => Token::Comma,
+            Token::Dot => Token::Dot,
+            Token::DotDot => Token::DotDot,
+            Token::FatArrow => Token::FatArrow,
+            Token::Assignment => Token::Assignment,
+            Token::PlusAssign => Token::PlusAssign,
+            Token::MinusAssign => Token::MinusAssign,
+            Token::MultAssign => Token::MultAssign,
+            Token::DivAssign => Token::DivAssign,
+            Token::ModAssign => Token::ModAssign,
+            Token::Lparen => Token::Lparen,
+            Token::RParen => Token::RParen,
+            Token::LSquirly => Token::LSquirly,
+            Token::RSquirly => Token::RSquirly,
+            Token::LBracket => Token::LBracket,
+            Token::RBracket => Token::RBracket,
+            Token::TokEof => Token::TokEof,
+        }
+    }
+
+    /// How a keyword or punctuation token is spelled in the standard
+    /// language.
+    fn spelling(&self) -> Option<&'static str> {
+        if let Some(op) = operator(self) {
+            return Some(op);
+        }
+        let spelling = match self {
+            Token::KwLet => "let",
+            Token::KwFn => "fn",
+            Token::KwVoid => "void",
+            Token::KwTrue => "true",
+            Token::KwFalse => "false",
+            Token::KwIf => "if",
+            Token::KwElse => "else",
+            Token::KwWhile => "while",
+            Token::KwReturn => "return",
+            Token::KwBreak => "break",
+            Token::KwContinue => "continue",
+            Token::KwFor => "for",
+            Token::KwIn => "in",
+            Token::KwStruct => "struct",
+            Token::KwImport => "import",
+            Token::KwMatch => "match",
+            Token::KwExtern => "extern",
+            Token::KwConst => "const",
+            Token::SemiColon => ";",
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::FatArrow => "=>",
+            Token::Assignment => "=",
+            Token::Lparen => "(",
+            Token::RParen => ")",
+            Token::LSquirly => "{",
+            Token::RSquirly => "}",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            _ => return None,
+        };
+        Some(spelling)
+    }
+}
+
+/// The token as a diagnostic names it: quoted as it is spelled, or else as
+/// the kind of token it is.
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(spelling) = self.spelling() {
+            return write!(f, "`{}`", spelling);
+        }
+        match self {
+            Token::IntLiteral(n) => write!(f, "`{}`", n),
+            Token::FloatLiteral(n) => write!(f, "`{:?}`", n),
+            Token::CharLiteral(ch) => write!(f, "`'{}'`", ch.escape_default()),
+            Token::StrLiteral(_) | Token::InterpolatedStr(_) => write!(f, "string literal"),
+            Token::Identifier(name) => write!(f, "`{}`", name),
+            Token::Comment(_) => write!(f, "comment"),
+            Token::DocComment(_) => write!(f, "doc comment"),
+            Token::TokEof => write!(f, "end of input"),
+            _ => unreachable!("{:?} has a spelling", self),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexError {
+    UnexpectedChar(char, Span),
+    UnterminatedString(Span),
+    UnterminatedComment(Span),
+    /// A character literal without its closing `'` on the same line.
+    UnterminatedChar(Span),
+    /// A character literal with no character in it, or more than one.
+    InvalidCharLiteral(Span),
+    InvalidEscape(char, Span),
+    IntegerOverflow(Span),
+    MissingDigits(&'static str, Span),
+    InvalidDigit(char, u32, Span),
+    InvalidUtf8(Span),
+    /// Strings interpolated inside each other more deeply than the lexer
+    /// allows; the span is the innermost literal.
+    NestingTooDeep(Span),
+    /// An operator the lexer's [`LanguageProfile`] disables.
+    DisabledOperator(&'static str, Span),
+}
+
+impl LexError {
+    pub fn span(&self) -> Span {
+        match self {
+            LexError::UnexpectedChar(_, span)
+            | LexError::UnterminatedString(span)
+            | LexError::UnterminatedComment(span)
+            | LexError::UnterminatedChar(span)
+            | LexError::InvalidCharLiteral(span)
+            | LexError::InvalidEscape(_, span)
+            | LexError::IntegerOverflow(span)
+            | LexError::MissingDigits(_, span)
+            | LexError::InvalidDigit(_, _, span)
+            | LexError::InvalidUtf8(span)
+            | LexError::NestingTooDeep(span)
+            | LexError::DisabledOperator(_, span) => *span,
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnexpectedChar(ch, _) => write!(f, "unexpected character `{}`", ch),
+            LexError::UnterminatedString(_) => write!(f, "unterminated string literal"),
+            LexError::UnterminatedComment(_) => write!(f, "unterminated block comment"),
+            LexError::UnterminatedChar(_) => write!(f, "unterminated character literal"),
+            LexError::InvalidCharLiteral(_) => {
+                write!(f, "character literals must hold exactly one character")
+            }
+            LexError::InvalidEscape(ch, _) => write!(f, "invalid escape sequence `\\{}`", ch),
+            LexError::IntegerOverflow(_) => write!(f, "integer literal is too large"),
+            LexError::MissingDigits(prefix, _) => write!(f, "no digits after `{}`", prefix),
+            LexError::InvalidDigit(ch, radix, _) => {
+                write!(f, "invalid digit `{}` in base {} literal", ch, radix)
+            }
+            LexError::InvalidUtf8(_) => write!(f, "source is not valid UTF-8"),
+            LexError::NestingTooDeep(_) => write!(
+                f,
+                "string interpolations nest more than {} levels deep",
+                MAX_INTERPOLATION_DEPTH
+            ),
+            LexError::DisabledOperator(op, _) => write!(f, "the operator `{}` is disabled", op),
+        }
+    }
+}
+
+impl Error for LexError {}
+
+/// Checks that `bytes` are UTF-8, as source text must be, pointing at the
+/// first invalid sequence if not.
+///
+/// ```
+/// use compyl::lexer::{decode, LexError};
+///
+/// assert_eq!(decode("é".as_bytes()), Ok("é"));
+/// let Err(LexError::InvalidUtf8(span)) = decode(b"ok\n\xe9 = 1;") else {
+///     panic!("not rejected");
+/// };
+/// assert_eq!((span.start, span.end, span.line, span.column), (3, 4, 2, 1));
+/// ```
+pub fn decode(bytes: &[u8]) -> Result<&str, LexError> {
+    std::str::from_utf8(bytes).map_err(|err| {
+        let valid = std::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap_or_default();
+        let line_start = valid.rfind('\n').map_or(0, |newline| newline + 1);
+        let line = valid.matches('\n').count() + 1;
+        let column = valid[line_start..].chars().count() + 1;
+        let len = err.error_len().unwrap_or(bytes.len() - valid.len());
+        LexError::InvalidUtf8(Span::new(valid.len(), valid.len() + len, line, column))
+    })
+}
+
+/// Whether `ch` can start an identifier: `_` or a Unicode letter.
+pub(crate) fn is_ident_start(ch: char) -> bool {
+    ch == '_' || ch.is_alphabetic()
+}
+
+/// Whether `ch` can continue an identifier: digits and combining marks too,
+/// approximating Unicode's `XID_Continue`.
+pub(crate) fn is_ident_continue(ch: char) -> bool {
+    is_ident_start(ch)
+        || ch.is_alphanumeric()
+        || matches!(
+            ch,
+            '\u{300}'..='\u{36f}'
+                | '\u{1ab0}'..='\u{1aff}'
+                | '\u{1dc0}'..='\u{1dff}'
+                | '\u{200c}'..='\u{200d}'
+                | '\u{20d0}'..='\u{20ff}'
+                | '\u{fe20}'..='\u{fe2f}'
+        )
+}
+
+/// The keyword spelled `word` in the standard language.
+pub(crate) fn keyword(word: &str) -> Option<Token<'static>> {
+    let keyword = match word {
+        "let" => Token::KwLet,
+        "fn" => Token::KwFn,
+        "void" => Token::KwVoid,
+        "true" => Token::KwTrue,
+        "false" => Token::KwFalse,
+        "if" => Token::KwIf,
+        "else" => Token::KwElse,
+        "while" => Token::KwWhile,
+        "return" => Token::KwReturn,
+        "break" => Token::KwBreak,
+        "continue" => Token::KwContinue,
+        "for" => Token::KwFor,
+        "in" => Token::KwIn,
+        "struct" => Token::KwStruct,
+        "import" => Token::KwImport,
+        "match" => Token::KwMatch,
+        "extern" => Token::KwExtern,
+        "const" => Token::KwConst,
+        _ => return None,
+    };
+    Some(keyword)
+}
+
+/// How the operator `token` is spelled, if it is one.
+pub(crate) fn operator(token: &Token) -> Option<&'static str> {
+    let op = match token {
+        Token::OpPlus => "+",
+        Token::OpMinus => "-",
+        Token::OpMult => "*",
+        Token::OpDiv => "/",
+        Token::OpMod => "%",
+        Token::OpAnd => "&&",
+        Token::OpOr => "||",
+        Token::OpNot => "!",
+        Token::OpGt => ">",
+        Token::OpGe => ">=",
+        Token::OpEq => "==",
+        Token::OpNe => "!=",
+        Token::OpLt => "<",
+        Token::OpLe => "<=",
+        Token::OpBitAnd => "&",
+        Token::OpBitOr => "|",
+        Token::OpBitXor => "^",
+        Token::OpBitNot => "~",
+        Token::OpShl => "<<",
+        Token::OpShr => ">>",
+        Token::DotDot => "..",
+        Token::PlusAssign => "+=",
+        Token::MinusAssign => "-=",
+        Token::MultAssign => "*=",
+        Token::DivAssign => "/=",
+        Token::ModAssign => "%=",
+        _ => return None,
+    };
+    Some(op)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LexerConfig {
+    /// Emit `Comment` tokens, for tools such as formatters; parsing wants them
+    /// skipped.
+    pub keep_comments: bool,
+}
+
+/// A replacement of the bytes `start..end` of a source text by `text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+/// How [`Lexer::relex`] changes a token list: the `removed` tokens from index
+/// `start` give way to `inserted`, and the spans of the tokens after them move.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenDelta {
+    pub start: usize,
+    pub removed: usize,
+    pub inserted: Vec<SpannedToken<'static>>,
+    offset: isize,
+    lines: isize,
+    /// The line, before the edit, on which tokens also move by `columns`.
+    line: usize,
+    columns: isize,
+}
+
+impl TokenDelta {
+    /// Turns the tokens of the unedited source into those of the edited one.
+    pub fn apply(self, tokens: &mut Vec<SpannedToken<'static>>) {
+        let end = self.start + self.removed;
+        for token in &mut tokens[end..] {
+            token.for_each_span(&mut |span| {
+                if span.line == self.line {
+                    span.column = (span.column as isize + self.columns) as usize;
+                }
+                span.line = (span.line as isize + self.lines) as usize;
+                span.start = (span.start as isize + self.offset) as usize;
+                span.end = (span.end as isize + self.offset) as usize;
+            });
+        }
+        tokens.splice(self.start..end, self.inserted);
+    }
+}
+
+/// Lexes a borrowed source text; comments and most string literals are
+/// slices of it rather than fresh allocations, and identifiers are interned.
+pub struct Lexer<'src> {
+    config: LexerConfig,
     position: usize,
     read_position: usize,
+    line: usize,
+    column: usize,
     ch: Option<u8>,
-    input: Vec<u8>,
+    source: &'src str,
+    input: &'src [u8],
+    file: FileId,
+    /// How many interpolations the lexer is inside.
+    interpolations: usize,
+    profile: Option<Arc<LanguageProfile>>,
+    /// The character starting comments, which the profile may change.
+    comment: u8,
 }
 
-impl Lexer {
-    pub fn new(input: String) -> Lexer {
+/// How deeply `${...}` may nest in string literals. Each level lexes
+/// recursively.
+const MAX_INTERPOLATION_DEPTH: usize = 64;
+
+impl<'src> Lexer<'src> {
+    /// Creates a lexer over `source` that skips comments.
+    ///
+    /// ```
+    /// use compyl::lexer::{Lexer, Token};
+    ///
+    /// let mut lexer = Lexer::new("let x");
+    /// assert_eq!(lexer.next_token().unwrap().token, Token::KwLet);
+    /// assert_eq!(lexer.next_token().unwrap().token, Token::Identifier("x".into()));
+    /// assert_eq!(lexer.next_token().unwrap().token, Token::TokEof);
+    /// ```
+    pub fn new(source: &'src str) -> Lexer<'src> {
+        Lexer::with_config(source, LexerConfig::default())
+    }
+
+    /// ```
+    /// use compyl::lexer::{Lexer, LexerConfig, Token};
+    ///
+    /// let config = LexerConfig { keep_comments: true };
+    /// let mut lexer = Lexer::with_config("?* a ?* nested *? comment *?", config);
+    /// assert!(matches!(lexer.next_token().unwrap().token, Token::Comment(_)));
+    /// ```
+    pub fn with_config(source: &'src str, config: LexerConfig) -> Lexer<'src> {
         let mut lex = Lexer {
+            config,
             position: 0,
             read_position: 0,
+            line: 1,
+            column: 1,
             ch: None,
-            input: input.into_bytes(),
+            source,
+            input: source.as_bytes(),
+            file: FileId(0),
+            interpolations: 0,
+            profile: None,
+            comment: b'?',
         };
         lex.read_char();
 
         lex
     }
 
-    pub fn from_file(file_path: String) -> Lexer {
-        let contents = fs::read_to_string(file_path).expect("Passed file does not exist.");
-        Lexer::new(contents)
+    /// Creates a lexer over `source` as if it started at `line` and `column` of
+    /// a larger text. Span offsets stay relative to `source`.
+    pub(crate) fn starting_at(
+        source: &'src str,
+        config: LexerConfig,
+        line: usize,
+        column: usize,
+    ) -> Lexer<'src> {
+        let mut lex = Lexer::with_config(source, config);
+        lex.line = line;
+        lex.column = column;
+
+        lex
     }
 
-    pub fn next_token(&mut self) -> Option<Token> {
-        self.skip_whitespace();
+    /// Makes the lexer's spans point into `file`.
+    pub fn with_file(mut self, file: FileId) -> Lexer<'src> {
+        self.file = file;
+        self
+    }
 
-        let tok = self.ch.and_then(|ch| match ch {
-            b'+' => Some(Token::OpPlus),
-            b'-' => Some(Token::OpMinus),
-            b'*' => Some(Token::OpMult),
-            b'/' => Some(Token::OpDiv),
-            b'%' => Some(Token::OpMod),
-            b'&' => Some(Token::OpAnd),
-            b'|' => Some(Token::OpOr),
-            b'>' => self.peek().map(|next_ch| {
-                if next_ch == b'=' {
-                    self.read_char();
-                    Token::OpGe
-                } else {
-                    Token::OpGt
-                }
-            }),
-            b'=' => self.peek().map(|next_ch| {
-                if next_ch == b'=' {
-                    self.read_char();
-                    Token::OpEq
-                } else {
-                    Token::Assignment
-                }
-            }),
-            b'!' => self.peek().map(|next_ch| {
-                if next_ch == b'=' {
-                    self.read_char();
-                    Token::OpNe
-                } else {
-                    Token::OpNot
+    /// Makes the lexer accept the language of `profile` instead of the
+    /// standard one.
+    pub fn with_profile(mut self, profile: Arc<LanguageProfile>) -> Lexer<'src> {
+        self.comment = profile.comment();
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Makes the lexer return comments, for the parser to find doc comments.
+    pub(crate) fn keep_comments(&mut self) {
+        self.config.keep_comments = true;
+    }
+
+    fn span(&self, start: usize, end: usize, line: usize, column: usize) -> Span {
+        Span::new(start, end, line, column).in_file(self.file)
+    }
+
+    /// Lexes only the part of `old`, the tokens ending in `TokEof` that this
+    /// lexer's config made of the source before `edit`, that the edit changed.
+    /// The lexer must be over the edited source. Lexing starts a token before
+    /// the edit and stops as soon as it meets an old token again.
+    ///
+    /// ```
+    /// use compyl::lexer::{Lexer, TextEdit, Token};
+    ///
+    /// let mut lexer = Lexer::new("let a = 1; let b = 2;");
+    /// let mut tokens = vec![lexer.next_token()?.into_owned()];
+    /// while tokens.last().unwrap().token != Token::TokEof {
+    ///     tokens.push(lexer.next_token()?.into_owned());
+    /// }
+    ///
+    /// let edit = TextEdit { start: 4, end: 5, text: "abc".into() };
+    /// let delta = Lexer::new("let abc = 1; let b = 2;").relex(&tokens, &edit)?;
+    /// assert_eq!((delta.start, delta.removed, delta.inserted.len()), (0, 2, 2));
+    ///
+    /// delta.apply(&mut tokens);
+    /// assert_eq!(tokens[1].token, Token::Identifier("abc".into()));
+    /// assert_eq!(tokens[6].span.start, 17);
+    /// # Ok::<(), compyl::lexer::LexError>(())
+    /// ```
+    pub fn relex(mut self, old: &[SpannedToken], edit: &TextEdit) -> Result<TokenDelta, LexError> {
+        // The token just before the edit may run into the edited text.
+        let touched = old
+            .iter()
+            .position(|token| token.span.end >= edit.start)
+            .unwrap_or(old.len());
+        let start = touched.saturating_sub(1);
+        let (position, line, column) = match start.checked_sub(1).map(|index| &old[index]) {
+            Some(token) => {
+                let (mut line, mut column) = (token.span.line, token.span.column);
+                for ch in self.source[token.span.start..token.span.end].chars() {
+                    if ch == '\n' {
+                        (line, column) = (line + 1, 1);
+                    } else {
+                        column += 1;
+                    }
                 }
-            }),
-            b'<' => self.peek().map(|next_ch| {
-                if next_ch == b'=' {
-                    self.read_char();
-                    Token::OpLe
-                } else {
-                    Token::OpLt
+                (token.span.end, line, column)
+            }
+            None => (0, 1, 1),
+        };
+
+        self.ch = self.input.get(position).copied();
+        (self.position, self.read_position) = (position, position + 1);
+        (self.line, self.column) = (line, column);
+
+        let offset = edit.text.len() as isize - (edit.end - edit.start) as isize;
+        let mut inserted = Vec::new();
+        let mut next = start;
+        loop {
+            let token = self.next_token()?;
+            // Old tokens after the edit are lexed the same way once the new
+            // tokens line up with one of them.
+            let moved = |old: &SpannedToken| old.span.start as isize + offset;
+            while next < old.len()
+                && (old[next].span.start < edit.end
+                    || moved(&old[next]) < token.span.start as isize)
+            {
+                next += 1;
+            }
+            if let Some(same) = old.get(next) {
+                if moved(same) == token.span.start as isize && same.token == token.token {
+                    return Ok(TokenDelta {
+                        start,
+                        removed: next - start,
+                        inserted,
+                        offset,
+                        lines: token.span.line as isize - same.span.line as isize,
+                        line: same.span.line,
+                        columns: token.span.column as isize - same.span.column as isize,
+                    });
                 }
-            }),
-            b';' => Some(Token::SemiColon),
-            b':' => Some(Token::Colon),
-            b',' => Some(Token::Comma),
-            b'(' => Some(Token::Lparen),
-            b')' => Some(Token::RParen),
-            b'{' => Some(Token::LSquirly),
-            b'}' => Some(Token::RSquirly),
-            b'[' => Some(Token::LBracket),
-            b']' => Some(Token::RBracket),
-            b'?' => Some(Token::Comment(self.read_comment())),
-            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
-                let ident = self.read_kw_or_identifier();
-                Some(match ident.as_str() {
-                    "let" => Token::KwLet,
-                    "fn" => Token::KwFn,
-                    "void" => Token::KwVoid,
-                    "true" => Token::KwTrue,
-                    "false" => Token::KwFalse,
-                    "if" => Token::KwIf,
-                    "else" => Token::KwElse,
-                    "while" => Token::KwWhile,
-                    "return" => Token::KwReturn,
-                    "break" => Token::KwBreak,
-                    _ => Token::Identifier(ident),
-                })
             }
-            b'0'..=b'9' => Some(Token::NumLiteral(self.read_num_literal())),
-            _ => None,
-        });
+
+            let eof = token.token == Token::TokEof;
+            inserted.push(token.into_owned());
+            if eof {
+                return Ok(TokenDelta {
+                    start,
+                    removed: old.len() - start,
+                    inserted,
+                    offset,
+                    lines: 0,
+                    line: 0,
+                    columns: 0,
+                });
+            }
+        }
+    }
+
+    /// Returns the next token, or `TokEof` once the input is exhausted. After an
+    /// error the lexer skips past the offending text, so lexing can resume.
+    pub fn next_token(&mut self) -> Result<SpannedToken<'src>, LexError> {
+        loop {
+            match self.scan() {
+                Ok(SpannedToken {
+                    token: Token::Comment(_) | Token::DocComment(_),
+                    ..
+                }) if !self.config.keep_comments => {}
+                result => return result,
+            }
+        }
+    }
+
+    fn scan(&mut self) -> Result<SpannedToken<'src>, LexError> {
+        self.skip_whitespace();
+
+        let (start, line, column) = (self.position, self.line, self.column);
+        let Some(ch) = self.ch else {
+            let span = self.span(start, start, line, column);
+            return Ok(SpannedToken {
+                token: Token::TokEof,
+                span,
+            });
+        };
+
+        let tok = self.scan_token(ch);
+        let span = self.span(start, self.position + 1, line, column);
+        if let (Ok(token), Some(profile)) = (&tok, &self.profile) {
+            if let Some(op) = operator(token).filter(|op| !profile.allows_operator(op)) {
+                self.read_char();
+                return Err(LexError::DisabledOperator(op, span));
+            }
+        }
 
         self.read_char();
-        tok
+        tok.map(|token| SpannedToken { token, span })
+    }
+
+    fn scan_token(&mut self, ch: u8) -> Result<Token<'src>, LexError> {
+        let comment = self.comment;
+        let tok = match ch {
+            ch if ch == comment && self.peek() == Some(b'*') => {
+                Token::Comment(self.read_block_comment()?.into())
+            }
+            ch if ch == comment && self.peek() == Some(comment) => {
+                Token::DocComment(self.read_comment().into())
+            }
+            ch if ch == comment => Token::Comment(self.read_comment().into()),
+            b'+' => self.either(b'=', Token::PlusAssign, Token::OpPlus),
+            b'-' => self.either(b'=', Token::MinusAssign, Token::OpMinus),
+            b'*' => self.either(b'=', Token::MultAssign, Token::OpMult),
+            b'/' => self.either(b'=', Token::DivAssign, Token::OpDiv),
+            b'%' => self.either(b'=', Token::ModAssign, Token::OpMod),
+            b'&' => self.either(b'&', Token::OpAnd, Token::OpBitAnd),
+            b'|' => self.either(b'|', Token::OpOr, Token::OpBitOr),
+            b'^' => Token::OpBitXor,
+            b'~' => Token::OpBitNot,
+            b'>' => match self.peek() {
+                Some(b'>') => self.either(b'>', Token::OpShr, Token::OpGt),
+                _ => self.either(b'=', Token::OpGe, Token::OpGt),
+            },
+            b'=' => match self.peek() {
+                Some(b'>') => self.either(b'>', Token::FatArrow, Token::Assignment),
+                _ => self.either(b'=', Token::OpEq, Token::Assignment),
+            },
+            b'!' => self.either(b'=', Token::OpNe, Token::OpNot),
+            b'<' => match self.peek() {
+                Some(b'<') => self.either(b'<', Token::OpShl, Token::OpLt),
+                _ => self.either(b'=', Token::OpLe, Token::OpLt),
+            },
+            b';' => Token::SemiColon,
+            b':' => Token::Colon,
+            b',' => Token::Comma,
+            b'.' => self.either(b'.', Token::DotDot, Token::Dot),
+            b'(' => Token::Lparen,
+            b')' => Token::RParen,
+            b'{' => Token::LSquirly,
+            b'}' => Token::RSquirly,
+            b'[' => Token::LBracket,
+            b']' => Token::RBracket,
+            b'"' => self.read_str_literal()?,
+            b'\'' => self.read_char_literal()?,
+            ch if ch.is_ascii_alphabetic() || ch == b'_' || self.at_ident_start(ch) => {
+                let ident = self.read_kw_or_identifier();
+                let keyword = match &self.profile {
+                    Some(profile) => profile.keyword(ident),
+                    None => keyword(ident),
+                };
+                keyword.unwrap_or_else(|| Token::Identifier(Symbol::intern(ident)))
+            }
+            b'0'..=b'9' => self.read_num_literal()?,
+            _ => {
+                let (start, line, column) = (self.position, self.line, self.column);
+                let ch = self.read_unexpected_char();
+                let span = self.span(start, self.position + 1, line, column);
+                return Err(LexError::UnexpectedChar(ch, span));
+            }
+        };
+
+        Ok(tok)
+    }
+
+    /// Consumes the next character and returns `yes` if it is `next`, else returns `no`.
+    fn either(&mut self, next: u8, yes: Token<'src>, no: Token<'src>) -> Token<'src> {
+        if self.peek() == Some(next) {
+            self.read_char();
+            yes
+        } else {
+            no
+        }
     }
 
     fn read_char(&mut self) {
+        if self.read_position > 0 {
+            if self.ch == Some(b'\n') {
+                self.line += 1;
+                self.column = 1;
+            } else if self
+                .input
+                .get(self.read_position)
+                .is_none_or(|&byte| byte & 0xC0 != 0x80)
+            {
+                // Columns count characters, so continuation bytes add none.
+                self.column += 1;
+            }
+        }
+
         if self.read_position >= self.input.len() {
             self.ch = None;
         } else {
@@ -179,67 +845,418 @@ impl Lexer {
         }
     }
 
-    fn read_kw_or_identifier(&mut self) -> String {
+    /// Whether the non-ASCII character starting with `byte` starts an identifier.
+    fn at_ident_start(&self, byte: u8) -> bool {
+        byte >= 0x80
+            && self.source[self.position..]
+                .chars()
+                .next()
+                .is_some_and(is_ident_start)
+    }
+
+    fn read_kw_or_identifier(&mut self) -> &'src str {
         let start_pos = self.position;
 
+        let mut len = self.source[start_pos..]
+            .chars()
+            .next()
+            .map_or(1, char::len_utf8);
+        loop {
+            // Step onto the last byte of the current character.
+            for _ in 1..len {
+                self.read_char();
+            }
+            match self.source[self.read_position..].chars().next() {
+                Some(next) if is_ident_continue(next) => {
+                    self.read_char();
+                    len = next.len_utf8();
+                }
+                _ => break,
+            }
+        }
+
+        &self.source[start_pos..=self.position]
+    }
+
+    fn read_num_literal(&mut self) -> Result<Token<'src>, LexError> {
+        let (start, line, column) = (self.position, self.line, self.column);
+        let (prefix, radix) = match (self.ch, self.peek()) {
+            (Some(b'0'), Some(b'x')) => ("0x", 16),
+            (Some(b'0'), Some(b'o')) => ("0o", 8),
+            (Some(b'0'), Some(b'b')) => ("0b", 2),
+            _ => return self.read_decimal_literal(),
+        };
+        self.read_char();
+
+        // Scan every alphanumeric so that a stray digit like the `2` in `0b12` is
+        // reported rather than starting a new token.
+        let digits_start = self.read_position;
         while self
             .peek()
-            .filter(|&ch| ch.is_ascii_alphabetic() || ch == b'_')
+            .filter(|&ch| ch.is_ascii_alphanumeric() || ch == b'_')
             .is_some()
         {
             self.read_char();
         }
+        let span = self.span(start, self.position + 1, line, column);
+
+        let digits = without_separators(&self.source[digits_start..self.read_position]);
+        if digits.is_empty() {
+            return Err(LexError::MissingDigits(prefix, span));
+        }
+        if let Some(bad) = digits.chars().find(|ch| !ch.is_digit(radix)) {
+            return Err(LexError::InvalidDigit(bad, radix, span));
+        }
 
-        return String::from_utf8_lossy(&self.input[start_pos..=self.position]).to_string();
+        i64::from_str_radix(&digits, radix)
+            .map(Token::IntLiteral)
+            .map_err(|_| LexError::IntegerOverflow(span))
     }
 
-    fn read_num_literal(&mut self) -> String {
-        let start_pos = self.position;
-        while self.peek().filter(|&ch| ch.is_ascii_digit()).is_some() {
+    fn read_decimal_literal(&mut self) -> Result<Token<'src>, LexError> {
+        let (start, line, column) = (self.position, self.line, self.column);
+        let is_digit = |ch: u8| ch.is_ascii_digit() || ch == b'_';
+        let mut is_float = false;
+        while self.peek().filter(|&ch| is_digit(ch)).is_some() {
             self.read_char();
         }
 
-        if self.peek().filter(|&ch| ch == b'.').is_some() {
+        // `0..n` is a range, not the float `0.` followed by a dot.
+        let range = self.input.get(self.read_position + 1) == Some(&b'.');
+        if self.peek() == Some(b'.') && !range {
+            is_float = true;
             self.read_char();
-            while self.peek().filter(|&ch| ch.is_ascii_digit()).is_some() {
+            while self.peek().filter(|&ch| is_digit(ch)).is_some() {
                 self.read_char();
             }
         }
 
-        return String::from_utf8_lossy(&self.input[start_pos..=self.position]).to_string();
+        // The scanned text is all ASCII digits with at most one dot, so only
+        // integer overflow can make parsing fail.
+        let text = without_separators(&self.source[start..=self.position]);
+        if is_float {
+            return Ok(Token::FloatLiteral(text.parse().unwrap_or(f64::NAN)));
+        }
+
+        text.parse().map(Token::IntLiteral).map_err(|_| {
+            let span = self.span(start, self.position + 1, line, column);
+            LexError::IntegerOverflow(span)
+        })
     }
 
-    fn read_comment(&mut self) -> String {
+    fn read_comment(&mut self) -> &'src str {
         let start_pos = self.position;
 
         while self.peek().filter(|&ch| ch != b'\n').is_some() {
             self.read_char();
         }
 
-        return String::from_utf8_lossy(&self.input[start_pos..=self.position]).to_string();
+        &self.source[start_pos..=self.position]
+    }
+
+    /// Reads a `?* ... *?` comment, which may span lines and nest.
+    fn read_block_comment(&mut self) -> Result<&'src str, LexError> {
+        let (start, line, column) = (self.position, self.line, self.column);
+        let comment = self.comment;
+        self.read_char();
+
+        let mut depth = 1;
+        while depth > 0 {
+            let Some(ch) = self.peek() else {
+                let span = self.span(start, self.input.len(), line, column);
+                return Err(LexError::UnterminatedComment(span));
+            };
+            self.read_char();
+
+            match (ch, self.peek()) {
+                (ch, Some(b'*')) if ch == comment => depth += 1,
+                (b'*', Some(next)) if next == comment => depth -= 1,
+                _ => continue,
+            }
+            self.read_char();
+        }
+
+        Ok(&self.source[start..=self.position])
+    }
+
+    /// Reads a string literal, which is a `StrLiteral` unless it has `${...}`
+    /// in it.
+    fn read_str_literal(&mut self) -> Result<Token<'src>, LexError> {
+        let (start, line, column) = (self.position, self.line, self.column);
+        let input = self.input;
+        let mut parts = Vec::new();
+        let mut text_start = start + 1;
+        // Only filled in once an escape means the text can't be borrowed.
+        let mut bytes: Option<Vec<u8>> = None;
+        let mut error = None;
+
+        loop {
+            let Some(ch) = self.peek() else {
+                let span = self.span(start, self.input.len(), line, column);
+                return Err(LexError::UnterminatedString(span));
+            };
+            self.read_char();
+
+            match ch {
+                b'"' => break,
+                b'\\' => {
+                    let bytes =
+                        bytes.get_or_insert_with(|| input[text_start..self.position].to_vec());
+                    let escape_span =
+                        self.span(self.position, self.position + 2, self.line, self.column);
+                    match self.peek() {
+                        Some(b'n') => bytes.push(b'\n'),
+                        Some(b't') => bytes.push(b'\t'),
+                        Some(b'\\') => bytes.push(b'\\'),
+                        Some(b'"') => bytes.push(b'"'),
+                        Some(b'$') => bytes.push(b'$'),
+                        Some(other) => {
+                            // Keep scanning to the closing quote so lexing resumes after it.
+                            error
+                                .get_or_insert(LexError::InvalidEscape(other as char, escape_span));
+                        }
+                        None => continue,
+                    }
+                    self.read_char();
+                }
+                b'$' if self.peek() == Some(b'{') => {
+                    parts.push(StrPart::Text(self.text(text_start, bytes.take())));
+                    self.read_char();
+                    let span = self.span(start, self.input.len(), line, column);
+                    // Like a bad escape, a bad interpolation leaves the rest
+                    // of the literal to read.
+                    match self.read_interpolation(span) {
+                        Ok(tokens) => parts.push(StrPart::Expr(tokens)),
+                        Err(err) => {
+                            error.get_or_insert(err);
+                        }
+                    }
+                    text_start = self.position + 1;
+                }
+                _ => {
+                    if let Some(bytes) = &mut bytes {
+                        bytes.push(ch);
+                    }
+                }
+            }
+        }
+
+        let text = self.text(text_start, bytes);
+        match error {
+            Some(err) => Err(err),
+            None if parts.is_empty() => Ok(Token::StrLiteral(text)),
+            None => {
+                parts.push(StrPart::Text(text));
+                Ok(Token::InterpolatedStr(parts))
+            }
+        }
+    }
+
+    /// Reads a character literal: one character, or one of the escapes strings
+    /// have, between `'`s.
+    fn read_char_literal(&mut self) -> Result<Token<'src>, LexError> {
+        let (start, line, column) = (self.position, self.line, self.column);
+        let mut error = None;
+        let value = match self.peek() {
+            None | Some(b'\n') => {
+                let span = self.span(start, self.position + 1, line, column);
+                return Err(LexError::UnterminatedChar(span));
+            }
+            Some(b'\'') => None,
+            Some(b'\\') => {
+                self.read_char();
+                let escape_span =
+                    self.span(self.position, self.position + 2, self.line, self.column);
+                let value = match self.peek() {
+                    Some(b'n') => '\n',
+                    Some(b't') => '\t',
+                    Some(b'\\') => '\\',
+                    Some(b'\'') => '\'',
+                    Some(b'"') => '"',
+                    None | Some(b'\n') => {
+                        let span = self.span(start, self.position + 1, line, column);
+                        return Err(LexError::UnterminatedChar(span));
+                    }
+                    Some(other) => {
+                        error = Some(LexError::InvalidEscape(other as char, escape_span));
+                        char::REPLACEMENT_CHARACTER
+                    }
+                };
+                self.read_char();
+                Some(value)
+            }
+            Some(_) => {
+                self.read_char();
+                Some(self.read_unexpected_char())
+            }
+        };
+        if let (Some(value), Some(b'\'')) = (value, self.peek()) {
+            self.read_char();
+            return match error {
+                Some(err) => Err(err),
+                None => Ok(Token::CharLiteral(value)),
+            };
+        }
+
+        // Skip what is left of the literal, up to a closing quote on this line.
+        while !matches!(self.peek(), None | Some(b'\n' | b'\'')) {
+            self.read_char();
+        }
+        let terminated = self.peek() == Some(b'\'');
+        if terminated {
+            self.read_char();
+        }
+        let span = self.span(start, self.position + 1, line, column);
+        Err(match terminated {
+            true => LexError::InvalidCharLiteral(span),
+            false => LexError::UnterminatedChar(span),
+        })
+    }
+
+    /// The text of a string literal from `start` up to the current character,
+    /// or `bytes` if it had escapes.
+    fn text(&self, start: usize, bytes: Option<Vec<u8>>) -> Cow<'src, str> {
+        match bytes {
+            // Escapes only replace ASCII, so the bytes stay valid UTF-8.
+            Some(bytes) => String::from_utf8_lossy(&bytes).into_owned().into(),
+            None => self.source[start..self.position].into(),
+        }
+    }
+
+    /// Lexes the expression after a `${`, up to the matching `}`, which becomes
+    /// the current character. `literal` is the span reported if there is none.
+    fn read_interpolation(&mut self, literal: Span) -> Result<Vec<SpannedToken<'src>>, LexError> {
+        if self.interpolations == MAX_INTERPOLATION_DEPTH {
+            // Skip to the closing `}` without lexing what is inside, which
+            // would go deeper.
+            self.read_char();
+            let mut depth = 0;
+            while let Some(ch) = self.ch {
+                match ch {
+                    b'{' => depth += 1,
+                    b'}' if depth == 0 => break,
+                    b'}' => depth -= 1,
+                    _ => {}
+                }
+                self.read_char();
+            }
+            return Err(LexError::NestingTooDeep(literal));
+        }
+        self.interpolations += 1;
+        let tokens = self.read_interpolation_tokens(literal);
+        self.interpolations -= 1;
+        tokens
+    }
+
+    fn read_interpolation_tokens(
+        &mut self,
+        literal: Span,
+    ) -> Result<Vec<SpannedToken<'src>>, LexError> {
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+        let mut error = None;
+        self.read_char();
+        loop {
+            self.skip_whitespace();
+            if self.ch == Some(b'}') && depth == 0 {
+                let span = self.span(self.position, self.position + 1, self.line, self.column);
+                tokens.push(SpannedToken {
+                    token: Token::TokEof,
+                    span,
+                });
+                return match error {
+                    Some(err) => Err(err),
+                    None => Ok(tokens),
+                };
+            }
+
+            let token = match self.scan() {
+                Ok(token) => token,
+                Err(err) => {
+                    error.get_or_insert(err);
+                    continue;
+                }
+            };
+            match token.token {
+                Token::LSquirly => depth += 1,
+                Token::RSquirly => depth -= 1,
+                Token::TokEof => return Err(LexError::UnterminatedString(literal)),
+                Token::Comment(_) | Token::DocComment(_) => continue,
+                _ => {}
+            }
+            tokens.push(token);
+        }
+    }
+
+    /// Consumes the (possibly multi-byte) character at the current position.
+    fn read_unexpected_char(&mut self) -> char {
+        let end = self.input.len().min(self.position + 4);
+        let ch = String::from_utf8_lossy(&self.input[self.position..end])
+            .chars()
+            .next()
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+        if ch != char::REPLACEMENT_CHARACTER {
+            for _ in 1..ch.len_utf8() {
+                self.read_char();
+            }
+        }
+
+        ch
+    }
+}
+
+/// Strips the `_` digit separators from a number literal, borrowing when there are none.
+fn without_separators(text: &str) -> Cow<'_, str> {
+    if text.contains('_') {
+        text.replace('_', "").into()
+    } else {
+        text.into()
+    }
+}
+
+impl<'src> Iterator for Lexer<'src> {
+    type Item = Result<SpannedToken<'src>, LexError>;
+
+    /// Yields tokens up to, but not including, `TokEof`.
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_token() {
+            Ok(SpannedToken {
+                token: Token::TokEof,
+                ..
+            }) => None,
+            result => Some(result),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{Lexer, Token};
+    use std::borrow::Cow;
 
-    fn test(input: String, expected_tokens: Vec<Token>) {
-        let mut lexer = Lexer::new(input);
+    use super::{LexError, Lexer, LexerConfig, SpannedToken, StrPart, TextEdit, Token};
+    use crate::intern::Symbol;
+    use crate::span::Span;
+
+    fn test(input: &str, expected_tokens: Vec<Token>) {
+        let config = LexerConfig {
+            keep_comments: true,
+        };
+        let mut lexer = Lexer::with_config(input, config);
         for expected_token in expected_tokens {
             let next_token = lexer
                 .next_token()
-                .expect("Next token is none when it should not have been.");
+                .expect("Next token is an error when it should not have been.")
+                .token;
             println!("expected: {:?}, received {:?}", expected_token, next_token);
             assert_eq!(expected_token, next_token)
         }
 
-        assert_eq!(lexer.next_token(), None);
+        assert_eq!(lexer.next_token().map(|tok| tok.token), Ok(Token::TokEof));
     }
 
     #[test]
     fn test_get_next_token() {
-        let input = "+-*/%&|!>>===!=<<=;:,(){}[]";
+        let input = "+-*/%&&||!> >===!=< <=&|^~<<>>;:,(){}[]";
 
         let expected_tokens = vec![
             Token::OpPlus,
@@ -256,6 +1273,12 @@ mod test {
             Token::OpNe,
             Token::OpLt,
             Token::OpLe,
+            Token::OpBitAnd,
+            Token::OpBitOr,
+            Token::OpBitXor,
+            Token::OpBitNot,
+            Token::OpShl,
+            Token::OpShr,
             Token::SemiColon,
             Token::Colon,
             Token::Comma,
@@ -267,7 +1290,373 @@ mod test {
             Token::RBracket,
         ];
 
-        test(input.into(), expected_tokens);
+        test(input, expected_tokens);
+    }
+
+    #[test]
+    fn test_spans() {
+        let lexer = Lexer::new("let ab = 5;\n  ab >= 10.5");
+        let spans: Vec<Span> = lexer.map(|tok| tok.unwrap().span).collect();
+
+        assert_eq!(
+            spans,
+            vec![
+                Span::new(0, 3, 1, 1),
+                Span::new(4, 6, 1, 5),
+                Span::new(7, 8, 1, 8),
+                Span::new(9, 10, 1, 10),
+                Span::new(10, 11, 1, 11),
+                Span::new(14, 16, 2, 3),
+                Span::new(17, 19, 2, 6),
+                Span::new(20, 24, 2, 9),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_str_literal() {
+        let input = r#"let s = "a \"quoted\"\tword\\\n"; "";"#;
+        let expected_tokens = vec![
+            Token::KwLet,
+            Token::Identifier("s".into()),
+            Token::Assignment,
+            Token::StrLiteral("a \"quoted\"\tword\\\n".into()),
+            Token::SemiColon,
+            Token::StrLiteral("".into()),
+            Token::SemiColon,
+        ];
+
+        test(input, expected_tokens);
+    }
+
+    #[test]
+    fn test_char_literal() {
+        test(
+            r#"'a' '\n' '\'' '"' 'é' '$'"#,
+            vec![
+                Token::CharLiteral('a'),
+                Token::CharLiteral('\n'),
+                Token::CharLiteral('\''),
+                Token::CharLiteral('"'),
+                Token::CharLiteral('é'),
+                Token::CharLiteral('$'),
+                Token::TokEof,
+            ],
+        );
+
+        let mut lexer = Lexer::new("'' 'ab' '\\q' 'x\nlet");
+        assert_eq!(
+            lexer.next_token(),
+            Err(LexError::InvalidCharLiteral(Span::new(0, 2, 1, 1)))
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Err(LexError::InvalidCharLiteral(Span::new(3, 7, 1, 4)))
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Err(LexError::InvalidEscape('q', Span::new(9, 11, 1, 10)))
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Err(LexError::UnterminatedChar(Span::new(13, 15, 1, 14)))
+        );
+        assert_eq!(lexer.next_token().map(|tok| tok.token), Ok(Token::KwLet));
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let mut lexer = Lexer::new(r#""a ${f({}, "${x}")} \${b}${ y }""#);
+        let token = |token, span| SpannedToken { token, span };
+        let inner = Token::InterpolatedStr(vec![
+            StrPart::Text("".into()),
+            StrPart::Expr(vec![
+                token(Token::Identifier("x".into()), Span::new(14, 15, 1, 15)),
+                token(Token::TokEof, Span::new(15, 16, 1, 16)),
+            ]),
+            StrPart::Text("".into()),
+        ]);
+
+        assert_eq!(
+            lexer.next_token().unwrap(),
+            token(
+                Token::InterpolatedStr(vec![
+                    StrPart::Text("a ".into()),
+                    StrPart::Expr(vec![
+                        token(Token::Identifier("f".into()), Span::new(5, 6, 1, 6)),
+                        token(Token::Lparen, Span::new(6, 7, 1, 7)),
+                        token(Token::LSquirly, Span::new(7, 8, 1, 8)),
+                        token(Token::RSquirly, Span::new(8, 9, 1, 9)),
+                        token(Token::Comma, Span::new(9, 10, 1, 10)),
+                        token(inner, Span::new(11, 17, 1, 12)),
+                        token(Token::RParen, Span::new(17, 18, 1, 18)),
+                        token(Token::TokEof, Span::new(18, 19, 1, 19)),
+                    ]),
+                    StrPart::Text(" ${b}".into()),
+                    StrPart::Expr(vec![
+                        token(Token::Identifier("y".into()), Span::new(28, 29, 1, 29)),
+                        token(Token::TokEof, Span::new(30, 31, 1, 31)),
+                    ]),
+                    StrPart::Text("".into()),
+                ]),
+                Span::new(0, 32, 1, 1),
+            )
+        );
+        assert_eq!(lexer.next_token().unwrap().token, Token::TokEof);
+
+        assert_eq!(
+            Lexer::new(r#""${x"#).next_token(),
+            Err(LexError::UnterminatedString(Span::new(0, 4, 1, 1)))
+        );
+    }
+
+    #[test]
+    fn test_borrows_from_input() {
+        let config = LexerConfig {
+            keep_comments: true,
+        };
+        let tokens: Vec<Token> = Lexer::with_config(r#"name "plain" "esc\n" ? note"#, config)
+            .map(|tok| tok.unwrap().token)
+            .collect();
+
+        assert_eq!(tokens[0], Token::Identifier(Symbol::intern("name")));
+        assert!(matches!(
+            tokens[1],
+            Token::StrLiteral(Cow::Borrowed("plain"))
+        ));
+        assert!(matches!(&tokens[2], Token::StrLiteral(Cow::Owned(s)) if s == "esc\n"));
+        assert!(matches!(tokens[3], Token::Comment(Cow::Borrowed("? note"))));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut lexer = Lexer::new(r#""bad \q" @§; "open"#);
+
+        assert_eq!(
+            lexer.next_token(),
+            Err(LexError::InvalidEscape('q', Span::new(5, 7, 1, 6)))
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Err(LexError::UnexpectedChar('@', Span::new(9, 10, 1, 10)))
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Err(LexError::UnexpectedChar('§', Span::new(10, 12, 1, 11)))
+        );
+        assert_eq!(
+            lexer.next_token().map(|tok| tok.token),
+            Ok(Token::SemiColon)
+        );
+        assert_eq!(
+            lexer.next_token(),
+            Err(LexError::UnterminatedString(Span::new(14, 19, 1, 14)))
+        );
+        assert_eq!(lexer.next_token().map(|tok| tok.token), Ok(Token::TokEof));
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        test(
+            "x += 1; x -= 2; x *= 3; x /= 4; x %= 5; x + = 6;",
+            vec![
+                Token::Identifier("x".into()),
+                Token::PlusAssign,
+                Token::IntLiteral(1),
+                Token::SemiColon,
+                Token::Identifier("x".into()),
+                Token::MinusAssign,
+                Token::IntLiteral(2),
+                Token::SemiColon,
+                Token::Identifier("x".into()),
+                Token::MultAssign,
+                Token::IntLiteral(3),
+                Token::SemiColon,
+                Token::Identifier("x".into()),
+                Token::DivAssign,
+                Token::IntLiteral(4),
+                Token::SemiColon,
+                Token::Identifier("x".into()),
+                Token::ModAssign,
+                Token::IntLiteral(5),
+                Token::SemiColon,
+                Token::Identifier("x".into()),
+                Token::OpPlus,
+                Token::Assignment,
+                Token::IntLiteral(6),
+                Token::SemiColon,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_field_access() {
+        // A dot right after digits belongs to the float literal.
+        test(
+            "struct P { x: int } p.x = 1.5; a[0].y",
+            vec![
+                Token::KwStruct,
+                Token::Identifier("P".into()),
+                Token::LSquirly,
+                Token::Identifier("x".into()),
+                Token::Colon,
+                Token::Identifier("int".into()),
+                Token::RSquirly,
+                Token::Identifier("p".into()),
+                Token::Dot,
+                Token::Identifier("x".into()),
+                Token::Assignment,
+                Token::FloatLiteral(1.5),
+                Token::SemiColon,
+                Token::Identifier("a".into()),
+                Token::LBracket,
+                Token::IntLiteral(0),
+                Token::RBracket,
+                Token::Dot,
+                Token::Identifier("y".into()),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_for_loops() {
+        test(
+            "for (i in 0..n) { continue; }",
+            vec![
+                Token::KwFor,
+                Token::Lparen,
+                Token::Identifier("i".into()),
+                Token::KwIn,
+                Token::IntLiteral(0),
+                Token::DotDot,
+                Token::Identifier("n".into()),
+                Token::RParen,
+                Token::LSquirly,
+                Token::KwContinue,
+                Token::SemiColon,
+                Token::RSquirly,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_match() {
+        test(
+            "match (x) { 1 => a, _ => b == c, } x = >",
+            vec![
+                Token::KwMatch,
+                Token::Lparen,
+                Token::Identifier("x".into()),
+                Token::RParen,
+                Token::LSquirly,
+                Token::IntLiteral(1),
+                Token::FatArrow,
+                Token::Identifier("a".into()),
+                Token::Comma,
+                Token::Identifier("_".into()),
+                Token::FatArrow,
+                Token::Identifier("b".into()),
+                Token::OpEq,
+                Token::Identifier("c".into()),
+                Token::Comma,
+                Token::RSquirly,
+                Token::Identifier("x".into()),
+                Token::Assignment,
+                Token::OpGt,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_extern() {
+        test(
+            "extern fn now(): int; externs",
+            vec![
+                Token::KwExtern,
+                Token::KwFn,
+                Token::Identifier("now".into()),
+                Token::Lparen,
+                Token::RParen,
+                Token::Colon,
+                Token::Identifier("int".into()),
+                Token::SemiColon,
+                Token::Identifier("externs".into()),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_num_literals() {
+        test(
+            "0 42 3.25 7. 9223372036854775807",
+            vec![
+                Token::IntLiteral(0),
+                Token::IntLiteral(42),
+                Token::FloatLiteral(3.25),
+                Token::FloatLiteral(7.0),
+                Token::IntLiteral(i64::MAX),
+            ],
+        );
+
+        let mut lexer = Lexer::new("x = 9223372036854775808;");
+        lexer.next_token().unwrap();
+        lexer.next_token().unwrap();
+        assert_eq!(
+            lexer.next_token(),
+            Err(LexError::IntegerOverflow(Span::new(4, 23, 1, 5)))
+        );
+        assert_eq!(
+            lexer.next_token().map(|tok| tok.token),
+            Ok(Token::SemiColon)
+        );
+    }
+
+    #[test]
+    fn test_radix_literals() {
+        test(
+            "0x1F 0XFF 0b1010 0o755 1_000_000 0xdead_BEEF 1_0.2_5 0",
+            vec![
+                Token::IntLiteral(0x1f),
+                Token::IntLiteral(0),
+                Token::Identifier("XFF".into()),
+                Token::IntLiteral(0b1010),
+                Token::IntLiteral(0o755),
+                Token::IntLiteral(1_000_000),
+                Token::IntLiteral(0xdead_beef),
+                Token::FloatLiteral(10.25),
+                Token::IntLiteral(0),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_malformed_radix_literals() {
+        let cases = [
+            ("0x;", LexError::MissingDigits("0x", Span::new(0, 2, 1, 1))),
+            ("0b_", LexError::MissingDigits("0b", Span::new(0, 3, 1, 1))),
+            (
+                "0b102",
+                LexError::InvalidDigit('2', 2, Span::new(0, 5, 1, 1)),
+            ),
+            ("0o8", LexError::InvalidDigit('8', 8, Span::new(0, 3, 1, 1))),
+            (
+                "0xfg",
+                LexError::InvalidDigit('g', 16, Span::new(0, 4, 1, 1)),
+            ),
+            (
+                "0x8000000000000000",
+                LexError::IntegerOverflow(Span::new(0, 18, 1, 1)),
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(Lexer::new(input).next_token(), Err(expected));
+        }
+    }
+
+    #[test]
+    fn test_operator_at_eof() {
+        test("a >", vec![Token::Identifier("a".into()), Token::OpGt]);
     }
 
     #[test]
@@ -278,11 +1667,11 @@ mod test {
             Token::KwLet,
             Token::Identifier("a".into()),
             Token::Assignment,
-            Token::NumLiteral("5".into()),
+            Token::IntLiteral(5),
             Token::SemiColon,
         ];
 
-        test(input.into(), expected_tokens);
+        test(input, expected_tokens);
     }
 
     #[test]
@@ -298,18 +1687,97 @@ mod test {
             Token::KwLet,
             Token::Identifier("a".into()),
             Token::Assignment,
-            Token::NumLiteral("5".into()),
+            Token::IntLiteral(5),
             Token::SemiColon,
             Token::Comment("? Foo+".into()),
             Token::KwLet,
             Token::Identifier("b".into()),
             Token::Assignment,
-            Token::NumLiteral("10".into()),
+            Token::IntLiteral(10),
             Token::SemiColon,
             Token::Comment("? Bar_".into()),
         ];
 
-        test(input.into(), expected_tokens);
+        test(input, expected_tokens);
+    }
+
+    #[test]
+    fn test_block_comments() {
+        test(
+            "a ?* one ?* two *? *? b ?**? ? line *?\nc",
+            vec![
+                Token::Identifier("a".into()),
+                Token::Comment("?* one ?* two *? *?".into()),
+                Token::Identifier("b".into()),
+                Token::Comment("?**?".into()),
+                Token::Comment("? line *?".into()),
+                Token::Identifier("c".into()),
+            ],
+        );
+
+        let mut lexer = Lexer::new("x ?* a ?* b *? y");
+        lexer.next_token().unwrap();
+        assert_eq!(
+            lexer.next_token(),
+            Err(LexError::UnterminatedComment(Span::new(2, 16, 1, 3)))
+        );
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        test(
+            "?? Adds.\n??\nfn ? no\n??* x",
+            vec![
+                Token::DocComment("?? Adds.".into()),
+                Token::DocComment("??".into()),
+                Token::KwFn,
+                Token::Comment("? no".into()),
+                Token::DocComment("??* x".into()),
+            ],
+        );
+        let tokens: Vec<Token> = Lexer::new("?? doc\nfn")
+            .map(|token| token.unwrap().token)
+            .collect();
+        assert_eq!(tokens, vec![Token::KwFn]);
+    }
+
+    #[test]
+    fn test_unicode_identifiers() {
+        test(
+            "let café_2 = naïve + x1;",
+            vec![
+                Token::KwLet,
+                Token::Identifier("café_2".into()),
+                Token::Assignment,
+                Token::Identifier("naïve".into()),
+                Token::OpPlus,
+                Token::Identifier("x1".into()),
+                Token::SemiColon,
+            ],
+        );
+
+        // Columns count characters.
+        let positions: Vec<_> = Lexer::new("é + \"ü\"\n  ñ")
+            .map(|tok| tok.map(|tok| (tok.span.line, tok.span.column)))
+            .collect();
+        assert_eq!(positions, [Ok((1, 1)), Ok((1, 3)), Ok((1, 5)), Ok((2, 3))]);
+
+        assert_eq!(
+            Lexer::new("→").next_token(),
+            Err(LexError::UnexpectedChar('→', Span::new(0, 3, 1, 1)))
+        );
+    }
+
+    #[test]
+    fn test_skips_comments() {
+        let tokens: Vec<Token> = Lexer::new("a ? one\n?* two *? b")
+            .map(|tok| tok.unwrap().token)
+            .collect();
+
+        assert_eq!(
+            tokens,
+            vec![Token::Identifier("a".into()), Token::Identifier("b".into())]
+        );
     }
 
     #[test]
@@ -344,12 +1812,12 @@ mod test {
             Token::KwLet,
             Token::Identifier("five".into()),
             Token::Assignment,
-            Token::NumLiteral("5".into()),
+            Token::IntLiteral(5),
             Token::SemiColon,
             Token::KwLet,
             Token::Identifier("ten".into()),
             Token::Assignment,
-            Token::NumLiteral("10.0".into()),
+            Token::FloatLiteral(10.0),
             Token::SemiColon,
             Token::KwFn,
             Token::Identifier("add".into()),
@@ -365,8 +1833,175 @@ mod test {
             Token::Identifier("y".into()),
             Token::SemiColon,
             Token::RSquirly,
+            Token::KwIf,
+            Token::Lparen,
+            Token::IntLiteral(5),
+            Token::OpLe,
+            Token::IntLiteral(10),
+            Token::RParen,
+            Token::LSquirly,
+            Token::KwReturn,
+            Token::Identifier("add".into()),
+            Token::Lparen,
+            Token::IntLiteral(5),
+            Token::Comma,
+            Token::IntLiteral(10),
+            Token::RParen,
+            Token::SemiColon,
+            Token::RSquirly,
+            Token::KwIf,
+            Token::Lparen,
+            Token::Identifier("five".into()),
+            Token::OpNe,
+            Token::Identifier("ten".into()),
+            Token::RParen,
+            Token::LSquirly,
+            Token::KwReturn,
+            Token::Identifier("add".into()),
+            Token::Lparen,
+            Token::Identifier("five".into()),
+            Token::Comma,
+            Token::Identifier("ten".into()),
+            Token::RParen,
+            Token::SemiColon,
+            Token::RSquirly,
+            Token::IntLiteral(10),
+            Token::OpEq,
+            Token::FloatLiteral(10.0),
+            Token::FloatLiteral(9.9),
+            Token::OpLt,
+            Token::IntLiteral(10),
+            Token::KwLet,
+            Token::Identifier("x".into()),
+            Token::Assignment,
+            Token::IntLiteral(7),
+            Token::SemiColon,
+            Token::KwWhile,
+            Token::Lparen,
+            Token::IntLiteral(11),
+            Token::OpGe,
+            Token::IntLiteral(10),
+            Token::RParen,
+            Token::LSquirly,
+            Token::Identifier("x".into()),
+            Token::Assignment,
+            Token::Identifier("x".into()),
+            Token::OpPlus,
+            Token::Identifier("five".into()),
+            Token::SemiColon,
+            Token::KwIf,
+            Token::Lparen,
+            Token::Identifier("x".into()),
+            Token::OpGt,
+            Token::IntLiteral(11),
+            Token::RParen,
+            Token::LSquirly,
+            Token::KwBreak,
+            Token::SemiColon,
+            Token::RSquirly,
+            Token::RSquirly,
+        ];
+
+        test(input, expected_tokens)
+    }
+
+    fn all_tokens(source: &str) -> Vec<SpannedToken<'static>> {
+        let config = LexerConfig {
+            keep_comments: true,
+        };
+        let mut lexer = Lexer::with_config(source, config);
+        let mut tokens = vec![lexer.next_token().unwrap().into_owned()];
+        while tokens.last().unwrap().token != Token::TokEof {
+            tokens.push(lexer.next_token().unwrap().into_owned());
+        }
+
+        tokens
+    }
+
+    #[test]
+    fn test_relex() {
+        // The tokens interpolated in `t` move along with it.
+        let source = "let s = \"a\";\nfn f(x) {\n    ? note\n    return x >= 10;\n}\nf(s);\nlet t = \"${s} ${f(\"${s}\")}\";";
+        let note = source.find("? note").unwrap();
+        let ten = source.find("10").unwrap();
+        let edits = [
+            (0, 0, "  "),
+            (4, 5, "name"),
+            (13, 13, "\n\n"),
+            (8, 8, "\"\n\" + "),
+            (17, 18, "xy, z"),
+            (note, note + 6, "?* a\n*?"),
+            (ten - 2, ten - 2, "="),
+            (ten, ten, "0x"),
+            (source.len(), source.len(), " g;"),
+            (0, source.len(), "x"),
         ];
+        let config = LexerConfig {
+            keep_comments: true,
+        };
+
+        for (start, end, text) in edits {
+            let edited = format!("{}{}{}", &source[..start], text, &source[end..]);
+            let edit = TextEdit {
+                start,
+                end,
+                text: text.into(),
+            };
+            let mut tokens = all_tokens(source);
+            match Lexer::with_config(&edited, config).relex(&tokens, &edit) {
+                Ok(delta) => delta.apply(&mut tokens),
+                Err(err) => panic!("relexing {:?} failed: {}", edited, err),
+            };
 
-        test(input.into(), expected_tokens)
+            assert_eq!(tokens, all_tokens(&edited), "after edit {:?}", edit);
+        }
+
+        let edit = TextEdit {
+            start: note,
+            end: note + 1,
+            text: "?*".into(),
+        };
+        let edited = source.replacen('?', "?*", 1);
+        let relexed = Lexer::with_config(&edited, config).relex(&all_tokens(source), &edit);
+        assert!(matches!(relexed, Err(LexError::UnterminatedComment(_))));
+    }
+
+    #[test]
+    fn test_relex_reuses_tokens() {
+        let source = "let a = 1;\n".repeat(100);
+        let start = source.len() / 2 + "let a = ".len();
+        let edit = TextEdit {
+            start,
+            end: start + 1,
+            text: "23".into(),
+        };
+        let edited = format!("{}23{}", &source[..start], &source[start + 1..]);
+
+        let mut tokens = all_tokens(&source);
+        let delta = Lexer::new(&edited).relex(&tokens, &edit).unwrap();
+        assert_eq!((delta.start, delta.removed), (252, 2));
+        assert_eq!(delta.inserted.len(), 2);
+
+        delta.apply(&mut tokens);
+        assert_eq!(tokens, all_tokens(&edited));
+    }
+
+    #[test]
+    fn test_display() {
+        let tokens = [
+            (Token::Assignment, "`=`"),
+            (Token::LSquirly, "`{`"),
+            (Token::OpShl, "`<<`"),
+            (Token::KwElse, "`else`"),
+            (Token::Identifier(Symbol::intern("foo")), "`foo`"),
+            (Token::IntLiteral(2), "`2`"),
+            (Token::FloatLiteral(1.0), "`1.0`"),
+            (Token::CharLiteral('\n'), "`'\\n'`"),
+            (Token::StrLiteral(Cow::Borrowed("s")), "string literal"),
+            (Token::TokEof, "end of input"),
+        ];
+        for (token, shown) in tokens {
+            assert_eq!(token.to_string(), shown);
+        }
     }
 }
@@ -0,0 +1,55 @@
+//! A simple compiler: a [`lexer`] turning source text into tokens, a [`parser`]
+//! building an [`ast`] from them, [`sema`]ntic analysis resolving its names, a
+//! [`types`] checker, and an [`interp`]reter to run the result. To run
+//! compyl from another program, use an [`Engine`].
+//!
+//! ```
+//! use compyl::lexer::Lexer;
+//! use compyl::parser::Parser;
+//!
+//! let program = Parser::new(Lexer::new("let a = 1 + 2;"))
+//!     .parse_program()
+//!     .unwrap();
+//! assert_eq!(program.stmts.len(), 1);
+//! ```
+
+pub mod analysis;
+pub mod ast;
+pub mod batch;
+pub mod builtins;
+pub mod bytecode;
+pub mod cfg;
+pub mod codegen;
+pub mod diagnostics;
+pub mod doc;
+pub mod engine;
+pub mod error;
+pub mod fmt;
+pub mod fuzz;
+pub mod gc;
+pub mod highlight;
+pub mod intern;
+pub mod interp;
+pub mod ir;
+pub mod json;
+pub mod lexer;
+pub mod lints;
+pub mod lsp;
+pub mod opt;
+pub mod parser;
+pub mod profile;
+pub mod regalloc;
+pub mod repl;
+pub mod sema;
+pub mod source;
+pub mod span;
+pub mod streaming;
+pub mod testgen;
+pub mod token_stream;
+pub mod trace;
+pub mod trivia;
+pub mod types;
+pub mod vm;
+pub mod watch;
+
+pub use engine::Engine;
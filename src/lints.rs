@@ -0,0 +1,451 @@
+//! Warnings about programs that are valid but probably not what was meant:
+//! unused bindings, code that can never run, and names that hide others.
+//! They run after [`sema`](crate::sema), over a program that resolved.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::{
+    Block, Expr, ExprKind, FnDecl, Ident, MatchArm, NodeId, PatternKind, Program, Stmt, StmtKind,
+};
+use crate::intern::Symbol;
+use crate::sema::{DeclKind, Resolution};
+use crate::span::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lint {
+    UnusedVariable {
+        name: String,
+        span: Span,
+    },
+    UnusedParameter {
+        name: String,
+        span: Span,
+    },
+    /// Statements after a `return`, `break` or `continue`, up to the end of
+    /// their block.
+    UnreachableCode(Span),
+    /// A `match` over the value at the span with no arm for some values.
+    NonExhaustiveMatch(Span),
+    /// A pattern that an earlier arm of its `match` already matches.
+    UnreachablePattern(Span),
+    /// A declaration of a name that one in an enclosing scope already has.
+    Shadowed {
+        name: String,
+        span: Span,
+        previous: Span,
+    },
+}
+
+impl Lint {
+    pub fn span(&self) -> Span {
+        match self {
+            Lint::UnusedVariable { span, .. }
+            | Lint::UnusedParameter { span, .. }
+            | Lint::UnreachableCode(span)
+            | Lint::NonExhaustiveMatch(span)
+            | Lint::UnreachablePattern(span)
+            | Lint::Shadowed { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::UnusedVariable { name, .. } => write!(f, "unused variable `{}`", name),
+            Lint::UnusedParameter { name, .. } => write!(f, "unused parameter `{}`", name),
+            Lint::UnreachableCode(_) => write!(f, "unreachable code"),
+            Lint::NonExhaustiveMatch(_) => write!(f, "`match` does not cover every value"),
+            Lint::UnreachablePattern(_) => write!(f, "unreachable pattern"),
+            Lint::Shadowed { name, .. } => {
+                write!(f, "`{}` shadows a declaration in an outer scope", name)
+            }
+        }
+    }
+}
+
+/// Finds every lint in `program`, in source order. Variables and parameters
+/// whose names start with `_` may go unused.
+pub fn check(program: &Program, resolution: &Resolution) -> Vec<Lint> {
+    let mut linter = Linter {
+        resolution,
+        scopes: Vec::new(),
+        assigned: HashSet::new(),
+        lints: Vec::new(),
+    };
+    linter.block(&program.stmts);
+
+    let used: HashSet<NodeId> = resolution
+        .uses
+        .iter()
+        .filter(|(id, _)| !linter.assigned.contains(id))
+        .map(|(_, decl)| *decl)
+        .collect();
+    for (id, decl) in &resolution.decls {
+        if used.contains(id) || decl.name.as_str().starts_with('_') {
+            continue;
+        }
+        let (name, span) = (decl.name.to_string(), decl.span);
+        match decl.kind {
+            DeclKind::Let | DeclKind::Const => {
+                linter.lints.push(Lint::UnusedVariable { name, span })
+            }
+            DeclKind::Param => linter.lints.push(Lint::UnusedParameter { name, span }),
+            DeclKind::Fn | DeclKind::Struct => {}
+        }
+    }
+
+    let mut lints = linter.lints;
+    lints.sort_by_key(|lint| (lint.span().file, lint.span().start));
+    lints
+}
+
+struct Linter<'a> {
+    resolution: &'a Resolution,
+    scopes: Vec<HashMap<Symbol, NodeId>>,
+    /// The uses that only assign to a name, which do not count as using it.
+    assigned: HashSet<NodeId>,
+    lints: Vec<Lint>,
+}
+
+impl Linter<'_> {
+    fn declare(&mut self, ident: &Ident) {
+        let outer = self.scopes[..self.scopes.len() - 1]
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&ident.name));
+        if let Some(previous) = outer {
+            self.lints.push(Lint::Shadowed {
+                name: ident.name.to_string(),
+                span: ident.span,
+                previous: self.resolution.decls[previous].span,
+            });
+        }
+        let scope = self.scopes.last_mut().expect("no scope to declare in");
+        scope.insert(ident.name, ident.id);
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.scopes.push(HashMap::new());
+        self.stmts(block);
+        self.scopes.pop();
+    }
+
+    /// Walks `stmts` in the innermost scope, declaring its functions and
+    /// structs first, as semantic analysis does.
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            match &stmt.kind {
+                StmtKind::Fn(decl) | StmtKind::Extern(decl) => self.declare(&decl.name),
+                StmtKind::Struct(decl) => self.declare(&decl.name),
+                _ => {}
+            }
+        }
+
+        let mut diverged = false;
+        for (index, stmt) in stmts.iter().enumerate() {
+            self.stmt(stmt);
+            if diverged || !diverges(stmt) {
+                continue;
+            }
+            diverged = true;
+            // Functions and structs declared after a `return` are still
+            // hoisted, so only count what would run.
+            let rest = &stmts[index + 1..];
+            let mut runs = rest.iter().filter(|stmt| {
+                !matches!(
+                    stmt.kind,
+                    StmtKind::Fn(_) | StmtKind::Extern(_) | StmtKind::Struct(_)
+                )
+            });
+            if let Some(first) = runs.next() {
+                let last = runs.next_back().unwrap_or(first);
+                self.lints
+                    .push(Lint::UnreachableCode(first.span.to(last.span)));
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { name, value, .. } | StmtKind::Const { name, value, .. } => {
+                self.expr(value);
+                self.declare(name);
+            }
+            StmtKind::Assign { target, op, value } => {
+                self.expr(value);
+                if op.is_none() {
+                    self.assigned.insert(target.id);
+                }
+            }
+            StmtKind::IndexAssign {
+                array,
+                index,
+                value,
+                ..
+            } => {
+                self.expr(array);
+                self.expr(index);
+                self.expr(value);
+            }
+            StmtKind::FieldAssign { object, value, .. } => {
+                self.expr(object);
+                self.expr(value);
+            }
+            StmtKind::Fn(decl) => self.function(decl),
+            StmtKind::Extern(_) | StmtKind::Struct(_) => {}
+            StmtKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(cond);
+                self.block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.block(else_branch);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                self.expr(cond);
+                self.block(body);
+            }
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                self.expr(start);
+                self.expr(end);
+                self.scopes.push(HashMap::new());
+                self.declare(var);
+                self.block(body);
+                self.scopes.pop();
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            StmtKind::Break | StmtKind::Continue | StmtKind::Import(_) | StmtKind::Error => {}
+            StmtKind::Block(block) => self.block(block),
+            StmtKind::Expr(expr) => self.expr(expr),
+        }
+    }
+
+    fn function(&mut self, decl: &FnDecl) {
+        self.scopes.push(HashMap::new());
+        for param in &decl.params {
+            self.declare(&param.name);
+        }
+        self.stmts(&decl.body);
+        self.scopes.pop();
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::Char(_)
+            | ExprKind::Str(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Ident(_) => {}
+            ExprKind::Unary { expr, .. } | ExprKind::Interpolation(expr) => self.expr(expr),
+            ExprKind::Binary { lhs, rhs, .. } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            ExprKind::Call { callee, args } => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(cond);
+                self.expr(then_branch);
+                self.expr(else_branch);
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                self.expr(scrutinee);
+                self.arms(scrutinee, arms);
+            }
+            ExprKind::Fn(decl) => {
+                self.scopes.push(HashMap::new());
+                if !decl.is_anonymous() {
+                    self.declare(&decl.name);
+                }
+                self.function(decl);
+                self.scopes.pop();
+            }
+            ExprKind::Array(elems) => {
+                for elem in elems {
+                    self.expr(elem);
+                }
+            }
+            ExprKind::Index { expr, index } => {
+                self.expr(expr);
+                self.expr(index);
+            }
+            ExprKind::Struct { fields, .. } => {
+                for field in fields {
+                    self.expr(&field.value);
+                }
+            }
+            ExprKind::Field { expr, .. } => self.expr(expr),
+        }
+    }
+
+    /// Walks the arms of a `match` on `scrutinee`, finding the patterns that
+    /// can never match and whether any value is left over. Only a name, `_`
+    /// or both `true` and `false` cover every value of a type.
+    fn arms(&mut self, scrutinee: &Expr, arms: &[MatchArm]) {
+        let mut seen: Vec<&PatternKind> = Vec::new();
+        let mut exhaustive = false;
+        for arm in arms {
+            let kind = &arm.pattern.kind;
+            if exhaustive || seen.contains(&kind) {
+                self.lints.push(Lint::UnreachablePattern(arm.pattern.span));
+            }
+            seen.push(kind);
+            exhaustive |= kind.is_irrefutable()
+                || [true, false]
+                    .iter()
+                    .all(|b| seen.contains(&&PatternKind::Bool(*b)));
+
+            self.scopes.push(HashMap::new());
+            if let PatternKind::Binding(name) = kind {
+                self.declare(name);
+            }
+            self.expr(&arm.body);
+            self.scopes.pop();
+        }
+        if !exhaustive {
+            self.lints.push(Lint::NonExhaustiveMatch(scrutinee.span));
+        }
+    }
+}
+
+/// Whether control never reaches the statement after `stmt`.
+fn diverges(stmt: &Stmt) -> bool {
+    match &stmt.kind {
+        StmtKind::Return(_) | StmtKind::Break | StmtKind::Continue => true,
+        StmtKind::Block(block) => block.iter().any(diverges),
+        StmtKind::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => then_branch.iter().any(diverges) && else_branch.iter().any(diverges),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, Lint};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::sema;
+    use crate::span::Span;
+
+    fn lints(input: &str) -> Vec<Lint> {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.");
+        let resolution = sema::analyze(&program).expect("Program failed analysis.");
+        check(&program, &resolution)
+    }
+
+    #[test]
+    fn test_unused() {
+        assert_eq!(
+            lints("fn f(a, _b, c) { let d = c; d = 2; let e = 0; e += 1; } let _x = f;"),
+            vec![
+                Lint::UnusedParameter {
+                    name: "a".into(),
+                    span: Span::new(5, 6, 1, 6),
+                },
+                Lint::UnusedVariable {
+                    name: "d".into(),
+                    span: Span::new(21, 22, 1, 22),
+                },
+            ]
+        );
+        assert_eq!(
+            lints("for (i in 0..3) {}"),
+            vec![Lint::UnusedVariable {
+                name: "i".into(),
+                span: Span::new(5, 6, 1, 6),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_unreachable() {
+        assert_eq!(
+            lints("fn f(x) { if (x) { return 1; } else { return 2; } print(x); fn g() {} x; }"),
+            vec![Lint::UnreachableCode(Span::new(50, 72, 1, 51))]
+        );
+        assert_eq!(
+            lints("while (true) { { break; } print(1); } fn h() { return; fn k() {} }"),
+            vec![Lint::UnreachableCode(Span::new(26, 35, 1, 27))]
+        );
+        assert_eq!(lints("fn f(x) { if (x) { return 1; } return 2; }"), vec![]);
+    }
+
+    #[test]
+    fn test_match() {
+        assert_eq!(
+            lints(
+                "let x = 1; let a = match (x) { 1 => 0, 1 => 2, _ => 3, n => n }; \
+                 let b = match (x > 0) { true => 1, false => 0 }; \
+                 let c = match (\"s\") { \"s\" => 1 }; print(a + b + c);"
+            ),
+            vec![
+                Lint::UnreachablePattern(Span::new(39, 40, 1, 40)),
+                Lint::UnreachablePattern(Span::new(55, 56, 1, 56)),
+                Lint::NonExhaustiveMatch(Span::new(129, 132, 1, 130)),
+            ]
+        );
+        assert_eq!(
+            lints("let _y = match (2) { n => 1 };"),
+            vec![Lint::UnusedVariable {
+                name: "n".into(),
+                span: Span::new(21, 22, 1, 22),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_shadowing() {
+        assert_eq!(
+            lints("let x = 1; fn f(x) { { let x = x; print(x); } } f(x);"),
+            vec![
+                Lint::Shadowed {
+                    name: "x".into(),
+                    span: Span::new(16, 17, 1, 17),
+                    previous: Span::new(4, 5, 1, 5),
+                },
+                Lint::Shadowed {
+                    name: "x".into(),
+                    span: Span::new(27, 28, 1, 28),
+                    previous: Span::new(16, 17, 1, 17),
+                },
+            ]
+        );
+        // Named function expressions shadow too, but not anonymous ones.
+        assert_eq!(
+            lints("fn g() {} let h = fn g() {}; let k = fn() {}; print(h); print(k);"),
+            vec![Lint::Shadowed {
+                name: "g".into(),
+                span: Span::new(21, 22, 1, 22),
+                previous: Span::new(3, 4, 1, 4),
+            }]
+        );
+    }
+}
@@ -0,0 +1,971 @@
+//! A language server: JSON-RPC over stdin and stdout, as the Language Server
+//! Protocol frames it. Documents are synced whole; every change republishes
+//! the diagnostics of the front end and its lints, and the server answers
+//! go-to-definition, document-symbol and semantic-token requests.
+//!
+//! A document's imports are linked from the path of its `file:` URI. An
+//! imported file that is open is read from its editor, and any other from
+//! disk.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use crate::ast::{Expr, ExprKind, NodeId, PatternKind, Stmt, StmtKind};
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::error::CompileError;
+use crate::highlight::{self, Class, Highlight};
+use crate::json::Json;
+use crate::lexer::Lexer;
+use crate::lints::{self, Lint};
+use crate::parser::Parser;
+use crate::source::{SourceFile, SourceManager};
+use crate::span::{FileId, Span};
+use crate::{sema, types};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_PARAMS: i64 = -32602;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+const SYMBOL_FUNCTION: i64 = 12;
+const SYMBOL_VARIABLE: i64 = 13;
+const SYMBOL_CONSTANT: i64 = 14;
+const SYMBOL_STRUCT: i64 = 23;
+
+/// Serves requests from `input` until the client sends `exit` or closes it.
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut server = Server::default();
+    while let Some(body) = read_message(&mut input)? {
+        let replies = match Json::parse(&body) {
+            Ok(message) => server.handle(&message),
+            Err(err) => vec![error_response(Json::Null, PARSE_ERROR, err.to_string())],
+        };
+        for reply in replies {
+            write_message(&mut output, &reply)?;
+        }
+        if server.exited {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads one message body, or `None` at the end of the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message without a Content-Length header",
+        ));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn response(id: Json, result: Json) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::Str("2.0".into())),
+        ("id", id),
+        ("result", result),
+    ])
+}
+
+fn error_response(id: Json, code: i64, message: String) -> Json {
+    let error = Json::object(vec![
+        ("code", Json::Int(code)),
+        ("message", Json::Str(message)),
+    ]);
+    Json::object(vec![
+        ("jsonrpc", Json::Str("2.0".into())),
+        ("id", id),
+        ("error", error),
+    ])
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::Str("2.0".into())),
+        ("method", Json::Str(method.into())),
+        ("params", params),
+    ])
+}
+
+#[derive(Default)]
+struct Server {
+    /// Every document opened so far, and every file they import, named by
+    /// their paths.
+    sources: SourceManager,
+    /// The open documents, by URI.
+    documents: HashMap<String, FileId>,
+    /// The imported files each document last published diagnostics for, so
+    /// that they are cleared once fixed.
+    imported: HashMap<FileId, Vec<FileId>>,
+    exited: bool,
+}
+
+impl Server {
+    /// Handles one request or notification, returning the messages to send.
+    fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let id = message.get("id").cloned();
+
+        let result = match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => Ok(Json::Null),
+            "exit" => {
+                self.exited = true;
+                return vec![];
+            }
+            "textDocument/didOpen" => {
+                let document = params.get("textDocument");
+                let uri = document
+                    .and_then(|doc| doc.get("uri"))
+                    .and_then(Json::as_str);
+                let text = document
+                    .and_then(|doc| doc.get("text"))
+                    .and_then(Json::as_str);
+                return match (uri, text) {
+                    (Some(uri), Some(text)) => self.update(uri, text.to_string()),
+                    _ => vec![],
+                };
+            }
+            "textDocument/didChange" => {
+                // Full sync: the last change holds the whole new text.
+                let uri = document_uri(params);
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Json::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Json::as_str);
+                return match (uri, text) {
+                    (Some(uri), Some(text)) => self.update(uri, text.to_string()),
+                    _ => vec![],
+                };
+            }
+            "textDocument/didClose" => {
+                let Some(uri) = document_uri(params) else {
+                    return vec![];
+                };
+                return match self.documents.remove(uri) {
+                    Some(file) => vec![publish_diagnostics(uri, &self.sources, file, &[])],
+                    None => vec![],
+                };
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/semanticTokens/full" => self.semantic_tokens(params),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method `{}`", method))),
+        };
+
+        // Notifications get no reply, not even an error.
+        let Some(id) = id else {
+            return vec![];
+        };
+        vec![match result {
+            Ok(result) => response(id, result),
+            Err((code, message)) => error_response(id, code, message),
+        }]
+    }
+
+    fn update(&mut self, uri: &str, text: String) -> Vec<Json> {
+        let path = path(uri);
+        let file = match self.sources.find(&path) {
+            Some(file) => {
+                self.sources.set_text(file, text);
+                file
+            }
+            None => self.sources.add(path, text),
+        };
+        self.documents.insert(uri.to_string(), file);
+
+        let diagnostics = match check(&mut self.sources, file) {
+            Ok(lints) => lints.iter().map(Diagnostic::from).collect(),
+            Err(err) => err.diagnostics(),
+        };
+
+        // Each diagnostic goes to the file it is in, which may be one this
+        // document imports.
+        let mut files: Vec<(FileId, Vec<Diagnostic>)> = vec![(file, vec![])];
+        for diag in diagnostics {
+            let in_file = diag.span.map_or(file, |span| span.file);
+            match files.iter_mut().find(|(other, _)| *other == in_file) {
+                Some((_, diagnostics)) => diagnostics.push(diag),
+                None => files.push((in_file, vec![diag])),
+            }
+        }
+        let imported: Vec<FileId> = files[1..].iter().map(|&(file, _)| file).collect();
+        for stale in self.imported.insert(file, imported).unwrap_or_default() {
+            if !files.iter().any(|&(other, _)| other == stale) {
+                files.push((stale, vec![]));
+            }
+        }
+
+        files
+            .iter()
+            .map(|(file, diagnostics)| {
+                publish_diagnostics(&self.uri(*file), &self.sources, *file, diagnostics)
+            })
+            .collect()
+    }
+
+    /// The URI of `file`: that it was opened under, or else one for its path.
+    fn uri(&self, file: FileId) -> String {
+        match self.documents.iter().find(|&(_, &other)| other == file) {
+            Some((uri, _)) => uri.clone(),
+            None => file_uri(&self.sources.file(file).path.display().to_string()),
+        }
+    }
+
+    fn document(&self, params: &Json) -> Result<(&str, FileId), (i64, String)> {
+        let uri = document_uri(params).ok_or((INVALID_PARAMS, "missing `textDocument`".into()))?;
+        match self.documents.get_key_value(uri) {
+            Some((uri, &file)) => Ok((uri, file)),
+            None => Err((INVALID_PARAMS, format!("`{}` is not open", uri))),
+        }
+    }
+
+    /// Finds where the name under the cursor is declared. Answers `null`
+    /// when there is no name there that resolves, or the document does not
+    /// parse.
+    fn definition(&mut self, params: &Json) -> Result<Json, (i64, String)> {
+        let (uri, file) = self.document(params)?;
+        let source = self.sources.file(file);
+        let position = params
+            .get("position")
+            .ok_or((INVALID_PARAMS, "missing `position`".into()))?;
+        let [line, character] = ["line", "character"].map(|field| {
+            let value = position.get(field).and_then(Json::as_i64);
+            value.and_then(|value| usize::try_from(value).ok())
+        });
+        let lines = source.line_column(source.text.len()).0;
+        let (Some(line), Some(character)) = (line.filter(|&line| line < lines), character) else {
+            return Err((INVALID_PARAMS, format!("`position` is not in `{}`", uri)));
+        };
+        let offset = offset(source, line, character);
+
+        let Ok(program) = self.sources.link(file) else {
+            return Ok(Json::Null);
+        };
+        let (resolution, _) = sema::analyze_partial(&program);
+
+        let mut found = Vec::new();
+        names(&program.stmts, &mut found);
+        let decl = found
+            .into_iter()
+            .find(|(_, span)| span.file == file && span.start <= offset && offset <= span.end)
+            .and_then(|(id, _)| match resolution.resolve(id) {
+                Some((_, decl)) => Some(decl),
+                None => resolution.decls.get(&id),
+            });
+
+        Ok(decl.map_or(Json::Null, |decl| {
+            Json::object(vec![
+                ("uri", Json::Str(self.uri(decl.span.file))),
+                ("range", range(self.sources.file(decl.span.file), decl.span)),
+            ])
+        }))
+    }
+
+    fn document_symbols(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let (_, file) = self.document(params)?;
+        let source = self.sources.file(file);
+        // The statements that parsed still have symbols while others have
+        // errors.
+        let lexer = Lexer::new(&source.text).with_file(file);
+        let (program, _) = Parser::new(lexer).parse_partial();
+
+        Ok(Json::Array(symbols(source, &program.stmts)))
+    }
+
+    fn semantic_tokens(&self, params: &Json) -> Result<Json, (i64, String)> {
+        let (_, file) = self.document(params)?;
+        let source = self.sources.file(file);
+        let highlights = highlight::highlight(&source.text);
+        Ok(Json::object(vec![(
+            "data",
+            Json::Array(semantic_tokens(source, &highlights)),
+        )]))
+    }
+}
+
+fn document_uri(params: &Json) -> Option<&str> {
+    params.get("textDocument")?.get("uri")?.as_str()
+}
+
+fn capabilities() -> Json {
+    let capabilities = Json::object(vec![
+        // Full document sync.
+        ("textDocumentSync", Json::Int(1)),
+        ("definitionProvider", Json::Bool(true)),
+        ("documentSymbolProvider", Json::Bool(true)),
+        (
+            "semanticTokensProvider",
+            Json::object(vec![("legend", legend()), ("full", Json::Bool(true))]),
+        ),
+    ]);
+    let info = Json::object(vec![
+        ("name", Json::Str("compyl".into())),
+        ("version", Json::Str(env!("CARGO_PKG_VERSION").into())),
+    ]);
+
+    Json::object(vec![("capabilities", capabilities), ("serverInfo", info)])
+}
+
+/// The semantic token types, indexed by [`Class::index`], and no modifiers.
+fn legend() -> Json {
+    let types = Class::ALL
+        .iter()
+        .map(|class| Json::Str(class.name().into()))
+        .collect();
+    Json::object(vec![
+        ("tokenTypes", Json::Array(types)),
+        ("tokenModifiers", Json::Array(vec![])),
+    ])
+}
+
+/// Encodes highlights as the protocol does, five integers to a token: its
+/// line and start relative to the token before, its length, its type and its
+/// modifiers. A token spanning lines is split at each, as clients need not
+/// support multiline tokens.
+fn semantic_tokens(source: &SourceFile, highlights: &[Highlight]) -> Vec<Json> {
+    let mut data = Vec::new();
+    let (mut prev_line, mut prev_start) = (0, 0);
+    for highlight in highlights {
+        let mut start = highlight.span.start;
+        for line in source.text[start..highlight.span.end].split_inclusive('\n') {
+            let text = line.strip_suffix('\n').unwrap_or(line);
+            let (line_index, character) = utf16_position(source, start);
+            start += line.len();
+            if text.is_empty() {
+                continue;
+            }
+            let delta_start = match line_index == prev_line {
+                true => character - prev_start,
+                false => character,
+            };
+            let length: usize = text.chars().map(char::len_utf16).sum();
+            for value in [
+                line_index - prev_line,
+                delta_start,
+                length,
+                highlight.class.index(),
+                0,
+            ] {
+                data.push(Json::Int(value as i64));
+            }
+            (prev_line, prev_start) = (line_index, character);
+        }
+    }
+    data
+}
+
+/// The path of a `file:` URI, or else the URI itself, as a document that is
+/// not saved has no path.
+fn path(uri: &str) -> PathBuf {
+    let Some(path) = uri.strip_prefix("file://") else {
+        return PathBuf::from(uri);
+    };
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, escaped) {
+            (b'%', Some(escaped)) => {
+                bytes.push(escaped);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// The `file:` URI of `path`, escaping the bytes a URI path cannot hold.
+fn file_uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// Links `file` and its imports, runs the front end over them, up to and
+/// including type checking, and returns their lints.
+fn check(sources: &mut SourceManager, file: FileId) -> Result<Vec<Lint>, CompileError> {
+    let program = sources.link(file)?;
+    let resolution = sema::analyze(&program)?;
+    types::check(&program, &resolution)?;
+    Ok(lints::check(&program, &resolution))
+}
+
+fn publish_diagnostics(
+    uri: &str,
+    sources: &SourceManager,
+    file: FileId,
+    diagnostics: &[Diagnostic],
+) -> Json {
+    let source = sources.file(file);
+    let diagnostics = diagnostics
+        .iter()
+        .map(|diag| {
+            let severity = match diag.severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+                Severity::Note => 3,
+            };
+            let mut message = diag.message.clone();
+            for note in &diag.notes {
+                message.push_str("\nnote: ");
+                message.push_str(&note.render(|id| sources.file(id).path.display().to_string()));
+            }
+            let span = diag.span.unwrap_or_default();
+
+            Json::object(vec![
+                ("range", range(source, span)),
+                ("severity", Json::Int(severity)),
+                ("code", Json::Str(diag.code.into())),
+                ("source", Json::Str("compyl".into())),
+                ("message", Json::Str(message)),
+            ])
+        })
+        .collect();
+
+    notification(
+        "textDocument/publishDiagnostics",
+        Json::object(vec![
+            ("uri", Json::Str(uri.into())),
+            ("diagnostics", Json::Array(diagnostics)),
+        ]),
+    )
+}
+
+/// The protocol's position of a byte offset: a zero-based line, and a column
+/// counted in UTF-16 code units.
+fn position(source: &SourceFile, offset: usize) -> Json {
+    let (line, character) = utf16_position(source, offset);
+    Json::object(vec![
+        ("line", Json::Int(line as i64)),
+        ("character", Json::Int(character as i64)),
+    ])
+}
+
+fn utf16_position(source: &SourceFile, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.text.len());
+    let (line, _) = source.line_column(offset);
+    let before = &source.text[source.line_start(line)..offset];
+    (line - 1, before.chars().map(char::len_utf16).sum())
+}
+
+fn range(source: &SourceFile, span: Span) -> Json {
+    Json::object(vec![
+        ("start", position(source, span.start)),
+        ("end", position(source, span.end)),
+    ])
+}
+
+/// The byte offset of a protocol position, clamped to its line.
+fn offset(source: &SourceFile, line: usize, character: usize) -> usize {
+    let start = source.line_start(line + 1);
+    let mut units = 0;
+    for (index, ch) in source.text[start..].char_indices() {
+        if units >= character || ch == '\n' {
+            return start + index;
+        }
+        units += ch.len_utf16();
+    }
+    source.text.len()
+}
+
+/// Collects every name in `stmts`, declared or used, with its span.
+fn names(stmts: &[Stmt], out: &mut Vec<(NodeId, Span)>) {
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::Let { name, value, .. } | StmtKind::Const { name, value, .. } => {
+                out.push((name.id, name.span));
+                expr_names(value, out);
+            }
+            StmtKind::Assign { target, value, .. } => {
+                out.push((target.id, target.span));
+                expr_names(value, out);
+            }
+            StmtKind::IndexAssign {
+                array,
+                index,
+                value,
+                ..
+            } => {
+                expr_names(array, out);
+                expr_names(index, out);
+                expr_names(value, out);
+            }
+            StmtKind::FieldAssign { object, value, .. } => {
+                expr_names(object, out);
+                expr_names(value, out);
+            }
+            StmtKind::Struct(decl) => out.push((decl.name.id, decl.name.span)),
+            StmtKind::Fn(decl) | StmtKind::Extern(decl) => {
+                out.push((decl.name.id, decl.name.span));
+                for param in &decl.params {
+                    out.push((param.name.id, param.name.span));
+                }
+                names(&decl.body, out);
+            }
+            StmtKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                expr_names(cond, out);
+                names(then_branch, out);
+                if let Some(else_branch) = else_branch {
+                    names(else_branch, out);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                expr_names(cond, out);
+                names(body, out);
+            }
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                expr_names(start, out);
+                expr_names(end, out);
+                out.push((var.id, var.span));
+                names(body, out);
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    expr_names(value, out);
+                }
+            }
+            StmtKind::Break | StmtKind::Continue | StmtKind::Import(_) | StmtKind::Error => {}
+            StmtKind::Block(block) => names(block, out),
+            StmtKind::Expr(expr) => expr_names(expr, out),
+        }
+    }
+}
+
+fn expr_names(expr: &Expr, out: &mut Vec<(NodeId, Span)>) {
+    match &expr.kind {
+        ExprKind::Int(_)
+        | ExprKind::Float(_)
+        | ExprKind::Char(_)
+        | ExprKind::Str(_)
+        | ExprKind::Bool(_) => {}
+        ExprKind::Ident(_) => out.push((expr.id, expr.span)),
+        ExprKind::Unary { expr, .. } | ExprKind::Interpolation(expr) => expr_names(expr, out),
+        ExprKind::Binary { lhs, rhs, .. } => {
+            expr_names(lhs, out);
+            expr_names(rhs, out);
+        }
+        ExprKind::Call { callee, args } => {
+            expr_names(callee, out);
+            for arg in args {
+                expr_names(arg, out);
+            }
+        }
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            expr_names(cond, out);
+            expr_names(then_branch, out);
+            expr_names(else_branch, out);
+        }
+        ExprKind::Match { scrutinee, arms } => {
+            expr_names(scrutinee, out);
+            for arm in arms {
+                if let PatternKind::Binding(name) = &arm.pattern.kind {
+                    out.push((name.id, name.span));
+                }
+                expr_names(&arm.body, out);
+            }
+        }
+        ExprKind::Fn(decl) => {
+            if !decl.is_anonymous() {
+                out.push((decl.name.id, decl.name.span));
+            }
+            for param in &decl.params {
+                out.push((param.name.id, param.name.span));
+            }
+            names(&decl.body, out);
+        }
+        ExprKind::Array(elems) => {
+            for elem in elems {
+                expr_names(elem, out);
+            }
+        }
+        ExprKind::Index { expr, index } => {
+            expr_names(expr, out);
+            expr_names(index, out);
+        }
+        ExprKind::Struct { name, fields } => {
+            out.push((name.id, name.span));
+            for field in fields {
+                expr_names(&field.value, out);
+            }
+        }
+        ExprKind::Field { expr, .. } => expr_names(expr, out),
+    }
+}
+
+/// The declarations directly in `stmts`, with those of each function body
+/// as its children.
+fn symbols(source: &SourceFile, stmts: &[Stmt]) -> Vec<Json> {
+    let symbol = |name: &str, kind, span, selection, children| {
+        Json::object(vec![
+            ("name", Json::Str(name.into())),
+            ("kind", Json::Int(kind)),
+            ("range", range(source, span)),
+            ("selectionRange", range(source, selection)),
+            ("children", Json::Array(children)),
+        ])
+    };
+
+    stmts
+        .iter()
+        .filter_map(|stmt| match &stmt.kind {
+            StmtKind::Let { name, .. } => Some(symbol(
+                name.name.as_str(),
+                SYMBOL_VARIABLE,
+                stmt.span,
+                name.span,
+                vec![],
+            )),
+            StmtKind::Const { name, .. } => Some(symbol(
+                name.name.as_str(),
+                SYMBOL_CONSTANT,
+                stmt.span,
+                name.span,
+                vec![],
+            )),
+            StmtKind::Fn(decl) | StmtKind::Extern(decl) => Some(symbol(
+                decl.name.name.as_str(),
+                SYMBOL_FUNCTION,
+                stmt.span,
+                decl.name.span,
+                symbols(source, &decl.body),
+            )),
+            StmtKind::Struct(decl) => Some(symbol(
+                decl.name.name.as_str(),
+                SYMBOL_STRUCT,
+                stmt.span,
+                decl.name.span,
+                vec![],
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{file_uri, offset, path, run, semantic_tokens};
+    use crate::highlight::highlight;
+    use crate::json::Json;
+    use crate::source::SourceFile;
+
+    /// Frames `messages`, runs the server over them and parses its replies.
+    fn serve(messages: &[&str]) -> Vec<Json> {
+        let input: String = messages
+            .iter()
+            .map(|body| format!("Content-Length: {}\r\n\r\n{}", body.len(), body))
+            .collect();
+        let mut output = Vec::new();
+        run(input.as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        output
+            .split("Content-Length: ")
+            .skip(1)
+            .map(|frame| Json::parse(frame.split_once("\r\n\r\n").unwrap().1).unwrap())
+            .collect()
+    }
+
+    fn open(text: &str) -> String {
+        open_at("file:///a.cpl", text)
+    }
+
+    fn open_at(uri: &str, text: &str) -> String {
+        let params = Json::object(vec![(
+            "textDocument",
+            Json::object(vec![
+                ("uri", Json::Str(uri.into())),
+                ("text", Json::Str(text.into())),
+            ]),
+        )]);
+        format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{}}}"#,
+            params
+        )
+    }
+
+    #[test]
+    fn test_lifecycle_and_diagnostics() {
+        let replies = serve(&[
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+            &open("let s = \"é\";\nlet b = s + c;"),
+            r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#,
+        ]);
+
+        assert_eq!(replies.len(), 3);
+        let capabilities = replies[0].get("result").unwrap().get("capabilities");
+        assert_eq!(
+            capabilities.unwrap().get("definitionProvider"),
+            Some(&Json::Bool(true))
+        );
+        assert_eq!(
+            replies[1].to_string(),
+            concat!(
+                r#"{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.cpl","#,
+                r#""diagnostics":[{"range":{"start":{"line":1,"character":12},"end":{"line":1,"character":13}},"#,
+                r#""severity":1,"code":"E0200","source":"compyl","message":"cannot find `c` in this scope"}]}}"#,
+            )
+        );
+        assert_eq!(replies[2].get("result"), Some(&Json::Null));
+    }
+
+    #[test]
+    fn test_definition_and_symbols() {
+        let source = "fn add(a, b) {\n    let sum = a + b;\n    return sum;\n}\nlet x = add(1, 2);\nprint(x);";
+        let replies = serve(&[
+            &open(source),
+            r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.cpl"},"position":{"line":4,"character":9}}}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.cpl"},"position":{"line":2,"character":4}}}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"textDocument/documentSymbol","params":{"textDocument":{"uri":"file:///a.cpl"}}}"#,
+            r#"{"jsonrpc":"2.0","id":4,"method":"textDocument/hover","params":{}}"#,
+            r#"{"jsonrpc":"2.0","id":5,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.cpl"},"position":{"line":-1,"character":0}}}"#,
+            r#"{"jsonrpc":"2.0","id":6,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.cpl"},"position":{"line":0,"character":-1}}}"#,
+            r#"{"jsonrpc":"2.0","id":7,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.cpl"},"position":{"line":6,"character":0}}}"#,
+            r#"{"jsonrpc":"2.0","id":8,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.cpl"},"position":{"line":4,"character":99}}}"#,
+        ]);
+
+        assert_eq!(
+            replies[0]
+                .get("params")
+                .unwrap()
+                .get("diagnostics")
+                .unwrap(),
+            &Json::Array(vec![])
+        );
+        assert_eq!(
+            replies[1].get("result").unwrap().to_string(),
+            r#"{"uri":"file:///a.cpl","range":{"start":{"line":0,"character":3},"end":{"line":0,"character":6}}}"#
+        );
+        assert_eq!(replies[2].get("result"), Some(&Json::Null));
+
+        let symbols = replies[3].get("result").and_then(Json::as_array).unwrap();
+        let names: Vec<_> = symbols
+            .iter()
+            .map(|symbol| symbol.get("name").and_then(Json::as_str).unwrap())
+            .collect();
+        assert_eq!(names, ["add", "x"]);
+        let children = symbols[0].get("children").and_then(Json::as_array).unwrap();
+        assert_eq!(children[0].get("name").and_then(Json::as_str), Some("sum"));
+
+        let error = replies[4].get("error").unwrap();
+        assert_eq!(error.get("code").and_then(Json::as_i64), Some(-32601));
+        for reply in &replies[5..8] {
+            let error = reply.get("error").unwrap();
+            assert_eq!(error.get("code").and_then(Json::as_i64), Some(-32602));
+        }
+        // A character past the end of its line is at the end.
+        assert_eq!(replies[8].get("result"), Some(&Json::Null));
+
+        // Names resolve in a document with errors.
+        let replies = serve(&[
+            &open(&format!("{}\nprint(nope);", source)),
+            r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.cpl"},"position":{"line":5,"character":6}}}"#,
+        ]);
+        assert_eq!(
+            replies[1].get("result").unwrap().to_string(),
+            r#"{"uri":"file:///a.cpl","range":{"start":{"line":4,"character":4},"end":{"line":4,"character":5}}}"#
+        );
+    }
+
+    #[test]
+    fn test_imports() {
+        let file = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/imports.cpl");
+        let uri = file_uri(file);
+        let text = std::fs::read_to_string(file).unwrap();
+        let definition = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"textDocument/definition","params":{{"textDocument":{{"uri":"{}"}},"position":{{"line":2,"character":9}}}}}}"#,
+            uri
+        );
+        let replies = serve(&[&open_at(&uri, &text), &definition]);
+
+        assert_eq!(replies.len(), 2);
+        assert_eq!(
+            replies[0].get("params").unwrap().to_string(),
+            format!(r#"{{"uri":"{}","diagnostics":[]}}"#, uri)
+        );
+        let result = replies[1].get("result").unwrap();
+        assert_eq!(
+            result.get("uri").and_then(Json::as_str),
+            Some(
+                file_uri(concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/testdata/modules/math.cpl"
+                ))
+                .as_str()
+            )
+        );
+        assert_eq!(
+            result.get("range").unwrap().to_string(),
+            r#"{"start":{"line":0,"character":3},"end":{"line":0,"character":9}}"#
+        );
+
+        // An error in an open import is published to it, and cleared once
+        // fixed.
+        let replies = serve(&[
+            &open_at("file:///lib%20dir/lib.cpl", "fn f(): int { return nope; }"),
+            &open_at("file:///lib%20dir/main.cpl", "import \"lib.cpl\";\nf();"),
+            &open_at("file:///lib%20dir/lib.cpl", "fn f(): int { return 1; }"),
+            &open_at("file:///lib%20dir/main.cpl", "import \"lib.cpl\";\nf();"),
+        ]);
+        let published: Vec<_> = replies
+            .iter()
+            .map(|reply| {
+                let params = reply.get("params").unwrap();
+                let diagnostics = params.get("diagnostics").and_then(Json::as_array);
+                (
+                    params.get("uri").and_then(Json::as_str).unwrap(),
+                    diagnostics.unwrap().len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            published,
+            [
+                ("file:///lib%20dir/lib.cpl", 1),
+                ("file:///lib%20dir/main.cpl", 0),
+                ("file:///lib%20dir/lib.cpl", 1),
+                ("file:///lib%20dir/lib.cpl", 0),
+                ("file:///lib%20dir/main.cpl", 0),
+                ("file:///lib%20dir/lib.cpl", 0),
+            ]
+        );
+        assert_eq!(
+            path("file:///lib%20dir/lib.cpl").to_str(),
+            Some("/lib dir/lib.cpl")
+        );
+        assert_eq!(path("untitled:1").to_str(), Some("untitled:1"));
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let replies = serve(&[
+            &open("let = 1;\nfn f() {}\nlet x 2;"),
+            r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/documentSymbol","params":{"textDocument":{"uri":"file:///a.cpl"}}}"#,
+        ]);
+
+        let diagnostics = replies[0].get("params").unwrap().get("diagnostics");
+        let lines: Vec<_> = diagnostics
+            .and_then(Json::as_array)
+            .unwrap()
+            .iter()
+            .map(|diag| {
+                let start = diag.get("range").unwrap().get("start").unwrap();
+                start.get("line").and_then(Json::as_i64).unwrap()
+            })
+            .collect();
+        assert_eq!(lines, [0, 2]);
+
+        let symbols = replies[1].get("result").and_then(Json::as_array).unwrap();
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols[0].get("name").and_then(Json::as_str), Some("f"));
+    }
+
+    #[test]
+    fn test_semantic_tokens() {
+        let replies = serve(&[
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            &open("fn f(x) {}"),
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/semanticTokens/full","params":{"textDocument":{"uri":"file:///a.cpl"}}}"#,
+        ]);
+
+        let capabilities = replies[0].get("result").unwrap().get("capabilities");
+        let provider = capabilities.unwrap().get("semanticTokensProvider").unwrap();
+        assert_eq!(
+            provider
+                .get("legend")
+                .unwrap()
+                .get("tokenTypes")
+                .unwrap()
+                .to_string(),
+            r#"["keyword","function","parameter","variable","type","property","number","string","comment","operator"]"#
+        );
+        assert_eq!(
+            replies[2].get("result").unwrap().to_string(),
+            r#"{"data":[0,0,2,0,0,0,3,1,1,0,0,2,1,2,0]}"#
+        );
+
+        // Tokens are split at line ends, and columns count UTF-16 units.
+        let source = SourceFile::new("a.cpl", "?* 😀\n*? let\n  x");
+        let data: Vec<_> = semantic_tokens(&source, &highlight(&source.text))
+            .iter()
+            .map(|value| value.as_i64().unwrap())
+            .collect();
+        assert_eq!(
+            data,
+            [0, 0, 5, 8, 0, 1, 0, 2, 8, 0, 0, 3, 3, 0, 0, 1, 2, 1, 3, 0]
+        );
+    }
+
+    #[test]
+    fn test_utf16_offsets() {
+        let source = SourceFile::new("a.cpl", "a😀b\nxy");
+
+        assert_eq!(offset(&source, 0, 3), "a😀".len());
+        assert_eq!(offset(&source, 0, 9), "a😀b".len());
+        assert_eq!(offset(&source, 1, 1), "a😀b\nx".len());
+    }
+}
@@ -1,16 +1,438 @@
-use std::env;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, fs, process, thread};
 
-use crate::lexer::Lexer;
+use compyl::ast::Program;
+use compyl::codegen::{llvm, wasm, x86_64};
+use compyl::diagnostics::{Diagnostic, Severity};
+use compyl::error::CompileError;
+use compyl::highlight;
+use compyl::interp::Interpreter;
+use compyl::json::ToJson;
+use compyl::lexer::{self, Lexer, LexerConfig};
+use compyl::opt::PassManager;
+use compyl::source::SourceManager;
+use compyl::span::FileId;
+use compyl::trace::Log;
+use compyl::vm::Vm;
+use compyl::{batch, bytecode, cfg, doc, fmt, ir, lints, lsp, repl, sema, types, watch};
 
-mod lexer;
+use crate::cli::{Backend, CliError, Command, Emit, Format, Options};
+
+mod cli;
+
+const EXIT_COMPILE_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_RUNTIME_ERROR: i32 = 3;
+
+/// The stack the compiler runs on. The parser's limits and the call depth
+/// limit are what bound recursion, and they fit in this even in debug
+/// builds, whose frames are much larger.
+const STACK_SIZE: usize = 64 << 20;
+
+/// How many calls programs may nest. [`STACK_SIZE`] has room for more than
+/// the library's default.
+const MAX_CALL_DEPTH: usize = 1000;
+
+/// How often `--watch` looks for changed files.
+const WATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+fn show(options: &Options, emit: Emit) -> bool {
+    !options.quiet && options.emit.contains(&emit)
+}
+
+fn execute(
+    options: &Options,
+    sources: &mut SourceManager,
+    root: FileId,
+    log: &mut Log,
+) -> Result<(), CompileError> {
+    lex(options, &sources.file(root).text)?;
+    if options.command == Command::Lex {
+        return Ok(());
+    }
+
+    if log.enabled() {
+        let text = &sources.file(root).text;
+        let lexed = log.time("lex", || Lexer::new(text).take_while(Result::is_ok).count());
+        log.note(format!("{} tokens", lexed));
+    }
+    let program = log.time("parse", || sources.link(root))?;
+    log.note(format!(
+        "{} statements in {} files",
+        program.stmts.len(),
+        sources.files().len()
+    ));
+    compile(options, sources, &program, log)
+}
+
+fn lex(options: &Options, source: &str) -> Result<(), CompileError> {
+    let show = |emit| show(options, emit);
+    // Printed tokens include comments, which the parser never sees.
+    let tokens = || {
+        let config = LexerConfig {
+            keep_comments: true,
+        };
+        Lexer::with_config(source, config)
+    };
+
+    if show(Emit::Tokens) && options.format == Format::Json {
+        let tokens = tokens().collect::<Result<Vec<_>, _>>()?;
+        println!("{}", tokens.to_json());
+    } else if show(Emit::Tokens) {
+        for token in tokens() {
+            let token = token?;
+            println!(
+                "{}:{}\t{:?}",
+                token.span.line, token.span.column, token.token
+            );
+        }
+    }
+    if options.command == Command::Lex {
+        // Still surface lexer errors when printing was suppressed.
+        return Lexer::new(source)
+            .try_for_each(|token| token.map(|_| ()))
+            .map_err(CompileError::from);
+    }
+
+    Ok(())
+}
+
+/// Everything after linking: printing the tree, checking it, and running it.
+fn compile(
+    options: &Options,
+    sources: &SourceManager,
+    program: &Program,
+    log: &mut Log,
+) -> Result<(), CompileError> {
+    let show = |emit| show(options, emit);
+    if show(Emit::Ast) {
+        match options.format {
+            Format::Text | Format::Html => print!("{}", program),
+            Format::Json => println!("{}", program.to_json()),
+        }
+    }
+
+    if options.command == Command::Parse {
+        return Ok(());
+    }
+    if options.command == Command::Doc {
+        let root = sources.file(FileId(0)).path.display().to_string();
+        match options.format {
+            Format::Html => print!("{}", doc::html(program, &root)),
+            Format::Text | Format::Json => print!("{}", doc::markdown(program, &root)),
+        }
+        return Ok(());
+    }
+
+    let resolution = log.time("sema", || sema::analyze(program))?;
+    log.note(format!(
+        "{} declarations, {} uses",
+        resolution.decls.len(),
+        resolution.uses.len()
+    ));
+    let types = log.time("types", || types::check(program, &resolution))?;
+    log.note(format!("{} expressions", types.exprs.len()));
+    let lints = log.time("lints", || lints::check(program, &resolution));
+    log.note(format!("{} warnings", lints.len()));
+    if options.deny_warnings && !lints.is_empty() {
+        return Err(CompileError::Lints(lints));
+    }
+    for lint in &lints {
+        sources.emit(&lint.into());
+    }
+
+    if show(Emit::Ir) || show(Emit::Cfg) || show(Emit::Asm) || options.dump_after.is_some() {
+        let mut module = log.time("lower", || ir::lower(program, &resolution, &types))?;
+        log.note(format!(
+            "{} functions, {} instructions",
+            module.functions.len(),
+            instructions(&module)
+        ));
+        optimize(options, &mut module, log);
+        if show(Emit::Ir) {
+            print!("{}", module);
+        }
+        if show(Emit::Cfg) {
+            print!("{}", cfg::dot(&module));
+        }
+        if show(Emit::Asm) {
+            let asm = log.time("x86-64", || x86_64::emit(&module));
+            log.note(format!("{} lines", asm.lines().count()));
+            print!("{}", asm);
+        }
+    }
+    if show(Emit::LlvmIr) {
+        let llvm = log.time("llvm", || llvm::emit(program, &resolution, &types))?;
+        log.note(format!("{} lines", llvm.lines().count()));
+        print!("{}", llvm);
+    }
+    if show(Emit::Wat) {
+        let wat = log.time("wasm", || wasm::emit(program, &resolution, &types))?;
+        log.note(format!("{} lines", wat.lines().count()));
+        print!("{}", wat);
+    }
+
+    if options.command == Command::Run {
+        match options.backend {
+            Backend::Interp => {
+                let mut interp = Interpreter::new().with_max_call_depth(MAX_CALL_DEPTH);
+                log.time("run", || interp.run(program)).map_err(|err| {
+                    CompileError::Runtime(err, Box::new(interp.backtrace().clone()))
+                })?;
+            }
+            Backend::Vm => {
+                let module = log.time("bytecode", || bytecode::compile(program, &resolution));
+                let ops: usize = module.functions.iter().map(|func| func.code.len()).sum();
+                log.note(format!("{} functions, {} ops", module.functions.len(), ops));
+                let mut vm = Vm::new().with_max_call_depth(MAX_CALL_DEPTH);
+                log.time("run", || vm.run(&module))
+                    .map_err(|err| CompileError::Runtime(err, Box::new(vm.backtrace().clone())))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn instructions(module: &ir::Module) -> usize {
+    module
+        .functions
+        .iter()
+        .flat_map(|func| &func.blocks)
+        .map(|block| block.insts.len())
+        .sum()
+}
+
+/// Runs the optimization passes, printing each function a pass changes if
+/// `-Z dump-after` names it, and noting how often each did.
+fn optimize(options: &Options, module: &mut ir::Module, log: &mut Log) {
+    let passes = PassManager::for_level(options.opt_level);
+    let mut changes: Vec<(&str, usize)> = passes.passes().map(|pass| (pass, 0)).collect();
+    log.time("opt", || {
+        passes.run_with(module, |pass, module, func| {
+            if let Some((_, count)) = changes.iter_mut().find(|(name, _)| *name == pass) {
+                *count += 1;
+            }
+            if options.dump_after.as_deref() == Some(pass) {
+                eprint!("; after {}\n{}", pass, module.display_function(func));
+            }
+        })
+    });
+    let changed: Vec<String> = changes
+        .iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(pass, count)| format!("{} {}", pass, count))
+        .collect();
+    log.note(format!(
+        "{} instructions; changes: {}",
+        instructions(module),
+        match changed.is_empty() {
+            true => "none".to_string(),
+            false => changed.join(", "),
+        }
+    ));
+}
+
+fn format(options: &Options, filename: &str, source: &str) -> Result<(), CompileError> {
+    let formatted = fmt::format(source)?;
+    if !options.check {
+        print!("{}", formatted);
+    } else if formatted != source {
+        eprintln!("`{}` is not formatted", filename);
+        process::exit(EXIT_COMPILE_ERROR);
+    }
+
+    Ok(())
+}
+
+fn highlight(options: &Options, source: &str) {
+    let highlights = highlight::highlight(source);
+    match options.format {
+        Format::Json => println!("{}", highlights.to_json()),
+        Format::Text | Format::Html => {
+            for highlight in highlights {
+                let span = highlight.span;
+                println!(
+                    "{}:{}\t{}\t{:?}",
+                    span.line,
+                    span.column,
+                    highlight.class.name(),
+                    &source[span.start..span.end]
+                );
+            }
+        }
+    }
+}
+
+/// The files to check: each input, or the `.cpl` files in it if it is a
+/// directory.
+fn input_paths(options: &Options) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for input in &options.inputs {
+        let path = Path::new(input);
+        if !path.is_dir() {
+            paths.push(path.to_path_buf());
+            continue;
+        }
+        if !options.emit.is_empty() {
+            let err = CliError::Usage("`--emit` requires a single input file".into());
+            eprintln!("{}", err);
+            process::exit(EXIT_USAGE);
+        }
+        match batch::sources_in(path) {
+            Ok(found) => paths.extend(found),
+            Err(err) => {
+                eprintln!("error: could not read `{}`: {}", input, err);
+                process::exit(EXIT_COMPILE_ERROR);
+            }
+        }
+    }
+    paths
+}
+
+/// Checks several files, or the files in directories, on a thread per CPU,
+/// and prints what was found.
+fn check_all(options: &Options) -> batch::Report {
+    let jobs = options
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, usize::from));
+    let report = batch::check(&input_paths(options), jobs, options.deny_warnings);
+    let mut stderr = io::stderr().lock();
+    for message in &report.messages {
+        let _ = stderr.write_all(message.text.as_bytes());
+    }
+    report
+}
+
+fn errors(report: &batch::Report) -> usize {
+    report
+        .messages
+        .iter()
+        .filter(|message| message.severity == Severity::Error)
+        .count()
+}
+
+/// Checks the inputs, then again each time one of them or a file they import
+/// changes, clearing the terminal first.
+fn watch(options: &Options) -> ! {
+    watch::watch(WATCH_INTERVAL, || {
+        if io::stderr().is_terminal() {
+            eprint!("\x1b[2J\x1b[H");
+        }
+        let report = check_all(options);
+        let warnings = report.messages.len() - errors(&report);
+        eprintln!(
+            "compyl: {} errors and {} warnings in {} files; watching for changes",
+            errors(&report),
+            warnings,
+            report.files.len()
+        );
+        // Directories are watched for files added to them.
+        let mut files: Vec<_> = options.inputs.iter().map(PathBuf::from).collect();
+        files.extend(report.files);
+        files
+    })
+}
 
 fn main() {
-    if let Some(filename) = env::args().nth(1) {
-        let mut lexer = Lexer::from_file(filename);
-        while let Some(token) = lexer.next_token() {
-            dbg!(token);
+    let compiler = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run)
+        .expect("failed to start the compiler thread");
+    // A panic has already been reported by the thread.
+    if compiler.join().is_err() {
+        process::exit(101);
+    }
+}
+
+fn run() {
+    let options = match cli::parse_args(env::args().skip(1), io::stdin().is_terminal()) {
+        Ok(options) => options,
+        Err(CliError::Help) => {
+            println!("{}", CliError::Help);
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    if options.command == Command::Repl {
+        if let Err(err) = repl::run(io::stdin().lock(), io::stdout()) {
+            eprintln!("error: {}", err);
+            process::exit(EXIT_COMPILE_ERROR);
+        }
+        return;
+    }
+    if options.command == Command::Lsp {
+        if let Err(err) = lsp::run(io::stdin().lock(), io::stdout()) {
+            eprintln!("error: {}", err);
+            process::exit(EXIT_COMPILE_ERROR);
+        }
+        return;
+    }
+
+    if options.watch {
+        watch(&options);
+    }
+    let batch = options.inputs.len() > 1 || Path::new(&options.inputs[0]).is_dir();
+    if options.command == Command::Check && batch {
+        if errors(&check_all(&options)) > 0 {
+            process::exit(EXIT_COMPILE_ERROR);
+        }
+        return;
+    }
+
+    let (filename, read) = match options.inputs[0].as_str() {
+        "-" => {
+            let mut bytes = Vec::new();
+            (
+                "<stdin>",
+                io::stdin().read_to_end(&mut bytes).map(|_| bytes),
+            )
+        }
+        path => (path, fs::read(path)),
+    };
+    let bytes = match read {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("error: could not read `{}`: {}", filename, err);
+            process::exit(EXIT_COMPILE_ERROR);
+        }
+    };
+    let source = match lexer::decode(&bytes) {
+        Ok(source) => source,
+        Err(err) => {
+            let diag = Diagnostic::from(&err);
+            diag.emit(filename, &String::from_utf8_lossy(&bytes));
+            process::exit(EXIT_COMPILE_ERROR);
+        }
+    };
+
+    let mut sources = SourceManager::new();
+    let root = sources.add(filename, source);
+    let mut log = Log::new(options.trace);
+    let result = match options.command {
+        Command::Fmt => format(&options, filename, source),
+        Command::Highlight => {
+            highlight(&options, source);
+            Ok(())
+        }
+        _ => execute(&options, &mut sources, root, &mut log),
+    };
+    for event in log.events() {
+        eprintln!("{}", event);
+    }
+    if let Err(err) = result {
+        for diag in err.diagnostics() {
+            sources.emit(&diag);
         }
-    } else {
-        panic!("Invalid usage. Pass a filename.");
+        process::exit(match err {
+            CompileError::Runtime(..) => EXIT_RUNTIME_ERROR,
+            _ => EXIT_COMPILE_ERROR,
+        });
     }
 }
@@ -0,0 +1,762 @@
+//! Optimization passes over the [`ir`](crate::ir), run by a [`PassManager`].
+//!
+//! Every pass keeps the interpreter's semantics: an instruction that may fail
+//! at run time, such as an overflowing `+` or a division by zero, is never
+//! folded away or removed.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{BinaryOp, UnaryOp};
+use crate::interp::{eval_binary, eval_unary, Value};
+use crate::ir::{BlockId, Function, Inst, Module, Operand, Reg, Terminator, Ty};
+
+/// How often the passes are repeated on a function that keeps changing.
+const MAX_ROUNDS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptLevel {
+    #[default]
+    O0,
+    O1,
+}
+
+pub trait Pass {
+    fn name(&self) -> &'static str;
+
+    /// Rewrites `func`, returning whether anything changed.
+    fn run(&self, func: &mut Function) -> bool;
+}
+
+/// Runs a sequence of passes over each function until none of them changes
+/// it any more.
+///
+/// ```
+/// use compyl::lexer::Lexer;
+/// use compyl::opt::{OptLevel, PassManager};
+/// use compyl::parser::Parser;
+/// use compyl::{ir, sema, types};
+///
+/// let program = Parser::new(Lexer::new("return 2 * 3 + 1;")).parse_program().unwrap();
+/// let resolution = sema::analyze(&program).unwrap();
+/// let types = types::check(&program, &resolution).unwrap();
+/// let mut module = ir::lower(&program, &resolution, &types).unwrap();
+/// PassManager::for_level(OptLevel::O1).run(&mut module);
+/// assert_eq!(module.to_string(), "fn main(): int {\nbb0:\n    ret 7\n}\n");
+/// ```
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl PassManager {
+    pub fn new() -> PassManager {
+        PassManager::default()
+    }
+
+    pub fn for_level(level: OptLevel) -> PassManager {
+        match level {
+            OptLevel::O0 => PassManager::new(),
+            OptLevel::O1 => PassManager::new()
+                .with(ConstantFolding)
+                .with(AlgebraicSimplification)
+                .with(CopyPropagation)
+                .with(UnreachableCode)
+                .with(DeadCode),
+        }
+    }
+
+    pub fn with(mut self, pass: impl Pass + 'static) -> PassManager {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    pub fn passes(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.passes.iter().map(|pass| pass.name())
+    }
+
+    pub fn run(&self, module: &mut Module) {
+        self.run_with(module, |_, _, _| {});
+    }
+
+    /// Runs the passes as [`PassManager::run`] does, calling `after` with a
+    /// pass's name and the function it rewrote each time one changes anything.
+    pub fn run_with(&self, module: &mut Module, mut after: impl FnMut(&str, &Module, &Function)) {
+        for at in 0..module.functions.len() {
+            for _ in 0..MAX_ROUNDS {
+                let mut changed = false;
+                for pass in &self.passes {
+                    if pass.run(&mut module.functions[at]) {
+                        after(pass.name(), module, &module.functions[at]);
+                        changed = true;
+                    }
+                }
+                if !changed {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn constant(operand: Operand) -> Option<Value> {
+    match operand {
+        Operand::Reg(_) => None,
+        Operand::Int(n) => Some(Value::Int(n)),
+        Operand::Float(n) => Some(Value::Float(n)),
+        Operand::Bool(b) => Some(Value::Bool(b)),
+    }
+}
+
+fn operand(value: Value) -> Option<Operand> {
+    match value {
+        Value::Int(n) => Some(Operand::Int(n)),
+        Value::Float(n) => Some(Operand::Float(n)),
+        Value::Bool(b) => Some(Operand::Bool(b)),
+        _ => None,
+    }
+}
+
+/// Evaluates instructions whose operands are all constants, propagates the
+/// results to where they are read, and turns branches on a constant into
+/// jumps.
+pub struct ConstantFolding;
+
+impl Pass for ConstantFolding {
+    fn name(&self) -> &'static str {
+        "constant-folding"
+    }
+
+    fn run(&self, func: &mut Function) -> bool {
+        // Registers are assigned in place, so only a register written once
+        // holds its constant everywhere; any other is followed within a block.
+        let mut defs = HashMap::<Reg, usize>::new();
+        for inst in func.blocks.iter().flat_map(|block| &block.insts) {
+            if let Some(dst) = inst.dst() {
+                *defs.entry(dst).or_default() += 1;
+            }
+        }
+        let mut constants = HashMap::new();
+        for inst in func.blocks.iter().flat_map(|block| &block.insts) {
+            if let Inst::Copy { dst, src } = *inst {
+                if !matches!(src, Operand::Reg(_)) && defs[&dst] == 1 && !func.params.contains(&dst)
+                {
+                    constants.insert(dst, src);
+                }
+            }
+        }
+
+        let mut changed = false;
+        for block in &mut func.blocks {
+            let mut known = constants.clone();
+            for inst in &mut block.insts {
+                for operand in inst.operands_mut() {
+                    if let Operand::Reg(reg) = operand {
+                        if let Some(&value) = known.get(reg) {
+                            *operand = value;
+                            changed = true;
+                        }
+                    }
+                }
+                if let (Some(dst), Some(value)) = (inst.dst(), fold(inst)) {
+                    *inst = Inst::Copy { dst, src: value };
+                    changed = true;
+                }
+                match *inst {
+                    Inst::Copy { dst, src } if !matches!(src, Operand::Reg(_)) => {
+                        known.insert(dst, src);
+                    }
+                    _ => {
+                        if let Some(dst) = inst.dst() {
+                            known.remove(&dst);
+                        }
+                    }
+                }
+            }
+
+            if let Some(operand) = block.term.operand_mut() {
+                if let Operand::Reg(reg) = *operand {
+                    if let Some(&value) = known.get(&reg) {
+                        *operand = value;
+                        changed = true;
+                    }
+                }
+            }
+            if let Terminator::Branch {
+                cond: Operand::Bool(cond),
+                then_block,
+                else_block,
+            } = block.term
+            {
+                block.term = Terminator::Jump(if cond { then_block } else { else_block });
+                changed = true;
+            }
+        }
+
+        changed
+    }
+}
+
+/// The constant an instruction computes, if its operands are constants and it
+/// cannot fail.
+fn fold(inst: &Inst) -> Option<Operand> {
+    match *inst {
+        Inst::Unary { op, src, .. } => operand(eval_unary(op, constant(src)?).ok()?),
+        Inst::Binary { op, lhs, rhs, .. } => {
+            operand(eval_binary(op, constant(lhs)?, constant(rhs)?).ok()?)
+        }
+        Inst::IntToFloat {
+            src: Operand::Int(n),
+            ..
+        } => Some(Operand::Float(n as f64)),
+        _ => None,
+    }
+}
+
+/// Rewrites operations with an identity or absorbing operand, such as `x + 0`
+/// or `x * 0`, and comparisons of a register with itself.
+pub struct AlgebraicSimplification;
+
+impl Pass for AlgebraicSimplification {
+    fn name(&self) -> &'static str {
+        "algebraic-simplification"
+    }
+
+    fn run(&self, func: &mut Function) -> bool {
+        let mut changed = false;
+        for inst in func.blocks.iter_mut().flat_map(|block| &mut block.insts) {
+            if let Inst::Binary { dst, op, lhs, rhs } = *inst {
+                let ty = match lhs {
+                    Operand::Reg(reg) => func.regs[reg.0 as usize],
+                    Operand::Int(_) => Ty::Int,
+                    Operand::Float(_) => Ty::Float,
+                    Operand::Bool(_) => Ty::Bool,
+                };
+                if let Some(src) = simplify(op, lhs, rhs, ty) {
+                    *inst = Inst::Copy { dst, src };
+                    changed = true;
+                }
+            }
+        }
+
+        changed
+    }
+}
+
+/// What `lhs op rhs` simplifies to, given the operands' type `ty`.
+fn simplify(op: BinaryOp, lhs: Operand, rhs: Operand, ty: Ty) -> Option<Operand> {
+    use BinaryOp::*;
+    use Operand::{Bool, Float, Int};
+
+    if let (Operand::Reg(a), Operand::Reg(b)) = (lhs, rhs) {
+        // Floats are left alone, since NaN is not equal to itself.
+        return match op {
+            _ if a != b => None,
+            Sub | BitXor if ty == Ty::Int => Some(Int(0)),
+            BitAnd | BitOr => Some(lhs),
+            Eq | Le | Ge if ty != Ty::Float => Some(Bool(true)),
+            Ne | Lt | Gt if ty != Ty::Float => Some(Bool(false)),
+            _ => None,
+        };
+    }
+
+    match (op, lhs, rhs) {
+        (Add | BitOr | BitXor, x, Int(0)) | (Add | BitOr | BitXor, Int(0), x) => Some(x),
+        (Sub | Shl | Shr, x, Int(0)) => Some(x),
+        (Mul, x, Int(1)) | (Mul, Int(1), x) | (Div, x, Int(1)) => Some(x),
+        (Mul | BitAnd, _, Int(0)) | (Mul | BitAnd, Int(0), _) | (Mod, _, Int(1)) => Some(Int(0)),
+        (BitAnd, x, Int(-1)) | (BitAnd, Int(-1), x) => Some(x),
+        (Mul, x, Float(1.0)) | (Mul, Float(1.0), x) => Some(x),
+        (Div, x, Float(1.0)) => Some(x),
+        // Unlike `x + 0.0`, this keeps the sign of a zero.
+        (Sub, x, Float(n)) if n == 0.0 && n.is_sign_positive() => Some(x),
+        (BitAnd, x, Bool(true)) | (BitAnd, Bool(true), x) => Some(x),
+        (BitOr | BitXor, x, Bool(false)) | (BitOr | BitXor, Bool(false), x) => Some(x),
+        (Eq, x, Bool(true)) | (Eq, Bool(true), x) => Some(x),
+        (BitAnd, _, Bool(false)) | (BitAnd, Bool(false), _) => Some(Bool(false)),
+        (BitOr, _, Bool(true)) | (BitOr, Bool(true), _) => Some(Bool(true)),
+        _ => None,
+    }
+}
+
+/// Reads the source of a register-to-register copy instead of its
+/// destination, within a block and while neither is written again.
+pub struct CopyPropagation;
+
+impl Pass for CopyPropagation {
+    fn name(&self) -> &'static str {
+        "copy-propagation"
+    }
+
+    fn run(&self, func: &mut Function) -> bool {
+        let mut changed = false;
+        for block in &mut func.blocks {
+            let mut copies = HashMap::<Reg, Reg>::new();
+            for inst in &mut block.insts {
+                for operand in inst.operands_mut() {
+                    if let Operand::Reg(reg) = operand {
+                        if let Some(&src) = copies.get(reg) {
+                            *reg = src;
+                            changed = true;
+                        }
+                    }
+                }
+                if let Some(dst) = inst.dst() {
+                    copies.retain(|&copy, &mut src| copy != dst && src != dst);
+                    if let Inst::Copy {
+                        src: Operand::Reg(src),
+                        ..
+                    } = *inst
+                    {
+                        if src != dst {
+                            copies.insert(dst, src);
+                        }
+                    }
+                }
+            }
+            if let Some(Operand::Reg(reg)) = block.term.operand_mut() {
+                if let Some(&src) = copies.get(reg) {
+                    *reg = src;
+                    changed = true;
+                }
+            }
+        }
+
+        changed
+    }
+}
+
+/// Removes blocks that control never reaches, after threading jumps through
+/// empty blocks and turning branches with one target into jumps.
+pub struct UnreachableCode;
+
+impl Pass for UnreachableCode {
+    fn name(&self) -> &'static str {
+        "unreachable-code"
+    }
+
+    fn run(&self, func: &mut Function) -> bool {
+        let mut changed = false;
+
+        // Where a jump to each block may go instead.
+        let forward: Vec<Option<BlockId>> = func
+            .blocks
+            .iter()
+            .map(|block| match block.term {
+                Terminator::Jump(target) if block.insts.is_empty() => Some(target),
+                _ => None,
+            })
+            .collect();
+        for block in &mut func.blocks {
+            for target in block.term.successors_mut() {
+                let mut seen = HashSet::new();
+                while let Some(next) = forward[target.0 as usize] {
+                    if next == *target || !seen.insert(next) {
+                        break;
+                    }
+                    *target = next;
+                    changed = true;
+                }
+            }
+            if let Terminator::Branch {
+                then_block,
+                else_block,
+                ..
+            } = block.term
+            {
+                if then_block == else_block {
+                    block.term = Terminator::Jump(then_block);
+                    changed = true;
+                }
+            }
+        }
+
+        let mut reachable = vec![false; func.blocks.len()];
+        let mut stack = vec![BlockId(0)];
+        while let Some(block) = stack.pop() {
+            if !std::mem::replace(&mut reachable[block.0 as usize], true) {
+                stack.extend(func.blocks[block.0 as usize].term.successors());
+            }
+        }
+        if reachable.iter().all(|&reachable| reachable) {
+            return changed;
+        }
+
+        let mut renumbered = Vec::with_capacity(func.blocks.len());
+        let mut next = 0;
+        for &reachable in &reachable {
+            renumbered.push(BlockId(next));
+            next += reachable as u32;
+        }
+        let blocks = std::mem::take(&mut func.blocks);
+        for (mut block, reachable) in blocks.into_iter().zip(reachable) {
+            if reachable {
+                for target in block.term.successors_mut() {
+                    *target = renumbered[target.0 as usize];
+                }
+                func.blocks.push(block);
+            }
+        }
+
+        true
+    }
+}
+
+/// Removes instructions whose result is never read and that have no other
+/// effect.
+pub struct DeadCode;
+
+impl Pass for DeadCode {
+    fn name(&self) -> &'static str {
+        "dead-code"
+    }
+
+    fn run(&self, func: &mut Function) -> bool {
+        let mut changed = false;
+        loop {
+            let mut read = HashSet::new();
+            for block in &func.blocks {
+                let operands = block.insts.iter().flat_map(|inst| inst.operands());
+                for operand in operands.chain(block.term.operand()) {
+                    if let Operand::Reg(reg) = operand {
+                        read.insert(*reg);
+                    }
+                }
+            }
+
+            let regs = &func.regs;
+            let mut removed = false;
+            for block in &mut func.blocks {
+                block.insts.retain(|inst| {
+                    let dead = inst
+                        .dst()
+                        .is_some_and(|dst| !read.contains(&dst) && !may_fail(inst, regs));
+                    removed |= dead;
+                    !dead
+                });
+            }
+            if !removed {
+                return changed;
+            }
+            changed = true;
+        }
+    }
+}
+
+/// Whether `inst` has an effect besides writing its register: a call, or
+/// integer arithmetic that may overflow, divide by zero or shift too far.
+fn may_fail(inst: &Inst, regs: &[Ty]) -> bool {
+    match *inst {
+        Inst::Call { .. } | Inst::StoreGlobal { .. } => true,
+        Inst::Unary {
+            op: UnaryOp::Neg,
+            dst,
+            ..
+        } => regs[dst.0 as usize] == Ty::Int,
+        Inst::Binary { op, dst, rhs, .. } if regs[dst.0 as usize] == Ty::Int => match (op, rhs) {
+            (BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul, _) => true,
+            (BinaryOp::Div | BinaryOp::Mod, Operand::Int(n)) => n == 0 || n == -1,
+            (BinaryOp::Shl | BinaryOp::Shr, Operand::Int(n)) => !(0..64).contains(&n),
+            (BinaryOp::Div | BinaryOp::Mod | BinaryOp::Shl | BinaryOp::Shr, _) => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        AlgebraicSimplification, ConstantFolding, CopyPropagation, DeadCode, OptLevel, Pass,
+        PassManager, UnreachableCode,
+    };
+    use crate::ir;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::{sema, types};
+
+    /// The `main` of a program with no top-level statements.
+    const EMPTY_MAIN: &str = "\nfn main(): int {\nbb0:\n    ret 0\n}\n";
+
+    /// Prints the IR of `input` before and after running `passes`, leaving out
+    /// an empty `main`.
+    fn snapshots(input: &str, passes: PassManager) -> (String, String) {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.");
+        let resolution = sema::analyze(&program).expect("Program failed analysis.");
+        let types = types::check(&program, &resolution).expect("Program failed to type check.");
+        let mut module =
+            ir::lower(&program, &resolution, &types).expect("Program failed to lower.");
+        let print = |module: &ir::Module| {
+            let text = module.to_string();
+            text.strip_suffix(EMPTY_MAIN).unwrap_or(&text).to_string()
+        };
+        let before = print(&module);
+        passes.run(&mut module);
+        (before, print(&module))
+    }
+
+    fn single(pass: impl Pass + 'static) -> PassManager {
+        PassManager::new().with(pass)
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        let (before, after) = snapshots(
+            "fn f(): int { let a = 6; let b = a * 7 - 2; if (b > 10) { return b; } return 1 / 0; }",
+            single(ConstantFolding),
+        );
+        let expected_before = "\
+fn f(): int {
+bb0:
+    %0 = 6
+    %1 = %0
+    %2 = %1 * 7
+    %3 = %2 - 2
+    %4 = %3
+    %5 = %4
+    %6 = %5 > 10
+    branch %6, bb1, bb2
+bb1:
+    %7 = %4
+    ret %7
+bb2:
+    %8 = 1 / 0
+    ret %8
+}
+";
+        let expected_after = "\
+fn f(): int {
+bb0:
+    %0 = 6
+    %1 = 6
+    %2 = 42
+    %3 = 40
+    %4 = 40
+    %5 = 40
+    %6 = true
+    jump bb1
+bb1:
+    %7 = 40
+    ret 40
+bb2:
+    %8 = 1 / 0
+    ret %8
+}
+";
+        assert_eq!(before, expected_before);
+        assert_eq!(after, expected_after);
+    }
+
+    #[test]
+    fn test_algebraic_simplification() {
+        let (before, after) = snapshots(
+            "fn f(x: int, y: float, b: bool): bool { let a = x * 1 + 0; let c = y * 1.0; return (a - a == 0 | b & false) & c == c; }",
+            single(AlgebraicSimplification),
+        );
+        let expected_before = "\
+fn f(%0: int, %1: float, %2: bool): bool {
+bb0:
+    %3 = %0
+    %4 = %3 * 1
+    %5 = %4 + 0
+    %6 = %5
+    %7 = %1
+    %8 = %7 * 1.0
+    %9 = %8
+    %10 = %6
+    %11 = %6
+    %12 = %10 - %11
+    %13 = %12 == 0
+    %14 = %2
+    %15 = %14 & false
+    %16 = %13 | %15
+    %17 = %9
+    %18 = %9
+    %19 = %17 == %18
+    %20 = %16 & %19
+    ret %20
+}
+";
+        let expected_after = "\
+fn f(%0: int, %1: float, %2: bool): bool {
+bb0:
+    %3 = %0
+    %4 = %3
+    %5 = %4
+    %6 = %5
+    %7 = %1
+    %8 = %7
+    %9 = %8
+    %10 = %6
+    %11 = %6
+    %12 = %10 - %11
+    %13 = %12 == 0
+    %14 = %2
+    %15 = false
+    %16 = %13 | %15
+    %17 = %9
+    %18 = %9
+    %19 = %17 == %18
+    %20 = %16 & %19
+    ret %20
+}
+";
+        assert_eq!(before, expected_before);
+        assert_eq!(after, expected_after);
+    }
+
+    #[test]
+    fn test_copy_propagation() {
+        let (_, after) = snapshots(
+            "fn f(x: int): int { let a = x; a = a + 1; return a + x; }",
+            single(CopyPropagation).with(DeadCode),
+        );
+        let expected = "\
+fn f(%0: int): int {
+bb0:
+    %4 = %0 + 1
+    %7 = %4 + %0
+    ret %7
+}
+";
+        assert_eq!(after, expected);
+    }
+
+    #[test]
+    fn test_unreachable_code() {
+        let (before, after) = snapshots(
+            "fn f(x: int): int { while (true) { if (x > 0) { return x; } else { return 0; } } }",
+            single(ConstantFolding).with(UnreachableCode),
+        );
+        let expected_before = "\
+fn f(%0: int): int {
+bb0:
+    jump bb1
+bb1:
+    branch true, bb2, bb3
+bb2:
+    %1 = %0
+    %2 = %1 > 0
+    branch %2, bb4, bb5
+bb3:
+    unreachable
+bb4:
+    %3 = %0
+    ret %3
+bb5:
+    ret 0
+bb6:
+    jump bb1
+}
+";
+        let expected_after = "\
+fn f(%0: int): int {
+bb0:
+    jump bb1
+bb1:
+    %1 = %0
+    %2 = %1 > 0
+    branch %2, bb2, bb3
+bb2:
+    %3 = %0
+    ret %3
+bb3:
+    ret 0
+}
+";
+        assert_eq!(before, expected_before);
+        assert_eq!(after, expected_after);
+    }
+
+    #[test]
+    fn test_dead_code() {
+        let (_, after) = snapshots(
+            "fn f(x: int): int { let a = x / 2; let b = x / 0; let c = x + 1; let d = x < 3; return x; }",
+            single(DeadCode),
+        );
+        let expected = "\
+fn f(%0: int): int {
+bb0:
+    %4 = %0
+    %5 = %4 / 0
+    %7 = %0
+    %8 = %7 + 1
+    %13 = %0
+    ret %13
+}
+";
+        assert_eq!(after, expected);
+    }
+
+    #[test]
+    fn test_levels() {
+        assert_eq!(PassManager::for_level(OptLevel::O0).passes().count(), 0);
+        assert_eq!(
+            PassManager::for_level(OptLevel::O1)
+                .passes()
+                .collect::<Vec<_>>(),
+            [
+                "constant-folding",
+                "algebraic-simplification",
+                "copy-propagation",
+                "unreachable-code",
+                "dead-code"
+            ]
+        );
+
+        let (_, after) = snapshots(
+            "let total = 0; let i = 0; while (i < 3 * 1) { total += i + 0; i = i + 1; if (false) { break; } } return total;",
+            PassManager::for_level(OptLevel::O1),
+        );
+        let expected = "\
+global @total: int
+global @i: int
+
+fn main(): int {
+bb0:
+    store @total, 0
+    store @i, 0
+    jump bb1
+bb1:
+    %0 = load @i
+    %2 = %0 < 3
+    branch %2, bb2, bb3
+bb2:
+    %3 = load @i
+    %5 = load @total
+    %6 = %5 + %3
+    store @total, %6
+    %7 = load @i
+    %8 = %7 + 1
+    store @i, %8
+    jump bb1
+bb3:
+    %9 = load @total
+    ret %9
+}
+";
+        assert_eq!(after, expected);
+    }
+
+    #[test]
+    fn test_run_with() {
+        let program = Parser::new(Lexer::new("fn f(): int { return 1 + 2; }"))
+            .parse_program()
+            .unwrap();
+        let resolution = sema::analyze(&program).unwrap();
+        let types = types::check(&program, &resolution).unwrap();
+        let mut module = ir::lower(&program, &resolution, &types).unwrap();
+        let mut changes = Vec::new();
+        PassManager::for_level(OptLevel::O1).run_with(&mut module, |pass, module, func| {
+            changes.push(format!("{}: {}", pass, module.display_function(func)));
+        });
+        assert_eq!(
+            changes,
+            [
+                "constant-folding: fn f(): int {\nbb0:\n    %0 = 3\n    ret 3\n}\n",
+                "dead-code: fn f(): int {\nbb0:\n    ret 3\n}\n",
+            ]
+        );
+    }
+}
@@ -0,0 +1,1470 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::ast::{
+    BinaryOp, Block, Expr, ExprKind, Field, FieldInit, FnDecl, Ident, MatchArm, NodeId, Param,
+    Pattern, PatternKind, Program, Stmt, StmtKind, StructDecl, TypeExpr, TypeExprKind, UnaryOp,
+    ANONYMOUS,
+};
+use crate::intern::Symbol;
+use crate::lexer::{LexError, Lexer, StrPart, Token};
+use crate::span::Span;
+use crate::token_stream::TokenStream;
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    UnexpectedToken {
+        expected: String,
+        found: Token<'static>,
+        span: Span,
+    },
+    UnexpectedEof {
+        expected: String,
+        span: Span,
+    },
+    /// Expressions, blocks or types nested deeper than the parser allows.
+    TooDeep {
+        limit: usize,
+        span: Span,
+    },
+    /// An expression with more binary operators than the parser allows.
+    TooLong {
+        limit: usize,
+        span: Span,
+    },
+    /// An assignment to something other than a name, an element or a field.
+    InvalidAssignment(Span),
+    Lex(LexError),
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::UnexpectedToken { span, .. }
+            | ParseError::UnexpectedEof { span, .. }
+            | ParseError::TooDeep { span, .. }
+            | ParseError::TooLong { span, .. }
+            | ParseError::InvalidAssignment(span) => *span,
+            ParseError::Lex(err) => err.span(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedToken {
+                expected, found, ..
+            } => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            ParseError::UnexpectedEof { expected, .. } => {
+                write!(f, "expected {}, found end of input", expected)
+            }
+            ParseError::TooDeep { limit, .. } => {
+                write!(f, "nesting exceeds the limit of {} levels", limit)
+            }
+            ParseError::TooLong { limit, .. } => {
+                write!(f, "expression has more than {} operators", limit)
+            }
+            ParseError::InvalidAssignment(_) => write!(f, "invalid left-hand side of assignment"),
+            ParseError::Lex(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<LexError> for ParseError {
+    fn from(err: LexError) -> Self {
+        ParseError::Lex(err)
+    }
+}
+
+type ParseResult<T> = Result<T, ParseError>;
+
+// Binding powers, from loosest to tightest.
+const PREC_LOWEST: u8 = 0;
+const PREC_OR: u8 = 1;
+const PREC_AND: u8 = 2;
+const PREC_BIT_OR: u8 = 3;
+const PREC_BIT_XOR: u8 = 4;
+const PREC_BIT_AND: u8 = 5;
+const PREC_EQUALITY: u8 = 6;
+const PREC_COMPARISON: u8 = 7;
+const PREC_SHIFT: u8 = 8;
+const PREC_SUM: u8 = 9;
+const PREC_PRODUCT: u8 = 10;
+const PREC_PREFIX: u8 = 11;
+
+pub struct Parser<'src> {
+    tokens: TokenStream<'src>,
+    prev_span: Span,
+    next_id: u32,
+    /// The errors recovered from so far.
+    errors: Vec<ParseError>,
+    /// How deeply the node being parsed is nested, and how deeply it may be.
+    depth: usize,
+    max_depth: usize,
+    /// How many operators join the expressions around the node being
+    /// parsed, and how many may.
+    operators: usize,
+    max_operators: usize,
+}
+
+impl<'src> Parser<'src> {
+    /// Creates a parser reading tokens from `lexer`.
+    ///
+    /// ```
+    /// use compyl::lexer::Lexer;
+    /// use compyl::parser::Parser;
+    ///
+    /// let program = Parser::new(Lexer::new("let x = 1 + 2;"))
+    ///     .parse_program()
+    ///     .unwrap();
+    /// assert_eq!(program.to_string(), "(let x (+ 1 2))\n");
+    /// ```
+    pub fn new(lexer: Lexer<'src>) -> Parser<'src> {
+        Parser::with_first_id(lexer, NodeId(0))
+    }
+
+    /// Like [`Parser::new`], numbering nodes from `first`, so that programs
+    /// parsed one after another can be linked without their ids clashing.
+    pub fn with_first_id(lexer: Lexer<'src>, first: NodeId) -> Parser<'src> {
+        Parser {
+            tokens: TokenStream::new(lexer),
+            prev_span: Span::default(),
+            next_id: first.0,
+            errors: Vec::new(),
+            depth: 0,
+            max_depth: Parser::DEFAULT_MAX_DEPTH,
+            operators: 0,
+            max_operators: Parser::DEFAULT_MAX_OPERATORS,
+        }
+    }
+
+    /// How deeply expressions, blocks and types may nest by default. Every
+    /// pass after the parser recurses over the tree, so this and
+    /// [`Parser::DEFAULT_MAX_OPERATORS`] bound how much stack they need: with
+    /// both, checking and interpreting a program fits in the 2 MiB a spawned
+    /// thread gets by default in release builds. Debug builds need about ten
+    /// times as much.
+    pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+    /// How many binary operators an expression may have by default. A chain
+    /// like `a + b + c` is a node per operator, and passes after the parser
+    /// recurse into each.
+    pub const DEFAULT_MAX_OPERATORS: usize = 1024;
+
+    /// Makes the parser reject programs nested deeper than `max_depth`.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Parser<'src> {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Makes the parser reject expressions with more than `max_operators`
+    /// binary operators.
+    pub fn with_max_operators(mut self, max_operators: usize) -> Parser<'src> {
+        self.max_operators = max_operators;
+        self
+    }
+
+    /// The id the next node parsed will get.
+    pub fn next_id(&self) -> NodeId {
+        NodeId(self.next_id)
+    }
+
+    /// Parses the whole input. A statement that fails to parse does not stop
+    /// the parser, so the errors are every one found, in source order.
+    pub fn parse_program(&mut self) -> Result<Program, Vec<ParseError>> {
+        let (program, errors) = self.parse_partial();
+        match errors.is_empty() {
+            true => Ok(program),
+            false => Err(errors),
+        }
+    }
+
+    /// Like [`Parser::parse_program`], but also returns the program when
+    /// there are errors, with a [`StmtKind::Error`] for each statement that
+    /// failed to parse.
+    pub fn parse_partial(&mut self) -> (Program, Vec<ParseError>) {
+        let mut stmts = Vec::new();
+        while !matches!(self.tokens.peek(), Ok(next) if next.token == Token::TokEof) {
+            stmts.push(self.parse_stmt_or_recover());
+        }
+
+        let program = Program {
+            stmts,
+            modules: Vec::new(),
+        };
+        (program, std::mem::take(&mut self.errors))
+    }
+
+    fn node_id(&mut self) -> NodeId {
+        self.next_id += 1;
+        NodeId(self.next_id - 1)
+    }
+
+    fn peek(&mut self) -> ParseResult<&Token<'src>> {
+        Ok(&self.tokens.peek()?.token)
+    }
+
+    fn peek_span(&mut self) -> ParseResult<Span> {
+        Ok(self.tokens.peek()?.span)
+    }
+
+    fn at(&mut self, token: &Token) -> ParseResult<bool> {
+        Ok(self.peek()? == token)
+    }
+
+    fn advance(&mut self) -> ParseResult<Token<'src>> {
+        let next = self.tokens.next_token()?;
+        self.prev_span = next.span;
+        Ok(next.token)
+    }
+
+    /// Takes the doc comment before the next token.
+    fn take_doc(&mut self) -> ParseResult<Option<String>> {
+        let offset = self.peek_span()?.start;
+        Ok(self.tokens.take_doc(offset))
+    }
+
+    fn expect(&mut self, token: Token) -> ParseResult<()> {
+        self.prev_span = self.tokens.expect(token)?.span;
+        Ok(())
+    }
+
+    /// Consumes `token` if it is next, returning whether it was.
+    fn eat(&mut self, token: &Token) -> ParseResult<bool> {
+        let found = self.at(token)?;
+        if found {
+            self.advance()?;
+        }
+
+        Ok(found)
+    }
+
+    /// Goes a level deeper into the tree, which callers undo by restoring
+    /// `depth` once done.
+    fn nest(&mut self) -> ParseResult<()> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            return Err(ParseError::TooDeep {
+                limit: self.max_depth,
+                span: self.peek_span()?,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Counts an operator joining two expressions, which callers undo by
+    /// restoring `operators` once the expression is done.
+    fn join(&mut self) -> ParseResult<()> {
+        self.operators += 1;
+        if self.operators > self.max_operators {
+            return Err(ParseError::TooLong {
+                limit: self.max_operators,
+                span: self.peek_span()?,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn unexpected<T>(&mut self, expected: &str) -> ParseResult<T> {
+        let expected = expected.into();
+        let next = self.tokens.peek()?;
+        let span = next.span;
+        Err(match &next.token {
+            Token::TokEof => ParseError::UnexpectedEof { expected, span },
+            token => ParseError::UnexpectedToken {
+                expected,
+                found: token.clone().into_owned(),
+                span,
+            },
+        })
+    }
+
+    fn expect_identifier(&mut self) -> ParseResult<Ident> {
+        match self.peek()? {
+            Token::Identifier(_) => match self.advance()? {
+                Token::Identifier(name) => Ok(Ident {
+                    name,
+                    span: self.prev_span,
+                    id: self.node_id(),
+                }),
+                _ => unreachable!(),
+            },
+            _ => self.unexpected("identifier"),
+        }
+    }
+
+    fn expr(&mut self, kind: ExprKind, span: Span) -> Expr {
+        Expr {
+            kind,
+            span,
+            id: self.node_id(),
+        }
+    }
+
+    /// Parses a statement, or records why it could not be and skips past it.
+    fn parse_stmt_or_recover(&mut self) -> Stmt {
+        let start = self.tokens.peek().map(|next| next.span);
+        let (depth, operators) = (self.depth, self.operators);
+        match self.parse_stmt() {
+            Ok(stmt) => stmt,
+            Err(err) => {
+                (self.depth, self.operators) = (depth, operators);
+                let start = start.unwrap_or_else(|_| err.span());
+                // An error can end several statements at once, like the end
+                // of the input inside a block.
+                if self.errors.last().map(ParseError::span) != Some(err.span()) {
+                    self.errors.push(err);
+                }
+                self.synchronize(start);
+                Stmt {
+                    kind: StmtKind::Error,
+                    span: start.to(self.prev_span),
+                }
+            }
+        }
+    }
+
+    /// Skips the rest of a statement that failed to parse: through its `;`
+    /// or a block it opened, or up to the `}` closing the enclosing block or
+    /// the keyword starting the next statement. Lexer errors on the way are
+    /// not reported, as they are often caused by the first error.
+    fn synchronize(&mut self, start: Span) {
+        let mut depth = 0usize;
+        loop {
+            let (token, span) = match self.tokens.peek() {
+                Ok(next) => (next.token.clone(), next.span),
+                Err(_) => {
+                    let _ = self.tokens.next_token();
+                    continue;
+                }
+            };
+            // The parser always makes progress, even past a stray `}`.
+            let moved = span.start > start.start;
+            match token {
+                // The end of an interpolated expression is not the end of
+                // the input, which keeps lexing to `TokEof`.
+                Token::TokEof => match self.tokens.peek_n(1) {
+                    Ok(after) if after.token == Token::TokEof => return,
+                    _ => {}
+                },
+                Token::SemiColon if depth == 0 => {
+                    let _ = self.advance();
+                    return;
+                }
+                Token::LSquirly => depth += 1,
+                Token::RSquirly if depth == 0 && moved => return,
+                Token::RSquirly if depth == 1 => {
+                    let _ = self.advance();
+                    return;
+                }
+                Token::RSquirly => depth = depth.saturating_sub(1),
+                Token::KwLet
+                | Token::KwConst
+                | Token::KwFn
+                | Token::KwStruct
+                | Token::KwIf
+                | Token::KwWhile
+                | Token::KwFor
+                | Token::KwReturn
+                | Token::KwBreak
+                | Token::KwContinue
+                | Token::KwImport
+                | Token::KwExtern
+                    if depth == 0 && moved =>
+                {
+                    return
+                }
+                _ => {}
+            }
+            let _ = self.advance();
+        }
+    }
+
+    fn parse_stmt(&mut self) -> ParseResult<Stmt> {
+        let start = self.peek_span()?;
+        let kind = self.parse_stmt_kind()?;
+
+        Ok(Stmt {
+            kind,
+            span: start.to(self.prev_span),
+        })
+    }
+
+    fn parse_stmt_kind(&mut self) -> ParseResult<StmtKind> {
+        let second = &self.tokens.peek_n(1)?.token;
+        let assignment = assignment_op(second);
+        let named = matches!(second, Token::Identifier(_));
+        match self.peek()? {
+            Token::KwLet | Token::KwConst => self.parse_let(),
+            Token::KwFn if named => Ok(StmtKind::Fn(self.parse_fn()?)),
+            Token::KwStruct => Ok(StmtKind::Struct(self.parse_struct()?)),
+            Token::KwIf => self.parse_if(),
+            Token::KwWhile => self.parse_while(),
+            Token::KwFor => self.parse_for(),
+            Token::KwReturn => self.parse_return(),
+            Token::KwBreak => {
+                self.advance()?;
+                self.expect(Token::SemiColon)?;
+                Ok(StmtKind::Break)
+            }
+            Token::KwContinue => {
+                self.advance()?;
+                self.expect(Token::SemiColon)?;
+                Ok(StmtKind::Continue)
+            }
+            Token::KwImport => self.parse_import(),
+            Token::KwExtern => self.parse_extern(),
+            Token::LSquirly => Ok(StmtKind::Block(self.parse_block()?)),
+            Token::Identifier(_) if assignment.is_some() => {
+                let target = self.expect_identifier()?;
+                self.advance()?;
+                let value = self.parse_expr(PREC_LOWEST)?;
+                self.expect(Token::SemiColon)?;
+                Ok(StmtKind::Assign {
+                    target,
+                    op: assignment.flatten(),
+                    value,
+                })
+            }
+            _ => {
+                let expr = self.parse_expr(PREC_LOWEST)?;
+                let Some(op) = assignment_op(self.peek()?) else {
+                    self.expect(Token::SemiColon)?;
+                    return Ok(StmtKind::Expr(expr));
+                };
+                if !matches!(
+                    expr.kind,
+                    ExprKind::Ident(_) | ExprKind::Index { .. } | ExprKind::Field { .. }
+                ) {
+                    return Err(ParseError::InvalidAssignment(expr.span));
+                }
+                self.advance()?;
+                let value = self.parse_expr(PREC_LOWEST)?;
+                self.expect(Token::SemiColon)?;
+                Ok(match expr.kind {
+                    // A name, such as `(x)`, that was not followed by the
+                    // assignment directly.
+                    ExprKind::Ident(name) => StmtKind::Assign {
+                        target: Ident {
+                            name,
+                            span: expr.span,
+                            id: expr.id,
+                        },
+                        op,
+                        value,
+                    },
+                    ExprKind::Index { expr: array, index } => StmtKind::IndexAssign {
+                        array: *array,
+                        index: *index,
+                        op,
+                        value,
+                    },
+                    ExprKind::Field {
+                        expr: object,
+                        field,
+                    } => StmtKind::FieldAssign {
+                        object: *object,
+                        field,
+                        op,
+                        value,
+                    },
+                    _ => unreachable!(),
+                })
+            }
+        }
+    }
+
+    /// Parses a `let`, or a `const`, which is written the same way.
+    fn parse_let(&mut self) -> ParseResult<StmtKind> {
+        let constant = self.eat(&Token::KwConst)?;
+        if !constant {
+            self.expect(Token::KwLet)?;
+        }
+        let name = self.expect_identifier()?;
+        let ty = self.parse_annotation()?;
+        self.expect(Token::Assignment)?;
+        let value = self.parse_expr(PREC_LOWEST)?;
+        self.expect(Token::SemiColon)?;
+
+        Ok(match constant {
+            true => StmtKind::Const { name, ty, value },
+            false => StmtKind::Let { name, ty, value },
+        })
+    }
+
+    /// Parses a function, which only a function expression may leave unnamed.
+    fn parse_fn(&mut self) -> ParseResult<FnDecl> {
+        let doc = self.take_doc()?;
+        self.expect(Token::KwFn)?;
+        let name = match self.peek()? {
+            Token::Identifier(_) => self.expect_identifier()?,
+            _ => Ident {
+                name: Symbol::intern(ANONYMOUS),
+                span: self.prev_span,
+                id: self.node_id(),
+            },
+        };
+        let (params, ret) = self.parse_params()?;
+        let body = self.parse_block()?;
+
+        Ok(FnDecl {
+            name,
+            params,
+            ret,
+            body,
+            doc,
+        })
+    }
+
+    /// Parses a function's parameter list and return type.
+    fn parse_params(&mut self) -> ParseResult<(Vec<Param>, Option<TypeExpr>)> {
+        self.expect(Token::Lparen)?;
+        let mut params = Vec::new();
+        while !self.at(&Token::RParen)? {
+            let name = self.expect_identifier()?;
+            let ty = self.parse_annotation()?;
+            params.push(Param { name, ty });
+            if !self.eat(&Token::Comma)? {
+                break;
+            }
+        }
+        self.expect(Token::RParen)?;
+        let ret = self.parse_annotation()?;
+        Ok((params, ret))
+    }
+
+    fn parse_extern(&mut self) -> ParseResult<StmtKind> {
+        let doc = self.take_doc()?;
+        self.expect(Token::KwExtern)?;
+        self.expect(Token::KwFn)?;
+        let name = self.expect_identifier()?;
+        let (params, ret) = self.parse_params()?;
+        self.expect(Token::SemiColon)?;
+
+        Ok(StmtKind::Extern(FnDecl {
+            name,
+            params,
+            ret,
+            body: Vec::new(),
+            doc,
+        }))
+    }
+
+    fn parse_struct(&mut self) -> ParseResult<StructDecl> {
+        let doc = self.take_doc()?;
+        self.expect(Token::KwStruct)?;
+        let name = self.expect_identifier()?;
+        self.expect(Token::LSquirly)?;
+
+        let mut fields = Vec::new();
+        while !self.at(&Token::RSquirly)? {
+            let name = self.expect_identifier()?;
+            self.expect(Token::Colon)?;
+            let ty = self.parse_type()?;
+            fields.push(Field { name, ty });
+            if !self.eat(&Token::Comma)? {
+                break;
+            }
+        }
+        self.expect(Token::RSquirly)?;
+
+        Ok(StructDecl { name, fields, doc })
+    }
+
+    /// Parses an optional `: type` annotation.
+    fn parse_annotation(&mut self) -> ParseResult<Option<TypeExpr>> {
+        if self.eat(&Token::Colon)? {
+            self.parse_type().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_type(&mut self) -> ParseResult<TypeExpr> {
+        let depth = self.depth;
+        self.nest()?;
+        let start = self.peek_span()?;
+        let kind = match self.peek()? {
+            Token::KwVoid => {
+                self.advance()?;
+                TypeExprKind::Void
+            }
+            Token::Identifier(_) => TypeExprKind::Named(self.expect_identifier()?.name),
+            Token::LBracket => {
+                self.advance()?;
+                let elem = self.parse_type()?;
+                self.expect(Token::RBracket)?;
+                TypeExprKind::Array(Box::new(elem))
+            }
+            Token::KwFn => {
+                self.advance()?;
+                self.expect(Token::Lparen)?;
+                let mut params = Vec::new();
+                while !self.at(&Token::RParen)? {
+                    params.push(self.parse_type()?);
+                    if !self.eat(&Token::Comma)? {
+                        break;
+                    }
+                }
+                self.expect(Token::RParen)?;
+                let ret = self.parse_annotation()?.map(Box::new);
+                TypeExprKind::Fn { params, ret }
+            }
+            _ => return self.unexpected("type"),
+        };
+
+        self.depth = depth;
+        Ok(TypeExpr {
+            kind,
+            span: start.to(self.prev_span),
+        })
+    }
+
+    fn parse_if(&mut self) -> ParseResult<StmtKind> {
+        self.expect(Token::KwIf)?;
+        let cond = self.parse_condition()?;
+        let then_branch = self.parse_block()?;
+        let else_branch = if !self.eat(&Token::KwElse)? {
+            None
+        } else if self.at(&Token::KwIf)? {
+            // `else if` is an `else` block holding just the `if`.
+            let start = self.peek_span()?;
+            let depth = self.depth;
+            self.nest()?;
+            let kind = self.parse_if()?;
+            self.depth = depth;
+            Some(vec![Stmt {
+                kind,
+                span: start.to(self.prev_span),
+            }])
+        } else {
+            Some(self.parse_block()?)
+        };
+
+        Ok(StmtKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        })
+    }
+
+    fn parse_while(&mut self) -> ParseResult<StmtKind> {
+        self.expect(Token::KwWhile)?;
+        let cond = self.parse_condition()?;
+        let body = self.parse_block()?;
+
+        Ok(StmtKind::While { cond, body })
+    }
+
+    fn parse_for(&mut self) -> ParseResult<StmtKind> {
+        self.expect(Token::KwFor)?;
+        self.expect(Token::Lparen)?;
+        let var = self.expect_identifier()?;
+        self.expect(Token::KwIn)?;
+        let start = self.parse_expr(PREC_LOWEST)?;
+        self.expect(Token::DotDot)?;
+        let end = self.parse_expr(PREC_LOWEST)?;
+        self.expect(Token::RParen)?;
+        let body = self.parse_block()?;
+
+        Ok(StmtKind::For {
+            var,
+            start,
+            end,
+            body,
+        })
+    }
+
+    fn parse_import(&mut self) -> ParseResult<StmtKind> {
+        self.expect(Token::KwImport)?;
+        let path = match self.peek()? {
+            Token::StrLiteral(_) | Token::Identifier(_) => match self.advance()? {
+                Token::StrLiteral(path) => path.into_owned(),
+                Token::Identifier(name) => format!("{}.cpl", name),
+                _ => unreachable!(),
+            },
+            _ => return self.unexpected("path"),
+        };
+        self.expect(Token::SemiColon)?;
+
+        Ok(StmtKind::Import(path))
+    }
+
+    fn parse_return(&mut self) -> ParseResult<StmtKind> {
+        self.expect(Token::KwReturn)?;
+        if self.eat(&Token::SemiColon)? {
+            return Ok(StmtKind::Return(None));
+        }
+
+        let value = self.parse_expr(PREC_LOWEST)?;
+        self.expect(Token::SemiColon)?;
+        Ok(StmtKind::Return(Some(value)))
+    }
+
+    fn parse_condition(&mut self) -> ParseResult<Expr> {
+        self.expect(Token::Lparen)?;
+        let cond = self.parse_expr(PREC_LOWEST)?;
+        self.expect(Token::RParen)?;
+
+        Ok(cond)
+    }
+
+    fn parse_block(&mut self) -> ParseResult<Block> {
+        let depth = self.depth;
+        self.nest()?;
+        self.expect(Token::LSquirly)?;
+        let mut stmts = Vec::new();
+        while !matches!(
+            self.tokens.peek(),
+            Ok(next) if matches!(next.token, Token::TokEof | Token::RSquirly)
+        ) {
+            stmts.push(self.parse_stmt_or_recover());
+        }
+        self.expect(Token::RSquirly)?;
+
+        self.depth = depth;
+        Ok(stmts)
+    }
+
+    fn parse_expr(&mut self, min_prec: u8) -> ParseResult<Expr> {
+        let (depth, operators) = (self.depth, self.operators);
+        self.nest()?;
+        let mut lhs = self.parse_prefix()?;
+
+        while let Some((op, prec)) = infix_op(self.peek()?) {
+            if prec <= min_prec {
+                break;
+            }
+            self.join()?;
+            self.advance()?;
+            let rhs = self.parse_expr(prec)?;
+            let span = lhs.span.to(rhs.span);
+            lhs = self.expr(
+                ExprKind::Binary {
+                    op,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                },
+                span,
+            );
+        }
+
+        (self.depth, self.operators) = (depth, operators);
+        Ok(lhs)
+    }
+
+    fn parse_prefix(&mut self) -> ParseResult<Expr> {
+        let op = match self.peek()? {
+            Token::OpMinus => UnaryOp::Neg,
+            Token::OpNot => UnaryOp::Not,
+            Token::OpBitNot => UnaryOp::BitNot,
+            _ => return self.parse_postfix(),
+        };
+        let start = self.peek_span()?;
+        self.advance()?;
+        let expr = self.parse_expr(PREC_PREFIX)?;
+        let span = start.to(expr.span);
+
+        Ok(self.expr(
+            ExprKind::Unary {
+                op,
+                expr: Box::new(expr),
+            },
+            span,
+        ))
+    }
+
+    fn parse_postfix(&mut self) -> ParseResult<Expr> {
+        let depth = self.depth;
+        let mut expr = self.parse_primary()?;
+
+        loop {
+            let start = expr.span;
+            if matches!(self.peek()?, Token::Lparen | Token::LBracket | Token::Dot) {
+                self.nest()?;
+            }
+            let kind = if self.eat(&Token::Lparen)? {
+                let args = self.parse_list(Token::RParen)?;
+                ExprKind::Call {
+                    callee: Box::new(expr),
+                    args,
+                }
+            } else if self.eat(&Token::LBracket)? {
+                let index = self.parse_expr(PREC_LOWEST)?;
+                self.expect(Token::RBracket)?;
+                ExprKind::Index {
+                    expr: Box::new(expr),
+                    index: Box::new(index),
+                }
+            } else if self.eat(&Token::Dot)? {
+                ExprKind::Field {
+                    expr: Box::new(expr),
+                    field: self.expect_identifier()?,
+                }
+            } else {
+                self.depth = depth;
+                return Ok(expr);
+            };
+            expr = self.expr(kind, start.to(self.prev_span));
+        }
+    }
+
+    /// Parses comma-separated expressions up to and including `close`,
+    /// allowing a trailing comma.
+    fn parse_list(&mut self, close: Token) -> ParseResult<Vec<Expr>> {
+        let mut exprs = Vec::new();
+        while !self.at(&close)? {
+            exprs.push(self.parse_expr(PREC_LOWEST)?);
+            if !self.eat(&Token::Comma)? {
+                break;
+            }
+        }
+        self.expect(close)?;
+
+        Ok(exprs)
+    }
+
+    fn parse_struct_literal(&mut self) -> ParseResult<Expr> {
+        let name = self.expect_identifier()?;
+        self.expect(Token::LSquirly)?;
+
+        let mut fields = Vec::new();
+        while !self.at(&Token::RSquirly)? {
+            let name = self.expect_identifier()?;
+            self.expect(Token::Colon)?;
+            let value = self.parse_expr(PREC_LOWEST)?;
+            fields.push(FieldInit { name, value });
+            if !self.eat(&Token::Comma)? {
+                break;
+            }
+        }
+        self.expect(Token::RSquirly)?;
+
+        let span = name.span.to(self.prev_span);
+        Ok(self.expr(ExprKind::Struct { name, fields }, span))
+    }
+
+    /// Joins the parts of a string literal with `+`, leaving out empty text.
+    /// Only the expressions inside keep spans of their own.
+    fn parse_interpolated(&mut self, parts: Vec<StrPart<'src>>) -> ParseResult<Expr> {
+        let span = self.prev_span;
+        let (depth, operators) = (self.depth, self.operators);
+        let mut joined: Option<Expr> = None;
+        for part in parts {
+            let part = match part {
+                StrPart::Text(text) if text.is_empty() => continue,
+                StrPart::Text(text) => self.expr(ExprKind::Str(text.into_owned()), span),
+                StrPart::Expr(tokens) => {
+                    let outer = self.depth;
+                    self.nest()?;
+                    self.tokens.splice(tokens);
+                    let expr = self.parse_expr(PREC_LOWEST)?;
+                    self.expect(Token::TokEof)?;
+                    self.depth = outer;
+                    self.expr(ExprKind::Interpolation(Box::new(expr)), span)
+                }
+            };
+            if joined.is_some() {
+                self.join()?;
+            }
+            joined = Some(match joined {
+                Some(lhs) => self.expr(
+                    ExprKind::Binary {
+                        op: BinaryOp::Add,
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(part),
+                    },
+                    span,
+                ),
+                None => part,
+            });
+        }
+
+        self.prev_span = span;
+        (self.depth, self.operators) = (depth, operators);
+        Ok(joined.expect("an interpolated literal has an expression"))
+    }
+
+    fn parse_primary(&mut self) -> ParseResult<Expr> {
+        let brace_next = self.tokens.peek_n(1)?.token == Token::LSquirly;
+        let kind = match self.peek()? {
+            Token::IntLiteral(n) => ExprKind::Int(*n),
+            Token::FloatLiteral(n) => ExprKind::Float(*n),
+            Token::CharLiteral(ch) => ExprKind::Char(*ch),
+            Token::KwTrue => ExprKind::Bool(true),
+            Token::KwFalse => ExprKind::Bool(false),
+            Token::StrLiteral(_) => match self.advance()? {
+                Token::StrLiteral(value) => {
+                    return Ok(self.expr(ExprKind::Str(value.into_owned()), self.prev_span))
+                }
+                _ => unreachable!(),
+            },
+            Token::InterpolatedStr(_) => match self.advance()? {
+                Token::InterpolatedStr(parts) => return self.parse_interpolated(parts),
+                _ => unreachable!(),
+            },
+            // Conditions are parenthesized, so a name followed by a brace is
+            // always a struct literal.
+            Token::Identifier(_) if brace_next => {
+                return self.parse_struct_literal();
+            }
+            Token::Identifier(_) => {
+                let ident = self.expect_identifier()?;
+                return Ok(Expr {
+                    kind: ExprKind::Ident(ident.name),
+                    span: ident.span,
+                    id: ident.id,
+                });
+            }
+            Token::Lparen => {
+                self.advance()?;
+                let expr = self.parse_expr(PREC_LOWEST)?;
+                self.expect(Token::RParen)?;
+                return Ok(expr);
+            }
+            Token::KwIf => return self.parse_if_expr(),
+            Token::KwMatch => return self.parse_match(),
+            Token::KwFn => {
+                let start = self.peek_span()?;
+                let decl = self.parse_fn()?;
+                return Ok(self.expr(ExprKind::Fn(Box::new(decl)), start.to(self.prev_span)));
+            }
+            Token::LBracket => {
+                let start = self.peek_span()?;
+                self.advance()?;
+                let elems = self.parse_list(Token::RBracket)?;
+                return Ok(self.expr(ExprKind::Array(elems), start.to(self.prev_span)));
+            }
+            _ => return self.unexpected("expression"),
+        };
+        self.advance()?;
+
+        Ok(self.expr(kind, self.prev_span))
+    }
+
+    /// `if (cond) a else b`. The `else` arm takes in all it can, as the body
+    /// of a `fn` expression does.
+    fn parse_if_expr(&mut self) -> ParseResult<Expr> {
+        let start = self.peek_span()?;
+        self.expect(Token::KwIf)?;
+        let cond = self.parse_condition()?;
+        let then_branch = self.parse_expr(PREC_LOWEST)?;
+        self.expect(Token::KwElse)?;
+        let else_branch = self.parse_expr(PREC_LOWEST)?;
+
+        let kind = ExprKind::If {
+            cond: Box::new(cond),
+            then_branch: Box::new(then_branch),
+            else_branch: Box::new(else_branch),
+        };
+        Ok(self.expr(kind, start.to(self.prev_span)))
+    }
+
+    /// `match (value) { pattern => result, ... }`, with at least one arm.
+    fn parse_match(&mut self) -> ParseResult<Expr> {
+        let start = self.peek_span()?;
+        self.expect(Token::KwMatch)?;
+        let scrutinee = self.parse_condition()?;
+        self.expect(Token::LSquirly)?;
+        let mut arms = Vec::new();
+        loop {
+            let pattern = self.parse_pattern()?;
+            self.expect(Token::FatArrow)?;
+            let body = self.parse_expr(PREC_LOWEST)?;
+            arms.push(MatchArm { pattern, body });
+            if !self.eat(&Token::Comma)? || self.at(&Token::RSquirly)? {
+                break;
+            }
+        }
+        self.expect(Token::RSquirly)?;
+
+        let kind = ExprKind::Match {
+            scrutinee: Box::new(scrutinee),
+            arms,
+        };
+        Ok(self.expr(kind, start.to(self.prev_span)))
+    }
+
+    fn parse_pattern(&mut self) -> ParseResult<Pattern> {
+        let start = self.peek_span()?;
+        let kind = match self.peek()? {
+            Token::IntLiteral(n) => PatternKind::Int(*n),
+            Token::OpMinus => match self.tokens.peek_n(1)?.token {
+                Token::IntLiteral(n) => {
+                    self.advance()?;
+                    PatternKind::Int(n.wrapping_neg())
+                }
+                _ => return self.unexpected("pattern"),
+            },
+            Token::CharLiteral(ch) => PatternKind::Char(*ch),
+            Token::KwTrue => PatternKind::Bool(true),
+            Token::KwFalse => PatternKind::Bool(false),
+            Token::StrLiteral(_) => match self.advance()? {
+                Token::StrLiteral(value) => PatternKind::Str(value.into_owned()),
+                _ => unreachable!(),
+            },
+            Token::Identifier(name) if name.as_str() == "_" => PatternKind::Wildcard,
+            Token::Identifier(_) => PatternKind::Binding(self.expect_identifier()?),
+            _ => return self.unexpected("pattern"),
+        };
+        if !matches!(kind, PatternKind::Str(_) | PatternKind::Binding(_)) {
+            self.advance()?;
+        }
+
+        Ok(Pattern {
+            kind,
+            span: start.to(self.prev_span),
+        })
+    }
+}
+
+/// Returns `Some(None)` for `=` and `Some(Some(op))` for a compound assignment.
+fn assignment_op(token: &Token) -> Option<Option<BinaryOp>> {
+    Some(match token {
+        Token::Assignment => None,
+        Token::PlusAssign => Some(BinaryOp::Add),
+        Token::MinusAssign => Some(BinaryOp::Sub),
+        Token::MultAssign => Some(BinaryOp::Mul),
+        Token::DivAssign => Some(BinaryOp::Div),
+        Token::ModAssign => Some(BinaryOp::Mod),
+        _ => return None,
+    })
+}
+
+fn infix_op(token: &Token) -> Option<(BinaryOp, u8)> {
+    Some(match token {
+        Token::OpOr => (BinaryOp::Or, PREC_OR),
+        Token::OpAnd => (BinaryOp::And, PREC_AND),
+        Token::OpBitOr => (BinaryOp::BitOr, PREC_BIT_OR),
+        Token::OpBitXor => (BinaryOp::BitXor, PREC_BIT_XOR),
+        Token::OpBitAnd => (BinaryOp::BitAnd, PREC_BIT_AND),
+        Token::OpEq => (BinaryOp::Eq, PREC_EQUALITY),
+        Token::OpNe => (BinaryOp::Ne, PREC_EQUALITY),
+        Token::OpGt => (BinaryOp::Gt, PREC_COMPARISON),
+        Token::OpGe => (BinaryOp::Ge, PREC_COMPARISON),
+        Token::OpLt => (BinaryOp::Lt, PREC_COMPARISON),
+        Token::OpLe => (BinaryOp::Le, PREC_COMPARISON),
+        Token::OpShl => (BinaryOp::Shl, PREC_SHIFT),
+        Token::OpShr => (BinaryOp::Shr, PREC_SHIFT),
+        Token::OpPlus => (BinaryOp::Add, PREC_SUM),
+        Token::OpMinus => (BinaryOp::Sub, PREC_SUM),
+        Token::OpMult => (BinaryOp::Mul, PREC_PRODUCT),
+        Token::OpDiv => (BinaryOp::Div, PREC_PRODUCT),
+        Token::OpMod => (BinaryOp::Mod, PREC_PRODUCT),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ParseError, Parser};
+    use crate::ast::StmtKind;
+    use crate::lexer::{LexError, Lexer, Token};
+    use crate::span::Span;
+
+    fn parse(input: &str) -> String {
+        Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.")
+            .to_string()
+    }
+
+    #[test]
+    fn test_let() {
+        assert_eq!(
+            parse("let a = 5; let b = 2.5; let c = \"hi\";"),
+            "(let a 5)\n(let b 2.5)\n(let c \"hi\")\n"
+        );
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(
+            parse("1 + 2 * 3 == -x || !y && z;"),
+            "(|| (== (+ 1 (* 2 3)) (- x)) (&& (! y) z))\n"
+        );
+    }
+
+    #[test]
+    fn test_bitwise_precedence() {
+        assert_eq!(
+            parse("a | b ^ c & d == 1 << 2 + 3 && ~e;"),
+            "(&& (| a (^ b (& c (== d (<< 1 (+ 2 3)))))) (~ e))\n"
+        );
+    }
+
+    #[test]
+    fn test_left_associativity() {
+        assert_eq!(parse("a - b - (c - d);"), "(- (- a b) (- c d))\n");
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let program = parse(
+            "
+            fn add(x, y) {
+                return x + y;
+            }
+
+            ? comments are skipped
+            while (true) {
+                if (add(1, 2) >= 3) {
+                    break;
+                } else {
+                    a = 1;
+                    a *= 2;
+                }
+            }
+            ",
+        );
+
+        assert_eq!(
+            program,
+            "(fn add (x y) (block (return (+ x y))))\n\
+             (while true (block (if (>= (call add 1 2) 3) (block (break)) \
+             (block (= a 1) (*= a 2)))))\n"
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        assert_eq!(
+            parse("if (a) { b; } else if (c) { d; } else { e; }"),
+            "(if a (block b) (block (if c (block d) (block e))))\n"
+        );
+        assert_eq!(
+            parse("let x = if (a) if (b) 1 else 2 else 3 + 4; f(if (c) d else e) * 2;"),
+            "(let x (if a (if b 1 2) (+ 3 4)))\n(* (call f (if c d e)) 2)\n"
+        );
+
+        let mut parser = Parser::new(Lexer::new("let x = if (a) 1;"));
+        assert_eq!(
+            parser.parse_program(),
+            Err(vec![ParseError::UnexpectedToken {
+                expected: "`else`".into(),
+                found: Token::SemiColon,
+                span: Span::new(16, 17, 1, 17),
+            }])
+        );
+    }
+
+    #[test]
+    fn test_match() {
+        assert_eq!(
+            parse("let s = match (n % 3) { 0 => \"a\", -1 => b + 1, 'c' => x, true => y, n => n, _ => z, };"),
+            "(let s (match (% n 3) (0 \"a\") (-1 (+ b 1)) ('c' x) (true y) (n n) (_ z)))\n"
+        );
+        assert_eq!(
+            parse("f(match (x) { \"s\" => 1 }) + 2;"),
+            "(+ (call f (match x (\"s\" 1))) 2)\n"
+        );
+
+        let mut parser = Parser::new(Lexer::new("match (x) {};"));
+        assert_eq!(
+            parser.parse_program(),
+            Err(vec![ParseError::UnexpectedToken {
+                expected: "pattern".into(),
+                found: Token::RSquirly,
+                span: Span::new(11, 12, 1, 12),
+            }])
+        );
+        let mut parser = Parser::new(Lexer::new("match (x) { 1 2 };"));
+        assert_eq!(
+            parser.parse_program().unwrap_err()[0],
+            ParseError::UnexpectedToken {
+                expected: "`=>`".into(),
+                found: Token::IntLiteral(2),
+                span: Span::new(14, 15, 1, 15),
+            }
+        );
+    }
+
+    #[test]
+    fn test_for_loops() {
+        assert_eq!(
+            parse("for (i in 0..len(a) - 1) { if (a[i]) { continue; } }"),
+            "(for i (.. 0 (- (call len a) 1)) (block (if (index a i) (block (continue)))))\n"
+        );
+    }
+
+    #[test]
+    fn test_imports() {
+        assert_eq!(
+            parse("import \"lib/math.cpl\"; import util;"),
+            "(import \"lib/math.cpl\")\n(import \"util.cpl\")\n"
+        );
+    }
+
+    #[test]
+    fn test_interpolation() {
+        assert_eq!(
+            parse(r#"let s = "${a} + ${b[0]} = ${a + b[0]}!"; "${"${x}"}".len;"#),
+            "(let s (+ (+ (+ (+ (+ (interpolate a) \" + \") (interpolate (index b 0))) \" = \") \
+             (interpolate (+ a (index b 0)))) \"!\"))\n\
+             (. (interpolate (interpolate x)) len)\n"
+        );
+
+        let mut parser = Parser::new(Lexer::new(r#"let s = "${}";"#));
+        assert_eq!(
+            parser.parse_program(),
+            Err(vec![ParseError::UnexpectedEof {
+                expected: "expression".into(),
+                span: Span::new(11, 12, 1, 12),
+            }])
+        );
+    }
+
+    #[test]
+    fn test_arrays() {
+        assert_eq!(
+            parse("let a: [[int]] = [[1, 2], [],]; a[0][f(1)] += -a[1][0]; [x][0];"),
+            "(let a:[[int]] (array (array 1 2) (array)))\n\
+             (+= (index (index a 0) (call f 1)) (- (index (index a 1) 0)))\n\
+             (index (array x) 0)\n"
+        );
+    }
+
+    #[test]
+    fn test_structs() {
+        assert_eq!(
+            parse("struct P { x: int, next: [P], } let p: P = P { x: 1, next: [], }; p.next[0].x *= f(p).x; P {}.y;"),
+            "(struct P (x:int next:[P]))\n\
+             (let p:P (new P (x 1) (next (array))))\n\
+             (*= (. (index (. p next) 0) x) (. (call f p) x))\n\
+             (. (new P) y)\n"
+        );
+        assert_eq!(
+            parse("if (a) { b.c = d; } while (x) {}"),
+            "(if a (block (= (. b c) d)))\n(while x (block))\n"
+        );
+    }
+
+    #[test]
+    fn test_fn_expressions() {
+        assert_eq!(
+            parse("let f = fn(x: int): int { return x; }; fn (a) {}(1); let g = fn fact(n) { return fact; };"),
+            "(let f (fn <anonymous> (x:int):int (block (return x))))\n\
+             (call (fn <anonymous> (a) (block)) 1)\n\
+             (let g (fn fact (n) (block (return fact))))\n"
+        );
+    }
+
+    #[test]
+    fn test_type_annotations() {
+        assert_eq!(
+            parse("let a: int = 1; fn f(g: fn(int): bool, s: str): void {}"),
+            "(let a:int 1)\n(fn f (g:fn(int):bool s:str):void (block))\n"
+        );
+    }
+
+    #[test]
+    fn test_spans_and_ids() {
+        let program = Parser::new(Lexer::new("let a = f(1, b);\n{ a += 2; }"))
+            .parse_program()
+            .unwrap();
+
+        let (name, value) = match &program.stmts[0].kind {
+            StmtKind::Let { name, value, .. } => (name, value),
+            other => panic!("expected a let, found {:?}", other),
+        };
+        assert_eq!(program.stmts[0].span, Span::new(0, 16, 1, 1));
+        assert_eq!(name.span, Span::new(4, 5, 1, 5));
+        assert_eq!(value.span, Span::new(8, 15, 1, 9));
+        assert_ne!(name.id, value.id);
+        assert_eq!(program.stmts[1].span, Span::new(17, 28, 2, 1));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut parser = Parser::new(Lexer::new("let = 5;"));
+        assert_eq!(
+            parser.parse_program(),
+            Err(vec![ParseError::UnexpectedToken {
+                expected: "identifier".into(),
+                found: Token::Assignment,
+                span: Span::new(4, 5, 1, 5),
+            }])
+        );
+
+        let mut parser = Parser::new(Lexer::new("let s = \"abc;"));
+        assert_eq!(
+            parser.parse_program(),
+            Err(vec![ParseError::Lex(LexError::UnterminatedString(
+                Span::new(8, 13, 1, 9)
+            ))])
+        );
+
+        let mut parser = Parser::new(Lexer::new("fn f() {"));
+        assert_eq!(
+            parser.parse_program(),
+            Err(vec![ParseError::UnexpectedEof {
+                expected: "`}`".into(),
+                span: Span::new(8, 8, 1, 9),
+            }])
+        );
+    }
+
+    #[test]
+    fn test_depth_limits() {
+        let too_deep = |input: &str| {
+            let mut parser = Parser::new(Lexer::new(input)).with_max_depth(8);
+            match parser.parse_program() {
+                Err(errors) => matches!(errors[..], [ParseError::TooDeep { limit: 8, .. }]),
+                Ok(_) => false,
+            }
+        };
+        assert!(!too_deep("return (((((((1)))))));"));
+        assert!(too_deep("return ((((((((1))))))));"));
+        assert!(too_deep("return --------1;"));
+        assert!(too_deep("{{{{{{{{{}}}}}}}}} let x = 1;"));
+        assert!(too_deep("let a: [[[[[[[[int]]]]]]]] = [];"));
+        // Chains are as deep as their operands.
+        assert!(!too_deep(&format!(
+            "return {};",
+            vec!["1"; 1000].join(" + ")
+        )));
+        assert!(too_deep("a.b.c.d.e.f.g.h.i;"));
+
+        let nested = format!("{}1{};", "\"${".repeat(100), "}\"".repeat(100));
+        let errors = Parser::new(Lexer::new(&nested))
+            .parse_program()
+            .unwrap_err();
+        assert!(matches!(
+            errors[..],
+            [ParseError::Lex(LexError::NestingTooDeep(_))]
+        ));
+    }
+
+    #[test]
+    fn test_operator_limits() {
+        let too_long = |input: &str| {
+            let mut parser = Parser::new(Lexer::new(input)).with_max_operators(8);
+            match parser.parse_program() {
+                Err(errors) => matches!(errors[..], [ParseError::TooLong { limit: 8, .. }]),
+                Ok(_) => false,
+            }
+        };
+        let chain = |terms: usize| format!("return {};", vec!["1"; terms].join(" + "));
+        assert!(!too_long(&chain(9)));
+        assert!(too_long(&chain(10)));
+        // Operators count across nested expressions, but not across siblings.
+        assert!(too_long(
+            "return 1 * 2 + f(3 - (4 / 5 % 6 & 7 | 8 + 9 + 10)) ^ 11;"
+        ));
+        assert!(!too_long(
+            "f(1 + 2 + 3 + 4 + 5, 6 + 7 + 8 + 9 + 10); let x = [1 + 2 + 3 + 4 + 5];"
+        ));
+        let joined = |parts: usize| format!("let s = \"{}\";", "${a}".repeat(parts));
+        assert!(!too_long(&joined(9)));
+        assert!(too_long(&joined(10)));
+
+        let errors = Parser::new(Lexer::new(&chain(Parser::DEFAULT_MAX_OPERATORS + 2)))
+            .parse_program()
+            .unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "expression has more than 1024 operators"
+        );
+    }
+
+    #[test]
+    fn test_recovery() {
+        let mut parser = Parser::new(Lexer::new(
+            "let = 1;\nfn f() { let y 2; return y; }\nif (1 { a; }\n} let z = 3;",
+        ));
+        let (program, errors) = parser.parse_partial();
+        let spans: Vec<_> = errors.iter().map(|err| err.span().line).collect();
+        assert_eq!(spans, [1, 2, 3, 4]);
+        assert_eq!(
+            program.to_string(),
+            "(error)\n(fn f () (block (error) (return y)))\n(error)\n(error)\n(let z 3)\n"
+        );
+
+        // Errors inside an interpolation do not end the input.
+        let mut parser = Parser::new(Lexer::new("let s = \"${1 +}\"; let t = 2;"));
+        let (program, errors) = parser.parse_partial();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(program.to_string(), "(error)\n(let t 2)\n");
+    }
+
+    #[test]
+    fn test_extern() {
+        assert_eq!(
+            parse("extern fn now(): int; extern fn log(msg: str, level);"),
+            "(extern now ():int)\n(extern log (msg:str level))\n"
+        );
+
+        let mut parser = Parser::new(Lexer::new("extern fn f() {}"));
+        assert_eq!(
+            parser.parse_program().unwrap_err()[0],
+            ParseError::UnexpectedToken {
+                expected: "`;`".into(),
+                found: Token::LSquirly,
+                span: Span::new(14, 15, 1, 15),
+            }
+        );
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let source = "\
+?? A point.
+struct P { x: int, }
+?? Adds.
+?? Twice.
+fn add(a, b) { ?? not a declaration
+    let f = fn() {};
+}
+?? Now.
+extern fn now(): int;
+let x = 1;
+fn g() {}";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let docs: Vec<_> = program
+            .stmts
+            .iter()
+            .filter_map(|stmt| match &stmt.kind {
+                StmtKind::Fn(decl) | StmtKind::Extern(decl) => Some(decl.doc.as_deref()),
+                StmtKind::Struct(decl) => Some(decl.doc.as_deref()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            docs,
+            [Some("A point."), Some("Adds.\nTwice."), Some("Now."), None]
+        );
+    }
+
+    #[test]
+    fn test_const() {
+        assert_eq!(
+            parse("const a: int = 1 + 2; { const b = a; }"),
+            "(const a:int (+ 1 2))\n(block (const b a))\n"
+        );
+    }
+
+    #[test]
+    fn test_assignment_targets() {
+        assert_eq!(
+            parse("(x) += 1; a[0].y = 2; f().z = 3;"),
+            "(+= x 1)\n(= (. (index a 0) y) 2)\n(= (. (call f) z) 3)\n"
+        );
+
+        let mut parser = Parser::new(Lexer::new("5 = x; f() = 1; a + b -= 2;"));
+        assert_eq!(
+            parser.parse_program(),
+            Err(vec![
+                ParseError::InvalidAssignment(Span::new(0, 1, 1, 1)),
+                ParseError::InvalidAssignment(Span::new(7, 10, 1, 8)),
+                ParseError::InvalidAssignment(Span::new(16, 21, 1, 17)),
+            ])
+        );
+    }
+}
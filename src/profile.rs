@@ -0,0 +1,273 @@
+//! Variations on compyl's surface syntax, for experimenting with it without
+//! forking the lexer. A [`LanguageProfile`] can change the character that
+//! starts comments, turn keywords back into identifiers, make operators
+//! errors, and add keywords that stand for existing ones. A lexer follows the
+//! profile it is given with [`Lexer::with_profile`]; everything after it sees
+//! the same tokens as ever.
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use compyl::lexer::Lexer;
+//! use compyl::parser::Parser;
+//! use compyl::profile::LanguageProfile;
+//!
+//! let profile = LanguageProfile::new()
+//!     .with_comment('#')?
+//!     .with_keyword("var", "let")?
+//!     .without_keyword("let")?
+//!     .without_operator("<<")?;
+//! let lexer = Lexer::new("var let = 1; # `let` is a name now").with_profile(Arc::new(profile));
+//! let program = Parser::new(lexer).parse_program().unwrap();
+//! assert_eq!(program.to_string(), "(let let 1)\n");
+//! # Ok::<(), compyl::profile::ProfileError>(())
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+
+use crate::lexer::{self, is_ident_continue, is_ident_start, LexError, Lexer, Token};
+
+/// The surface syntax a [`Lexer`] accepts. The default is the standard one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguageProfile {
+    comment: u8,
+    /// Keywords lexed as identifiers instead.
+    disabled_keywords: HashSet<String>,
+    disabled_operators: HashSet<String>,
+    /// Extra keywords, each with the standard keyword it stands for.
+    keywords: HashMap<String, String>,
+}
+
+impl Default for LanguageProfile {
+    fn default() -> Self {
+        LanguageProfile {
+            comment: b'?',
+            disabled_keywords: HashSet::new(),
+            disabled_operators: HashSet::new(),
+            keywords: HashMap::new(),
+        }
+    }
+}
+
+impl LanguageProfile {
+    pub fn new() -> LanguageProfile {
+        LanguageProfile::default()
+    }
+
+    /// Starts comments with `leader` instead of `?`: `#` makes `# line`,
+    /// `## doc` and `#* block *#` comments. The leader must be ASCII
+    /// punctuation that starts no other token.
+    pub fn with_comment(mut self, leader: char) -> Result<LanguageProfile, ProfileError> {
+        let starts_token = !matches!(
+            Lexer::new(leader.encode_utf8(&mut [0; 4])).next_token(),
+            Err(LexError::UnexpectedChar(..))
+        );
+        if !leader.is_ascii_punctuation() || (starts_token && leader != '?') {
+            return Err(ProfileError::InvalidComment(leader));
+        }
+        self.comment = leader as u8;
+        Ok(self)
+    }
+
+    /// Lexes the keyword `keyword` as an identifier.
+    pub fn without_keyword(mut self, keyword: &str) -> Result<LanguageProfile, ProfileError> {
+        if lexer::keyword(keyword).is_none() {
+            return Err(ProfileError::UnknownKeyword(keyword.to_string()));
+        }
+        self.disabled_keywords.insert(keyword.to_string());
+        Ok(self)
+    }
+
+    /// Makes the operator `op`, such as `<<` or `+=`, an error to use.
+    pub fn without_operator(mut self, op: &str) -> Result<LanguageProfile, ProfileError> {
+        let mut lexer = Lexer::new(op);
+        let is_operator = match (lexer.next_token(), lexer.next_token()) {
+            (Ok(first), Ok(second)) => {
+                lexer::operator(&first.token) == Some(op) && second.token == Token::TokEof
+            }
+            _ => false,
+        };
+        if !is_operator {
+            return Err(ProfileError::UnknownOperator(op.to_string()));
+        }
+        self.disabled_operators.insert(op.to_string());
+        Ok(self)
+    }
+
+    /// Lexes `word` as the standard keyword `like`, even once `like` itself
+    /// is disabled. Otherwise `word` would be an identifier.
+    pub fn with_keyword(mut self, word: &str, like: &str) -> Result<LanguageProfile, ProfileError> {
+        let mut chars = word.chars();
+        let is_identifier =
+            chars.next().is_some_and(is_ident_start) && chars.all(is_ident_continue);
+        if !is_identifier {
+            return Err(ProfileError::InvalidKeyword(word.to_string()));
+        }
+        if lexer::keyword(like).is_none() {
+            return Err(ProfileError::UnknownKeyword(like.to_string()));
+        }
+        self.keywords.insert(word.to_string(), like.to_string());
+        Ok(self)
+    }
+
+    pub(crate) fn comment(&self) -> u8 {
+        self.comment
+    }
+
+    /// The keyword `word` is, if it is one in this profile.
+    pub(crate) fn keyword(&self, word: &str) -> Option<Token<'static>> {
+        if let Some(like) = self.keywords.get(word) {
+            return lexer::keyword(like);
+        }
+        match self.disabled_keywords.contains(word) {
+            true => None,
+            false => lexer::keyword(word),
+        }
+    }
+
+    pub(crate) fn allows_operator(&self, op: &str) -> bool {
+        !self.disabled_operators.contains(op)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileError {
+    InvalidComment(char),
+    UnknownKeyword(String),
+    UnknownOperator(String),
+    /// A new keyword that is not spelled like an identifier.
+    InvalidKeyword(String),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::InvalidComment(ch) => write!(
+                f,
+                "`{}` cannot start comments: it must be punctuation that starts no other token",
+                ch
+            ),
+            ProfileError::UnknownKeyword(word) => write!(f, "`{}` is not a keyword", word),
+            ProfileError::UnknownOperator(op) => write!(f, "`{}` is not an operator", op),
+            ProfileError::InvalidKeyword(word) => {
+                write!(
+                    f,
+                    "`{}` cannot be a keyword, as it is not an identifier",
+                    word
+                )
+            }
+        }
+    }
+}
+
+impl Error for ProfileError {}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{LanguageProfile, ProfileError};
+    use crate::lexer::{LexError, Lexer, LexerConfig, Token};
+    use crate::parser::Parser;
+    use crate::span::Span;
+
+    fn tokens(profile: &LanguageProfile, input: &str) -> Vec<Result<Token<'static>, LexError>> {
+        let config = LexerConfig {
+            keep_comments: true,
+        };
+        let mut lexer = Lexer::with_config(input, config).with_profile(Arc::new(profile.clone()));
+        let mut tokens = Vec::new();
+        loop {
+            let token = lexer.next_token().map(|token| token.token.into_owned());
+            if token == Ok(Token::TokEof) {
+                return tokens;
+            }
+            tokens.push(token);
+        }
+    }
+
+    #[test]
+    fn test_profiles() {
+        let profile = LanguageProfile::new()
+            .with_comment('#')
+            .and_then(|profile| profile.without_keyword("fn"))
+            .and_then(|profile| profile.with_keyword("def", "fn"))
+            .and_then(|profile| profile.without_operator("+="))
+            .unwrap();
+        assert_eq!(
+            tokens(
+                &profile,
+                "def fn #* a #* nested *# one *#\n## doc\nx += \"${y += 1}\" ?"
+            ),
+            vec![
+                Ok(Token::KwFn),
+                Ok(Token::Identifier("fn".into())),
+                Ok(Token::Comment("#* a #* nested *# one *#".into())),
+                Ok(Token::DocComment("## doc".into())),
+                Ok(Token::Identifier("x".into())),
+                Err(LexError::DisabledOperator("+=", Span::new(41, 43, 3, 3))),
+                Err(LexError::DisabledOperator("+=", Span::new(49, 51, 3, 11))),
+                Err(LexError::UnexpectedChar('?', Span::new(56, 57, 3, 18))),
+            ]
+        );
+
+        // The standard profile changes nothing.
+        let source = "x += 1 ? note\n?? doc\nlet";
+        let mut lexer = Lexer::with_config(
+            source,
+            LexerConfig {
+                keep_comments: true,
+            },
+        );
+        let standard: Vec<_> = std::iter::from_fn(|| match lexer.next_token() {
+            Ok(token) if token.token == Token::TokEof => None,
+            token => Some(token.map(|token| token.token.into_owned())),
+        })
+        .collect();
+        assert_eq!(tokens(&LanguageProfile::new(), source), standard);
+    }
+
+    #[test]
+    fn test_errors() {
+        let profile = LanguageProfile::new();
+        assert_eq!(
+            profile.clone().with_comment('/'),
+            Err(ProfileError::InvalidComment('/'))
+        );
+        assert_eq!(
+            profile.clone().with_comment('a'),
+            Err(ProfileError::InvalidComment('a'))
+        );
+        assert_eq!(
+            profile.clone().without_keyword("lets"),
+            Err(ProfileError::UnknownKeyword("lets".into()))
+        );
+        assert_eq!(
+            profile.clone().without_operator("<<<"),
+            Err(ProfileError::UnknownOperator("<<<".into()))
+        );
+        assert_eq!(
+            profile.clone().without_operator(";"),
+            Err(ProfileError::UnknownOperator(";".into()))
+        );
+        assert_eq!(
+            profile.clone().with_keyword("1x", "let"),
+            Err(ProfileError::InvalidKeyword("1x".into()))
+        );
+        assert_eq!(
+            profile.with_keyword("var", "variable"),
+            Err(ProfileError::UnknownKeyword("variable".into()))
+        );
+    }
+
+    #[test]
+    fn test_send() {
+        // Lexers and parsers can be handed to other threads, profile or not.
+        fn assert_send<T: Send>(_: &T) {}
+        let lexer = Lexer::new("let x;").with_profile(Arc::new(LanguageProfile::new()));
+        assert_send(&lexer);
+        assert_send(&Parser::new(lexer));
+    }
+}
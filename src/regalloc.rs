@@ -0,0 +1,375 @@
+//! Register allocation: assigning each of a function's virtual registers a
+//! machine register or a stack slot.
+//!
+//! Allocators implement [`RegisterAllocator`], so a backend can take any of
+//! them; [`LinearScan`] is the default and [`SpillEverything`] the simplest.
+//! The caller describes its machine with a [`Machine`].
+
+use std::collections::BTreeSet;
+
+use crate::analysis;
+use crate::cfg::Cfg;
+use crate::ir::{Function, Inst, Operand, Reg, Ty};
+
+/// A machine register, named as the backend prints it.
+pub type PhysReg = &'static str;
+
+/// The registers of one kind that an allocator may hand out.
+#[derive(Debug, Clone)]
+pub struct RegisterClass {
+    /// Registers that keep their value across calls, which the function must
+    /// save if it uses them.
+    pub callee_saved: Vec<PhysReg>,
+    /// Registers that calls may overwrite.
+    pub caller_saved: Vec<PhysReg>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Machine {
+    /// For `int` and `bool` values.
+    pub int: RegisterClass,
+    pub float: RegisterClass,
+    /// Whether the backend compiles `inst` to a call, which overwrites the
+    /// caller-saved registers.
+    pub is_call: fn(&Function, &Inst) -> bool,
+}
+
+impl Machine {
+    fn class(&self, ty: Ty) -> &RegisterClass {
+        match ty {
+            Ty::Int | Ty::Bool => &self.int,
+            Ty::Float => &self.float,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Reg(PhysReg),
+    /// The index of an 8-byte stack slot.
+    Stack(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Allocation {
+    /// By register; `None` for registers the function never uses.
+    locations: Vec<Option<Location>>,
+    pub stack_slots: u32,
+}
+
+impl Allocation {
+    pub fn location(&self, reg: Reg) -> Location {
+        self.locations[reg.0 as usize].expect("only used registers have a location")
+    }
+
+    /// The callee-saved registers handed out, which the function must save.
+    pub fn callee_saved(&self, machine: &Machine) -> Vec<PhysReg> {
+        let mut used: BTreeSet<_> = self
+            .locations
+            .iter()
+            .filter_map(|location| match location {
+                Some(Location::Reg(reg)) => Some(*reg),
+                _ => None,
+            })
+            .collect();
+        [&machine.int, &machine.float]
+            .into_iter()
+            .flat_map(|class| &class.callee_saved)
+            .copied()
+            .filter(|reg| used.remove(reg))
+            .collect()
+    }
+}
+
+pub trait RegisterAllocator {
+    fn name(&self) -> &'static str;
+
+    fn allocate(&self, func: &Function, machine: &Machine) -> Allocation;
+}
+
+/// Where a register is live, as a range of instruction positions numbered
+/// through the function's blocks in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    pub reg: Reg,
+    pub start: usize,
+    pub end: usize,
+    /// Whether a call happens strictly inside the interval.
+    pub crosses_call: bool,
+}
+
+/// The live interval of each register the function uses, by start.
+pub fn intervals(func: &Function, machine: &Machine) -> Vec<Interval> {
+    let cfg = Cfg::new(func);
+    let liveness = analysis::liveness(func, &cfg);
+    let mut ranges: Vec<Option<(usize, usize)>> = vec![None; func.regs.len()];
+    let mut extend = |reg: Reg, pos: usize| {
+        let range = &mut ranges[reg.0 as usize];
+        *range = Some(match *range {
+            Some((start, end)) => (start.min(pos), end.max(pos)),
+            None => (pos, pos),
+        });
+    };
+
+    for &param in &func.params {
+        extend(param, 0);
+    }
+    let mut calls = Vec::new();
+    // Position 0 is the entry, before the first block.
+    let mut pos = 1;
+    for (id, block) in func.blocks.iter().enumerate() {
+        let start = pos;
+        for &reg in &liveness.live_in[id] {
+            extend(reg, start);
+        }
+        for inst in &block.insts {
+            for operand in inst.operands() {
+                if let Operand::Reg(reg) = operand {
+                    extend(*reg, pos);
+                }
+            }
+            if let Some(dst) = inst.dst() {
+                extend(dst, pos);
+            }
+            if (machine.is_call)(func, inst) {
+                calls.push(pos);
+            }
+            pos += 1;
+        }
+        if let Some(Operand::Reg(reg)) = block.term.operand() {
+            extend(*reg, pos);
+        }
+        for &reg in &liveness.live_out[id] {
+            extend(reg, pos);
+        }
+        pos += 1;
+    }
+
+    let mut intervals: Vec<Interval> = ranges
+        .into_iter()
+        .enumerate()
+        .filter_map(|(reg, range)| {
+            let (start, end) = range?;
+            Some(Interval {
+                reg: Reg(reg as u32),
+                start,
+                end,
+                crosses_call: calls.iter().any(|&call| start < call && call < end),
+            })
+        })
+        .collect();
+    intervals.sort_by_key(|interval| (interval.start, interval.reg));
+    intervals
+}
+
+/// Gives every register its own stack slot.
+pub struct SpillEverything;
+
+impl RegisterAllocator for SpillEverything {
+    fn name(&self) -> &'static str {
+        "spill-everything"
+    }
+
+    fn allocate(&self, func: &Function, machine: &Machine) -> Allocation {
+        let mut locations = vec![None; func.regs.len()];
+        let mut stack_slots = 0;
+        for interval in intervals(func, machine) {
+            locations[interval.reg.0 as usize] = Some(Location::Stack(stack_slots));
+            stack_slots += 1;
+        }
+
+        Allocation {
+            locations,
+            stack_slots,
+        }
+    }
+}
+
+/// Linear-scan allocation (Poletto and Sarkar): walks the live intervals in
+/// order of start, and when no register is free spills whichever interval
+/// ends last. Intervals that cross a call only get callee-saved registers.
+pub struct LinearScan;
+
+impl RegisterAllocator for LinearScan {
+    fn name(&self) -> &'static str {
+        "linear-scan"
+    }
+
+    fn allocate(&self, func: &Function, machine: &Machine) -> Allocation {
+        let mut allocation = Allocation {
+            locations: vec![None; func.regs.len()],
+            stack_slots: 0,
+        };
+        // The intervals holding a register, with the register.
+        let mut active: Vec<(Interval, PhysReg)> = Vec::new();
+
+        for interval in intervals(func, machine) {
+            active.retain(|(other, _)| other.end > interval.start);
+
+            let ty = func.regs[interval.reg.0 as usize];
+            let class = machine.class(ty);
+            // Caller-saved registers come first, as they cost no saving.
+            let allowed: Vec<PhysReg> = match interval.crosses_call {
+                true => class.callee_saved.clone(),
+                false => class
+                    .caller_saved
+                    .iter()
+                    .chain(&class.callee_saved)
+                    .copied()
+                    .collect(),
+            };
+            let free = allowed
+                .iter()
+                .copied()
+                .find(|reg| active.iter().all(|(_, taken)| taken != reg));
+            if let Some(reg) = free {
+                allocation.locations[interval.reg.0 as usize] = Some(Location::Reg(reg));
+                active.push((interval, reg));
+                continue;
+            }
+
+            // Spill the interval that ends last, among those whose register
+            // this one could take.
+            let victim = active
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, reg))| allowed.contains(reg))
+                .max_by_key(|(_, (other, _))| other.end)
+                .map(|(index, _)| index);
+            let slot = Location::Stack(allocation.stack_slots);
+            allocation.stack_slots += 1;
+            match victim {
+                Some(index) if active[index].0.end > interval.end => {
+                    let (spilled, reg) = active.swap_remove(index);
+                    allocation.locations[spilled.reg.0 as usize] = Some(slot);
+                    allocation.locations[interval.reg.0 as usize] = Some(Location::Reg(reg));
+                    active.push((interval, reg));
+                }
+                _ => allocation.locations[interval.reg.0 as usize] = Some(slot),
+            }
+        }
+
+        allocation
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        intervals, Interval, LinearScan, Location, Machine, RegisterAllocator, RegisterClass,
+        SpillEverything,
+    };
+    use crate::ir::{self, Function, Inst, Reg};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::{sema, types};
+
+    fn lower(input: &str) -> ir::Module {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.");
+        let resolution = sema::analyze(&program).expect("Program failed analysis.");
+        let types = types::check(&program, &resolution).expect("Program failed to type check.");
+        ir::lower(&program, &resolution, &types).expect("Program failed to lower.")
+    }
+
+    fn machine(callee_saved: &[&'static str], caller_saved: &[&'static str]) -> Machine {
+        fn is_call(_: &Function, inst: &Inst) -> bool {
+            matches!(inst, Inst::Call { .. })
+        }
+        let class = RegisterClass {
+            callee_saved: callee_saved.to_vec(),
+            caller_saved: caller_saved.to_vec(),
+        };
+        Machine {
+            int: class.clone(),
+            float: class,
+            is_call,
+        }
+    }
+
+    // %0 = a, %1 = b; %2 and %3 copy them, and %4 is the sum.
+    const ADD: &str = "fn add(a: int, b: int): int { return a + b; }";
+
+    #[test]
+    fn test_intervals() {
+        let module =
+            lower("fn g(): int { return 1; } fn f(x: int): int { let y = g(); return x + y; }");
+        let f = &module.functions[1];
+        let interval = |reg, start, end, crosses_call| Interval {
+            reg: Reg(reg),
+            start,
+            end,
+            crosses_call,
+        };
+
+        // `x` is read after the call at position 1, which defines `%1`.
+        assert_eq!(
+            intervals(f, &machine(&[], &["r"])),
+            [
+                interval(0, 0, 3, true),
+                interval(1, 1, 2, false),
+                interval(2, 2, 4, false),
+                interval(3, 3, 5, false),
+                interval(4, 4, 5, false),
+                interval(5, 5, 6, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_linear_scan() {
+        let module = lower(ADD);
+        let add = &module.functions[0];
+
+        let allocation = LinearScan.allocate(add, &machine(&[], &["a", "b", "c"]));
+        let locations: Vec<_> = (0..5).map(|reg| allocation.location(Reg(reg))).collect();
+        // Each register is free again once the interval holding it ends.
+        assert_eq!(
+            locations,
+            [
+                Location::Reg("a"),
+                Location::Reg("b"),
+                Location::Reg("a"),
+                Location::Reg("b"),
+                Location::Reg("a"),
+            ]
+        );
+        assert_eq!(allocation.stack_slots, 0);
+
+        // Three parameters are live at once, so with two registers the one
+        // read last, `c`, is spilled.
+        let module = lower("fn f(a: int, b: int, c: int): int { return a + b + c; }");
+        let allocation = LinearScan.allocate(&module.functions[0], &machine(&[], &["a", "b"]));
+        assert_eq!(allocation.location(Reg(0)), Location::Reg("a"));
+        assert_eq!(allocation.location(Reg(1)), Location::Reg("b"));
+        assert_eq!(allocation.location(Reg(2)), Location::Stack(0));
+        assert_eq!(allocation.stack_slots, 1);
+    }
+
+    #[test]
+    fn test_calls() {
+        let module =
+            lower("fn g(): int { return 1; } fn f(x: int): int { let y = g(); return x + y; }");
+        let f = &module.functions[1];
+
+        let allocation = LinearScan.allocate(f, &machine(&["saved"], &["scratch"]));
+        assert_eq!(allocation.location(Reg(0)), Location::Reg("saved"));
+        assert_eq!(allocation.location(Reg(1)), Location::Reg("scratch"));
+        assert_eq!(
+            allocation.callee_saved(&machine(&["saved"], &[])),
+            ["saved"]
+        );
+
+        let allocation = LinearScan.allocate(f, &machine(&[], &["scratch"]));
+        assert_eq!(allocation.location(Reg(0)), Location::Stack(0));
+    }
+
+    #[test]
+    fn test_spill_everything() {
+        let module = lower(ADD);
+        let allocation = SpillEverything.allocate(&module.functions[0], &machine(&[], &["a"]));
+        assert_eq!(allocation.stack_slots, 5);
+        assert_eq!(allocation.location(Reg(4)), Location::Stack(4));
+    }
+}
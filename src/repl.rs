@@ -0,0 +1,260 @@
+//! An interactive read-eval-print loop over the interpreter.
+//!
+//! Each entry is checked and run straight away on an [`Engine`], in an
+//! environment that lives as long as the session, so it sees the names of
+//! the entries before it. What the program prints is kept and shown before the entry's result, and
+//! `input()` reads nothing, as the session's input holds the entries.
+
+use std::io::{self, BufRead, Write};
+
+use crate::ast::{NodeId, Program, StmtKind};
+use crate::builtins::Io;
+use crate::diagnostics::Diagnostic;
+use crate::engine::Engine;
+use crate::error::CompileError;
+use crate::interp::Value;
+use crate::lexer::{LexError, Lexer, Token};
+use crate::parser::{ParseError, Parser};
+use crate::source::SourceManager;
+use crate::span::FileId;
+
+const PROMPT: &str = "> ";
+const CONTINUE_PROMPT: &str = "... ";
+const FILENAME: &str = "<repl>";
+
+pub struct Repl {
+    engine: Engine,
+    /// Every entry so far, each a file of its own, so that an error in a
+    /// function is shown in the entry that declared it.
+    sources: SourceManager,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Repl::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl {
+            engine: Engine::with_io(Io::buffered("")),
+            sources: SourceManager::new(),
+        }
+    }
+
+    /// What the entries run so far have printed, since the last call.
+    pub fn take_output(&mut self) -> String {
+        self.engine.io().take_output()
+    }
+
+    /// Runs one entry, returning the value of its final expression statement
+    /// unless that is `void`.
+    ///
+    /// ```
+    /// use compyl::interp::Value;
+    /// use compyl::repl::Repl;
+    ///
+    /// let mut repl = Repl::new();
+    /// assert_eq!(repl.eval("let a = 2;").unwrap(), None);
+    /// assert_eq!(repl.eval("a * 21").unwrap(), Some(Value::Int(42)));
+    /// ```
+    pub fn eval(&mut self, source: &str) -> Result<Option<Value>, CompileError> {
+        let file = self.sources.add(FILENAME, source);
+        // Calls like `println(x)` show nothing more than what they print.
+        Ok(match self.engine.eval_in(source, file)? {
+            Value::Void => None,
+            value => Some(value),
+        })
+    }
+
+    /// Renders `diag` against the entry it points into.
+    pub fn render(&self, diag: &Diagnostic) -> String {
+        self.sources.render(diag)
+    }
+}
+
+/// Turns a trailing expression statement into the program's result, so that
+/// it is printed.
+pub(crate) fn return_last(program: &mut Program) {
+    let Some(stmt) = program.stmts.last_mut() else {
+        return;
+    };
+    if let StmtKind::Expr(_) = stmt.kind {
+        let StmtKind::Expr(expr) = std::mem::replace(&mut stmt.kind, StmtKind::Break) else {
+            unreachable!();
+        };
+        stmt.kind = StmtKind::Return(Some(expr));
+    }
+}
+
+/// Parses an entry, allowing the semicolon after a final expression to be left
+/// out. Its nodes are numbered from `first`; the id after the last is returned
+/// too.
+pub(crate) fn parse(
+    source: &str,
+    file: FileId,
+    first: NodeId,
+) -> Result<(Program, NodeId), Vec<ParseError>> {
+    let parse = |source: &str| -> Result<_, Vec<ParseError>> {
+        let mut parser = Parser::with_first_id(Lexer::new(source).with_file(file), first);
+        let program = parser.parse_program()?;
+        Ok((program, parser.next_id()))
+    };
+    match parse(source) {
+        Err(errs) if matches!(errs.last(), Some(ParseError::UnexpectedEof { expected, .. }) if expected == "`;`") => {
+            parse(&format!("{};", source))
+        }
+        result => result,
+    }
+}
+
+/// Whether `source` has unclosed brackets, strings or block comments, so the
+/// entry continues on the next line without trying to parse it.
+pub fn needs_more(source: &str) -> bool {
+    let mut depth = 0i32;
+    for token in Lexer::new(source) {
+        match token {
+            Ok(token) => match token.token {
+                Token::Lparen | Token::LSquirly | Token::LBracket => depth += 1,
+                Token::RParen | Token::RSquirly | Token::RBracket => depth -= 1,
+                _ => {}
+            },
+            Err(LexError::UnterminatedString(_) | LexError::UnterminatedComment(_)) => return true,
+            Err(_) => {}
+        }
+    }
+
+    depth > 0
+}
+
+/// Reads entries from `input` until it ends, writing prompts, results and
+/// diagnostics to `output`.
+pub fn run(input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut repl = Repl::new();
+    let mut entry = String::new();
+    let mut lines = input.lines();
+
+    loop {
+        let prompt = if entry.is_empty() {
+            PROMPT
+        } else {
+            CONTINUE_PROMPT
+        };
+        write!(output, "{}", prompt)?;
+        output.flush()?;
+
+        let Some(line) = lines.next().transpose()? else {
+            writeln!(output)?;
+            return Ok(());
+        };
+        entry.push_str(&line);
+        entry.push('\n');
+        if needs_more(&entry) {
+            continue;
+        }
+
+        if entry.trim().is_empty() {
+            entry.clear();
+            continue;
+        }
+        let result = repl.eval(&entry);
+        write!(output, "{}", repl.take_output())?;
+        match result {
+            Ok(Some(value)) => writeln!(output, "{}", value)?,
+            Ok(None) => {}
+            // An entry cut off mid-statement continues too, until a blank line.
+            Err(CompileError::Parse(errs))
+                if matches!(errs.last(), Some(ParseError::UnexpectedEof { .. }))
+                    && !line.is_empty() =>
+            {
+                continue
+            }
+            Err(err) => {
+                for diag in err.diagnostics() {
+                    write!(output, "{}", repl.render(&diag))?;
+                }
+            }
+        }
+        entry.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{needs_more, run};
+
+    fn session(input: &str) -> String {
+        let mut output = Vec::new();
+        run(input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_needs_more() {
+        assert!(!needs_more("let a = 1;"));
+        assert!(needs_more("fn f() {"));
+        assert!(needs_more("f(1,\n"));
+        assert!(needs_more("let s = \"open"));
+        assert!(needs_more("?* still ?* open *?"));
+        assert!(!needs_more("}"));
+    }
+
+    #[test]
+    fn test_session() {
+        let output = session(
+            "let a = 20;\n\
+             fn add(x, y) {\n\
+             return x + y;\n\
+             }\n\
+             add(a, 1) * 2\n\
+             \n\
+             a = a +\n\
+             1;\n\
+             a\n\
+             ",
+        );
+
+        assert_eq!(output, "> > ... ... > 42\n> > ... > 21\n> \n");
+    }
+
+    #[test]
+    fn test_printed_output() {
+        let output = session("println(\"hi\");\nprint(1); 2\n");
+
+        assert_eq!(output, "> hi\n> 12\n> \n");
+    }
+
+    #[test]
+    fn test_errors_keep_the_session() {
+        let output = session("let a = 1 / 0;\nlet = 2;\nlet b = 1 +\n\n\"still here\"\n");
+
+        assert!(output.contains("error[E0905]: division by zero"));
+        assert!(output.contains("error[E0100]: expected identifier, found `=`"));
+        assert!(output.contains("error[E0101]: expected expression, found end of input"));
+        assert!(output.ends_with("> still here\n> \n"));
+    }
+
+    #[test]
+    fn test_checks() {
+        let output = session(
+            "let a: int = \"s\";\n\
+             undefined_var\n\
+             break;\n\
+             fn f(x: int): int { return x; }\n\
+             f(\"no\")\n\
+             let b: int = f(2);\n\
+             b + 1\n\
+             a\n",
+        );
+
+        assert!(output.starts_with("> error[E0301]: expected `int`, found `str`"));
+        assert!(output.contains("error[E0200]: cannot find `undefined_var` in this scope"));
+        assert!(output.contains("1 | break;\n  | ^^^^^^ cannot `break` here"));
+        assert!(output.contains("1 | f(\"no\")\n  |   ^^^^ expected `int`"));
+        assert!(!output.contains("E0900") && !output.contains("E0907"));
+        assert!(output.contains("> 3\n"));
+        // The entry that failed to check never declared `a`.
+        assert!(output.contains("cannot find `a` in this scope"));
+    }
+}
@@ -0,0 +1,1096 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::ast::{
+    BinaryOp, Block, Expr, ExprKind, FieldInit, FnDecl, Ident, Module, NodeId, PatternKind,
+    Program, Stmt, StmtKind,
+};
+use crate::builtins::Builtin;
+use crate::intern::Symbol;
+use crate::interp::{eval_binary, eval_unary, RuntimeError, Value};
+use crate::span::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeclKind {
+    Let,
+    Const,
+    Fn,
+    Param,
+    Struct,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decl {
+    pub name: Symbol,
+    pub kind: DeclKind,
+    pub span: Span,
+}
+
+/// The side tables produced by [`analyze`]: every declared name keyed by the
+/// id of its `Ident`, and every use of a name mapped to that declaration.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Resolution {
+    pub decls: HashMap<NodeId, Decl>,
+    pub uses: HashMap<NodeId, NodeId>,
+    /// The uses of names that refer to a builtin function instead.
+    pub builtins: HashMap<NodeId, Builtin>,
+    /// The declarations each function uses from the functions around it, by
+    /// the id of its name. Top-level names are never captured.
+    pub captures: HashMap<NodeId, Vec<NodeId>>,
+    /// The value of each constant, by the id of its name.
+    pub consts: HashMap<NodeId, Value>,
+}
+
+impl Resolution {
+    /// Looks up the declaration that the use `id` refers to.
+    pub fn resolve(&self, id: NodeId) -> Option<(NodeId, &Decl)> {
+        let decl = *self.uses.get(&id)?;
+        Some((decl, &self.decls[&decl]))
+    }
+}
+
+/// The top-level names of programs analyzed before, which a program analyzed
+/// with [`analyze_in`] may use as if they were declared ahead of it, like the
+/// evaluations of an [`Engine`](crate::Engine) do.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Globals {
+    /// The declaration each name refers to, the latest if it was redeclared.
+    names: HashMap<Symbol, NodeId>,
+    decls: HashMap<NodeId, Decl>,
+    fields: HashMap<NodeId, Vec<Symbol>>,
+    consts: HashMap<NodeId, Value>,
+}
+
+impl Globals {
+    pub fn new() -> Globals {
+        Globals::default()
+    }
+
+    /// Declares `name`, which a program declared nowhere, with the id `id`.
+    pub fn declare(&mut self, name: Symbol, kind: DeclKind, id: NodeId) {
+        let span = Span::default();
+        self.names.insert(name, id);
+        self.decls.insert(id, Decl { name, kind, span });
+    }
+
+    /// Adds the top-level declarations of `program`, which `resolution`
+    /// resolved.
+    pub fn extend(&mut self, program: &Program, resolution: &Resolution) {
+        for stmt in &program.stmts {
+            let name = match &stmt.kind {
+                StmtKind::Let { name, .. } | StmtKind::Const { name, .. } => name,
+                StmtKind::Fn(decl) | StmtKind::Extern(decl) => &decl.name,
+                StmtKind::Struct(decl) => {
+                    let names = decl.fields.iter().map(|field| field.name.name).collect();
+                    self.fields.insert(decl.name.id, names);
+                    &decl.name
+                }
+                _ => continue,
+            };
+            self.names.insert(name.name, name.id);
+            self.decls
+                .insert(name.id, resolution.decls[&name.id].clone());
+            if let Some(value) = resolution.consts.get(&name.id) {
+                self.consts.insert(name.id, value.clone());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SemaError {
+    UndefinedName(String, Span),
+    DuplicateDefinition {
+        name: String,
+        span: Span,
+        previous: Span,
+    },
+    BreakOutsideLoop(Span),
+    ContinueOutsideLoop(Span),
+    AssignToBuiltin(String, Span),
+    AssignToConst(String, Span),
+    /// An assignment to a name declared with `fn`, which is never rebound.
+    AssignToFunction(String, Span),
+    /// A part of a constant's value that is not known at compile time.
+    NotConstant(Span),
+    /// A constant whose value cannot be computed, such as by dividing by zero.
+    ConstEval {
+        error: RuntimeError,
+        span: Span,
+    },
+    BuiltinArity {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// A struct declared anywhere but the top level.
+    NestedStruct(Span),
+    NotAStruct(String, Span),
+    /// A struct's name used where a value is expected.
+    StructAsValue(String, Span),
+    UnknownField {
+        name: String,
+        field: String,
+        span: Span,
+    },
+    MissingField {
+        name: String,
+        field: String,
+        span: Span,
+    },
+    /// An `import` anywhere but the top level.
+    NestedImport(Span),
+    /// An `extern fn` declared anywhere but the top level.
+    NestedExtern(Span),
+    /// A top-level name of a module that the using module does not import.
+    NotImported {
+        name: String,
+        module: String,
+        span: Span,
+    },
+}
+
+impl SemaError {
+    pub fn span(&self) -> Span {
+        match self {
+            SemaError::UndefinedName(_, span)
+            | SemaError::DuplicateDefinition { span, .. }
+            | SemaError::BreakOutsideLoop(span)
+            | SemaError::ContinueOutsideLoop(span)
+            | SemaError::AssignToBuiltin(_, span)
+            | SemaError::AssignToConst(_, span)
+            | SemaError::AssignToFunction(_, span)
+            | SemaError::NotConstant(span)
+            | SemaError::ConstEval { span, .. }
+            | SemaError::BuiltinArity { span, .. }
+            | SemaError::NestedStruct(span)
+            | SemaError::NotAStruct(_, span)
+            | SemaError::StructAsValue(_, span)
+            | SemaError::UnknownField { span, .. }
+            | SemaError::MissingField { span, .. }
+            | SemaError::NestedImport(span)
+            | SemaError::NestedExtern(span)
+            | SemaError::NotImported { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for SemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SemaError::UndefinedName(name, _) => {
+                write!(f, "cannot find `{}` in this scope", name)
+            }
+            SemaError::DuplicateDefinition { name, .. } => {
+                write!(f, "`{}` is defined more than once in this scope", name)
+            }
+            SemaError::BreakOutsideLoop(_) => write!(f, "`break` outside of a loop"),
+            SemaError::ContinueOutsideLoop(_) => write!(f, "`continue` outside of a loop"),
+            SemaError::AssignToBuiltin(name, _) => {
+                write!(f, "cannot assign to the builtin `{}`", name)
+            }
+            SemaError::AssignToConst(name, _) => {
+                write!(f, "cannot assign to the constant `{}`", name)
+            }
+            SemaError::AssignToFunction(name, _) => {
+                write!(f, "cannot assign to the function `{}`", name)
+            }
+            SemaError::NotConstant(_) => {
+                write!(f, "a constant's value must be known at compile time")
+            }
+            SemaError::ConstEval { error, .. } => {
+                write!(f, "cannot evaluate this constant: {}", error)
+            }
+            SemaError::BuiltinArity {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "`{}` takes {} argument(s), found {}",
+                name, expected, found
+            ),
+            SemaError::NestedStruct(_) => {
+                write!(f, "structs can only be declared at the top level")
+            }
+            SemaError::NotAStruct(name, _) => write!(f, "`{}` is not a struct", name),
+            SemaError::StructAsValue(name, _) => {
+                write!(f, "expected a value, found the struct `{}`", name)
+            }
+            SemaError::UnknownField { name, field, .. } => {
+                write!(f, "struct `{}` has no field `{}`", name, field)
+            }
+            SemaError::MissingField { name, field, .. } => {
+                write!(f, "missing field `{}` in `{}`", field, name)
+            }
+            SemaError::NestedImport(_) => {
+                write!(f, "imports can only appear at the top level")
+            }
+            SemaError::NestedExtern(_) => {
+                write!(
+                    f,
+                    "`extern` functions can only be declared at the top level"
+                )
+            }
+            SemaError::NotImported { name, module, .. } => {
+                write!(
+                    f,
+                    "`{}` is declared in `{}`, which is not imported",
+                    name, module
+                )
+            }
+        }
+    }
+}
+
+impl Error for SemaError {}
+
+/// Resolves every name in `program` to its declaration.
+///
+/// Names must be declared before they are used, except functions, which are
+/// visible throughout the block that declares them, and structs, which are
+/// declared at the top level and visible throughout. A name declared nowhere
+/// in scope may be a [`Builtin`]. The value of each `const` is computed from
+/// literals and other constants. All errors are collected rather than
+/// stopping at the first.
+///
+/// In a program linked from several files, the top-level declarations of all
+/// of them share one scope, but each file may only use those of the files it
+/// imports directly.
+pub fn analyze(program: &Program) -> Result<Resolution, Vec<SemaError>> {
+    analyze_in(program, &Globals::default())
+}
+
+/// Like [`analyze`], for a program that may also use the names in `globals`.
+/// Its own declarations shadow them. The resolution has the declarations of
+/// the globals it uses too.
+pub fn analyze_in(program: &Program, globals: &Globals) -> Result<Resolution, Vec<SemaError>> {
+    match resolve(program, globals) {
+        (resolution, errors) if errors.is_empty() => Ok(resolution),
+        (_, errors) => Err(errors),
+    }
+}
+
+/// Like [`analyze`], but also returns the resolution when there are errors.
+/// Names that fail to resolve are left out of it, and the rest resolve as
+/// they would otherwise.
+pub fn analyze_partial(program: &Program) -> (Resolution, Vec<SemaError>) {
+    resolve(program, &Globals::default())
+}
+
+fn resolve(program: &Program, globals: &Globals) -> (Resolution, Vec<SemaError>) {
+    let mut resolver = Resolver {
+        resolution: Resolution {
+            decls: globals.decls.clone(),
+            consts: globals.consts.clone(),
+            ..Resolution::default()
+        },
+        scopes: Vec::new(),
+        globals: &globals.names,
+        fields: globals.fields.clone(),
+        functions: Vec::new(),
+        owners: HashMap::new(),
+        modules: &program.modules,
+        module: None,
+        decl_modules: HashMap::new(),
+        loop_depth: 0,
+        errors: Vec::new(),
+    };
+    resolver.block(&program.stmts);
+    (resolver.resolution, resolver.errors)
+}
+
+struct Resolver<'a> {
+    resolution: Resolution,
+    scopes: Vec<HashMap<Symbol, NodeId>>,
+    /// The names declared by programs before this one.
+    globals: &'a HashMap<Symbol, NodeId>,
+    /// The field names of each struct, by the id of its name.
+    fields: HashMap<NodeId, Vec<Symbol>>,
+    /// The names of the functions being resolved, innermost last.
+    functions: Vec<NodeId>,
+    /// How many functions enclose each declaration below the top level.
+    owners: HashMap<NodeId, usize>,
+    modules: &'a [Module],
+    /// The module of the top-level statement being resolved.
+    module: Option<usize>,
+    /// The module declaring each top-level name.
+    decl_modules: HashMap<NodeId, usize>,
+    loop_depth: usize,
+    errors: Vec<SemaError>,
+}
+
+impl Resolver<'_> {
+    fn declare(&mut self, ident: &Ident, kind: DeclKind) {
+        let scope = self.scopes.last_mut().expect("no scope to declare in");
+        if let Some(previous) = scope.insert(ident.name, ident.id) {
+            self.errors.push(SemaError::DuplicateDefinition {
+                name: ident.name.to_string(),
+                span: ident.span,
+                previous: self.resolution.decls[&previous].span,
+            });
+        }
+
+        if self.scopes.len() > 1 {
+            self.owners.insert(ident.id, self.functions.len());
+        } else if let Some(module) = self.module {
+            self.decl_modules.insert(ident.id, module);
+        }
+        self.resolution.decls.insert(
+            ident.id,
+            Decl {
+                name: ident.name,
+                kind,
+                span: ident.span,
+            },
+        );
+    }
+
+    /// Resolves the use `id` of `name`, returning its declaration if it has one.
+    fn lookup(&mut self, name: Symbol, id: NodeId, span: Span) -> Option<NodeId> {
+        let decl = self.scopes.iter().rev().find_map(|scope| scope.get(&name));
+        match decl.or_else(|| self.globals.get(&name)) {
+            Some(&decl) => {
+                self.resolution.uses.insert(id, decl);
+                self.capture(decl);
+                self.check_imported(decl, span);
+                return Some(decl);
+            }
+            None => match Builtin::lookup(name.as_str()) {
+                Some(builtin) => {
+                    self.resolution.builtins.insert(id, builtin);
+                }
+                None => self
+                    .errors
+                    .push(SemaError::UndefinedName(name.to_string(), span)),
+            },
+        }
+        None
+    }
+
+    /// Records `decl` as captured by each function between the use and the
+    /// function that declares it.
+    fn capture(&mut self, decl: NodeId) {
+        let Some(&owner) = self.owners.get(&decl) else {
+            return;
+        };
+        for &func in &self.functions[owner..] {
+            let captures = self.resolution.captures.entry(func).or_default();
+            if !captures.contains(&decl) {
+                captures.push(decl);
+            }
+        }
+    }
+
+    /// Reports a use of `decl` from a module that cannot see it.
+    fn check_imported(&mut self, decl: NodeId, span: Span) {
+        let (Some(current), Some(&owner)) = (self.module, self.decl_modules.get(&decl)) else {
+            return;
+        };
+        if owner != current && !self.modules[current].imports.contains(&owner) {
+            self.errors.push(SemaError::NotImported {
+                name: self.resolution.decls[&decl].name.to_string(),
+                module: self.modules[owner].path.clone(),
+                span,
+            });
+        }
+    }
+
+    /// Like [`Resolver::lookup`], for a use that must be a value.
+    fn lookup_value(&mut self, name: Symbol, id: NodeId, span: Span) {
+        let decl = self.lookup(name, id, span);
+        if decl.is_some_and(|decl| self.resolution.decls[&decl].kind == DeclKind::Struct) {
+            self.errors
+                .push(SemaError::StructAsValue(name.to_string(), span));
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.scopes.push(HashMap::new());
+        self.stmts(block);
+        self.scopes.pop();
+    }
+
+    /// Resolves `stmts` in the innermost scope, hoisting its functions and
+    /// structs first.
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        let top_level = self.scopes.len() == 1;
+        for (index, stmt) in stmts.iter().enumerate() {
+            if top_level {
+                self.enter_module(index);
+            }
+            match &stmt.kind {
+                StmtKind::Fn(decl) | StmtKind::Extern(decl) => {
+                    self.declare(&decl.name, DeclKind::Fn)
+                }
+                StmtKind::Struct(decl) if self.scopes.len() == 1 => {
+                    self.declare(&decl.name, DeclKind::Struct);
+                    let mut seen = HashMap::new();
+                    for field in &decl.fields {
+                        self.unique_field(&mut seen, &field.name);
+                    }
+                    let names = decl.fields.iter().map(|field| field.name.name).collect();
+                    self.fields.insert(decl.name.id, names);
+                }
+                _ => {}
+            }
+        }
+
+        for (index, stmt) in stmts.iter().enumerate() {
+            if top_level {
+                self.enter_module(index);
+            }
+            self.stmt(stmt);
+        }
+    }
+
+    /// Notes the module of the top-level statement at `index`, if the program
+    /// was linked from several.
+    fn enter_module(&mut self, index: usize) {
+        self.module = self
+            .modules
+            .iter()
+            .position(|module| module.stmts.contains(&index));
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { name, value, .. } => {
+                self.expr(value);
+                self.declare(name, DeclKind::Let);
+            }
+            StmtKind::Const { name, value, .. } => {
+                let errors = self.errors.len();
+                self.expr(value);
+                // A value that failed to resolve has been reported already.
+                if self.errors.len() == errors {
+                    match self.evaluate(value) {
+                        Ok(value) => {
+                            self.resolution.consts.insert(name.id, value);
+                        }
+                        Err(Some(err)) => self.errors.push(err),
+                        Err(None) => {}
+                    }
+                }
+                self.declare(name, DeclKind::Const);
+            }
+            StmtKind::Assign { target, value, .. } => {
+                self.expr(value);
+                let decl = self.lookup(target.name, target.id, target.span);
+                let kind = decl.map(|decl| self.resolution.decls[&decl].kind);
+                if kind == Some(DeclKind::Struct) {
+                    self.errors.push(SemaError::StructAsValue(
+                        target.name.to_string(),
+                        target.span,
+                    ));
+                } else if kind == Some(DeclKind::Const) {
+                    self.errors.push(SemaError::AssignToConst(
+                        target.name.to_string(),
+                        target.span,
+                    ));
+                } else if kind == Some(DeclKind::Fn) {
+                    self.errors.push(SemaError::AssignToFunction(
+                        target.name.to_string(),
+                        target.span,
+                    ));
+                } else if self.resolution.builtins.contains_key(&target.id) {
+                    self.errors.push(SemaError::AssignToBuiltin(
+                        target.name.to_string(),
+                        target.span,
+                    ));
+                }
+            }
+            StmtKind::IndexAssign {
+                array,
+                index,
+                value,
+                ..
+            } => {
+                self.expr(array);
+                self.expr(index);
+                self.expr(value);
+            }
+            StmtKind::FieldAssign { object, value, .. } => {
+                self.expr(object);
+                self.expr(value);
+            }
+            StmtKind::Fn(decl) => self.function(decl),
+            StmtKind::Extern(_) => {
+                if self.scopes.len() > 1 {
+                    self.errors.push(SemaError::NestedExtern(stmt.span));
+                }
+            }
+            StmtKind::Struct(_) => {
+                if self.scopes.len() > 1 {
+                    self.errors.push(SemaError::NestedStruct(stmt.span));
+                }
+            }
+            StmtKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(cond);
+                self.block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.block(else_branch);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                self.expr(cond);
+                self.loop_depth += 1;
+                self.block(body);
+                self.loop_depth -= 1;
+            }
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                self.expr(start);
+                self.expr(end);
+                self.scopes.push(HashMap::new());
+                self.declare(var, DeclKind::Let);
+                self.loop_depth += 1;
+                self.block(body);
+                self.loop_depth -= 1;
+                self.scopes.pop();
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            StmtKind::Break => {
+                if self.loop_depth == 0 {
+                    self.errors.push(SemaError::BreakOutsideLoop(stmt.span));
+                }
+            }
+            StmtKind::Continue => {
+                if self.loop_depth == 0 {
+                    self.errors.push(SemaError::ContinueOutsideLoop(stmt.span));
+                }
+            }
+            StmtKind::Import(_) => {
+                if self.scopes.len() > 1 {
+                    self.errors.push(SemaError::NestedImport(stmt.span));
+                }
+            }
+            StmtKind::Block(block) => self.block(block),
+            StmtKind::Expr(expr) => self.expr(expr),
+            StmtKind::Error => {}
+        }
+    }
+
+    /// Computes the value of a constant's initializer, failing with `None`
+    /// where it uses a constant whose own value could not be computed.
+    fn evaluate(&self, expr: &Expr) -> Result<Value, Option<SemaError>> {
+        let fail = |error| {
+            Some(SemaError::ConstEval {
+                error,
+                span: expr.span,
+            })
+        };
+        match &expr.kind {
+            ExprKind::Int(n) => Ok(Value::Int(*n)),
+            ExprKind::Float(n) => Ok(Value::Float(*n)),
+            ExprKind::Char(ch) => Ok(Value::Char(*ch)),
+            ExprKind::Str(s) => Ok(Value::Str(s.as_str().into())),
+            ExprKind::Bool(b) => Ok(Value::Bool(*b)),
+            ExprKind::Ident(_) => match self.resolution.resolve(expr.id) {
+                Some((
+                    decl,
+                    Decl {
+                        kind: DeclKind::Const,
+                        ..
+                    },
+                )) => self.resolution.consts.get(&decl).cloned().ok_or(None),
+                _ => Err(Some(SemaError::NotConstant(expr.span))),
+            },
+            ExprKind::Unary { op, expr: operand } => {
+                eval_unary(*op, self.evaluate(operand)?).map_err(fail)
+            }
+            // Short-circuits like at runtime, so `false && 1 / 0 == 0` is fine.
+            ExprKind::Binary {
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                lhs,
+                rhs,
+            } => {
+                let lhs = self.evaluate(lhs)?;
+                match (op, &lhs) {
+                    (BinaryOp::And, Value::Bool(false)) | (BinaryOp::Or, Value::Bool(true)) => {
+                        Ok(lhs)
+                    }
+                    (_, Value::Bool(_)) => match self.evaluate(rhs)? {
+                        rhs @ Value::Bool(_) => Ok(rhs),
+                        rhs => Err(fail(RuntimeError::TypeMismatch {
+                            op: op.to_string(),
+                            lhs: lhs.type_name(),
+                            rhs: Some(rhs.type_name()),
+                        })),
+                    },
+                    _ => Err(fail(RuntimeError::TypeMismatch {
+                        op: op.to_string(),
+                        lhs: lhs.type_name(),
+                        rhs: None,
+                    })),
+                }
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                eval_binary(*op, self.evaluate(lhs)?, self.evaluate(rhs)?).map_err(fail)
+            }
+            _ => Err(Some(SemaError::NotConstant(expr.span))),
+        }
+    }
+
+    fn function(&mut self, decl: &FnDecl) {
+        // A loop around the declaration does not make `break` valid inside it.
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+
+        self.functions.push(decl.name.id);
+        self.scopes.push(HashMap::new());
+        for param in &decl.params {
+            self.declare(&param.name, DeclKind::Param);
+        }
+        self.stmts(&decl.body);
+        self.scopes.pop();
+        self.functions.pop();
+
+        self.loop_depth = loop_depth;
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::Char(_)
+            | ExprKind::Str(_)
+            | ExprKind::Bool(_) => {}
+            ExprKind::Ident(name) => self.lookup_value(*name, expr.id, expr.span),
+            ExprKind::Unary { expr, .. } | ExprKind::Interpolation(expr) => self.expr(expr),
+            ExprKind::Binary { lhs, rhs, .. } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            ExprKind::Call { callee, args } => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+                if let Some(builtin) = self.resolution.builtins.get(&callee.id) {
+                    if builtin.arity() != args.len() {
+                        self.errors.push(SemaError::BuiltinArity {
+                            name: builtin.name().to_string(),
+                            expected: builtin.arity(),
+                            found: args.len(),
+                            span: expr.span,
+                        });
+                    }
+                }
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(cond);
+                self.expr(then_branch);
+                self.expr(else_branch);
+            }
+            // Each arm is a scope, holding the name its pattern binds.
+            ExprKind::Match { scrutinee, arms } => {
+                self.expr(scrutinee);
+                for arm in arms {
+                    self.scopes.push(HashMap::new());
+                    if let PatternKind::Binding(name) = &arm.pattern.kind {
+                        self.declare(name, DeclKind::Let);
+                    }
+                    self.expr(&arm.body);
+                    self.scopes.pop();
+                }
+            }
+            // The function's own name is only in scope in its body.
+            ExprKind::Fn(decl) => {
+                self.scopes.push(HashMap::new());
+                self.declare(&decl.name, DeclKind::Fn);
+                self.function(decl);
+                self.scopes.pop();
+            }
+            ExprKind::Array(elems) => {
+                for elem in elems {
+                    self.expr(elem);
+                }
+            }
+            ExprKind::Index { expr, index } => {
+                self.expr(expr);
+                self.expr(index);
+            }
+            ExprKind::Struct { name, fields } => self.struct_literal(name, fields, expr.span),
+            ExprKind::Field { expr, .. } => self.expr(expr),
+        }
+    }
+
+    /// Checks that `fields` initializes each field of the struct `name` once.
+    fn struct_literal(&mut self, name: &Ident, fields: &[FieldInit], span: Span) {
+        let mut seen = HashMap::new();
+        for field in fields {
+            self.expr(&field.value);
+            self.unique_field(&mut seen, &field.name);
+        }
+
+        let decl = self.lookup(name.name, name.id, name.span);
+        if decl.is_none() && !self.resolution.builtins.contains_key(&name.id) {
+            return;
+        }
+        let Some(declared) = decl.and_then(|decl| self.fields.get(&decl)) else {
+            self.errors
+                .push(SemaError::NotAStruct(name.name.to_string(), name.span));
+            return;
+        };
+        for field in fields {
+            if !declared.contains(&field.name.name) {
+                self.errors.push(SemaError::UnknownField {
+                    name: name.name.to_string(),
+                    field: field.name.name.to_string(),
+                    span: field.name.span,
+                });
+            }
+        }
+        for &field in declared {
+            if !seen.contains_key(&field) {
+                self.errors.push(SemaError::MissingField {
+                    name: name.name.to_string(),
+                    field: field.to_string(),
+                    span,
+                });
+            }
+        }
+    }
+
+    /// Reports `field` if `seen` already has a field of its name.
+    fn unique_field(&mut self, seen: &mut HashMap<Symbol, Span>, field: &Ident) {
+        if let Some(previous) = seen.insert(field.name, field.span) {
+            self.errors.push(SemaError::DuplicateDefinition {
+                name: field.name.to_string(),
+                span: field.span,
+                previous,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{analyze, DeclKind, Resolution, SemaError};
+    use crate::ast::{ExprKind, StmtKind};
+    use crate::interp::{RuntimeError, Value};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::source::SourceManager;
+    use crate::span::{FileId, Span};
+
+    fn errors(input: &str) -> Vec<SemaError> {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.");
+        analyze(&program).expect_err("Program passed analysis when it should not have.")
+    }
+
+    fn resolve(input: &str) -> Resolution {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.");
+        analyze(&program).expect("Program failed analysis.")
+    }
+
+    #[test]
+    fn test_resolves_to_innermost() {
+        let program = Parser::new(Lexer::new("let a = 1; { let a = 2; a; }"))
+            .parse_program()
+            .unwrap();
+        let resolution = analyze(&program).unwrap();
+
+        let StmtKind::Block(block) = &program.stmts[1].kind else {
+            panic!("expected a block");
+        };
+        let (StmtKind::Let { name, .. }, StmtKind::Expr(use_)) = (&block[0].kind, &block[1].kind)
+        else {
+            panic!("expected a let and an expression");
+        };
+        assert!(matches!(use_.kind, ExprKind::Ident(_)));
+
+        let (decl_id, decl) = resolution.resolve(use_.id).unwrap();
+        assert_eq!(decl_id, name.id);
+        assert_eq!(decl.kind, DeclKind::Let);
+        assert_eq!(decl.span, Span::new(17, 18, 1, 18));
+    }
+
+    #[test]
+    fn test_functions_are_hoisted() {
+        let resolution = resolve(
+            "
+            let n = fact(5);
+            fn fact(n) {
+                if (n <= 1) { return 1; }
+                return n * fact(n - 1);
+            }
+            ",
+        );
+
+        let kinds = |kind| resolution.decls.values().filter(|d| d.kind == kind).count();
+        assert_eq!(kinds(DeclKind::Fn), 1);
+        assert_eq!(kinds(DeclKind::Param), 1);
+        assert_eq!(kinds(DeclKind::Let), 1);
+        assert_eq!(resolution.uses.len(), 5);
+    }
+
+    #[test]
+    fn test_use_before_declare() {
+        assert_eq!(
+            errors("a = 1; let b = b; let a = 2;"),
+            vec![
+                SemaError::UndefinedName("a".into(), Span::new(0, 1, 1, 1)),
+                SemaError::UndefinedName("b".into(), Span::new(15, 16, 1, 16)),
+            ]
+        );
+        assert_eq!(
+            errors("{ let x = 1; } x;"),
+            vec![SemaError::UndefinedName(
+                "x".into(),
+                Span::new(15, 16, 1, 16)
+            )]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_definitions() {
+        assert_eq!(
+            errors("let a = 1; let a = 2; fn f(x, x) {}"),
+            vec![
+                SemaError::DuplicateDefinition {
+                    name: "a".into(),
+                    span: Span::new(15, 16, 1, 16),
+                    previous: Span::new(4, 5, 1, 5),
+                },
+                SemaError::DuplicateDefinition {
+                    name: "x".into(),
+                    span: Span::new(30, 31, 1, 31),
+                    previous: Span::new(27, 28, 1, 28),
+                },
+            ]
+        );
+
+        // Shadowing in an inner block is fine.
+        resolve("let a = 1; { let a = 2; }");
+    }
+
+    #[test]
+    fn test_builtins() {
+        let resolution = resolve("println(len(\"abc\")); fn f(print) { return print; }");
+        assert_eq!(resolution.builtins.len(), 2);
+        assert_eq!(resolution.uses.len(), 1);
+
+        assert_eq!(
+            errors("len = 1; input(1); int();"),
+            vec![
+                SemaError::AssignToBuiltin("len".into(), Span::new(0, 3, 1, 1)),
+                SemaError::BuiltinArity {
+                    name: "input".into(),
+                    expected: 0,
+                    found: 1,
+                    span: Span::new(9, 17, 1, 10),
+                },
+                SemaError::BuiltinArity {
+                    name: "int".into(),
+                    expected: 1,
+                    found: 0,
+                    span: Span::new(19, 24, 1, 20),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_captures() {
+        let resolution = resolve(
+            "
+            let top = 1;
+            fn outer(n) {
+                let local = n;
+                fn middle() {
+                    return fn() { return n + top; };
+                }
+                return middle;
+            }
+            let fact = fn fact(m) { return fact(m - 1); };
+            ",
+        );
+        let decl = |name: &str, kind| {
+            let found = resolution
+                .decls
+                .iter()
+                .find(|(_, decl)| decl.name.as_str() == name && decl.kind == kind);
+            *found.unwrap().0
+        };
+
+        // `n` is threaded through `middle` into the function it returns.
+        let n = decl("n", DeclKind::Param);
+        let middle = decl("middle", DeclKind::Fn);
+        assert_eq!(resolution.captures[&middle], [n]);
+        assert_eq!(resolution.captures.get(&decl("outer", DeclKind::Fn)), None);
+        assert_eq!(resolution.captures.len(), 3);
+
+        // A function expression finds itself through its own name.
+        let fact = resolution
+            .decls
+            .iter()
+            .find(|(id, decl)| decl.name.as_str() == "fact" && resolution.captures.contains_key(id))
+            .map(|(id, _)| *id)
+            .unwrap();
+        assert_eq!(resolution.captures[&fact], [fact]);
+    }
+
+    #[test]
+    fn test_structs() {
+        let resolution = resolve("let p = P { x: 1 }; struct P { x: int } p.x = p.y;");
+        let kinds = |kind| resolution.decls.values().filter(|d| d.kind == kind).count();
+        assert_eq!(kinds(DeclKind::Struct), 1);
+
+        assert_eq!(
+            errors("struct P { x: int, x: int } let a = P { x: 1, y: 2 }; let b = a { x: 1 }; P; fn f() { struct Q {} }"),
+            vec![
+                SemaError::DuplicateDefinition {
+                    name: "x".into(),
+                    span: Span::new(19, 20, 1, 20),
+                    previous: Span::new(11, 12, 1, 12),
+                },
+                SemaError::UnknownField {
+                    name: "P".into(),
+                    field: "y".into(),
+                    span: Span::new(46, 47, 1, 47),
+                },
+                SemaError::NotAStruct("a".into(), Span::new(62, 63, 1, 63)),
+                SemaError::StructAsValue("P".into(), Span::new(74, 75, 1, 75)),
+                SemaError::NestedStruct(Span::new(86, 97, 1, 87)),
+            ]
+        );
+        assert_eq!(
+            errors("struct P { x: int, y: int } P { y: 1 };"),
+            vec![SemaError::MissingField {
+                name: "P".into(),
+                field: "x".into(),
+                span: Span::new(28, 38, 1, 29),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        assert_eq!(
+            errors("break; while (true) { fn f() { break; } break; }"),
+            vec![
+                SemaError::BreakOutsideLoop(Span::new(0, 6, 1, 1)),
+                SemaError::BreakOutsideLoop(Span::new(31, 37, 1, 32)),
+            ]
+        );
+        assert_eq!(
+            errors("for (i in 0..3) { continue; } continue;"),
+            vec![SemaError::ContinueOutsideLoop(Span::new(30, 39, 1, 31))]
+        );
+    }
+
+    #[test]
+    fn test_for_loops() {
+        // The loop variable is only in scope in the body.
+        assert_eq!(
+            errors("for (i in 0..i) { let j = i; } let k = i;"),
+            vec![
+                SemaError::UndefinedName("i".into(), Span::new(13, 14, 1, 14)),
+                SemaError::UndefinedName("i".into(), Span::new(39, 40, 1, 40)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_imports() {
+        // `main` can use `b`'s names only through `a`, which does not export them.
+        let mut sources = SourceManager::new();
+        let root = sources.add("main.cpl", "import a; f(); g();");
+        sources.add("a.cpl", "import b; fn f() { g(); }");
+        sources.add("b.cpl", "fn g() { import c; }");
+        let program = sources.link(root).expect("Program failed to link.");
+        assert_eq!(
+            analyze(&program),
+            Err(vec![
+                SemaError::NestedImport(Span::new(9, 18, 1, 10).in_file(FileId(2))),
+                SemaError::NotImported {
+                    name: "g".into(),
+                    module: "b.cpl".into(),
+                    span: Span::new(15, 16, 1, 16),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_extern() {
+        assert_eq!(
+            errors("f(); extern fn f(); fn g() { extern fn h(); h(); }"),
+            vec![SemaError::NestedExtern(Span::new(29, 43, 1, 30))]
+        );
+    }
+
+    #[test]
+    fn test_consts() {
+        let program = Parser::new(Lexer::new(
+            "const a = 6 * 7; const b = a > 40 && !false; const c = false && 1 / 0 == 0;",
+        ))
+        .parse_program()
+        .unwrap();
+        let resolution = analyze(&program).unwrap();
+        let values: Vec<_> = program
+            .stmts
+            .iter()
+            .map(|stmt| match &stmt.kind {
+                StmtKind::Const { name, .. } => resolution.consts[&name.id].clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            values,
+            [Value::Int(42), Value::Bool(true), Value::Bool(false)]
+        );
+
+        assert_eq!(
+            errors(
+                "let a = 1; const b = 2 + a; const c = 1 / 0; const d = c; const e = f(); b = 3;"
+            ),
+            vec![
+                SemaError::NotConstant(Span::new(25, 26, 1, 26)),
+                SemaError::ConstEval {
+                    error: RuntimeError::DivisionByZero,
+                    span: Span::new(38, 43, 1, 39),
+                },
+                SemaError::UndefinedName("f".into(), Span::new(68, 69, 1, 69)),
+                SemaError::AssignToConst("b".into(), Span::new(73, 74, 1, 74)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assignments() {
+        resolve("let a = 1; a = 2; fn g(b) { b += a; }");
+        assert_eq!(
+            errors("fn f() {} f = fn() {}; struct P {} P = 1; const c = 1; c += 1;"),
+            vec![
+                SemaError::AssignToFunction("f".into(), Span::new(10, 11, 1, 11)),
+                SemaError::StructAsValue("P".into(), Span::new(35, 36, 1, 36)),
+                SemaError::AssignToConst("c".into(), Span::new(55, 56, 1, 56)),
+            ]
+        );
+    }
+}
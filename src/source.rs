@@ -0,0 +1,424 @@
+//! The source files of a program. A [`SourceManager`] owns their text and
+//! gives each a [`FileId`], which every [`Span`] lexed from it carries, so a
+//! span alone says where to point a diagnostic. It also loads each file
+//! once, follows its `import`s, and links the files into one [`Program`] in
+//! which every file comes after the files it imports.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::{fmt, fs, io};
+
+use crate::ast::{Module, NodeId, Program, StmtKind};
+use crate::diagnostics::Diagnostic;
+use crate::error::CompileError;
+use crate::lexer::{self, Lexer};
+use crate::parser::Parser;
+use crate::span::{FileId, Span};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    /// The path as given, relative to the working directory or absolute.
+    pub path: PathBuf,
+    pub text: String,
+    /// The byte offset of the start of each line.
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(path: impl Into<PathBuf>, text: impl Into<String>) -> SourceFile {
+        let text = text.into();
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        SourceFile {
+            path: path.into(),
+            text,
+            line_starts,
+        }
+    }
+
+    /// The line and column, both 1-based, of the byte `offset`. Columns count
+    /// characters, like those of a [`Span`].
+    pub fn line_column(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.text.len());
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let before = self.text.get(self.line_starts[line - 1]..offset);
+        (line, before.map_or(0, |text| text.chars().count()) + 1)
+    }
+
+    /// The byte offset that the 1-based `line` starts at, or the end of the
+    /// text past the last line.
+    pub fn line_start(&self, line: usize) -> usize {
+        line.checked_sub(1)
+            .and_then(|index| self.line_starts.get(index))
+            .map_or(self.text.len(), |&start| start)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportError {
+    /// The imported file could not be read.
+    Unreadable {
+        path: String,
+        reason: String,
+        span: Span,
+    },
+    /// A file imports itself through `chain`, which starts and ends with it.
+    Cycle { chain: Vec<String>, span: Span },
+}
+
+impl ImportError {
+    pub fn span(&self) -> Span {
+        match self {
+            ImportError::Unreadable { span, .. } | ImportError::Cycle { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Unreadable { path, reason, .. } => {
+                write!(f, "could not read `{}`: {}", path, reason)
+            }
+            ImportError::Cycle { chain, .. } => {
+                write!(f, "import cycle: {}", chain.join(" -> "))
+            }
+        }
+    }
+}
+
+impl Error for ImportError {}
+
+#[derive(Debug, Default)]
+pub struct SourceManager {
+    files: Vec<SourceFile>,
+    /// Each file by its canonical path, so that one file imported under two
+    /// paths is loaded once.
+    ids: HashMap<PathBuf, FileId>,
+}
+
+impl SourceManager {
+    pub fn new() -> SourceManager {
+        SourceManager::default()
+    }
+
+    /// Adds a file whose text is already known, like standard input. Imports
+    /// of `path` get this text instead of reading the file.
+    pub fn add(&mut self, path: impl Into<PathBuf>, text: impl Into<String>) -> FileId {
+        let path = path.into();
+        let id = FileId(self.files.len() as u32);
+        self.ids.insert(canonical(&path), id);
+        self.files.push(SourceFile::new(path, text));
+        id
+    }
+
+    /// Replaces the text of `file`, as an editor does while it is edited.
+    pub fn set_text(&mut self, file: FileId, text: impl Into<String>) {
+        let file = &mut self.files[file.0 as usize];
+        *file = SourceFile::new(std::mem::take(&mut file.path), text);
+    }
+
+    pub fn file(&self, id: FileId) -> &SourceFile {
+        &self.files[id.0 as usize]
+    }
+
+    /// The file loaded from `path`, if any.
+    pub fn find(&self, path: impl AsRef<Path>) -> Option<FileId> {
+        self.ids.get(&canonical(path.as_ref())).copied()
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// Reads the file at `path`, unless it is loaded already.
+    pub fn load(&mut self, path: &Path) -> io::Result<FileId> {
+        if let Some(&id) = self.ids.get(&canonical(path)) {
+            return Ok(id);
+        }
+
+        let bytes = fs::read(path)?;
+        let text = lexer::decode(&bytes).map_err(|err| {
+            let span = err.span();
+            let message = format!("{} at {}:{}", err, span.line, span.column);
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        })?;
+        Ok(self.add(path, text))
+    }
+
+    /// Parses `root` and every file it imports, directly or not, into one
+    /// program. Each file's top-level statements run once, after those of
+    /// the files it imports.
+    pub fn link(&mut self, root: FileId) -> Result<Program, CompileError> {
+        self.link_from(root, NodeId(0)).map(|(program, _)| program)
+    }
+
+    /// Like [`SourceManager::link`], numbering nodes from `first`, and
+    /// returning the id after the last.
+    pub fn link_from(
+        &mut self,
+        root: FileId,
+        first: NodeId,
+    ) -> Result<(Program, NodeId), CompileError> {
+        let mut linker = Linker {
+            sources: self,
+            program: Program {
+                stmts: Vec::new(),
+                modules: Vec::new(),
+            },
+            modules: HashMap::new(),
+            stack: Vec::new(),
+            next_id: first,
+        };
+        linker.visit(root)?;
+        Ok((linker.program, linker.next_id))
+    }
+
+    /// Renders `diag` against the file its span is in. A diagnostic without a
+    /// span is about the first file added.
+    pub fn render(&self, diag: &Diagnostic) -> String {
+        let file = self.file(diag.span.map_or(FileId(0), |span| span.file));
+        diag.render_in(&file.text, |id| self.file(id).path.display().to_string())
+    }
+
+    /// Writes the rendered diagnostic to stderr.
+    pub fn emit(&self, diag: &Diagnostic) {
+        let _ = io::stderr().write_all(self.render(diag).as_bytes());
+    }
+}
+
+struct Linker<'a> {
+    sources: &'a mut SourceManager,
+    program: Program,
+    /// The index in `program.modules` of each file linked so far.
+    modules: HashMap<FileId, usize>,
+    /// The files being linked, each imported by the one before it.
+    stack: Vec<FileId>,
+    next_id: NodeId,
+}
+
+impl Linker<'_> {
+    fn visit(&mut self, file: FileId) -> Result<(), CompileError> {
+        let parsed = {
+            let lexer = Lexer::new(&self.sources.file(file).text).with_file(file);
+            let mut parser = Parser::with_first_id(lexer, self.next_id);
+            let parsed = parser.parse_program()?;
+            self.next_id = parser.next_id();
+            parsed
+        };
+
+        self.stack.push(file);
+        let dir = self.sources.file(file).path.parent().map(Path::to_path_buf);
+        let mut imports = Vec::new();
+        for stmt in &parsed.stmts {
+            let StmtKind::Import(path) = &stmt.kind else {
+                continue;
+            };
+            let path = dir.as_deref().unwrap_or(Path::new("")).join(path);
+            let path = normalize(&path);
+            let import = self
+                .sources
+                .load(&path)
+                .map_err(|err| ImportError::Unreadable {
+                    path: path.display().to_string(),
+                    reason: err.to_string(),
+                    span: stmt.span,
+                })?;
+
+            if let Some(start) = self.stack.iter().position(|&other| other == import) {
+                let chain = self.stack[start..]
+                    .iter()
+                    .chain([&import])
+                    .map(|&id| self.sources.file(id).path.display().to_string())
+                    .collect();
+                return Err(ImportError::Cycle {
+                    chain,
+                    span: stmt.span,
+                }
+                .into());
+            }
+            if !self.modules.contains_key(&import) {
+                self.visit(import)?;
+            }
+            imports.push(self.modules[&import]);
+        }
+        self.stack.pop();
+
+        let start = self.program.stmts.len();
+        self.program.stmts.extend(parsed.stmts);
+        self.modules.insert(file, self.program.modules.len());
+        self.program.modules.push(Module {
+            path: self.sources.file(file).path.display().to_string(),
+            stmts: start..self.program.stmts.len(),
+            imports,
+        });
+        Ok(())
+    }
+}
+
+/// The path with `.` and `..` components resolved without touching the file
+/// system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(out.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                out.pop();
+            }
+            component => out.push(component),
+        }
+    }
+    out
+}
+
+/// The key a file is known by: its canonical path if it exists, so that
+/// symbolic links and absolute paths find the same file.
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| normalize(path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ImportError, SourceFile, SourceManager};
+    use crate::ast::Program;
+    use crate::diagnostics::Diagnostic;
+    use crate::error::CompileError;
+    use crate::interp::Interpreter;
+    use crate::sema;
+    use crate::span::{FileId, Span};
+
+    fn link(files: &[(&str, &str)]) -> Result<Program, CompileError> {
+        let mut sources = SourceManager::new();
+        for (path, text) in files {
+            sources.add(*path, *text);
+        }
+        sources.link(FileId(0))
+    }
+
+    #[test]
+    fn test_link() {
+        // `lib/b.cpl` is imported twice, under different paths, but linked once.
+        let program = link(&[
+            ("main.cpl", "import a; import \"lib/b.cpl\"; f();"),
+            ("a.cpl", "import \"./lib/../lib/b.cpl\"; fn f() { g(); }"),
+            ("lib/b.cpl", "fn g() {}"),
+        ])
+        .unwrap();
+        assert_eq!(
+            program.to_string(),
+            "(fn g () (block))\n\
+             (import \"./lib/../lib/b.cpl\")\n\
+             (fn f () (block (call g)))\n\
+             (import \"a.cpl\")\n\
+             (import \"lib/b.cpl\")\n\
+             (call f)\n"
+        );
+        assert_eq!(program.stmts[0].span.file, FileId(2));
+        assert_eq!(program.stmts[5].span.file, FileId(0));
+
+        let modules: Vec<_> = program
+            .modules
+            .iter()
+            .map(|module| {
+                (
+                    module.path.as_str(),
+                    module.stmts.clone(),
+                    &module.imports[..],
+                )
+            })
+            .collect();
+        assert_eq!(
+            modules,
+            [
+                ("lib/b.cpl", 0..1, &[][..]),
+                ("a.cpl", 1..3, &[0][..]),
+                ("main.cpl", 3..6, &[1, 0][..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cycles() {
+        let err = link(&[
+            ("main.cpl", "import a;"),
+            ("a.cpl", "import b;"),
+            ("b.cpl", "\nimport a;"),
+        ])
+        .unwrap_err();
+        let CompileError::Import(err) = err else {
+            panic!("not an import error: {:?}", err);
+        };
+        assert_eq!(
+            err,
+            ImportError::Cycle {
+                chain: vec!["a.cpl".into(), "b.cpl".into(), "a.cpl".into()],
+                span: Span::new(1, 10, 2, 1).in_file(FileId(2)),
+            }
+        );
+        assert_eq!(err.to_string(), "import cycle: a.cpl -> b.cpl -> a.cpl");
+
+        let err = link(&[("main.cpl", "import \"main.cpl\";")]).unwrap_err();
+        assert!(err.to_string().ends_with("main.cpl -> main.cpl"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = link(&[("main.cpl", "import missing;")]).unwrap_err();
+        assert!(matches!(
+            err,
+            CompileError::Import(ImportError::Unreadable { ref path, .. }) if path == "missing.cpl"
+        ));
+
+        // Errors in an imported file point into that file.
+        let mut sources = SourceManager::new();
+        let root = sources.add("main.cpl", "import a;");
+        sources.add("a.cpl", "\nlet x = ;");
+        let err = sources.link(root).unwrap_err();
+        let diag = &err.diagnostics()[0];
+        assert_eq!(diag.span.map(|span| span.file), Some(FileId(1)));
+        assert!(sources.render(diag).contains(" --> a.cpl:2:9\n"));
+
+        // So do notes about other places.
+        let mut sources = SourceManager::new();
+        let root = sources.add("main.cpl", "import a;\nfn f() {}");
+        sources.add("a.cpl", "\n\nfn f() {}");
+        let program = sources.link(root).unwrap();
+        let err = CompileError::Sema(sema::analyze(&program).unwrap_err());
+        let rendered = sources.render(&err.diagnostics()[0]);
+        assert!(rendered.contains(" --> main.cpl:2:4\n"), "{}", rendered);
+        assert!(rendered.contains(" = note: previously defined at a.cpl:3:4\n"));
+
+        let mut sources = SourceManager::new();
+        let root = sources.add("main.cpl", "import a;\nf(0);");
+        sources.add("a.cpl", "fn f(n) { return 1 / n; }");
+        let program = sources.link(root).unwrap();
+        let mut interp = Interpreter::new();
+        let err = interp.run(&program).unwrap_err();
+        let diag = Diagnostic::from(&err).with_backtrace(interp.backtrace());
+        let rendered = sources.render(&diag);
+        assert!(rendered.contains(" --> a.cpl:1:18\n"), "{}", rendered);
+        assert!(rendered.contains(" = note: in `f`, called at main.cpl:2:1\n"));
+    }
+
+    #[test]
+    fn test_lines() {
+        let file = SourceFile::new("a.cpl", "ab\néx\n\ny");
+        assert_eq!(file.line_column(0), (1, 1));
+        assert_eq!(file.line_column(2), (1, 3));
+        assert_eq!(file.line_column(3), (2, 1));
+        // Columns count characters: `é` is two bytes.
+        assert_eq!(file.line_column(5), (2, 2));
+        assert_eq!(file.line_column(8), (4, 1));
+        assert_eq!(file.line_column(100), (4, 2));
+
+        assert_eq!(file.line_start(2), 3);
+        assert_eq!(file.line_start(4), 8);
+        assert_eq!(file.line_start(5), 9);
+    }
+}
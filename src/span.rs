@@ -0,0 +1,49 @@
+/// Identifies a source file loaded by a [`crate::source::SourceManager`]. A
+/// program compiled from a single source is all in `FileId(0)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct FileId(pub u32);
+
+/// A region of source text: a half-open byte range of one file plus the line
+/// and column (both 1-based) of its first character. Columns count characters,
+/// not bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    pub file: FileId,
+}
+
+impl Span {
+    /// A span in `FileId(0)`.
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Span {
+        Span {
+            start,
+            end,
+            line,
+            column,
+            file: FileId(0),
+        }
+    }
+
+    pub fn in_file(self, file: FileId) -> Span {
+        Span { file, ..self }
+    }
+
+    /// Returns a span covering both `self` and `other`, assuming `self` comes first.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..self
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
@@ -0,0 +1,377 @@
+//! Lexing straight from a reader, without loading the whole source first.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+
+use crate::lexer::{LexError, Lexer, LexerConfig, SpannedToken, Token};
+use crate::span::Span;
+
+/// A lexer over any [`BufRead`] source. It keeps a sliding buffer of whole
+/// lines: only as much text as the tokens in flight need, which is a single
+/// line unless a string literal or a kept block comment runs over several.
+/// Tokens own their text, and their spans are positions in the full stream.
+///
+/// ```
+/// use compyl::lexer::Token;
+/// use compyl::streaming::StreamingLexer;
+///
+/// let mut lexer = StreamingLexer::new("let x\n= 1;".as_bytes());
+/// assert_eq!(lexer.next_token()?.unwrap().token, Token::KwLet);
+/// assert_eq!(lexer.next_token()?.unwrap().token, Token::Identifier("x".into()));
+/// assert_eq!(lexer.next_token()?.unwrap().span.line, 2);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct StreamingLexer<R> {
+    reader: R,
+    config: LexerConfig,
+    buffer: String,
+    /// Where `buffer` starts in the stream.
+    offset: usize,
+    line: usize,
+    column: usize,
+    pending: VecDeque<Result<SpannedToken<'static>, LexError>>,
+    at_eof: bool,
+    /// The string literal or block comment the buffer starts with, while it
+    /// may continue on a line not read yet.
+    open: Option<Open>,
+}
+
+impl<R: BufRead> StreamingLexer<R> {
+    pub fn new(reader: R) -> StreamingLexer<R> {
+        StreamingLexer::with_config(reader, LexerConfig::default())
+    }
+
+    pub fn with_config(reader: R, config: LexerConfig) -> StreamingLexer<R> {
+        StreamingLexer {
+            reader,
+            config,
+            buffer: String::new(),
+            offset: 0,
+            line: 1,
+            column: 1,
+            pending: VecDeque::new(),
+            at_eof: false,
+            open: None,
+        }
+    }
+
+    /// Returns the next token, or `TokEof` once the stream is exhausted. Reading
+    /// fails with an I/O error, or with `InvalidData` if the text is not UTF-8.
+    pub fn next_token(&mut self) -> io::Result<Result<SpannedToken<'static>, LexError>> {
+        loop {
+            if let Some(next) = self.pending.pop_front() {
+                return Ok(next);
+            }
+            if self.at_eof {
+                let span = Span::new(self.offset, self.offset, self.line, self.column);
+                return Ok(Ok(SpannedToken {
+                    token: Token::TokEof,
+                    span,
+                }));
+            }
+
+            if self.reader.read_line(&mut self.buffer)? == 0 {
+                self.at_eof = true;
+            }
+            self.lex_buffer();
+        }
+    }
+
+    /// Lexes the buffered lines, keeping back a string literal or block comment
+    /// that may continue on a line not read yet. Until the line it ends on,
+    /// each new line is only scanned for its end, and a comment that is not
+    /// kept is dropped as it goes.
+    fn lex_buffer(&mut self) {
+        if let Some(open) = &mut self.open {
+            let end = open.scan(&self.buffer);
+            match (end, open.skipped) {
+                (None, None) if !self.at_eof => return,
+                (None, Some(_)) if !self.at_eof => {
+                    open.scanned = 0;
+                    self.advance(self.buffer.len());
+                    return;
+                }
+                (None, Some(start)) => {
+                    let end = self.offset + self.buffer.len();
+                    let span = Span::new(start.start, end, start.line, start.column);
+                    self.pending
+                        .push_back(Err(LexError::UnterminatedComment(span)));
+                    self.advance(self.buffer.len());
+                }
+                (Some(end), Some(_)) => self.advance(end),
+                _ => {}
+            }
+            self.open = None;
+        }
+
+        let mut lexer = Lexer::starting_at(&self.buffer, self.config, self.line, self.column);
+        let mut consumed = self.buffer.len();
+        loop {
+            match lexer.next_token() {
+                Ok(SpannedToken {
+                    token: Token::TokEof,
+                    ..
+                }) => break,
+                Err(LexError::UnterminatedString(span)) if !self.at_eof => {
+                    consumed = span.start;
+                    self.open = Some(Open::new(Within::Str, 1));
+                    break;
+                }
+                Err(LexError::UnterminatedComment(span)) if !self.at_eof => {
+                    consumed = span.start;
+                    let mut open = Open::new(Within::Comment(1), 2);
+                    if !self.config.keep_comments {
+                        open.skipped = Some(shift(span, self.offset));
+                    }
+                    self.open = Some(open);
+                    break;
+                }
+                Ok(token) => {
+                    let mut token = token.into_owned();
+                    token.for_each_span(&mut |span| *span = shift(*span, self.offset));
+                    self.pending.push_back(Ok(token));
+                }
+                Err(err) => self.pending.push_back(Err(shift_error(err, self.offset))),
+            }
+        }
+
+        self.advance(consumed);
+        if let Some(open) = &mut self.open {
+            if open.scan(&self.buffer).is_some() {
+                // The lexer knows better; it starts over on the next line.
+                self.open = None;
+            } else if open.skipped.is_some() {
+                open.scanned = 0;
+                self.advance(self.buffer.len());
+            }
+        }
+    }
+
+    /// Drops the first `len` bytes of the buffer, which have been lexed.
+    fn advance(&mut self, len: usize) {
+        for ch in self.buffer[..len].chars() {
+            if ch == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+        self.offset += len;
+        self.buffer.drain(..len);
+    }
+}
+
+/// A string literal or block comment left open at the end of the buffer, and
+/// how far it has been scanned, so each line is looked at once. This only
+/// finds where it ends; the lexer still makes its token.
+struct Open {
+    /// What the scan is inside, innermost last.
+    within: Vec<Within>,
+    /// How many bytes of the buffer have been scanned.
+    scanned: usize,
+    /// Where a comment that is not kept starts. Its text is dropped as it is
+    /// scanned, rather than buffered.
+    skipped: Option<Span>,
+}
+
+enum Within {
+    /// A block comment, nested this deeply.
+    Comment(usize),
+    Str,
+    /// The expression in a `${...}`, inside this many braces of its own.
+    Interpolation(usize),
+}
+
+impl Open {
+    /// Starts scanning the buffer `scanned` bytes in, past what opens `within`.
+    fn new(within: Within, scanned: usize) -> Open {
+        Open {
+            within: vec![within],
+            scanned,
+            skipped: None,
+        }
+    }
+
+    /// Scans the rest of `buffer`, returning the offset just past the end of
+    /// what is open if it ends there.
+    fn scan(&mut self, buffer: &str) -> Option<usize> {
+        let bytes = buffer.as_bytes();
+        let mut at = self.scanned;
+        while at < bytes.len() {
+            let (ch, next) = (bytes[at], bytes.get(at + 1).copied());
+            at += 1;
+            match (self.within.last_mut()?, ch, next) {
+                (Within::Comment(depth), b'?', Some(b'*')) => {
+                    *depth += 1;
+                    at += 1;
+                }
+                (Within::Comment(depth), b'*', Some(b'?')) => {
+                    *depth -= 1;
+                    at += 1;
+                    if *depth == 0 {
+                        self.within.pop();
+                    }
+                }
+                (Within::Str, b'"', _) | (Within::Interpolation(0), b'}', _) => {
+                    self.within.pop();
+                }
+                (Within::Str, b'\\', _) => at += 1,
+                (Within::Str, b'$', Some(b'{')) => {
+                    self.within.push(Within::Interpolation(0));
+                    at += 1;
+                }
+                (Within::Interpolation(_), b'"', _) => self.within.push(Within::Str),
+                (Within::Interpolation(_), b'?', Some(b'*')) => {
+                    self.within.push(Within::Comment(1));
+                    at += 1;
+                }
+                // Line comments and character literals end on their line.
+                (Within::Interpolation(_), b'?', _) => {
+                    while at < bytes.len() && bytes[at] != b'\n' {
+                        at += 1;
+                    }
+                }
+                (Within::Interpolation(_), b'\'', _) => {
+                    while at < bytes.len() && !matches!(bytes[at], b'\n' | b'\'') {
+                        at += if bytes[at] == b'\\' { 2 } else { 1 };
+                    }
+                    at += 1;
+                }
+                (Within::Interpolation(braces), b'{', _) => *braces += 1,
+                (Within::Interpolation(braces), b'}', _) => *braces -= 1,
+                _ => {}
+            }
+            if self.within.is_empty() {
+                self.scanned = at;
+                return Some(at);
+            }
+        }
+
+        self.scanned = at.min(bytes.len());
+        None
+    }
+}
+
+impl<R: BufRead> Iterator for StreamingLexer<R> {
+    type Item = io::Result<Result<SpannedToken<'static>, LexError>>;
+
+    /// Yields tokens up to, but not including, `TokEof`.
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_token() {
+            Ok(Ok(SpannedToken {
+                token: Token::TokEof,
+                ..
+            })) => None,
+            result => Some(result),
+        }
+    }
+}
+
+fn shift(span: Span, offset: usize) -> Span {
+    Span {
+        start: span.start + offset,
+        end: span.end + offset,
+        ..span
+    }
+}
+
+fn shift_error(err: LexError, offset: usize) -> LexError {
+    match err {
+        LexError::UnexpectedChar(ch, span) => LexError::UnexpectedChar(ch, shift(span, offset)),
+        LexError::UnterminatedString(span) => LexError::UnterminatedString(shift(span, offset)),
+        LexError::UnterminatedComment(span) => LexError::UnterminatedComment(shift(span, offset)),
+        LexError::UnterminatedChar(span) => LexError::UnterminatedChar(shift(span, offset)),
+        LexError::InvalidCharLiteral(span) => LexError::InvalidCharLiteral(shift(span, offset)),
+        LexError::InvalidEscape(ch, span) => LexError::InvalidEscape(ch, shift(span, offset)),
+        LexError::IntegerOverflow(span) => LexError::IntegerOverflow(shift(span, offset)),
+        LexError::MissingDigits(prefix, span) => {
+            LexError::MissingDigits(prefix, shift(span, offset))
+        }
+        LexError::InvalidDigit(ch, radix, span) => {
+            LexError::InvalidDigit(ch, radix, shift(span, offset))
+        }
+        LexError::InvalidUtf8(span) => LexError::InvalidUtf8(shift(span, offset)),
+        LexError::NestingTooDeep(span) => LexError::NestingTooDeep(shift(span, offset)),
+        LexError::DisabledOperator(op, span) => LexError::DisabledOperator(op, shift(span, offset)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufRead;
+
+    use super::StreamingLexer;
+    use crate::lexer::{Lexer, LexerConfig};
+
+    fn assert_matches_lexer(input: &str) {
+        for keep_comments in [false, true] {
+            let config = LexerConfig { keep_comments };
+            let expected: Vec<_> = Lexer::with_config(input, config)
+                .map(|token| token.map(|token| (token.token.into_owned(), token.span)))
+                .collect();
+            let streamed: Vec<_> = StreamingLexer::with_config(input.as_bytes(), config)
+                .map(|token| token.unwrap().map(|token| (token.token, token.span)))
+                .collect();
+
+            assert_eq!(streamed, expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_matches_lexer() {
+        assert_matches_lexer(
+            "let s = \"two\nlines\" ? note\n  x >= 0x1F; ?* a\n?* b *?\n*?\n\
+             let t = \"${s} ${f(\"${x}\n\")}\"; \"bad \\q\" @\n\"open",
+        );
+    }
+
+    #[test]
+    fn test_open_literals() {
+        for input in [
+            "?* a\n?* b\n*? c\n*? let x = 1;\n",
+            "x ?* never closed\nlet y\n",
+            "let s = \"a\nb ${ f(\"c\nd\") + '\"' ? }\"\n }\" \"e\n\";\nx",
+            "\"${ ?* \"*? 1 }\nx\" y",
+            "\"a\\\n\\\"b\"\nz",
+            "\"${\"${\"${x\n}\"}\"}\" w",
+            "\"${ {a: \"}\"} \n} \"\nq",
+            "let c = '\\''; \"${'\\'' + \"}\"}\n\" k",
+            "\"abc\ndef",
+            "\"${ '\n' }\"\nhow",
+        ] {
+            assert_matches_lexer(input);
+        }
+    }
+
+    #[test]
+    fn test_dropped_comments() {
+        // Lines of a comment that is not kept are dropped as they are read.
+        let input = format!("a ?* {}*? b", "?* nested *? line\n".repeat(100));
+        let mut lexer = StreamingLexer::new(input.as_bytes());
+        while !lexer.at_eof {
+            if lexer.reader.read_line(&mut lexer.buffer).unwrap() == 0 {
+                lexer.at_eof = true;
+            }
+            lexer.lex_buffer();
+            assert!(lexer.buffer.is_empty());
+        }
+        assert_matches_lexer(&input);
+    }
+
+    #[test]
+    fn test_eof_span() {
+        let mut lexer = StreamingLexer::new("a\nbc".as_bytes());
+        lexer.by_ref().for_each(drop);
+
+        let eof = lexer.next_token().unwrap().unwrap();
+        assert_eq!((eof.span.start, eof.span.line, eof.span.column), (4, 2, 3));
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let mut lexer = StreamingLexer::new(&b"x \xff"[..]);
+
+        assert!(lexer.next_token().is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//! Generates programs that look like real ones, for benchmarks and tests that
+//! need a lot of source. Every program parses and type checks: functions call
+//! the ones before them, loop, branch, and build structs and arrays, with
+//! comments and interpolated strings between.
+//!
+//! ```
+//! use compyl::testgen;
+//!
+//! let source = testgen::program(1, 10);
+//! assert_eq!(source, testgen::program(1, 10));
+//! assert!(source.contains("fn f9("));
+//! ```
+
+/// How deeply blocks nest inside a function.
+const MAX_DEPTH: usize = 3;
+
+/// A program of `functions` functions, each called from the top level, and
+/// a struct per ten of them. The same `seed` always gives the same program.
+pub fn program(seed: u64, functions: usize) -> String {
+    let mut gen = Generator {
+        // Xorshift never leaves zero.
+        state: seed | 1,
+        out: String::new(),
+        indent: 0,
+        locals: Vec::new(),
+        structs: functions.div_ceil(10),
+    };
+    gen.program(functions);
+    gen.out
+}
+
+struct Generator {
+    state: u64,
+    out: String,
+    indent: usize,
+    /// The int variables in scope, innermost last.
+    locals: Vec<String>,
+    structs: usize,
+}
+
+impl Generator {
+    fn below(&mut self, n: usize) -> usize {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn program(&mut self, functions: usize) {
+        self.line("? Generated by compyl::testgen.");
+        for index in 0..self.structs {
+            self.line(&format!("struct S{} {{", index));
+            self.line("    a: int,");
+            self.line("    b: int,");
+            self.line("}");
+            self.line("");
+        }
+        for index in 0..functions {
+            self.function(index);
+            self.line("");
+        }
+        for index in 0..functions {
+            let (a, b) = (self.below(100), self.below(100));
+            self.line(&format!("let r{} = f{}({}, {});", index, index, a, b));
+            if self.chance(30) {
+                self.line(&format!("println(\"f{} gives ${{r{}}}\");", index, index));
+            }
+        }
+    }
+
+    fn function(&mut self, index: usize) {
+        if self.chance(40) {
+            self.line(&format!(
+                "? Computes something from `x` and `y`, #{}.",
+                index
+            ));
+        }
+        self.line(&format!("fn f{}(x: int, y: int): int {{", index));
+        self.indent += 1;
+        self.locals = vec!["x".into(), "y".into()];
+        let callees = index;
+        for _ in 0..2 + self.below(5) {
+            self.stmt(0, callees);
+        }
+        let value = self.expr(2, callees);
+        self.line(&format!("return {};", value));
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    fn stmt(&mut self, depth: usize, callees: usize) {
+        let choice = match depth < MAX_DEPTH {
+            true => self.below(10),
+            false => self.below(5),
+        };
+        match choice {
+            0 | 1 => {
+                let name = format!("v{}", self.locals.len());
+                let value = self.expr(2, callees);
+                self.line(&format!("let {} = {};", name, value));
+                self.locals.push(name);
+            }
+            2 => {
+                let target = self.local();
+                let op = ["+=", "-=", "*="][self.below(3)];
+                let value = self.expr(2, callees);
+                self.line(&format!("{} {} {};", target, op, value));
+            }
+            3 if self.structs > 0 => {
+                let name = format!("v{}", self.locals.len());
+                let ty = self.below(self.structs);
+                let (a, b) = (self.expr(1, callees), self.expr(1, callees));
+                self.line(&format!(
+                    "let {}_s = S{} {{ a: {}, b: {} }};",
+                    name, ty, a, b
+                ));
+                self.line(&format!("let {} = {}_s.a * {}_s.b;", name, name, name));
+                self.locals.push(name);
+            }
+            3 | 4 => {
+                let name = format!("v{}", self.locals.len());
+                let elems: Vec<_> = (0..3).map(|_| self.expr(1, callees)).collect();
+                let at = self.below(3);
+                self.line(&format!("let {}_items = [{}];", name, elems.join(", ")));
+                self.line(&format!(
+                    "let {} = {}_items[{}] + len({}_items);",
+                    name, name, at, name
+                ));
+                self.locals.push(name);
+            }
+            5 | 6 => {
+                let cond = self.condition(callees);
+                self.line(&format!("if ({}) {{", cond));
+                self.block(depth, callees);
+                if self.chance(50) {
+                    self.line("} else {");
+                    self.block(depth, callees);
+                }
+                self.line("}");
+            }
+            7 => {
+                let (target, bound, step) = (self.local(), self.below(1000), 1 + self.below(9));
+                self.line(&format!("while ({} < {}) {{", target, bound));
+                self.indent += 1;
+                self.line(&format!("{} += {};", target, step));
+                self.indent -= 1;
+                self.block(depth, callees);
+                self.line("}");
+            }
+            _ => {
+                let (var, end) = (format!("i{}", depth), 1 + self.below(16));
+                self.line(&format!("for ({} in 0..{}) {{", var, end));
+                self.locals.push(var);
+                self.block(depth, callees);
+                self.locals.pop();
+                self.line("}");
+            }
+        }
+    }
+
+    fn block(&mut self, depth: usize, callees: usize) {
+        let scope = self.locals.len();
+        self.indent += 1;
+        for _ in 0..1 + self.below(3) {
+            self.stmt(depth + 1, callees);
+        }
+        self.indent -= 1;
+        self.locals.truncate(scope);
+    }
+
+    fn local(&mut self) -> String {
+        let index = self.below(self.locals.len());
+        self.locals[index].clone()
+    }
+
+    fn condition(&mut self, callees: usize) -> String {
+        let op = ["<", "<=", ">", "==", "!="][self.below(5)];
+        // Bitwise operators bind looser than comparisons.
+        let (lhs, rhs) = (self.expr(1, callees), self.expr(0, callees));
+        format!("({}) {} {}", lhs, op, rhs)
+    }
+
+    /// An int expression at most `depth` operators deep.
+    fn expr(&mut self, depth: usize, callees: usize) -> String {
+        let choice = match depth {
+            0 => self.below(3),
+            _ => self.below(8),
+        };
+        match choice {
+            0 => self.local(),
+            1 => self.below(1000).to_string(),
+            2 => format!("0x{:x}", self.below(0x1_0000)),
+            3 if callees > 0 => {
+                let callee = self.below(callees);
+                let (a, b) = (self.expr(depth - 1, 0), self.expr(depth - 1, 0));
+                format!("f{}({}, {})", callee, a, b)
+            }
+            4 => format!("-({})", self.expr(depth - 1, callees)),
+            5 => {
+                let shifted = self.expr(depth - 1, callees);
+                format!("({} << {})", shifted, self.below(8))
+            }
+            _ => {
+                let op = ["+", "-", "*", "&", "|", "^"][self.below(6)];
+                let lhs = self.expr(depth - 1, callees);
+                let rhs = self.expr(depth - 1, callees);
+                format!("{} {} {}", lhs, op, rhs)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::program;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::{sema, types};
+
+    #[test]
+    fn test_programs_check() {
+        for seed in 0..20 {
+            let source = program(seed, 30);
+            let program = Parser::new(Lexer::new(&source))
+                .parse_program()
+                .expect("Program failed to parse.");
+            let resolution = sema::analyze(&program).expect("Program failed analysis.");
+            types::check(&program, &resolution).expect("Program failed to type check.");
+        }
+        assert_ne!(program(1, 30), program(2, 30));
+    }
+}
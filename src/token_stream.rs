@@ -0,0 +1,183 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::lexer::{LexError, Lexer, SpannedToken, Token};
+use crate::parser::ParseError;
+
+/// A comment-free token stream with arbitrary lookahead, for recursive-descent parsing.
+pub struct TokenStream<'src> {
+    lexer: Lexer<'src>,
+    buffer: VecDeque<Result<SpannedToken<'src>, LexError>>,
+    /// The doc comments read so far, by the offset of the token after them.
+    docs: HashMap<usize, String>,
+}
+
+impl<'src> TokenStream<'src> {
+    pub fn new(mut lexer: Lexer<'src>) -> TokenStream<'src> {
+        lexer.keep_comments();
+        TokenStream {
+            lexer,
+            buffer: VecDeque::new(),
+            docs: HashMap::new(),
+        }
+    }
+
+    /// Returns the next token without consuming it.
+    pub fn peek(&mut self) -> Result<&SpannedToken<'src>, LexError> {
+        self.peek_n(0)
+    }
+
+    /// Returns the token `k` positions ahead without consuming anything; `peek_n(0)`
+    /// is the same as `peek()`. Past the end of input this keeps returning `TokEof`.
+    pub fn peek_n(&mut self, k: usize) -> Result<&SpannedToken<'src>, LexError> {
+        while self.buffer.len() <= k {
+            let next = self.lex_significant();
+            self.buffer.push_back(next);
+        }
+
+        self.buffer[k].as_ref().map_err(|err| err.clone())
+    }
+
+    /// Consumes and returns the next token.
+    pub fn next_token(&mut self) -> Result<SpannedToken<'src>, LexError> {
+        match self.buffer.pop_front() {
+            Some(next) => next,
+            None => self.lex_significant(),
+        }
+    }
+
+    /// Makes `tokens` the next ones read, ahead of the rest of the input.
+    pub fn splice(&mut self, tokens: Vec<SpannedToken<'src>>) {
+        for token in tokens.into_iter().rev() {
+            self.buffer.push_front(Ok(token));
+        }
+    }
+
+    /// Consumes the next token if it is `token`, and errors otherwise.
+    pub fn expect(&mut self, token: Token) -> Result<SpannedToken<'src>, ParseError> {
+        let next = self.peek()?;
+        if next.token == token {
+            return Ok(self.next_token()?);
+        }
+
+        let expected = token.to_string();
+        let span = next.span;
+        Err(match &next.token {
+            Token::TokEof => ParseError::UnexpectedEof { expected, span },
+            found => ParseError::UnexpectedToken {
+                expected,
+                found: found.clone().into_owned(),
+                span,
+            },
+        })
+    }
+
+    /// Takes the text of the doc comment lines right before the token at
+    /// `offset`, without their `??`, if it has any.
+    pub fn take_doc(&mut self, offset: usize) -> Option<String> {
+        self.docs.remove(&offset)
+    }
+
+    /// Reads up to the next token that is not a comment. Other comments may
+    /// come between a doc comment and the token it documents.
+    fn lex_significant(&mut self) -> Result<SpannedToken<'src>, LexError> {
+        let mut doc = Vec::new();
+        loop {
+            let next = self.lexer.next_token()?;
+            match &next.token {
+                Token::Comment(_) => {}
+                Token::DocComment(text) => {
+                    let text = text.get(2..).unwrap_or_default();
+                    doc.push(text.strip_prefix(' ').unwrap_or(text).to_string());
+                }
+                _ => {
+                    if !doc.is_empty() {
+                        self.docs.insert(next.span.start, doc.join("\n"));
+                    }
+                    return Ok(next);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TokenStream;
+    use crate::lexer::{LexError, Lexer, Token};
+    use crate::parser::ParseError;
+    use crate::span::Span;
+
+    fn stream(input: &str) -> TokenStream<'_> {
+        TokenStream::new(Lexer::new(input))
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut tokens = stream("let ? comment\n x = 1;");
+
+        assert_eq!(tokens.peek().unwrap().token, Token::KwLet);
+        assert_eq!(tokens.peek_n(2).unwrap().token, Token::Assignment);
+        assert_eq!(tokens.peek_n(5).unwrap().token, Token::TokEof);
+        assert_eq!(tokens.peek_n(9).unwrap().token, Token::TokEof);
+        assert_eq!(tokens.next_token().unwrap().token, Token::KwLet);
+        assert_eq!(
+            tokens.next_token().unwrap().token,
+            Token::Identifier("x".into())
+        );
+        assert_eq!(tokens.peek().unwrap().token, Token::Assignment);
+    }
+
+    #[test]
+    fn test_expect() {
+        let mut tokens = stream("( }");
+
+        assert_eq!(
+            tokens.expect(Token::Lparen).unwrap().span,
+            Span::new(0, 1, 1, 1)
+        );
+        assert_eq!(
+            tokens.expect(Token::RParen),
+            Err(ParseError::UnexpectedToken {
+                expected: "`)`".into(),
+                found: Token::RSquirly,
+                span: Span::new(2, 3, 1, 3),
+            })
+        );
+        assert_eq!(tokens.next_token().unwrap().token, Token::RSquirly);
+        assert_eq!(
+            tokens.expect(Token::RParen),
+            Err(ParseError::UnexpectedEof {
+                expected: "`)`".into(),
+                span: Span::new(3, 3, 1, 4),
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors_are_buffered_in_order() {
+        let mut tokens = stream("a @ b");
+
+        assert_eq!(
+            tokens.peek_n(2).unwrap().token,
+            Token::Identifier("b".into())
+        );
+        assert_eq!(
+            tokens.peek_n(1),
+            Err(LexError::UnexpectedChar('@', Span::new(2, 3, 1, 3)))
+        );
+        assert!(tokens.next_token().is_ok());
+        assert!(tokens.next_token().is_err());
+        assert!(tokens.next_token().is_ok());
+    }
+
+    #[test]
+    fn test_docs() {
+        let mut tokens = stream("?? One.\n? note\n??\n??  Two.\nfn f ?? three\n;");
+
+        assert_eq!(tokens.peek_n(2).unwrap().token, Token::SemiColon);
+        assert_eq!(tokens.take_doc(0), None);
+        assert_eq!(tokens.take_doc(27), Some("One.\n\n Two.".into()));
+        assert_eq!(tokens.take_doc(27), None);
+        assert_eq!(tokens.take_doc(41), Some("three".into()));
+    }
+}
@@ -0,0 +1,104 @@
+//! Instrumentation for `compyl --trace`: how long each phase of the pipeline
+//! took, and what it produced. A disabled [`Log`] only runs the phases, so the
+//! driver can thread one through unconditionally.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// A phase of the pipeline, once it has run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub phase: &'static str,
+    pub elapsed: Duration,
+    /// What the phase produced, such as how many tokens or instructions.
+    pub detail: String,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self.elapsed.as_secs_f64() * 1000.0;
+        write!(f, "trace: {:<8} {:>9.3}ms", self.phase, millis)?;
+        if !self.detail.is_empty() {
+            write!(f, "  {}", self.detail)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Log {
+    enabled: bool,
+    events: Vec<Event>,
+}
+
+impl Log {
+    pub fn new(enabled: bool) -> Log {
+        Log {
+            enabled,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Runs `phase`, recording how long it took.
+    pub fn time<T>(&mut self, phase: &'static str, run: impl FnOnce() -> T) -> T {
+        if !self.enabled {
+            return run();
+        }
+        let start = Instant::now();
+        let result = run();
+        self.events.push(Event {
+            phase,
+            elapsed: start.elapsed(),
+            detail: String::new(),
+        });
+        result
+    }
+
+    /// Describes what the phase recorded last produced.
+    pub fn note(&mut self, detail: impl Into<String>) {
+        if let Some(event) = self.events.last_mut() {
+            event.detail = detail.into();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{Event, Log};
+
+    #[test]
+    fn test_log() {
+        let mut log = Log::new(true);
+        assert_eq!(log.time("lex", || 1 + 2), 3);
+        log.note("3 tokens");
+        log.time("sema", || ());
+        assert_eq!(log.events().len(), 2);
+        assert_eq!(log.events()[0].detail, "3 tokens");
+        assert_eq!(log.events()[1].detail, "");
+
+        let mut disabled = Log::new(false);
+        assert_eq!(disabled.time("lex", || 1), 1);
+        disabled.note("1 token");
+        assert!(disabled.events().is_empty());
+
+        let event = Event {
+            phase: "opt",
+            elapsed: Duration::from_micros(1250),
+            detail: "12 instructions".into(),
+        };
+        assert_eq!(
+            event.to_string(),
+            "trace: opt          1.250ms  12 instructions"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! Lossless lexing: every token keeps the whitespace and comments around it,
+//! so the token stream reproduces its source byte for byte.
+
+use crate::lexer::{LexError, Lexer, LexerConfig, SpannedToken, Token};
+use crate::span::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+    Whitespace,
+    Comment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia<'src> {
+    pub kind: TriviaKind,
+    pub text: &'src str,
+    pub span: Span,
+}
+
+/// A token with its source text and surrounding trivia. Trailing trivia runs
+/// to the end of the token's line, including the newline; everything after
+/// that leads the next token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriviaToken<'src> {
+    pub leading: Vec<Trivia<'src>>,
+    pub token: SpannedToken<'src>,
+    pub text: &'src str,
+    pub trailing: Vec<Trivia<'src>>,
+}
+
+/// Lexes `source` into tokens carrying trivia, ending with a `TokEof` that
+/// holds whatever trivia is left.
+///
+/// ```
+/// use compyl::trivia;
+///
+/// let source = "let x = 1; ? one\n\n?* two *? x;\n";
+/// let tokens = trivia::lex(source).unwrap();
+/// assert_eq!(trivia::to_source(&tokens), source);
+/// ```
+pub fn lex(source: &str) -> Result<Vec<TriviaToken<'_>>, LexError> {
+    let config = LexerConfig {
+        keep_comments: true,
+    };
+    let mut lexer = Lexer::with_config(source, config);
+    let mut builder = Builder {
+        source,
+        tokens: Vec::new(),
+        leading: Vec::new(),
+        trailing_open: false,
+    };
+    let (mut end, mut line, mut column) = (0, 1, 1);
+
+    loop {
+        let token = lexer.next_token()?;
+        let mut gap = &source[end..token.span.start];
+        if builder.trailing_open {
+            if let Some(newline) = gap.find('\n') {
+                builder.push(
+                    TriviaKind::Whitespace,
+                    Span::new(end, end + newline + 1, line, column),
+                );
+                (end, line, column) = (end + newline + 1, line + 1, 1);
+                gap = &gap[newline + 1..];
+            }
+        }
+        if !gap.is_empty() {
+            builder.push(
+                TriviaKind::Whitespace,
+                Span::new(end, token.span.start, line, column),
+            );
+        }
+
+        end = token.span.end;
+        (line, column) = (token.span.line, token.span.column);
+        for ch in source[token.span.start..end].chars() {
+            if ch == '\n' {
+                (line, column) = (line + 1, 1);
+            } else {
+                column += 1;
+            }
+        }
+
+        match token.token {
+            Token::Comment(_) | Token::DocComment(_) => {
+                builder.push(TriviaKind::Comment, token.span)
+            }
+            Token::TokEof => {
+                builder.token(token);
+                return Ok(builder.tokens);
+            }
+            _ => builder.token(token),
+        }
+    }
+}
+
+struct Builder<'src> {
+    source: &'src str,
+    tokens: Vec<TriviaToken<'src>>,
+    leading: Vec<Trivia<'src>>,
+    /// Whether trivia still trails the last token, which stops after a newline.
+    trailing_open: bool,
+}
+
+impl<'src> Builder<'src> {
+    fn push(&mut self, kind: TriviaKind, span: Span) {
+        let text = &self.source[span.start..span.end];
+        let trivia = Trivia { kind, text, span };
+        match self.tokens.last_mut() {
+            Some(last) if self.trailing_open => {
+                self.trailing_open = !(kind == TriviaKind::Whitespace && text.contains('\n'));
+                last.trailing.push(trivia);
+            }
+            _ => self.leading.push(trivia),
+        }
+    }
+
+    fn token(&mut self, token: SpannedToken<'src>) {
+        self.tokens.push(TriviaToken {
+            leading: std::mem::take(&mut self.leading),
+            text: &self.source[token.span.start..token.span.end],
+            token,
+            trailing: Vec::new(),
+        });
+        self.trailing_open = true;
+    }
+}
+
+/// Reassembles the source text that `tokens` were lexed from.
+pub fn to_source(tokens: &[TriviaToken]) -> String {
+    let mut source = String::new();
+    for token in tokens {
+        for trivia in &token.leading {
+            source.push_str(trivia.text);
+        }
+        source.push_str(token.text);
+        for trivia in &token.trailing {
+            source.push_str(trivia.text);
+        }
+    }
+
+    source
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lex, to_source, Trivia, TriviaKind};
+
+    fn texts<'src>(trivia: &[Trivia<'src>]) -> Vec<&'src str> {
+        trivia.iter().map(|trivia| trivia.text).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let sources = [
+            "",
+            "  \n",
+            "? only a comment",
+            "let s = \"a\\tb\";\r\n\tfn f() { return 0x1F; } ?* a ?* b *? *?\n\n",
+            "x\n  ? note\n  y",
+        ];
+
+        for source in sources {
+            assert_eq!(to_source(&lex(source).unwrap()), source);
+        }
+    }
+
+    #[test]
+    fn test_leading_and_trailing() {
+        let tokens = lex("a; ? end of line\n  ? own line\n  b").unwrap();
+
+        assert_eq!(tokens[1].text, ";");
+        assert_eq!(texts(&tokens[1].trailing), [" ", "? end of line", "\n"]);
+        assert_eq!(tokens[2].text, "b");
+        assert_eq!(texts(&tokens[2].leading), ["  ", "? own line", "\n  "]);
+        assert_eq!(tokens[2].leading[1].kind, TriviaKind::Comment);
+        let span = tokens[2].leading[2].span;
+        assert_eq!((span.line, span.column), (2, 13));
+    }
+}
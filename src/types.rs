@@ -0,0 +1,1266 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::ast::{
+    BinaryOp, Block, Expr, ExprKind, FieldInit, FnDecl, Ident, NodeId, Pattern, PatternKind,
+    Program, Stmt, StmtKind, TypeExpr, TypeExprKind, UnaryOp, ANONYMOUS,
+};
+use crate::builtins::Builtin;
+use crate::intern::Symbol;
+use crate::sema::Resolution;
+use crate::span::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Char,
+    Str,
+    Void,
+    Array(Box<Type>),
+    /// A struct, by name; its fields are in [`TypeTable::structs`].
+    Struct(Symbol),
+    Fn {
+        params: Vec<Type>,
+        ret: Box<Type>,
+    },
+    /// The type of an unannotated parameter or return value. It is compatible
+    /// with every other type, leaving the check to run time.
+    Unknown,
+}
+
+impl Type {
+    /// Whether a value of type `found` may be used where `self` is expected.
+    pub fn accepts(&self, found: &Type) -> bool {
+        match (self, found) {
+            (Type::Unknown, _) | (_, Type::Unknown) => true,
+            (Type::Array(elem), Type::Array(found)) => elem.accepts(found),
+            (
+                Type::Fn { params, ret },
+                Type::Fn {
+                    params: found_params,
+                    ret: found_ret,
+                },
+            ) => {
+                params.len() == found_params.len()
+                    && params.iter().zip(found_params).all(|(a, b)| a.accepts(b))
+                    && ret.accepts(found_ret)
+            }
+            (expected, found) => expected == found,
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::Char => write!(f, "char"),
+            Type::Str => write!(f, "str"),
+            Type::Void => write!(f, "void"),
+            Type::Array(elem) => write!(f, "[{}]", elem),
+            Type::Struct(name) => write!(f, "{}", name),
+            Type::Fn { params, ret } => {
+                write!(f, "fn(")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", param)?;
+                }
+                write!(f, "): {}", ret)
+            }
+            Type::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// The types found by [`check`], keyed by the id of each expression and of
+/// each declared name.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TypeTable {
+    pub exprs: HashMap<NodeId, Type>,
+    pub decls: HashMap<NodeId, Type>,
+    /// The fields of each struct, in declaration order.
+    pub structs: HashMap<Symbol, Vec<(Symbol, Type)>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeError {
+    UnknownType(String, Span),
+    Mismatch {
+        expected: Type,
+        found: Type,
+        span: Span,
+    },
+    InvalidOperands {
+        op: String,
+        lhs: Type,
+        rhs: Option<Type>,
+        span: Span,
+    },
+    NotCallable(Type, Span),
+    NotIndexable(Type, Span),
+    /// An assignment to an element of a value that cannot change, like a
+    /// string.
+    NotAssignable(Type, Span),
+    NoSuchField {
+        ty: Type,
+        field: String,
+        span: Span,
+    },
+    ArityMismatch {
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// An argument a builtin function cannot take.
+    InvalidArgument {
+        name: String,
+        found: Type,
+        span: Span,
+    },
+    /// A function with a return type whose body can end without a `return`;
+    /// the span is the return type.
+    MissingReturn {
+        name: String,
+        ty: Type,
+        span: Span,
+    },
+    /// A `void` expression, like a call to a function without a return
+    /// value, used where a value is needed.
+    VoidValue(Span),
+}
+
+impl TypeError {
+    pub fn span(&self) -> Span {
+        match self {
+            TypeError::UnknownType(_, span)
+            | TypeError::Mismatch { span, .. }
+            | TypeError::InvalidOperands { span, .. }
+            | TypeError::NotCallable(_, span)
+            | TypeError::NotIndexable(_, span)
+            | TypeError::NotAssignable(_, span)
+            | TypeError::NoSuchField { span, .. }
+            | TypeError::ArityMismatch { span, .. }
+            | TypeError::InvalidArgument { span, .. }
+            | TypeError::MissingReturn { span, .. }
+            | TypeError::VoidValue(span) => *span,
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeError::UnknownType(name, _) => write!(f, "unknown type `{}`", name),
+            TypeError::Mismatch {
+                expected, found, ..
+            } => write!(f, "expected `{}`, found `{}`", expected, found),
+            TypeError::InvalidOperands {
+                op,
+                lhs,
+                rhs: Some(rhs),
+                ..
+            } => write!(f, "cannot apply `{}` to `{}` and `{}`", op, lhs, rhs),
+            TypeError::InvalidOperands {
+                op, lhs, rhs: None, ..
+            } => write!(f, "cannot apply `{}` to `{}`", op, lhs),
+            TypeError::NotCallable(ty, _) => write!(f, "`{}` is not a function", ty),
+            TypeError::NotIndexable(ty, _) => write!(f, "`{}` cannot be indexed", ty),
+            TypeError::NotAssignable(ty, _) => {
+                write!(f, "elements of `{}` cannot be assigned", ty)
+            }
+            TypeError::NoSuchField { ty, field, .. } => {
+                write!(f, "`{}` has no field `{}`", ty, field)
+            }
+            TypeError::ArityMismatch {
+                expected, found, ..
+            } => write!(f, "expected {} argument(s), found {}", expected, found),
+            TypeError::InvalidArgument { name, found, .. } => {
+                write!(f, "`{}` cannot take an argument of type `{}`", name, found)
+            }
+            TypeError::MissingReturn { name, ty, .. } if name == ANONYMOUS => {
+                write!(f, "function can end without returning a `{}` value", ty)
+            }
+            TypeError::MissingReturn { name, ty, .. } => {
+                write!(f, "`{}` can end without returning a `{}` value", name, ty)
+            }
+            TypeError::VoidValue(_) => write!(f, "a `void` expression has no value to use"),
+        }
+    }
+}
+
+impl Error for TypeError {}
+
+/// Type checks `program`, using the name resolution from semantic analysis.
+///
+/// `let` bindings without an annotation take the type of their initializer;
+/// unannotated parameters and return types are [`Type::Unknown`].
+pub fn check(program: &Program, resolution: &Resolution) -> Result<TypeTable, Vec<TypeError>> {
+    check_in(program, resolution, &TypeTable::default())
+}
+
+/// Like [`check`], for a program analyzed with [`sema::analyze_in`], where
+/// `globals` has the types of the declarations and structs of the programs
+/// before it. The table returned has them too.
+///
+/// [`sema::analyze_in`]: crate::sema::analyze_in
+pub fn check_in(
+    program: &Program,
+    resolution: &Resolution,
+    globals: &TypeTable,
+) -> Result<TypeTable, Vec<TypeError>> {
+    let mut checker = Checker {
+        resolution,
+        table: TypeTable {
+            exprs: HashMap::new(),
+            decls: globals.decls.clone(),
+            structs: globals.structs.clone(),
+        },
+        returns: Vec::new(),
+        errors: Vec::new(),
+    };
+    checker.structs(&program.stmts);
+    checker.block(&program.stmts);
+
+    if checker.errors.is_empty() {
+        Ok(checker.table)
+    } else {
+        Err(checker.errors)
+    }
+}
+
+struct Checker<'a> {
+    resolution: &'a Resolution,
+    table: TypeTable,
+    /// The declared return type of each enclosing function.
+    returns: Vec<Type>,
+    errors: Vec<TypeError>,
+}
+
+impl Checker<'_> {
+    fn lower(&mut self, ty: &TypeExpr) -> Type {
+        match &ty.kind {
+            TypeExprKind::Named(name) => match name.as_str() {
+                "int" => Type::Int,
+                "float" => Type::Float,
+                "bool" => Type::Bool,
+                "char" => Type::Char,
+                "str" => Type::Str,
+                _ if self.table.structs.contains_key(name) => Type::Struct(*name),
+                _ => {
+                    self.errors
+                        .push(TypeError::UnknownType(name.to_string(), ty.span));
+                    Type::Unknown
+                }
+            },
+            TypeExprKind::Void => Type::Void,
+            TypeExprKind::Array(elem) => Type::Array(Box::new(self.lower(elem))),
+            TypeExprKind::Fn { params, ret } => Type::Fn {
+                params: params.iter().map(|param| self.lower(param)).collect(),
+                ret: Box::new(ret.as_ref().map_or(Type::Void, |ret| self.lower(ret))),
+            },
+        }
+    }
+
+    /// Collects the fields of the structs declared in `stmts`. All their
+    /// names are known first, so fields may refer to any of them.
+    fn structs(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            if let StmtKind::Struct(decl) = &stmt.kind {
+                self.table.structs.insert(decl.name.name, Vec::new());
+            }
+        }
+        for stmt in stmts {
+            if let StmtKind::Struct(decl) = &stmt.kind {
+                let fields = decl
+                    .fields
+                    .iter()
+                    .map(|field| (field.name.name, self.lower(&field.ty)))
+                    .collect();
+                self.table.structs.insert(decl.name.name, fields);
+            }
+        }
+    }
+
+    fn lower_annotation(&mut self, ty: &Option<TypeExpr>) -> Type {
+        ty.as_ref().map_or(Type::Unknown, |ty| self.lower(ty))
+    }
+
+    fn expect(&mut self, expected: &Type, found: Type, span: Span) {
+        if !expected.accepts(&found) {
+            self.errors.push(TypeError::Mismatch {
+                expected: expected.clone(),
+                found,
+                span,
+            });
+        }
+    }
+
+    fn type_of_use(&self, id: NodeId) -> Type {
+        if let Some(builtin) = self.resolution.builtins.get(&id) {
+            return builtin.ty();
+        }
+        self.resolution
+            .resolve(id)
+            .and_then(|(decl, _)| self.table.decls.get(&decl))
+            .cloned()
+            .unwrap_or(Type::Unknown)
+    }
+
+    fn block(&mut self, block: &Block) {
+        // Functions are callable before their declaration, so their
+        // signatures are needed up front.
+        for stmt in block {
+            if let StmtKind::Fn(decl) | StmtKind::Extern(decl) = &stmt.kind {
+                let ty = self.signature(decl);
+                self.table.decls.insert(decl.name.id, ty);
+            }
+        }
+
+        for stmt in block {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { name, ty, value } | StmtKind::Const { name, ty, value } => {
+                let found = self.value(value);
+                let ty = match ty {
+                    Some(ty) => {
+                        let ty = self.lower(ty);
+                        self.expect(&ty, found, value.span);
+                        ty
+                    }
+                    None => found,
+                };
+                self.table.decls.insert(name.id, ty);
+            }
+            StmtKind::Assign { target, op, value } => {
+                let expected = self.type_of_use(target.id);
+                let mut found = self.value(value);
+                if let Some(op) = op {
+                    found = self.binary(*op, expected.clone(), found, stmt.span);
+                }
+                self.expect(&expected, found, value.span);
+            }
+            StmtKind::IndexAssign {
+                array,
+                index,
+                op,
+                value,
+            } => {
+                let expected = self.index(array, index);
+                if self.table.exprs.get(&array.id) == Some(&Type::Str) {
+                    self.errors
+                        .push(TypeError::NotAssignable(Type::Str, array.span));
+                }
+                let mut found = self.value(value);
+                if let Some(op) = op {
+                    found = self.binary(*op, expected.clone(), found, stmt.span);
+                }
+                self.expect(&expected, found, value.span);
+            }
+            StmtKind::FieldAssign {
+                object,
+                field,
+                op,
+                value,
+            } => {
+                let expected = self.field(object, field);
+                let mut found = self.value(value);
+                if let Some(op) = op {
+                    found = self.binary(*op, expected.clone(), found, stmt.span);
+                }
+                self.expect(&expected, found, value.span);
+            }
+            StmtKind::Fn(decl) => self.function(decl),
+            StmtKind::Extern(_) | StmtKind::Struct(_) => {}
+            StmtKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.condition(cond);
+                self.block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.block(else_branch);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                self.condition(cond);
+                self.block(body);
+            }
+            StmtKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                for bound in [start, end] {
+                    let found = self.expr(bound);
+                    self.expect(&Type::Int, found, bound.span);
+                }
+                self.table.decls.insert(var.id, Type::Int);
+                self.block(body);
+            }
+            StmtKind::Return(value) => {
+                let (found, span) = match value {
+                    Some(value) => (self.expr(value), value.span),
+                    None => (Type::Void, stmt.span),
+                };
+                // A `return` at the top level ends the program with any value.
+                if let Some(expected) = self.returns.last().cloned() {
+                    self.expect(&expected, found, span);
+                }
+            }
+            StmtKind::Break | StmtKind::Continue | StmtKind::Import(_) | StmtKind::Error => {}
+            StmtKind::Block(block) => self.block(block),
+            StmtKind::Expr(expr) => {
+                self.expr(expr);
+            }
+        }
+    }
+
+    fn signature(&mut self, decl: &FnDecl) -> Type {
+        Type::Fn {
+            params: decl
+                .params
+                .iter()
+                .map(|param| self.lower_annotation(&param.ty))
+                .collect(),
+            ret: Box::new(self.lower_annotation(&decl.ret)),
+        }
+    }
+
+    fn function(&mut self, decl: &FnDecl) {
+        let Some(Type::Fn { params, ret }) = self.table.decls.get(&decl.name.id).cloned() else {
+            unreachable!("function signatures are collected before their block is checked");
+        };
+
+        for (param, ty) in decl.params.iter().zip(params) {
+            self.table.decls.insert(param.name.id, ty);
+        }
+        if !matches!(*ret, Type::Void | Type::Unknown) && !returns(&decl.body) {
+            self.errors.push(TypeError::MissingReturn {
+                name: decl.name.name.to_string(),
+                ty: (*ret).clone(),
+                span: decl.ret.as_ref().map_or(decl.name.span, |ret| ret.span),
+            });
+        }
+        self.returns.push(*ret);
+        self.block(&decl.body);
+        self.returns.pop();
+    }
+
+    /// Checks an expression whose value is used, which `void` ones lack.
+    fn value(&mut self, expr: &Expr) -> Type {
+        let found = self.expr(expr);
+        if found == Type::Void {
+            self.errors.push(TypeError::VoidValue(expr.span));
+            return Type::Unknown;
+        }
+        found
+    }
+
+    fn condition(&mut self, cond: &Expr) {
+        let found = self.expr(cond);
+        self.expect(&Type::Bool, found, cond.span);
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Type {
+        let ty = match &expr.kind {
+            ExprKind::Int(_) => Type::Int,
+            ExprKind::Float(_) => Type::Float,
+            ExprKind::Char(_) => Type::Char,
+            ExprKind::Str(_) => Type::Str,
+            ExprKind::Interpolation(value) => {
+                let found = self.expr(value);
+                if !Builtin::Str.accepts(&found) {
+                    self.errors.push(TypeError::InvalidArgument {
+                        name: Builtin::Str.name().to_string(),
+                        found,
+                        span: value.span,
+                    });
+                }
+                Type::Str
+            }
+            ExprKind::Bool(_) => Type::Bool,
+            ExprKind::Ident(_) => self.type_of_use(expr.id),
+            ExprKind::Unary { op, expr: operand } => {
+                let found = self.expr(operand);
+                self.unary(*op, found, expr.span)
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let lhs = self.expr(lhs);
+                let rhs = self.expr(rhs);
+                self.binary(*op, lhs, rhs, expr.span)
+            }
+            ExprKind::Call { callee, args } => self.call(callee, args, expr.span),
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.condition(cond);
+                let then_ty = self.expr(then_branch);
+                let else_ty = self.expr(else_branch);
+                self.expect(&then_ty, else_ty.clone(), else_branch.span);
+                match then_ty {
+                    Type::Unknown => else_ty,
+                    ty => ty,
+                }
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                let found = self.expr(scrutinee);
+                // The first arm with a known type decides the others'.
+                let mut ty = Type::Unknown;
+                for arm in arms {
+                    self.pattern(&arm.pattern, &found);
+                    let body = self.expr(&arm.body);
+                    self.expect(&ty, body.clone(), arm.body.span);
+                    if ty == Type::Unknown {
+                        ty = body;
+                    }
+                }
+                ty
+            }
+            ExprKind::Fn(decl) => {
+                let ty = self.signature(decl);
+                self.table.decls.insert(decl.name.id, ty.clone());
+                self.function(decl);
+                ty
+            }
+            ExprKind::Array(elems) => {
+                let found: Vec<Type> = elems.iter().map(|elem| self.value(elem)).collect();
+                // The first element with a known type decides the others'.
+                let elem = found
+                    .iter()
+                    .find(|ty| **ty != Type::Unknown)
+                    .cloned()
+                    .unwrap_or(Type::Unknown);
+                for (found, elem_expr) in found.into_iter().zip(elems) {
+                    self.expect(&elem, found, elem_expr.span);
+                }
+                Type::Array(Box::new(elem))
+            }
+            ExprKind::Index { expr, index } => self.index(expr, index),
+            ExprKind::Struct { name, fields } => self.struct_literal(name, fields),
+            ExprKind::Field { expr, field } => self.field(expr, field),
+        };
+
+        self.table.exprs.insert(expr.id, ty.clone());
+        ty
+    }
+
+    /// Checks that `pattern` can match a value of type `scrutinee`, binding
+    /// a name it declares to that type.
+    fn pattern(&mut self, pattern: &Pattern, scrutinee: &Type) {
+        let found = match &pattern.kind {
+            PatternKind::Int(_) => Type::Int,
+            PatternKind::Char(_) => Type::Char,
+            PatternKind::Str(_) => Type::Str,
+            PatternKind::Bool(_) => Type::Bool,
+            PatternKind::Binding(name) => {
+                self.table.decls.insert(name.id, scrutinee.clone());
+                return;
+            }
+            PatternKind::Wildcard => return,
+        };
+        self.expect(scrutinee, found, pattern.span);
+    }
+
+    /// The type of the element `array[index]`.
+    fn index(&mut self, array: &Expr, index: &Expr) -> Type {
+        let found = self.expr(array);
+        let index_ty = self.expr(index);
+        self.expect(&Type::Int, index_ty, index.span);
+        match found {
+            Type::Array(elem) => *elem,
+            Type::Str => Type::Char,
+            Type::Unknown => Type::Unknown,
+            other => {
+                self.errors.push(TypeError::NotIndexable(other, array.span));
+                Type::Unknown
+            }
+        }
+    }
+
+    /// The type of a literal of the struct `name`. Semantic analysis has
+    /// checked that it sets each field once.
+    fn struct_literal(&mut self, name: &Ident, fields: &[FieldInit]) -> Type {
+        for field in fields {
+            let found = self.value(&field.value);
+            let expected = self.table.structs.get(&name.name).and_then(|declared| {
+                declared
+                    .iter()
+                    .find(|(declared, _)| *declared == field.name.name)
+                    .map(|(_, ty)| ty.clone())
+            });
+            if let Some(expected) = expected {
+                self.expect(&expected, found, field.value.span);
+            }
+        }
+        Type::Struct(name.name)
+    }
+
+    /// The type of the field `object.field`.
+    fn field(&mut self, object: &Expr, field: &Ident) -> Type {
+        let found = self.expr(object);
+        if found == Type::Unknown {
+            return Type::Unknown;
+        }
+        let ty = match &found {
+            Type::Struct(name) => self.table.structs[name]
+                .iter()
+                .find(|(declared, _)| *declared == field.name)
+                .map(|(_, ty)| ty.clone()),
+            _ => None,
+        };
+        ty.unwrap_or_else(|| {
+            self.errors.push(TypeError::NoSuchField {
+                ty: found,
+                field: field.name.to_string(),
+                span: field.span,
+            });
+            Type::Unknown
+        })
+    }
+
+    fn unary(&mut self, op: UnaryOp, found: Type, span: Span) -> Type {
+        let result = match (op, &found) {
+            (_, Type::Unknown) => Some(match op {
+                UnaryOp::Neg => Type::Unknown,
+                UnaryOp::Not => Type::Bool,
+                UnaryOp::BitNot => Type::Int,
+            }),
+            (UnaryOp::Neg, ty) if ty.is_numeric() => Some(found.clone()),
+            (UnaryOp::Not, Type::Bool) => Some(Type::Bool),
+            (UnaryOp::BitNot, Type::Int) => Some(Type::Int),
+            _ => None,
+        };
+
+        result.unwrap_or_else(|| {
+            self.errors.push(TypeError::InvalidOperands {
+                op: op.to_string(),
+                lhs: found,
+                rhs: None,
+                span,
+            });
+            Type::Unknown
+        })
+    }
+
+    fn binary(&mut self, op: BinaryOp, lhs: Type, rhs: Type, span: Span) -> Type {
+        match binary_type(op, &lhs, &rhs) {
+            Some(ty) => ty,
+            None => {
+                self.errors.push(TypeError::InvalidOperands {
+                    op: op.to_string(),
+                    lhs,
+                    rhs: Some(rhs),
+                    span,
+                });
+                Type::Unknown
+            }
+        }
+    }
+
+    fn call(&mut self, callee: &Expr, args: &[Expr], span: Span) -> Type {
+        let callee_ty = self.expr(callee);
+        let mut found: Vec<Type> = args.iter().map(|arg| self.expr(arg)).collect();
+
+        // Semantic analysis has checked the number of arguments already.
+        if let Some(&builtin) = self.resolution.builtins.get(&callee.id) {
+            for (found, arg) in found.into_iter().zip(args) {
+                if !builtin.accepts(&found) {
+                    self.errors.push(TypeError::InvalidArgument {
+                        name: builtin.name().to_string(),
+                        found,
+                        span: arg.span,
+                    });
+                }
+            }
+            return builtin.ret();
+        }
+
+        for (found, arg) in found.iter_mut().zip(args) {
+            if *found == Type::Void {
+                self.errors.push(TypeError::VoidValue(arg.span));
+                *found = Type::Unknown;
+            }
+        }
+        match callee_ty {
+            Type::Fn { params, ret } => {
+                if params.len() != args.len() {
+                    self.errors.push(TypeError::ArityMismatch {
+                        expected: params.len(),
+                        found: args.len(),
+                        span,
+                    });
+                } else {
+                    for ((expected, found), arg) in params.iter().zip(found).zip(args) {
+                        self.expect(expected, found, arg.span);
+                    }
+                }
+                *ret
+            }
+            Type::Unknown => Type::Unknown,
+            other => {
+                self.errors.push(TypeError::NotCallable(other, callee.span));
+                Type::Unknown
+            }
+        }
+    }
+}
+
+/// The types `decl` declares for its parameters and result, with names other
+/// than the primitive types taken to be structs, as checking has made sure.
+pub(crate) fn signature(decl: &FnDecl) -> (Vec<Type>, Type) {
+    fn lower(ty: &TypeExpr) -> Type {
+        match &ty.kind {
+            TypeExprKind::Named(name) => match name.as_str() {
+                "int" => Type::Int,
+                "float" => Type::Float,
+                "bool" => Type::Bool,
+                "char" => Type::Char,
+                "str" => Type::Str,
+                _ => Type::Struct(*name),
+            },
+            TypeExprKind::Void => Type::Void,
+            TypeExprKind::Array(elem) => Type::Array(Box::new(lower(elem))),
+            TypeExprKind::Fn { params, ret } => Type::Fn {
+                params: params.iter().map(lower).collect(),
+                ret: Box::new(ret.as_deref().map_or(Type::Void, lower)),
+            },
+        }
+    }
+    let lower_annotation = |ty: &Option<TypeExpr>| ty.as_ref().map_or(Type::Unknown, lower);
+    let params = decl.params.iter().map(|param| lower_annotation(&param.ty));
+    (params.collect(), lower_annotation(&decl.ret))
+}
+
+/// Whether every path through `block` ends in a `return`, or in a
+/// `while (true)` loop without a `break` that it never leaves. `match` arms
+/// are expressions, which cannot return.
+fn returns(block: &[Stmt]) -> bool {
+    block.iter().any(|stmt| match &stmt.kind {
+        StmtKind::Return(_) => true,
+        StmtKind::Block(block) => returns(block),
+        StmtKind::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => returns(then_branch) && returns(else_branch),
+        StmtKind::While { cond, body } => {
+            matches!(cond.kind, ExprKind::Bool(true)) && !breaks(body)
+        }
+        _ => false,
+    })
+}
+
+/// Whether `block` has a `break` out of the loop whose body it is.
+fn breaks(block: &[Stmt]) -> bool {
+    block.iter().any(|stmt| match &stmt.kind {
+        StmtKind::Break => true,
+        StmtKind::Block(block) => breaks(block),
+        StmtKind::If {
+            then_branch,
+            else_branch,
+            ..
+        } => breaks(then_branch) || else_branch.as_deref().is_some_and(breaks),
+        _ => false,
+    })
+}
+
+/// The result of applying `op` to operands of the given types, mirroring
+/// what the interpreter accepts.
+fn binary_type(op: BinaryOp, lhs: &Type, rhs: &Type) -> Option<Type> {
+    use Type::{Bool, Char, Float, Int, Str, Unknown};
+
+    if *lhs == Type::Void || *rhs == Type::Void {
+        return None;
+    }
+    let unknown = *lhs == Unknown || *rhs == Unknown;
+    Some(match op {
+        BinaryOp::Eq | BinaryOp::Ne => Bool,
+        BinaryOp::And | BinaryOp::Or => {
+            if !Bool.accepts(lhs) || !Bool.accepts(rhs) {
+                return None;
+            }
+            Bool
+        }
+        BinaryOp::Gt | BinaryOp::Ge | BinaryOp::Lt | BinaryOp::Le => match (lhs, rhs) {
+            _ if unknown => Bool,
+            (Str, Str) | (Char, Char) => Bool,
+            (a, b) if a.is_numeric() && b.is_numeric() => Bool,
+            _ => return None,
+        },
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => {
+            match (lhs, rhs) {
+                _ if unknown => Unknown,
+                (Int, Int) => Int,
+                (Str, Str) if op == BinaryOp::Add => Str,
+                (a, b) if a.is_numeric() && b.is_numeric() => Float,
+                _ => return None,
+            }
+        }
+        BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor => match (lhs, rhs) {
+            _ if unknown => Unknown,
+            (Int, Int) => Int,
+            (Bool, Bool) => Bool,
+            _ => return None,
+        },
+        BinaryOp::Shl | BinaryOp::Shr => {
+            if !Int.accepts(lhs) || !Int.accepts(rhs) {
+                return None;
+            }
+            Int
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, Type, TypeError, TypeTable};
+    use crate::ast::{StmtKind, ANONYMOUS};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::sema;
+    use crate::span::Span;
+
+    fn run(input: &str) -> Result<TypeTable, Vec<TypeError>> {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.");
+        let resolution = sema::analyze(&program).expect("Program failed analysis.");
+        check(&program, &resolution)
+    }
+
+    fn errors(input: &str) -> Vec<TypeError> {
+        run(input).expect_err("Program type checked when it should not have.")
+    }
+
+    #[test]
+    fn test_let_inference() {
+        let input = "let a = 1; let b = a * 2.0; let c = \"x\" + \"y\"; let d = a < 2 && true;";
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        let table = check(&program, &sema::analyze(&program).unwrap()).unwrap();
+
+        let types: Vec<_> = program
+            .stmts
+            .iter()
+            .map(|stmt| match &stmt.kind {
+                StmtKind::Let { name, .. } => table.decls[&name.id].clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(types, vec![Type::Int, Type::Float, Type::Str, Type::Bool]);
+    }
+
+    #[test]
+    fn test_annotated_functions() {
+        run("
+            fn add(a: int, b: int): int { return a + b; }
+            fn apply(f: fn(int, int): int, x: int): int { return f(x, x); }
+            fn log(msg: str): void { return; }
+            let n: int = apply(add, 2);
+            log(\"done\");
+            ")
+        .expect("Program failed to type check.");
+
+        run("let inc: fn(int): int = fn(x: int): int { return x + 1; }; let n: int = inc(1);")
+            .expect("Program failed to type check.");
+        assert_eq!(
+            errors("let f: fn(): int = fn(): bool { return 1; };"),
+            vec![
+                TypeError::Mismatch {
+                    expected: Type::Bool,
+                    found: Type::Int,
+                    span: Span::new(39, 40, 1, 40),
+                },
+                TypeError::Mismatch {
+                    expected: Type::Fn {
+                        params: vec![],
+                        ret: Box::new(Type::Int),
+                    },
+                    found: Type::Fn {
+                        params: vec![],
+                        ret: Box::new(Type::Bool),
+                    },
+                    span: Span::new(19, 43, 1, 20),
+                },
+            ]
+        );
+
+        // Unannotated code is only checked where types are known.
+        run("fn id(x) { return x; } let a = id(1) + id(true);").unwrap();
+    }
+
+    #[test]
+    fn test_mismatches() {
+        assert_eq!(
+            errors("let a: int = 1.5; fn f(): bool { return 1; } if (a) {}"),
+            vec![
+                TypeError::Mismatch {
+                    expected: Type::Int,
+                    found: Type::Float,
+                    span: Span::new(13, 16, 1, 14),
+                },
+                TypeError::Mismatch {
+                    expected: Type::Bool,
+                    found: Type::Int,
+                    span: Span::new(40, 41, 1, 41),
+                },
+                TypeError::Mismatch {
+                    expected: Type::Bool,
+                    found: Type::Int,
+                    span: Span::new(49, 50, 1, 50),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        run("let a: str = if (1 < 2) \"x\" else \"y\"; fn id(x) { return x; } let b: int = if (true) id(1) else 2;")
+            .expect("Program failed to type check.");
+        assert_eq!(
+            errors("let a = if (1) 2 else 3.5; let b: bool = a;"),
+            vec![
+                TypeError::Mismatch {
+                    expected: Type::Bool,
+                    found: Type::Int,
+                    span: Span::new(12, 13, 1, 13),
+                },
+                TypeError::Mismatch {
+                    expected: Type::Int,
+                    found: Type::Float,
+                    span: Span::new(22, 25, 1, 23),
+                },
+                TypeError::Mismatch {
+                    expected: Type::Bool,
+                    found: Type::Int,
+                    span: Span::new(41, 42, 1, 42),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_match() {
+        run("let s: str = match (\"x\") { \"x\" => \"y\", other => other + \"!\" }; let c: int = match ('c') { 'c' => 1, _ => 2 };")
+            .expect("Program failed to type check.");
+        assert_eq!(
+            errors("let a = match (1) { \"one\" => 1, n => n + 1 }; let b: str = match (true) { true => \"y\", _ => 0 };"),
+            vec![
+                TypeError::Mismatch {
+                    expected: Type::Int,
+                    found: Type::Str,
+                    span: Span::new(20, 25, 1, 21),
+                },
+                TypeError::Mismatch {
+                    expected: Type::Str,
+                    found: Type::Int,
+                    span: Span::new(92, 93, 1, 93),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_for_loops() {
+        run("for (i in 0..3) { let j: int = i; }").expect("Program failed to type check.");
+        assert_eq!(
+            errors("for (i in 0.5..\"a\") { let b: bool = i; }"),
+            vec![
+                TypeError::Mismatch {
+                    expected: Type::Int,
+                    found: Type::Float,
+                    span: Span::new(10, 13, 1, 11),
+                },
+                TypeError::Mismatch {
+                    expected: Type::Int,
+                    found: Type::Str,
+                    span: Span::new(15, 18, 1, 16),
+                },
+                TypeError::Mismatch {
+                    expected: Type::Bool,
+                    found: Type::Int,
+                    span: Span::new(36, 37, 1, 37),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_operands() {
+        assert_eq!(
+            errors("let a = 1 + true; let b = !2; let c = \"s\"; c -= 1;"),
+            vec![
+                TypeError::InvalidOperands {
+                    op: "+".into(),
+                    lhs: Type::Int,
+                    rhs: Some(Type::Bool),
+                    span: Span::new(8, 16, 1, 9),
+                },
+                TypeError::InvalidOperands {
+                    op: "!".into(),
+                    lhs: Type::Int,
+                    rhs: None,
+                    span: Span::new(26, 28, 1, 27),
+                },
+                TypeError::InvalidOperands {
+                    op: "-".into(),
+                    lhs: Type::Str,
+                    rhs: Some(Type::Int),
+                    span: Span::new(43, 50, 1, 44),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_arrays() {
+        let table = run("let a: [[int]] = [[1], [], [2, 3]]; let b = a[0][1] + 1; a[1] = [b];")
+            .expect("Program failed to type check.");
+        assert!(table
+            .exprs
+            .values()
+            .any(|ty| *ty == Type::Array(Box::new(Type::Int))));
+
+        assert_eq!(
+            errors("let a = [1, true]; let n = 1; n[0]; a[\"0\"]; a[0] += 1.5;"),
+            vec![
+                TypeError::Mismatch {
+                    expected: Type::Int,
+                    found: Type::Bool,
+                    span: Span::new(12, 16, 1, 13),
+                },
+                TypeError::NotIndexable(Type::Int, Span::new(30, 31, 1, 31)),
+                TypeError::Mismatch {
+                    expected: Type::Int,
+                    found: Type::Str,
+                    span: Span::new(38, 41, 1, 39),
+                },
+                TypeError::Mismatch {
+                    expected: Type::Int,
+                    found: Type::Float,
+                    span: Span::new(52, 55, 1, 53),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chars() {
+        let table = run("let s = \"ab\"; let c: char = s[1]; let b = c < 'z' && int(c) == 98; let d = char(99);")
+            .expect("Program failed to type check.");
+        assert!(table.decls.values().any(|ty| *ty == Type::Char));
+
+        assert_eq!(
+            errors("let s = \"ab\"; s[0] = 'c'; let n = 'a' + 1; let t: str = 'x';"),
+            vec![
+                TypeError::NotAssignable(Type::Str, Span::new(14, 15, 1, 15)),
+                TypeError::InvalidOperands {
+                    op: "+".into(),
+                    lhs: Type::Char,
+                    rhs: Some(Type::Int),
+                    span: Span::new(34, 41, 1, 35),
+                },
+                TypeError::Mismatch {
+                    expected: Type::Str,
+                    found: Type::Char,
+                    span: Span::new(56, 59, 1, 57),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_structs() {
+        let input = "struct P { x: int, next: [P] } let p = P { x: 1, next: [] }; \
+                     let n = p.next[0].x + 1; p.x = \"a\"; p.y; n.x; P { x: 1.5, next: [] };";
+        let p = Type::Struct("P".into());
+        assert_eq!(
+            errors(input),
+            vec![
+                TypeError::Mismatch {
+                    expected: Type::Int,
+                    found: Type::Str,
+                    span: Span::new(92, 95, 1, 93),
+                },
+                TypeError::NoSuchField {
+                    ty: p,
+                    field: "y".into(),
+                    span: Span::new(99, 100, 1, 100),
+                },
+                TypeError::NoSuchField {
+                    ty: Type::Int,
+                    field: "x".into(),
+                    span: Span::new(104, 105, 1, 105),
+                },
+                TypeError::Mismatch {
+                    expected: Type::Int,
+                    found: Type::Float,
+                    span: Span::new(114, 117, 1, 115),
+                },
+            ]
+        );
+
+        let table = run("struct P { x: int } fn f(p: P): int { return p.x; }")
+            .expect("Program failed to type check.");
+        assert_eq!(table.structs[&"P".into()], vec![("x".into(), Type::Int)]);
+    }
+
+    #[test]
+    fn test_calls() {
+        assert_eq!(
+            errors("fn f(a: int) {} f(1, 2); f(\"s\"); let x = 1; x();"),
+            vec![
+                TypeError::ArityMismatch {
+                    expected: 1,
+                    found: 2,
+                    span: Span::new(16, 23, 1, 17),
+                },
+                TypeError::Mismatch {
+                    expected: Type::Int,
+                    found: Type::Str,
+                    span: Span::new(27, 30, 1, 28),
+                },
+                TypeError::NotCallable(Type::Int, Span::new(44, 45, 1, 45)),
+            ]
+        );
+        assert_eq!(
+            errors("let n: int = len(\"abc\"); len(n); fn f(): void {} print(f());"),
+            vec![
+                TypeError::InvalidArgument {
+                    name: "len".into(),
+                    found: Type::Int,
+                    span: Span::new(29, 30, 1, 30),
+                },
+                TypeError::InvalidArgument {
+                    name: "print".into(),
+                    found: Type::Void,
+                    span: Span::new(55, 58, 1, 56),
+                },
+            ]
+        );
+        run("let x: float = float(input()) + float(int(\"2\")); println(str(x));").unwrap();
+        run("let n = 1; let s: str = \"${n} and ${[n] == [1]}\";").unwrap();
+        assert_eq!(
+            errors("fn f(): void {} let s: int = \"${f()}\";"),
+            vec![
+                TypeError::InvalidArgument {
+                    name: "str".into(),
+                    found: Type::Void,
+                    span: Span::new(32, 35, 1, 33),
+                },
+                TypeError::Mismatch {
+                    expected: Type::Int,
+                    found: Type::Str,
+                    span: Span::new(29, 37, 1, 30),
+                },
+            ]
+        );
+        assert_eq!(
+            errors("let a: num = 1;"),
+            vec![TypeError::UnknownType("num".into(), Span::new(7, 10, 1, 8))]
+        );
+    }
+
+    #[test]
+    fn test_extern() {
+        // Calls are checked against the declared signature.
+        run("extern fn now(): int; extern fn log(msg: str); log(\"${now() + 1}\");")
+            .expect("Program failed to type check.");
+        assert_eq!(
+            errors("extern fn log(msg: str); log(1); log();"),
+            vec![
+                TypeError::Mismatch {
+                    expected: Type::Str,
+                    found: Type::Int,
+                    span: Span::new(29, 30, 1, 30),
+                },
+                TypeError::ArityMismatch {
+                    expected: 1,
+                    found: 0,
+                    span: Span::new(33, 38, 1, 34),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_returns() {
+        run("
+            fn a(x: int): int { if (x > 0) { return 1; } else if (x < 0) { return -1; } else { return 0; } }
+            fn b(): int { while (true) { if (a(1) == 1) { return 1; } } }
+            fn c(): int { { return 1; } }
+            fn d(x: int): void { if (x > 0) { return; } }
+            fn e(x) { if (x) { return 1; } }
+            ")
+        .expect("Program failed to type check.");
+        assert_eq!(
+            errors("fn f(x: int): int { if (x > 0) { return 1; } } let g = fn(): bool { while (true) { break; } };"),
+            vec![
+                TypeError::MissingReturn {
+                    name: "f".into(),
+                    ty: Type::Int,
+                    span: Span::new(14, 17, 1, 15),
+                },
+                TypeError::MissingReturn {
+                    name: ANONYMOUS.into(),
+                    ty: Type::Bool,
+                    span: Span::new(61, 65, 1, 62),
+                },
+            ]
+        );
+        assert_eq!(
+            errors("fn f(n: int): int { match (n) { _ => 0 }; }"),
+            vec![TypeError::MissingReturn {
+                name: "f".into(),
+                ty: Type::Int,
+                span: Span::new(14, 17, 1, 15),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_void_values() {
+        assert_eq!(
+            errors("fn f(): void {} let a = f(); let b: int = f(); let c = f() == f(); let d = [f()]; a = f(); len(f());"),
+            vec![
+                TypeError::VoidValue(Span::new(24, 27, 1, 25)),
+                TypeError::VoidValue(Span::new(42, 45, 1, 43)),
+                TypeError::InvalidOperands {
+                    op: "==".into(),
+                    lhs: Type::Void,
+                    rhs: Some(Type::Void),
+                    span: Span::new(55, 65, 1, 56),
+                },
+                TypeError::VoidValue(Span::new(76, 79, 1, 77)),
+                TypeError::VoidValue(Span::new(86, 89, 1, 87)),
+                TypeError::InvalidArgument {
+                    name: "len".into(),
+                    found: Type::Void,
+                    span: Span::new(95, 98, 1, 96),
+                },
+            ]
+        );
+        assert_eq!(
+            errors("fn f(): void {} fn g(x) {} g(f());"),
+            vec![TypeError::VoidValue(Span::new(29, 32, 1, 30))]
+        );
+    }
+}
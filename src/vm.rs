@@ -0,0 +1,873 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ast::BinaryOp;
+use crate::builtins::Io;
+use crate::bytecode::{Capture, Function, Module, Op};
+use crate::gc::{Gc, GcConfig, Heap, Trace, Tracer};
+use crate::intern::Symbol;
+use crate::interp::{
+    eval_binary, eval_field, eval_index, eval_unary, new_struct, store_field, store_index,
+    Backtrace, Call, Extern, HostFn, RuntimeError, RuntimeResult, Value, MAX_CALL_DEPTH,
+};
+
+/// A function value created by the VM, with the cells it captured.
+#[derive(Debug)]
+pub struct Closure {
+    pub func: Rc<Function>,
+    upvalues: Vec<Gc<RefCell<Value>>>,
+}
+
+impl Trace for Closure {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.upvalues.iter().for_each(|cell| tracer.visit(cell));
+    }
+}
+
+/// A variable some closure captures.
+impl Trace for RefCell<Value> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(value) = self.try_borrow() {
+            value.trace(tracer);
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut value) = self.try_borrow_mut() {
+            *value = Value::Void;
+        }
+    }
+}
+
+struct Frame {
+    closure: Gc<Closure>,
+    ip: usize,
+    locals: Vec<Value>,
+    cells: Vec<Gc<RefCell<Value>>>,
+}
+
+impl Frame {
+    fn new(heap: &mut Heap, closure: Gc<Closure>, mut args: Vec<Value>) -> Frame {
+        args.resize(closure.func.locals, Value::Void);
+        Frame {
+            ip: 0,
+            locals: args,
+            cells: (0..closure.func.cells).map(|_| new_cell(heap)).collect(),
+            closure,
+        }
+    }
+}
+
+fn new_cell(heap: &mut Heap) -> Gc<RefCell<Value>> {
+    heap.alloc(RefCell::new(Value::Void))
+}
+
+/// A stack-based virtual machine running [`Module`]s from the
+/// [`bytecode`](crate::bytecode) compiler.
+pub struct Vm {
+    stack: Vec<Value>,
+    globals: Vec<Value>,
+    hosts: HashMap<Symbol, HostFn>,
+    io: Io,
+    max_call_depth: usize,
+    backtrace: Backtrace,
+    /// Last, so that it collects what the globals leave behind when dropped.
+    heap: Heap,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm {
+            stack: Vec::new(),
+            globals: Vec::new(),
+            hosts: HashMap::new(),
+            io: Io::default(),
+            max_call_depth: MAX_CALL_DEPTH,
+            backtrace: Backtrace::default(),
+            heap: Heap::default(),
+        }
+    }
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        Vm::default()
+    }
+
+    pub fn with_io(io: Io) -> Vm {
+        Vm {
+            io,
+            ..Vm::default()
+        }
+    }
+
+    /// Makes more than `max_call_depth` nested calls a
+    /// [`RuntimeError::StackOverflow`].
+    pub fn with_max_call_depth(mut self, max_call_depth: usize) -> Vm {
+        self.max_call_depth = max_call_depth;
+        self
+    }
+
+    /// Collects garbage when `config` says to rather than by default.
+    pub fn with_gc(mut self, config: GcConfig) -> Vm {
+        self.heap.configure(config);
+        self
+    }
+
+    /// Registers `func` to be called for `extern fn name`, like
+    /// [`Interpreter::register_fn`](crate::interp::Interpreter::register_fn).
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        func: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) {
+        self.hosts.insert(Symbol::intern(name), Rc::new(func));
+    }
+
+    pub fn io(&mut self) -> &mut Io {
+        &mut self.io
+    }
+
+    pub fn heap(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    /// Where the error the last run failed with happened, like
+    /// [`Interpreter::backtrace`](crate::interp::Interpreter::backtrace).
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
+    }
+
+    /// Runs `module`, returning the value of a top-level `return`, or `Void`.
+    pub fn run(&mut self, module: &Module) -> RuntimeResult<Value> {
+        self.stack.clear();
+        self.globals.resize(module.globals, Value::Void);
+        self.backtrace = Backtrace::default();
+
+        let main = self.heap.alloc(Closure {
+            func: module.functions[0].clone(),
+            upvalues: Vec::new(),
+        });
+        let mut frames = vec![Frame::new(&mut self.heap, main, Vec::new())];
+        let result = self.execute(module, &mut frames);
+        if result.is_err() {
+            self.backtrace = backtrace(&frames);
+        }
+        result
+    }
+
+    fn execute(&mut self, module: &Module, frames: &mut Vec<Frame>) -> RuntimeResult<Value> {
+        loop {
+            // Everything the program can reach is on the stack, in the
+            // globals or in `frames` here, between instructions.
+            self.heap.maybe_collect();
+            let frame = frames.last_mut().expect("a frame is running");
+            let op = frame.closure.func.code[frame.ip];
+            frame.ip += 1;
+
+            match op {
+                Op::Const(idx) => self.stack.push(module.constants[idx as usize].clone()),
+                Op::Void => self.stack.push(Value::Void),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Swap => {
+                    let len = self.stack.len();
+                    self.stack.swap(len - 1, len - 2);
+                }
+                Op::LoadLocal(slot) => self.stack.push(frame.locals[slot as usize].clone()),
+                Op::StoreLocal(slot) => frame.locals[slot as usize] = self.pop(),
+                Op::LoadGlobal(slot) => self.stack.push(self.globals[slot as usize].clone()),
+                Op::StoreGlobal(slot) => self.globals[slot as usize] = self.pop(),
+                Op::NewCell(slot) => frame.cells[slot as usize] = new_cell(&mut self.heap),
+                Op::LoadCell(slot) => {
+                    let value = frame.cells[slot as usize].borrow().clone();
+                    self.stack.push(value);
+                }
+                Op::StoreCell(slot) => *frame.cells[slot as usize].borrow_mut() = self.pop(),
+                Op::LoadUpvalue(slot) => {
+                    let value = frame.closure.upvalues[slot as usize].borrow().clone();
+                    self.stack.push(value);
+                }
+                Op::StoreUpvalue(slot) => {
+                    *frame.closure.upvalues[slot as usize].borrow_mut() = self.pop();
+                }
+                Op::Closure(idx) => {
+                    let func = module.functions[idx as usize].clone();
+                    let upvalues = func
+                        .captures
+                        .iter()
+                        .map(|capture| match capture {
+                            Capture::Cell(slot) => frame.cells[*slot as usize].clone(),
+                            Capture::Upvalue(slot) => {
+                                frame.closure.upvalues[*slot as usize].clone()
+                            }
+                        })
+                        .collect();
+                    let closure = self.heap.alloc(Closure { func, upvalues });
+                    self.stack.push(Value::Closure(closure));
+                }
+                Op::Extern(idx) => {
+                    let func = Extern::bind(&self.hosts, &module.externs[idx as usize])?;
+                    self.stack.push(func);
+                }
+                Op::Unary(op) => {
+                    let value = self.pop();
+                    self.stack.push(eval_unary(op, value)?);
+                }
+                Op::Binary(op) => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    self.stack.push(eval_binary(op, lhs, rhs)?);
+                }
+                Op::Array(len) => {
+                    let elems = self.stack.split_off(self.stack.len() - len as usize);
+                    let elems = self.heap.alloc(RefCell::new(elems));
+                    self.stack.push(Value::Array(elems));
+                }
+                Op::Index => {
+                    let index = self.pop();
+                    let array = self.pop();
+                    self.stack.push(eval_index(&array, &index)?);
+                }
+                Op::StoreIndex(op) => {
+                    let mut value = self.pop();
+                    let index = self.pop();
+                    let array = self.pop();
+                    if let Some(op) = op {
+                        value = eval_binary(op, eval_index(&array, &index)?, value)?;
+                    }
+                    store_index(&array, &index, value)?;
+                }
+                Op::Struct(idx) => {
+                    let literal = &module.literals[idx as usize];
+                    let values = self
+                        .stack
+                        .split_off(self.stack.len() - literal.fields.len());
+                    let fields = literal.fields.iter().copied().zip(values).collect();
+                    let value = new_struct(&mut self.heap, literal.layout.clone(), fields)?;
+                    self.stack.push(value);
+                }
+                Op::Field(field) => {
+                    let object = self.pop();
+                    self.stack.push(eval_field(&object, field)?);
+                }
+                Op::StoreField(field, op) => {
+                    let mut value = self.pop();
+                    let object = self.pop();
+                    if let Some(op) = op {
+                        value = eval_binary(op, eval_field(&object, field)?, value)?;
+                    }
+                    store_field(&object, field, value)?;
+                }
+                Op::Jump(target) => frame.ip = target as usize,
+                Op::JumpIfFalse(target) => match self.pop() {
+                    Value::Bool(true) => {}
+                    Value::Bool(false) => frame.ip = target as usize,
+                    other => return Err(RuntimeError::NonBoolCondition(other.type_name())),
+                },
+                Op::ShortCircuit(op, target) => match self.stack.last() {
+                    Some(Value::Bool(b)) => {
+                        if *b == (op == BinaryOp::Or) {
+                            frame.ip = target as usize;
+                        } else {
+                            self.pop();
+                        }
+                    }
+                    other => {
+                        return Err(RuntimeError::TypeMismatch {
+                            op: op.to_string(),
+                            lhs: other.map_or("void", Value::type_name),
+                            rhs: None,
+                        })
+                    }
+                },
+                Op::CheckBool(op) => {
+                    if let Some(other) = self.stack.last().filter(|v| !matches!(v, Value::Bool(_)))
+                    {
+                        return Err(RuntimeError::TypeMismatch {
+                            op: op.to_string(),
+                            lhs: "bool",
+                            rhs: Some(other.type_name()),
+                        });
+                    }
+                }
+                Op::CheckRange => {
+                    let bounds = &self.stack[self.stack.len() - 2..];
+                    if let [start, end] = bounds {
+                        if !matches!((start, end), (Value::Int(_), Value::Int(_))) {
+                            return Err(RuntimeError::TypeMismatch {
+                                op: "..".to_string(),
+                                lhs: start.type_name(),
+                                rhs: Some(end.type_name()),
+                            });
+                        }
+                    }
+                }
+                Op::NoMatch => return Err(RuntimeError::NoMatch(self.pop().to_string())),
+                Op::Call(argc) => {
+                    let args = self.stack.split_off(self.stack.len() - argc as usize);
+                    let closure = match self.pop() {
+                        Value::Closure(closure) => closure,
+                        Value::Builtin(builtin) => {
+                            let result = builtin.call(args, &mut self.io)?;
+                            self.stack.push(result);
+                            continue;
+                        }
+                        Value::Extern(func) => {
+                            self.stack.push(func.call(args)?);
+                            continue;
+                        }
+                        other => return Err(RuntimeError::NotCallable(other.type_name())),
+                    };
+                    if closure.func.arity != args.len() {
+                        return Err(RuntimeError::ArityMismatch {
+                            name: closure.func.name.clone(),
+                            expected: closure.func.arity,
+                            found: args.len(),
+                        });
+                    }
+                    // The first frame runs the top level, not a call.
+                    if frames.len() > self.max_call_depth {
+                        return Err(RuntimeError::StackOverflow(self.max_call_depth));
+                    }
+                    frames.push(Frame::new(&mut self.heap, closure, args));
+                }
+                Op::Return => {
+                    frames.pop();
+                    if frames.is_empty() {
+                        return Ok(self.pop());
+                    }
+                }
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack
+            .pop()
+            .expect("the compiler keeps the stack balanced")
+    }
+}
+
+/// Locates an error at the op each frame of `frames` last ran, which for all
+/// but the innermost is the call it is waiting on.
+fn backtrace(frames: &[Frame]) -> Backtrace {
+    let span = |frame: &Frame| frame.closure.func.spans[frame.ip - 1];
+    Backtrace {
+        span: frames.last().map(span),
+        calls: frames
+            .windows(2)
+            .rev()
+            .map(|pair| Call {
+                function: pair[1].closure.func.name.clone(),
+                span: span(&pair[0]),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::Vm;
+    use crate::builtins::Io;
+    use crate::bytecode;
+    use crate::gc::GcConfig;
+    use crate::interp::{Backtrace, Call, Interpreter, RuntimeError, Value};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::sema;
+    use crate::span::Span;
+    use crate::types::Type;
+
+    fn run(input: &str) -> Result<Value, RuntimeError> {
+        run_with_input(input, "").0
+    }
+
+    /// Runs `input` reading `stdin`, returning the result and what it printed.
+    fn run_with_input(input: &str, stdin: &str) -> (Result<Value, RuntimeError>, String) {
+        let program = Parser::new(Lexer::new(input))
+            .parse_program()
+            .expect("Program failed to parse.");
+        let resolution = sema::analyze(&program).expect("Program failed analysis.");
+        let mut vm = Vm::with_io(Io::buffered(stdin));
+        let result = vm.run(&bytecode::compile(&program, &resolution));
+        let output = vm.io().take_output();
+
+        // The VM must agree with the tree-walking interpreter.
+        let mut interp = Interpreter::with_io(Io::buffered(stdin));
+        assert_eq!(result, interp.run(&program));
+        assert_eq!(output, interp.io().take_output());
+        (result, output)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(run("return 1 + 2 * 3 - 8 / 4 % 3;"), Ok(Value::Int(5)));
+        assert_eq!(
+            run("return -(2 - 5) >= 3 && !false;"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            run("let s = \"a\"; s += \"b\"; return s;"),
+            Ok(Value::Str("ab".into()))
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let input = "
+        let i = 0;
+        let sum = 0;
+        while (true) {
+            i += 1;
+            if (i > 10) {
+                break;
+            } else {
+                if (i % 2 == 0) {
+                    sum = sum + i;
+                }
+            }
+        }
+        {
+            let sum = 100;
+        }
+        return sum;
+        ";
+
+        assert_eq!(run(input), Ok(Value::Int(30)));
+    }
+
+    #[test]
+    fn test_functions_and_closures() {
+        let input = "
+        let total = fib(10);
+        fn fib(n) {
+            if (n < 2) {
+                return n;
+            }
+            return fib(n - 1) + fib(n - 2);
+        }
+
+        fn make_counter(step) {
+            let count = 0;
+            fn bump() {
+                count = count + step;
+                return count;
+            }
+            return bump;
+        }
+
+        let a = make_counter(1);
+        let b = make_counter(10);
+        a();
+        b();
+        return total + a() + b();
+        ";
+
+        assert_eq!(run(input), Ok(Value::Int(77)));
+    }
+
+    #[test]
+    fn test_fn_expressions() {
+        let input = "
+        fn compose(f, g) {
+            return fn(x) { return g(f(x)); };
+        }
+        let counters = [];
+        let makers = [0, 0, 0];
+        let i = 0;
+        while (i < 3) {
+            let start = i * 10;
+            makers[i] = fn() {
+                start += 1;
+                return start;
+            };
+            i += 1;
+        }
+        makers[1]();
+        let twice = compose(fn(x) { return x * 2; }, fn(x) { return x + 1; });
+        let fib = fn fib(n) {
+            if (n < 2) {
+                return n;
+            }
+            return fib(n - 1) + fib(n - 2);
+        };
+        println(fn() {});
+        return makers[0]() + makers[1]() + twice(5) + fib(10);
+        ";
+
+        let (result, output) = run_with_input(input, "");
+        assert_eq!(output, "<fn <anonymous>>\n");
+        assert_eq!(result, Ok(Value::Int(1 + 12 + 11 + 55)));
+    }
+
+    #[test]
+    fn test_fresh_cells_per_iteration() {
+        let input = "
+        let first = 0;
+        let i = 0;
+        while (i < 3) {
+            let x = i;
+            fn get() { return x; }
+            if (i == 0) { first = get; }
+            i += 1;
+        }
+        return first();
+        ";
+
+        assert_eq!(run(input), Ok(Value::Int(0)));
+    }
+
+    #[test]
+    fn test_for_loops() {
+        let input = "
+        let fns = [0, 0, 0];
+        let total = 0;
+        for (i in 0..3) {
+            fns[i] = fn() { return i; };
+            i *= 10;
+            for (j in 0..100) {
+                if (j == 2) {
+                    break;
+                }
+                if (j == 0) {
+                    continue;
+                }
+                total += i + j;
+            }
+        }
+        return total * 100 + fns[0]() + fns[1]() + fns[2]();
+        ";
+
+        // Each closure captures the variable of its own iteration.
+        let (result, _) = run_with_input(input, "");
+        assert_eq!(result, Ok(Value::Int(3300 + 30)));
+        assert_eq!(
+            run("for (i in true..1) {}"),
+            Err(RuntimeError::TypeMismatch {
+                op: "..".into(),
+                lhs: "bool",
+                rhs: Some("int"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        assert_eq!(
+            run("fn boom() { return 1 / 0; } return false && boom() || true;"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            run("return true && 1;"),
+            Err(RuntimeError::TypeMismatch {
+                op: "&&".into(),
+                lhs: "bool",
+                rhs: Some("int"),
+            })
+        );
+        assert_eq!(
+            run("return 1 || true;"),
+            Err(RuntimeError::TypeMismatch {
+                op: "||".into(),
+                lhs: "int",
+                rhs: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_arrays() {
+        let input = "
+        fn push_all(a, n) {
+            let out = [0, 0, 0, 0];
+            let i = 0;
+            while (i < n) {
+                out[i] = a[i] * 2;
+                out[i] += 1;
+                i += 1;
+            }
+            return out;
+        }
+        let a = push_all([1, 2, 3, 4], 3);
+        println(a);
+        println([\"x\", \"y\"]);
+        return a[4];
+        ";
+
+        let (result, output) = run_with_input(input, "");
+        assert_eq!(output, "[3, 5, 7, 0]\n[\"x\", \"y\"]\n");
+        assert_eq!(
+            result,
+            Err(RuntimeError::IndexOutOfBounds { index: 4, len: 4 })
+        );
+    }
+
+    #[test]
+    fn test_structs() {
+        let input = "
+        struct Node { value: int, next: [Node] }
+        fn push(list, value) {
+            return Node { next: [list], value: value };
+        }
+        let list = push(push(Node { value: 1, next: [] }, 2), 3);
+        list.next[0].value += 10;
+        let total = 0;
+        let node = list;
+        while (true) {
+            total += node.value;
+            if (len(node.next) == 0) {
+                break;
+            }
+            node = node.next[0];
+        }
+        println(list.next[0].next[0]);
+        return total + list.missing;
+        ";
+
+        let (result, output) = run_with_input(input, "");
+        assert_eq!(output, "Node { value: 1, next: [] }\n");
+        assert_eq!(
+            result,
+            Err(RuntimeError::NoSuchField {
+                ty: "Node",
+                field: "missing".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_builtins() {
+        let input = "
+        let n = int(input());
+        let total = 0.0;
+        while (n > 0) {
+            total += float(input());
+            n -= 1;
+        }
+        let p = println;
+        p(\"total: ${total}\");
+        print(len(input()));
+        return int(\"x\");
+        ";
+
+        let (result, output) = run_with_input(input, "2\n1.5\n2\nabc");
+        assert_eq!(output, "total: 3.5\n3");
+        assert_eq!(
+            result,
+            Err(RuntimeError::InvalidConversion {
+                value: "x".into(),
+                to: "int",
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(run("return 1 / 0;"), Err(RuntimeError::DivisionByZero));
+        assert_eq!(
+            run("fn f(a) { return a; } f(1, 2);"),
+            Err(RuntimeError::ArityMismatch {
+                name: "f".into(),
+                expected: 1,
+                found: 2,
+            })
+        );
+        assert_eq!(run("5();"), Err(RuntimeError::NotCallable("int")));
+        assert_eq!(run("if (1) {}"), Err(RuntimeError::NonBoolCondition("int")));
+    }
+
+    #[test]
+    fn test_call_depth() {
+        // `f(n)` makes `n + 1` nested calls.
+        let run = |n| {
+            let input = format!(
+                "fn f(n) {{ if (n == 0) {{ return 0; }} return f(n - 1); }} return f({});",
+                n
+            );
+            let program = Parser::new(Lexer::new(&input)).parse_program().unwrap();
+            let resolution = sema::analyze(&program).unwrap();
+            let result = Vm::new()
+                .with_max_call_depth(20)
+                .run(&bytecode::compile(&program, &resolution));
+            let interp = Interpreter::new().with_max_call_depth(20).run(&program);
+            assert_eq!(result, interp);
+            result
+        };
+        assert_eq!(run(19), Ok(Value::Int(0)));
+        assert_eq!(run(20), Err(RuntimeError::StackOverflow(20)));
+    }
+
+    #[test]
+    fn test_garbage() {
+        // Each iteration leaves a function in a cycle with its scope, and an
+        // array holding itself; the last function is kept.
+        let input = "
+        let kept = [0];
+        for (i in 0..2000) {
+            fn f() { return f; }
+            let a = [i];
+            a[0] = a;
+            kept[0] = f;
+        }
+        return kept[0]() == kept[0];
+        ";
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        let resolution = sema::analyze(&program).unwrap();
+        let config = GcConfig {
+            initial_threshold: 100,
+            growth_factor: 2.0,
+        };
+        let mut vm = Vm::new().with_gc(config);
+        let result = vm.run(&bytecode::compile(&program, &resolution));
+        assert_eq!(result, Ok(Value::Bool(true)));
+        let mut interp = Interpreter::new().with_gc(config);
+        assert_eq!(interp.run(&program), result);
+
+        for heap in [vm.heap(), interp.heap()] {
+            assert!(heap.len() <= 200, "{} objects are alive", heap.len());
+            heap.collect();
+            assert!(heap.len() < 10, "{} objects are reachable", heap.len());
+        }
+    }
+
+    #[test]
+    fn test_extern() {
+        let run = |input: &str| {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            let resolution = sema::analyze(&program).unwrap();
+            let log = Rc::new(RefCell::new(Vec::new()));
+            let mut vm = Vm::new();
+            let mut interp = Interpreter::new();
+            let sink = log.clone();
+            let record = move |args: &[Value]| {
+                sink.borrow_mut().push(args[0].to_string());
+                Ok(Value::Void)
+            };
+            let add = |args: &[Value]| match args {
+                [Value::Int(a), Value::Int(b)] => Ok(Value::Int(a + b)),
+                _ => Err("expected two ints".to_string()),
+            };
+            vm.register_fn("log", record.clone());
+            vm.register_fn("add", add);
+            interp.register_fn("log", record);
+            interp.register_fn("add", add);
+
+            let result = vm.run(&bytecode::compile(&program, &resolution));
+            assert_eq!(result, interp.run(&program));
+            let log = log.borrow().clone();
+            (result, log)
+        };
+
+        let (result, log) = run("
+        extern fn log(msg);
+        extern fn add(a: int, b: int): int;
+        fn twice(x) { return add(x, x); }
+        log(\"${twice(2)}\");
+        let f = log;
+        f(add);
+        return add(1, 2);
+        ");
+        assert_eq!(result, Ok(Value::Int(3)));
+        // Each runtime logs once per call.
+        assert_eq!(log, ["4", "<fn add>", "4", "<fn add>"]);
+
+        assert_eq!(
+            run("extern fn add(a, b); add(1, true);").0,
+            Err(RuntimeError::Host {
+                name: "add".into(),
+                message: "expected two ints".into(),
+            })
+        );
+        // Values crossing to and from the host must have the declared types.
+        assert_eq!(
+            run(
+                "extern fn add(a: int, b: int): int; fn call(f) { return f(1, \"2\"); } call(add);"
+            )
+            .0,
+            Err(RuntimeError::HostArgument {
+                name: "add".into(),
+                index: 1,
+                expected: Type::Int,
+                found: "str",
+            })
+        );
+        assert_eq!(
+            run("extern fn log(msg): int; log(1);"),
+            (
+                Err(RuntimeError::HostResult {
+                    name: "log".into(),
+                    expected: Type::Int,
+                    found: "void",
+                }),
+                vec!["1".to_string(), "1".to_string()]
+            )
+        );
+        assert_eq!(
+            run("extern fn add(a); add(1, 2);").0,
+            Err(RuntimeError::ArityMismatch {
+                name: "add".into(),
+                expected: 1,
+                found: 2,
+            })
+        );
+        assert_eq!(
+            run("extern fn missing(); println(1);"),
+            (
+                Err(RuntimeError::UnregisteredExtern("missing".into())),
+                vec![]
+            )
+        );
+    }
+
+    #[test]
+    fn test_backtraces() {
+        let backtrace = |input: &str| {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            let resolution = sema::analyze(&program).unwrap();
+            let mut vm = Vm::new();
+            assert!(vm.run(&bytecode::compile(&program, &resolution)).is_err());
+            let mut interp = Interpreter::new();
+            assert!(interp.run(&program).is_err());
+            assert_eq!(vm.backtrace(), interp.backtrace());
+            vm.backtrace().clone()
+        };
+
+        assert_eq!(
+            backtrace("fn f(a) { return 1 / a; }\nfn g() { f(0); }\ng();"),
+            Backtrace {
+                span: Some(Span::new(17, 22, 1, 18)),
+                calls: vec![
+                    Call {
+                        function: "f".into(),
+                        span: Span::new(35, 39, 2, 10),
+                    },
+                    Call {
+                        function: "g".into(),
+                        span: Span::new(43, 46, 3, 1),
+                    },
+                ],
+            }
+        );
+        // Calling a function with the wrong arguments fails at the call.
+        assert_eq!(
+            backtrace("fn f(a) {} let a = [1]; a[f(1, 2)] = 2;"),
+            Backtrace {
+                span: Some(Span::new(26, 33, 1, 27)),
+                calls: vec![],
+            }
+        );
+        assert_eq!(
+            backtrace("let a = [1];\na[1] += 2;"),
+            Backtrace {
+                span: Some(Span::new(13, 23, 2, 1)),
+                calls: vec![],
+            }
+        );
+    }
+}
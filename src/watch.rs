@@ -0,0 +1,94 @@
+//! Waiting for source files to change, for `compyl check --watch`. The
+//! standard library has no file system notifications, so this polls the
+//! files' modification times.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// The modification time of each of a set of files, `None` for those that are
+/// missing. A directory counts with its subdirectories, whose times change as
+/// files are added to or removed from them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot(BTreeMap<PathBuf, Option<SystemTime>>);
+
+impl Snapshot {
+    pub fn take<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Snapshot {
+        let mut times = BTreeMap::new();
+        let mut pending: Vec<PathBuf> = paths.into_iter().map(Path::to_path_buf).collect();
+        while let Some(path) = pending.pop() {
+            let metadata = fs::metadata(&path).ok();
+            if metadata.as_ref().is_some_and(|metadata| metadata.is_dir()) {
+                let entries = fs::read_dir(&path).into_iter().flatten().flatten();
+                pending.extend(
+                    entries
+                        .map(|entry| entry.path())
+                        .filter(|path| path.is_dir()),
+                );
+            }
+            let modified = metadata.and_then(|metadata| metadata.modified().ok());
+            times.insert(path, modified);
+        }
+        Snapshot(times)
+    }
+}
+
+/// Calls `check`, and again each time one of the files it returns changes,
+/// forever. Changes are looked for every `interval`.
+pub fn watch(interval: Duration, mut check: impl FnMut() -> Vec<PathBuf>) -> ! {
+    let mut files: Vec<PathBuf> = Vec::new();
+    loop {
+        // The files of the last check are taken before this one reads them,
+        // so that changes made while it runs are seen. Those it finds for
+        // the first time can only be taken after.
+        let mut before = Snapshot::take(files.iter().map(PathBuf::as_path));
+        let found = check();
+        for (path, modified) in Snapshot::take(found.iter().map(PathBuf::as_path)).0 {
+            before.0.entry(path).or_insert(modified);
+        }
+
+        let watched: Vec<&Path> = files.iter().chain(&found).map(PathBuf::as_path).collect();
+        while Snapshot::take(watched.iter().copied()) == before {
+            thread::sleep(interval);
+        }
+        files = found;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+    use std::{env, fs, process};
+
+    use super::Snapshot;
+
+    #[test]
+    fn test_snapshot() {
+        let dir = env::temp_dir().join(format!("compyl-watch-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("main.cpl");
+        fs::write(&file, "let a = 1;").unwrap();
+        let missing = dir.join("missing.cpl");
+        let paths = [file.as_path(), missing.as_path(), Path::new("testdata")];
+
+        let before = Snapshot::take(paths);
+        assert_eq!(before.0[&missing], None);
+        assert!(before.0.contains_key(Path::new("testdata/modules")));
+        assert_eq!(Snapshot::take(paths), before);
+
+        // Set the time rather than wait for the clock to move on.
+        let later = SystemTime::now() + Duration::from_secs(10);
+        let handle = fs::File::options().write(true).open(&file).unwrap();
+        handle.set_modified(later).unwrap();
+        let after = Snapshot::take(paths);
+        assert_ne!(after, before);
+        assert_eq!(after.0[&file], Some(later));
+
+        fs::write(&missing, "").unwrap();
+        assert_ne!(Snapshot::take(paths), after);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
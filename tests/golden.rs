@@ -0,0 +1,167 @@
+//! Golden-file tests: each `testdata/*.cpl` program is lexed, parsed, checked
+//! and run, and what each phase printed is compared with the file next to it
+//! of the same name: `.tokens`, `.ast`, `.diagnostics` and `.out`. A phase
+//! that prints nothing has no file.
+//!
+//! Run with `UPDATE_SNAPSHOTS=1` to write the files from the current output
+//! instead, and review the changes in the diff.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use compyl::builtins::Io;
+use compyl::diagnostics::Diagnostic;
+use compyl::error::CompileError;
+use compyl::interp::Interpreter;
+use compyl::lexer::{self, Lexer, LexerConfig};
+use compyl::source::SourceManager;
+use compyl::vm::Vm;
+use compyl::{bytecode, lints, sema, types};
+
+/// What each phase printed for one program.
+#[derive(Default)]
+struct Output {
+    tokens: String,
+    ast: String,
+    diagnostics: String,
+    out: String,
+}
+
+fn tokens(source: &str) -> String {
+    let config = LexerConfig {
+        keep_comments: true,
+    };
+    let mut out = String::new();
+    for token in Lexer::with_config(source, config) {
+        match token {
+            Ok(token) => out.push_str(&format!(
+                "{}:{}\t{:?}\n",
+                token.span.line, token.span.column, token.token
+            )),
+            Err(err) => {
+                out.push_str(&format!("error: {}\n", err));
+                break;
+            }
+        }
+    }
+    out
+}
+
+fn run(path: &Path) -> Output {
+    let text = fs::read(path).unwrap();
+    let mut output = Output::default();
+    let mut sources = SourceManager::new();
+    let source = match lexer::decode(&text) {
+        Ok(source) => source,
+        Err(err) => {
+            output.diagnostics = Diagnostic::from(&err).render(&path.display().to_string(), "");
+            return output;
+        }
+    };
+    output.tokens = tokens(source);
+
+    let root = sources.add(path, source);
+    let mut report = |sources: &SourceManager, diags: Vec<Diagnostic>| {
+        for diag in diags {
+            output.diagnostics.push_str(&sources.render(&diag));
+        }
+    };
+    let program = match sources.link(root) {
+        Ok(program) => program,
+        Err(err) => {
+            report(&sources, err.diagnostics());
+            return output;
+        }
+    };
+    output.ast = program.to_string();
+
+    let checked = sema::analyze(&program)
+        .map_err(CompileError::from)
+        .and_then(|resolution| {
+            types::check(&program, &resolution)?;
+            Ok(resolution)
+        });
+    let resolution = match checked {
+        Ok(resolution) => resolution,
+        Err(err) => {
+            report(&sources, err.diagnostics());
+            return output;
+        }
+    };
+    let lints = lints::check(&program, &resolution);
+    report(&sources, lints.iter().map(Diagnostic::from).collect());
+
+    let mut interp = Interpreter::with_io(Io::buffered(""));
+    let result = interp.run(&program);
+    let printed = interp.io().take_output();
+
+    // The VM must agree with the interpreter.
+    let mut vm = Vm::with_io(Io::buffered(""));
+    let module = bytecode::compile(&program, &resolution);
+    assert_eq!(
+        vm.run(&module),
+        result,
+        "the VM disagrees on {}",
+        path.display()
+    );
+    assert_eq!(vm.io().take_output(), printed);
+
+    output.out = printed;
+    if let Err(err) = result {
+        output.out.push_str(&format!("error: {}\n", err));
+    }
+    output
+}
+
+fn programs() -> Vec<PathBuf> {
+    let mut paths: Vec<_> = fs::read_dir("testdata")
+        .expect("the tests run from the crate root")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "cpl"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn golden() {
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some_and(|value| value == "1");
+    let mut failures = Vec::new();
+    for path in programs() {
+        let output = run(&path);
+        let phases = [
+            ("tokens", &output.tokens),
+            ("ast", &output.ast),
+            ("diagnostics", &output.diagnostics),
+            ("out", &output.out),
+        ];
+        for (extension, actual) in phases {
+            let expected_path = path.with_extension(extension);
+            if update {
+                match actual.is_empty() {
+                    true if expected_path.exists() => fs::remove_file(&expected_path).unwrap(),
+                    true => {}
+                    false => fs::write(&expected_path, actual).unwrap(),
+                }
+                continue;
+            }
+
+            let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+            if expected != *actual {
+                failures.push(format!(
+                    "{}:\n--- expected\n{}--- actual\n{}",
+                    expected_path.display(),
+                    expected,
+                    actual
+                ));
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} snapshots differ; rerun with UPDATE_SNAPSHOTS=1 to accept them\n\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}